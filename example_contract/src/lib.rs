@@ -1,6 +1,6 @@
 #![no_std]
 
-use starstream::{token_import, utxo_import, PublicKey};
+use starstream::{token_import, utxo_import, utxo_owner, PublicKey};
 
 // "starstream:example_contract" should probably be something content-addressed
 #[link(wasm_import_module = "starstream_utxo:example_contract")]
@@ -11,7 +11,6 @@ unsafe extern "C" {
 
     safe fn starstream_new_MyMain_new() -> MyMain;
     safe fn starstream_query_MyMain_get_supply(utxo: MyMain) -> u32;
-    safe fn starstream_handle_MyMain_my_effect(handler: on_my_effect) -> on_my_effect;
 
     safe fn starstream_new_StarToken_new(owner: PublicKey, amount: u64) -> StarToken;
     safe fn starstream_query_StarToken_get_owner(utxo: StarToken) -> PublicKey;
@@ -31,6 +30,13 @@ utxo_import! {
     starstream_status_PayToPublicKeyHash;
     starstream_resume_PayToPublicKeyHash;
     ();
+    ();
+}
+
+utxo_owner! {
+    from "starstream_utxo:example_contract";
+    for PayToPublicKeyHash;
+    query fn starstream_query_PayToPublicKeyHash_get_owner;
 }
 
 impl PayToPublicKeyHash {
@@ -53,6 +59,7 @@ utxo_import! {
     starstream_status_MyMain;
     starstream_resume_MyMain;
     ();
+    ();
 }
 
 impl MyMain {
@@ -61,28 +68,19 @@ impl MyMain {
         starstream_new_MyMain_new()
     }
 
-    pub fn handle_my_effect<R, F: FnOnce() -> R>(scope: F, handler: on_my_effect) -> R {
-        let old = starstream_handle_MyMain_my_effect(handler);
-        let r = scope();
-        starstream_handle_MyMain_my_effect(old);
-        r
-    }
-
     #[inline]
     pub fn get_supply(self) -> u32 {
         starstream_query_MyMain_get_supply(self)
     }
 }
 
-#[allow(non_camel_case_types)]
-pub type on_my_effect = extern "C" fn(supply: u32);
-
 utxo_import! {
     "starstream_utxo:example_contract";
     StarToken;
     starstream_status_StarToken;
     starstream_resume_StarToken;
     ();
+    ();
 }
 
 impl StarToken {
@@ -113,6 +111,7 @@ utxo_import! {
     starstream_status_StarNftMint;
     starstream_resume_StarNftMint;
     ();
+    ();
 }
 
 impl StarNftMint {
@@ -139,3 +138,5 @@ token_import! {
     mint fn starstream_mint_StarNft;
     burn fn starstream_burn_StarNft;
 }
+
+starstream::assert_abi_layout!(StarNftIntermediate, 8, 8, id: 0);
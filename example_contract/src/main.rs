@@ -3,7 +3,9 @@
 #![allow(dead_code)]
 
 use example_contract::{StarNft, StarNftIntermediate};
-use starstream::{assert_tx_signed_by, token_export, PublicKey, Token, TokenStorage};
+use starstream::{
+    assert_tx_signed_by, owner_export, token_export, FfiSafe, Owned, PublicKey, Token, TokenStorage,
+};
 
 // fn foo(_: A, _: B, sleep: fn(Yield) -> (E, F)) -> Yield
 // entry point name: "foo"
@@ -43,6 +45,8 @@ unsafe extern "C" {
 }
 */
 
+#[derive(FfiSafe)]
+#[repr(C)]
 pub struct PayToPublicKeyHash {
     owner: PublicKey,
 }
@@ -72,8 +76,16 @@ impl PayToPublicKeyHash {
     }
 }
 
+impl Owned for PayToPublicKeyHash {
+    fn owner(&self) -> PublicKey {
+        self.owner
+    }
+}
+
 // This is kind of a cheap UTXO that is meant to function like a "Star" token.
 // This isn't how tokens are planned to be represented in the final design.
+#[derive(FfiSafe)]
+#[repr(C)]
 pub struct StarToken {
     owner: PublicKey,
     amount: u64,
@@ -117,10 +129,14 @@ impl StarToken {
     }
 }
 
+#[derive(FfiSafe)]
+#[repr(C)]
 pub struct MyMain {
     supply: u32,
 }
 
+starstream::assert_abi_layout!(MyMain, 4, 4, supply: 0);
+
 impl MyMain {
     //#[starstream::new]
     pub fn new(sleep: fn(&MyMain)) {
@@ -129,7 +145,7 @@ impl MyMain {
             supply += 1;
             //my_event(supply);
             starstream::log(10 + supply);
-            //my_effect(supply);
+            let () = starstream::raise!(my_effect(&supply));
             starstream::log(20 + supply);
             //my_error(supply);
             sleep(&MyMain { supply });
@@ -142,10 +158,15 @@ impl MyMain {
     }
 }
 
+#[derive(FfiSafe)]
+#[repr(C)]
 pub struct StarNftMint {
     supply: u64,
 }
 
+starstream::assert_abi_layout!(StarNftMint, 8, 8, supply: 0);
+
+#[starstream::utxo]
 impl StarNftMint {
     // `sleep` is supplied by the scheduler and suspends execution.
     pub fn new(max_supply: u64, sleep: fn(&mut StarNftMint)) {
@@ -222,29 +243,14 @@ pub unsafe extern "C" fn starstream_consume_StarToken_burn(this: *mut StarToken)
     core::ptr::read(this).burn()
 }
 
-#[no_mangle]
-pub extern "C" fn starstream_new_StarNftMint_new(max_supply: u64) {
-    StarNftMint::new(max_supply, starstream::sleep_mut::<(), StarNftMint>)
-}
-
-#[no_mangle]
-pub extern "C" fn starstream_query_StarNftMint_get_supply(this: &StarNftMint) -> u64 {
-    this.get_supply()
-}
-
-#[no_mangle]
-pub extern "C" fn starstream_mutate_StarNftMint_prepare_to_mint(this: &mut StarNftMint) -> StarNftIntermediate {
-    this.prepare_to_mint()
-}
-
 #[no_mangle]
 pub extern "C" fn starstream_new_PayToPublicKeyHash_new(owner: PublicKey) {
     PayToPublicKeyHash::new(owner, starstream::sleep_mut::<(), PayToPublicKeyHash>)
 }
 
-#[no_mangle]
-pub extern "C" fn starstream_query_PayToPublicKeyHash_get_owner(this: &PayToPublicKeyHash) -> PublicKey {
-    this.get_owner()
+owner_export! {
+    for PayToPublicKeyHash;
+    query fn starstream_query_PayToPublicKeyHash_get_owner;
 }
 
 #[no_mangle]
@@ -1,35 +1,29 @@
 #![no_std]
 #![no_main]
 
-use example_contract::{MyMain, StarNftMint, StarToken};
-use starstream::{PublicKey, Utxo};
+use example_contract::{MyMain, StarNft, StarNftMint, StarToken};
+use starstream::{coordination_export, handle, tx, PublicKey, Utxo};
 
-extern "C" fn my_effect_handler(supply: u32) {
-    starstream::log(100 + supply);
-}
-
-// This is the tap that makes this freely mintable.
-#[no_mangle]
-pub extern "C" fn star_mint(owner: PublicKey, amount: u64) -> StarToken {
-    StarToken::new(owner, amount)
-}
+coordination_export! {
+    // This is the tap that makes this freely mintable.
+    fn star_mint(owner: PublicKey, amount: u64) -> StarToken {
+        StarToken::new(owner, amount)
+    }
 
-#[no_mangle]
-pub extern "C" fn new_nft() -> StarNftMint {
-    StarNftMint::new(u64::MAX)
-}
+    fn new_nft() -> StarNftMint {
+        StarNftMint::new(u64::MAX)
+    }
 
-#[no_mangle]
-pub extern "C" fn mint_seven_nfts(nft_contract: StarNftMint) {
-    for _ in 0..7 {
-        nft_contract.next();
+    fn mint_seven_nfts(nft_contract: StarNftMint) {
+        for _ in 0..7 {
+            nft_contract.next();
+        }
     }
-}
 
-#[no_mangle]
-pub extern "C" fn mint_until_10_nfts(nft_contract: StarNftMint) {
-    while nft_contract.get_supply() < 10 {
-        nft_contract.next();
+    fn mint_until_10_nfts(nft_contract: StarNftMint) {
+        while nft_contract.get_supply() < 10 {
+            nft_contract.next();
+        }
     }
 }
 
@@ -58,20 +52,56 @@ pub extern "C" fn star_split(from: Utxo<StarToken>, amount: u64) -> Utxo<StarTok
 }
      */
 
-#[no_mangle]
-pub fn produce() {
-    // All UTXOs that aren't exhausted are implicitly part of the output.
-    MyMain::handle_my_effect(
-        || {
-            _ = MyMain::new();
-        },
-        my_effect_handler,
-    );
-    // ^ not pretty but it illustrates the implementation
-}
+coordination_export! {
+    fn produce() {
+        // All UTXOs that aren't exhausted are implicitly part of the output.
+        handle! {
+            fn my_effect(supply: u32) -> () {
+                starstream::log(100 + supply);
+            }
+            in {
+                _ = MyMain::new();
+            }
+        }
+    }
 
-#[no_mangle]
-pub fn consume(utxo: MyMain) {
-    utxo.get_supply();
-    utxo.next();
+    fn consume(utxo: MyMain) {
+        utxo.get_supply();
+        utxo.next();
+    }
+
+    // Resume `utxo` once, returning its supply after.
+    fn bump(utxo: MyMain) -> u32 {
+        handle! {
+            fn my_effect(_supply: u32) -> () {}
+            in {
+                utxo.next();
+                utxo.get_supply()
+            }
+        }
+    }
+
+    // Traps: `utxo` hasn't accepted a resume from this script, so the host
+    // won't let it take what `utxo` carries.
+    fn detach_unresumed(utxo: MyMain) {
+        let _nft: StarNft = utxo.detach(1);
+    }
+
+    // A resume that `tx::atomic` rolls back leaves the UTXO as it was, so
+    // it resumes from there again and this returns 2.
+    fn resume_after_rollback() -> u32 {
+        handle! {
+            fn my_effect(_supply: u32) -> () {}
+            in {
+                let utxo = MyMain::new();
+                let _ = tx::atomic(|_| {
+                    utxo.next();
+                    Err::<(), ()>(())
+                });
+                assert!(utxo.get_supply() == 1);
+                utxo.next();
+                utxo.get_supply()
+            }
+        }
+    }
 }
@@ -1,4 +1,6 @@
+import assert from "assert";
 import binaryen from "binaryen";
+import { createHash } from "crypto";
 import { readFile } from "fs/promises";
 import { inspect } from "util";
 
@@ -37,6 +39,9 @@ function asyncify(blob: Uint8Array): Uint8Array {
     "asyncify-imports",
     [
       `env.${UtxoEnv.prototype.starstream_yield.name}`,
+      `env.${UtxoEnv.prototype.starstream_yield_mut.name}`,
+      `env.${UtxoEnv.prototype.starstream_yield_small.name}`,
+      `env.${UtxoEnv.prototype.starstream_yield_mut_small.name}`,
       //`env.${UtxoInstance.utxoEnv.starstream_effect_my_effect.name}`,
     ].join(),
   );
@@ -50,6 +55,47 @@ function asyncify(blob: Uint8Array): Uint8Array {
 
 // ----------------------------------------------------------------------------
 
+/** Mirrors `starstream::UtxoStatus`. */
+enum UtxoStatus {
+  Returned = 0,
+  Yielded = 1,
+  Consumed = 2,
+  Errored = 3,
+}
+
+/** Mirrors `starstream::HostError`. */
+enum HostError {
+  NotFound = 1,
+  AlreadyConsumed = 2,
+  SizeMismatch = 3,
+  Unauthorized = 4,
+  Reentered = 5,
+  TooDeep = 6,
+  InsufficientFunds = 7,
+  BadEncoding = 8,
+  BudgetExceeded = 9,
+  Poisoned = 10,
+}
+
+/** The `starstream_abort` code the guest panic handler uses; mirrors `starstream::env::PANIC_CODE`. */
+const PANIC_CODE = 0;
+
+/** A contract stopping itself with `env::abort_with`, or by panicking with `PANIC_CODE`. */
+class ContractAbort extends Error {
+  constructor(readonly code: number, readonly reason: string) {
+    super(code === PANIC_CODE ? `contract panicked: ${reason}` : `contract aborted with code ${code}: ${reason}`);
+  }
+}
+
+/** A failure reported to the calling contract through `starstream_last_error` instead of trapping it. */
+class HostFailure extends Error {
+  constructor(readonly code: HostError, message: string) {
+    super(message);
+  }
+}
+
+// ----------------------------------------------------------------------------
+
 function fakeModule(message: string, items: Record<string, WebAssembly.ModuleImportDescriptor>): WebAssembly.ModuleImports {
   const r: WebAssembly.ModuleImports = {};
   for (const [k, v] of Object.entries(items)) {
@@ -68,7 +114,10 @@ function collateImports(module: WebAssembly.Module) {
   return Object.entries(r);
 }
 
-/** Generate a random integer in the range [1, 1 << 30]. */
+/** Mirrors `TokenHandle::INVALID` and `UtxoHandle::INVALID`: "no handle", returned by lookups that find nothing and failed mints. */
+const INVALID_HANDLE = 0;
+
+/** Generate a random integer in the range [1, 1 << 30], so never `INVALID_HANDLE`. */
 function randomU32() {
   return Math.ceil((1 - Math.random()) * (1 << 30));
 }
@@ -77,22 +126,68 @@ function randomU32() {
 
 type ContractCodeId = string;
 
+/** Mirrors `starstream::METADATA_SECTION` and `starstream::METADATA_VERSION_EXPORT`. */
+const METADATA_SECTION = "starstream.metadata.v1";
+const METADATA_VERSION_EXPORT = "starstream_metadata_version";
+
+/** The ABI this host implements; mirrors `starstream::ABI_VERSION`. */
+const ABI_VERSION = 1;
+
+/** Mirrors `starstream::StateId::of`: FNV-1a of the last `::` segment of the name. */
+function stateId(name: string): number {
+  let hash = 0x811c9dc5;
+  for (const byte of new TextEncoder().encode(name.split("::").pop())) {
+    hash = Math.imul(hash ^ byte, 0x01000193) >>> 0;
+  }
+  return hash;
+}
+
+/** The name of a `StateId`: registered with a `state` metadata line, or `#` and the ID in hex. */
+function stateName(code: ContractCode, state: number): string {
+  return code.states.get(state) ?? `#${state.toString(16).padStart(8, "0")}`;
+}
+
+/** The lines of the metadata section; throws if the module vouches for one that was stripped. */
+function readMetadata(module: WebAssembly.Module): string[] {
+  const sections = WebAssembly.Module.customSections(module, METADATA_SECTION);
+  if (sections.length === 0) {
+    if (WebAssembly.Module.exports(module).some((e) => e.name === METADATA_VERSION_EXPORT)) {
+      throw new Error(`${METADATA_SECTION} was stripped`);
+    }
+    return [];
+  }
+  const decoder = new TextDecoder();
+  return sections.flatMap((section) => decoder.decode(section).split("\n").filter((line) => line));
+}
+
 class ContractCode {
   readonly module: WebAssembly.Module;
   readonly hash: ArrayBufferLike;
+  readonly metadata: string[];
+  /** Names of the states registered in the metadata, by `StateId`. */
+  readonly states: Map<number, string>;
 
   readonly #wasm: Uint8Array | null;
 
-  private constructor(module: WebAssembly.Module, hash: ArrayBufferLike, wasm: Uint8Array | null) {
+  private constructor(module: WebAssembly.Module, hash: ArrayBufferLike, metadata: string[], wasm: Uint8Array | null) {
     this.module = module;
     this.hash = hash;
+    this.metadata = metadata;
+    this.states = new Map(
+      metadata
+        .filter((line) => line.startsWith("state "))
+        .map((line) => line.substring("state ".length))
+        .map((name) => [stateId(name), name]),
+    );
     this.#wasm = wasm;
   }
 
   static async load(wasm: Uint8Array): Promise<ContractCode> {
+    const module = new WebAssembly.Module(wasm);
     return new ContractCode(
-      new WebAssembly.Module(wasm),
+      module,
       await crypto.subtle.digest("sha-256", wasm),
+      readMetadata(module),
       wasm,
     );
   }
@@ -102,6 +197,7 @@ class ContractCode {
       return new ContractCode(
         new WebAssembly.Module(asyncify(this.#wasm)),
         this.hash,
+        this.metadata,
         null,
       );
     } else {
@@ -118,7 +214,154 @@ interface CoordinationContext {
 
 // TODO: needs to be asynclocal or something crazy?
 let coordinationContext: CoordinationContext | null = null;
-const effectHandlers = new Map<string, Function>();
+/** Coordination scripts currently running, innermost (most recently called) last. */
+const coordinationStack: CoordinationScriptInstance[] = [];
+/** Limit on nested `starstream_call`s. */
+const MAX_CALL_DEPTH = 8;
+/** Effects currently being handled, innermost last. */
+const effectStack: { payload: Uint8Array, replySize: number, reply?: Uint8Array }[] = [];
+
+/** The secp256k1 curve, y² = x³ + 7 over `p`, with generator `g` of order `n`. */
+const SECP256K1 = {
+  p: 0xfffffffffffffffffffffffffffffffffffffffffffffffffffffffefffffc2fn,
+  n: 0xfffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141n,
+  g: [
+    0x79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798n,
+    0x483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8n,
+  ] as Point,
+};
+
+/** An affine curve point, or `null` for the point at infinity. */
+type Point = [bigint, bigint] | null;
+
+function mod(a: bigint, m: bigint): bigint {
+  const r = a % m;
+  return r < 0n ? r + m : r;
+}
+
+function modPow(base: bigint, exponent: bigint, m: bigint): bigint {
+  let result = 1n;
+  for (base = mod(base, m); exponent > 0n; exponent >>= 1n) {
+    if (exponent & 1n) result = (result * base) % m;
+    base = (base * base) % m;
+  }
+  return result;
+}
+
+function modInverse(a: bigint, m: bigint): bigint {
+  let [r0, r1, x0, x1] = [m, mod(a, m), 0n, 1n];
+  while (r1 !== 0n) {
+    const q = r0 / r1;
+    [r0, r1] = [r1, r0 - q * r1];
+    [x0, x1] = [x1, x0 - q * x1];
+  }
+  return mod(x0, m);
+}
+
+function pointAdd(a: Point, b: Point): Point {
+  const { p } = SECP256K1;
+  if (!a) return b;
+  if (!b) return a;
+  if (a[0] === b[0] && mod(a[1] + b[1], p) === 0n) return null;
+  const slope = a[0] === b[0]
+    ? mod(3n * a[0] * a[0] * modInverse(2n * a[1], p), p)
+    : mod((b[1] - a[1]) * modInverse(b[0] - a[0], p), p);
+  const x = mod(slope * slope - a[0] - b[0], p);
+  return [x, mod(slope * (a[0] - x) - a[1], p)];
+}
+
+function pointMul(k: bigint, point: Point): Point {
+  let result: Point = null;
+  for (; k > 0n; k >>= 1n) {
+    if (k & 1n) result = pointAdd(result, point);
+    point = pointAdd(point, point);
+  }
+  return result;
+}
+
+/**
+ * The public key, uncompressed and without its tag, that made `signature`
+ * (`r`, `s`, and `v`) over `hash`, as Ethereum's `ecrecover` finds it.
+ */
+function secp256k1Recover(hash: Uint8Array, signature: Uint8Array): Uint8Array | undefined {
+  const { p, n, g } = SECP256K1;
+  const big = (bytes: Uint8Array) => BigInt("0x" + Buffer.from(bytes).toString("hex"));
+  const [r, s, v] = [big(signature.subarray(0, 32)), big(signature.subarray(32, 64)), signature[64]];
+  const id = v >= 27 ? v - 27 : v;
+  if (id > 1 || r === 0n || r >= n || s === 0n || s >= n) return undefined;
+  // The point whose x is `r` and whose y has the parity of the recovery id.
+  const ySquared = mod(r * r * r + 7n, p);
+  let y = modPow(ySquared, (p + 1n) / 4n, p);
+  if ((y * y) % p !== ySquared) return undefined;
+  if ((y & 1n) !== BigInt(id)) y = p - y;
+  const rInverse = modInverse(r, n);
+  const e = mod(big(hash), n);
+  const key = pointAdd(pointMul(mod(s * rInverse, n), [r, y]), pointMul(mod(-e * rInverse, n), g));
+  if (!key) return undefined;
+  return Buffer.from(key.map(c => c.toString(16).padStart(64, "0")).join(""), "hex");
+}
+
+/** BLAKE2b's initialization vector, which is SHA-512's. */
+const BLAKE2B_IV = [
+  0x6a09e667f3bcc908n, 0xbb67ae8584caa73bn, 0x3c6ef372fe94f82bn, 0xa54ff53a5f1d36f1n,
+  0x510e527fade682d1n, 0x9b05688c2b3e6c1fn, 0x1f83d9abfb41bd6bn, 0x5be0cd19137e2179n,
+];
+
+/** The message word order of each BLAKE2b round; rounds 10 and 11 repeat the first two. */
+const BLAKE2B_SIGMA = [
+  [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+  [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+  [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+  [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+  [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+  [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+  [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+  [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+  [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+  [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/** BLAKE2b-256 (RFC 7693, unkeyed), which Node's `crypto` only has with a 64-byte digest. */
+function blake2b256(message: Uint8Array): Uint8Array {
+  const add = (a: bigint, b: bigint) => BigInt.asUintN(64, a + b);
+  const rotr = (x: bigint, n: bigint) => BigInt.asUintN(64, (x >> n) | (x << (64n - n)));
+  const h = [...BLAKE2B_IV];
+  h[0] ^= 0x01010020n;
+  // An empty message is one block of zeros.
+  const count = Math.max(1, Math.ceil(message.length / 128));
+  for (let i = 0; i < count; ++i) {
+    const block = new Uint8Array(128);
+    block.set(message.subarray(i * 128, (i + 1) * 128));
+    const m = new BigUint64Array(block.buffer);
+    const v = [...h, ...BLAKE2B_IV];
+    const bytes = BigInt(Math.min(message.length, (i + 1) * 128));
+    v[12] ^= bytes;
+    if (i + 1 === count) v[14] ^= 0xffffffffffffffffn;
+    const mix = (a: number, b: number, c: number, d: number, x: bigint, y: bigint) => {
+      v[a] = add(add(v[a], v[b]), x);
+      v[d] = rotr(v[d] ^ v[a], 32n);
+      v[c] = add(v[c], v[d]);
+      v[b] = rotr(v[b] ^ v[c], 24n);
+      v[a] = add(add(v[a], v[b]), y);
+      v[d] = rotr(v[d] ^ v[a], 16n);
+      v[c] = add(v[c], v[d]);
+      v[b] = rotr(v[b] ^ v[c], 63n);
+    };
+    for (let round = 0; round < 12; ++round) {
+      const s = BLAKE2B_SIGMA[round % 10];
+      mix(0, 4, 8, 12, m[s[0]], m[s[1]]);
+      mix(1, 5, 9, 13, m[s[2]], m[s[3]]);
+      mix(2, 6, 10, 14, m[s[4]], m[s[5]]);
+      mix(3, 7, 11, 15, m[s[6]], m[s[7]]);
+      mix(0, 5, 10, 15, m[s[8]], m[s[9]]);
+      mix(1, 6, 11, 12, m[s[10]], m[s[11]]);
+      mix(2, 7, 8, 13, m[s[12]], m[s[13]]);
+      mix(3, 4, 9, 14, m[s[14]], m[s[15]]);
+    }
+    for (let j = 0; j < 8; ++j) h[j] ^= v[j] ^ v[j + 8];
+  }
+  return new Uint8Array(BigUint64Array.from(h.slice(0, 4)).buffer);
+}
 
 /** Fulfiller of imports from `env` */
 class StarstreamEnv {
@@ -126,19 +369,82 @@ class StarstreamEnv {
     private readonly me: ContractInstance,
   ) {
     this.abort = this.abort.bind(this);
+    this.starstream_abort = this.starstream_abort.bind(this);
     this.starstream_log = this.starstream_log.bind(this);
+    this.starstream_log_bytes = this.starstream_log_bytes.bind(this);
     this.starstream_coordination_code = this.starstream_coordination_code.bind(this);
     this.starstream_this_code = this.starstream_this_code.bind(this);
+    this.starstream_is_coordination = this.starstream_is_coordination.bind(this);
+    this.starstream_is_simulation = this.starstream_is_simulation.bind(this);
+    this.starstream_caller_code = this.starstream_caller_code.bind(this);
+    this.starstream_block_height = this.starstream_block_height.bind(this);
+    this.starstream_block_time = this.starstream_block_time.bind(this);
+    this.starstream_chain_id = this.starstream_chain_id.bind(this);
+    this.starstream_host_abi_version = this.starstream_host_abi_version.bind(this);
+    this.starstream_sha256 = this.starstream_sha256.bind(this);
+    this.starstream_blake2b_256 = this.starstream_blake2b_256.bind(this);
+    this.starstream_tx_fee = this.starstream_tx_fee.bind(this);
+    this.starstream_tx_pay_fee = this.starstream_tx_pay_fee.bind(this);
+    this.starstream_last_error = this.starstream_last_error.bind(this);
+    this.starstream_utxo_id = this.starstream_utxo_id.bind(this);
+    this.starstream_utxo_expire = this.starstream_utxo_expire.bind(this);
+    this.starstream_utxo_last_error = this.starstream_utxo_last_error.bind(this);
+    this.starstream_utxo_memo = this.starstream_utxo_memo.bind(this);
+    this.starstream_utxo_resume_batch = this.starstream_utxo_resume_batch.bind(this);
+    this.starstream_utxo_is = this.starstream_utxo_is.bind(this);
+    this.starstream_find_utxos = this.starstream_find_utxos.bind(this);
+    this.starstream_lookup_utxo = this.starstream_lookup_utxo.bind(this);
+    this.starstream_utxo_attach = this.starstream_utxo_attach.bind(this);
+    this.starstream_utxo_detach = this.starstream_utxo_detach.bind(this);
+    this.starstream_utxo_detach_amount = this.starstream_utxo_detach_amount.bind(this);
+    this.starstream_utxo_tokens = this.starstream_utxo_tokens.bind(this);
+    this.starstream_tx_inputs = this.starstream_tx_inputs.bind(this);
+    this.starstream_tx_outputs = this.starstream_tx_outputs.bind(this);
+    this.starstream_tx_signer_count = this.starstream_tx_signer_count.bind(this);
+    this.starstream_output_begin = this.starstream_output_begin.bind(this);
+    this.starstream_output_args = this.starstream_output_args.bind(this);
+    this.starstream_output_attach = this.starstream_output_attach.bind(this);
+    this.starstream_output_finish = this.starstream_output_finish.bind(this);
+    this.starstream_handle = this.starstream_handle.bind(this);
+    this.starstream_call = this.starstream_call.bind(this);
+    this.starstream_tx_payload_len = this.starstream_tx_payload_len.bind(this);
+    this.starstream_tx_payload = this.starstream_tx_payload.bind(this);
+    this.starstream_tx_result = this.starstream_tx_result.bind(this);
+    this.starstream_tx_require_code = this.starstream_tx_require_code.bind(this);
+    this.starstream_emit_event = this.starstream_emit_event.bind(this);
+    this.starstream_emit_event_parts = this.starstream_emit_event_parts.bind(this);
+    this.starstream_tx_event = this.starstream_tx_event.bind(this);
+    this.starstream_scratch_set = this.starstream_scratch_set.bind(this);
+    this.starstream_scratch_get = this.starstream_scratch_get.bind(this);
+    this.starstream_blob_new = this.starstream_blob_new.bind(this);
+    this.starstream_blob_len = this.starstream_blob_len.bind(this);
+    this.starstream_blob_read = this.starstream_blob_read.bind(this);
+    this.starstream_bridge_root = this.starstream_bridge_root.bind(this);
+    this.starstream_secp256k1_recover = this.starstream_secp256k1_recover.bind(this);
+    this.starstream_checkpoint = this.starstream_checkpoint.bind(this);
+    this.starstream_rollback = this.starstream_rollback.bind(this);
+    this.starstream_release = this.starstream_release.bind(this);
+    this.starstream_effect_payload = this.starstream_effect_payload.bind(this);
+    this.starstream_effect_reply = this.starstream_effect_reply.bind(this);
   }
 
   abort() {
     throw new Error("abort() called");
   }
 
+  starstream_abort(code: number, msg: number, msg_len: number): never {
+    const reason = new TextDecoder().decode(new Uint8Array(this.me.memory.buffer, msg, msg_len));
+    throw new ContractAbort(code >>> 0, reason);
+  }
+
   starstream_log(...args: unknown[]) {
     console.log('starstream_log', ...args);
   }
 
+  starstream_log_bytes(ptr: number, len: number) {
+    console.log('starstream_log', new TextDecoder().decode(new Uint8Array(this.me.memory.buffer, ptr, len)));
+  }
+
   starstream_coordination_code(return_addr: number) {
     // TODO: this should probably be an effect?
     if (!coordinationContext) {
@@ -151,34 +457,780 @@ class StarstreamEnv {
   starstream_this_code(return_addr: number) {
     new Uint8Array(this.me.memory.buffer, return_addr, 32).set(new Uint8Array(this.me.code.hash));
   }
+
+  starstream_is_coordination(): boolean {
+    return this.me instanceof CoordinationScriptInstance;
+  }
+
+  starstream_is_simulation(): boolean {
+    return this.me.universe.simulating;
+  }
+
+  starstream_caller_code(return_addr: number) {
+    const caller = coordinationStack[coordinationStack.length - 1];
+    if (!caller) {
+      throw new Error("no coordination script is running");
+    }
+    new Uint8Array(this.me.memory.buffer, return_addr, 32).set(new Uint8Array(caller.code.hash));
+  }
+
+  starstream_block_height(): bigint {
+    return this.me.universe.blockHeight;
+  }
+
+  starstream_block_time(): bigint {
+    return this.me.universe.blockTime;
+  }
+
+  starstream_tx_fee(): bigint {
+    return this.#coordination().universe.fee;
+  }
+
+  starstream_tx_pay_fee(token_handle: number, amount: bigint) {
+    const me = this.#coordination();
+    me.fallible(undefined, () => {
+      const token = me.tokens.get(token_handle);
+      if (!token) {
+        throw new HostFailure(HostError.NotFound, `bad token handle: ${token_handle}`);
+      } else if (token.amount < amount) {
+        throw new HostFailure(HostError.InsufficientFunds, `token holds ${token.amount}, fee payment is ${amount}`);
+      }
+      // TODO: Only accept the chain's fee token, rather than any token at face value.
+      token.amount -= amount;
+      if (token.amount === 0n) {
+        me.takeToken(token_handle);
+      }
+      me.universe.feePaid += amount;
+    });
+  }
+
+  starstream_chain_id(): bigint {
+    return this.me.universe.chainId;
+  }
+
+  starstream_host_abi_version(): number {
+    return ABI_VERSION;
+  }
+
+  starstream_sha256(parts: number, count: number, out: number) {
+    const hash = createHash("sha256");
+    // struct Part { ptr: *const u8, len: usize }
+    const table = new Uint32Array(this.me.memory.buffer, parts, count * 2);
+    for (let i = 0; i < count; ++i) {
+      hash.update(new Uint8Array(this.me.memory.buffer, table[2 * i], table[2 * i + 1]));
+    }
+    new Uint8Array(this.me.memory.buffer, out, 32).set(hash.digest());
+  }
+
+  starstream_blake2b_256(parts: number, count: number, out: number) {
+    // struct Part { ptr: *const u8, len: usize }
+    const table = new Uint32Array(this.me.memory.buffer, parts, count * 2);
+    const message = Buffer.concat(Array.from({ length: count }, (_, i) =>
+      new Uint8Array(this.me.memory.buffer, table[2 * i], table[2 * i + 1])));
+    new Uint8Array(this.me.memory.buffer, out, 32).set(blake2b256(message));
+  }
+
+  starstream_secp256k1_recover(hash: number, signature: number, out: number): boolean {
+    const key = secp256k1Recover(
+      new Uint8Array(this.me.memory.buffer, hash, 32),
+      new Uint8Array(this.me.memory.buffer, signature, 65),
+    );
+    if (!key) {
+      return false;
+    }
+    new Uint8Array(this.me.memory.buffer, out, 64).set(key);
+    return true;
+  }
+
+  starstream_last_error(return_addr: number) {
+    new Uint32Array(this.me.memory.buffer, return_addr, 2).set(this.me.callError ?? [0, 0]);
+  }
+
+  #coordination(): CoordinationScriptInstance {
+    if (!(this.me instanceof CoordinationScriptInstance)) {
+      throw new Error("available in Coordination context only");
+    }
+    return this.me;
+  }
+
+  starstream_utxo_id(return_addr: number, utxo_handle: number) {
+    const me = this.#coordination();
+    new Uint8Array(me.memory.buffer, return_addr, 32).set(me.getUtxo(utxo_handle).id);
+  }
+
+  starstream_utxo_expire(utxo_handle: number) {
+    const me = this.#coordination();
+    const utxo = me.getUtxo(utxo_handle);
+    const instance = utxo.load();
+    instance.expire();
+    if (instance.rejection === 0 && instance.status() !== UtxoStatus.Errored) {
+      me.resumed.add(utxo);
+    }
+  }
+
+  starstream_utxo_last_error(return_addr: number, utxo_handle: number) {
+    const me = this.#coordination();
+    const error = me.getUtxo(utxo_handle).load().lastError;
+    new Uint32Array(me.memory.buffer, return_addr, 2).set(
+      error?.kind === "rejected" ? [1, error.code] :
+      error?.kind === "errored" ? [2, 0] :
+      [0, 0]
+    );
+  }
+
+  starstream_utxo_memo(utxo_handle: number, out: number) {
+    const me = this.#coordination();
+    new Uint8Array(me.memory.buffer, out, 64).set(me.getUtxo(utxo_handle).memo);
+  }
+
+  starstream_utxo_resume_batch(entries: number, len: number): number {
+    const me = this.#coordination();
+    // struct BatchResume { utxo: u32, arg: *const u8, arg_len: usize }
+    const view = new Uint32Array(me.memory.buffer, entries, len * 3);
+    for (let i = 0; i < len; ++i) {
+      const [utxo_handle, arg, arg_len] = view.subarray(i * 3, i * 3 + 3);
+      // As for one resume: a failure is reported through
+      // `starstream_last_error`, and ends the batch at this entry.
+      const failed = me.fallible(true, () => {
+        const utxo = me.getUtxo(utxo_handle);
+        const instance = utxo.load();
+        instance.resume(new Uint8Array(me.memory.buffer).slice(arg, arg + arg_len));
+        const rejected = instance.takeRejection();
+        if (rejected !== 0) {
+          me.callError = [1, rejected];
+          return true;
+        } else if (instance.status() === UtxoStatus.Errored) {
+          me.callError = [2, 0];
+          return true;
+        }
+        me.resumed.add(utxo);
+        return false;
+      });
+      if (failed) {
+        // 1-based index of the failed entry.
+        return i + 1;
+      }
+    }
+    return 0;
+  }
+
+  starstream_utxo_is(utxo_handle: number, module: number, module_len: number, name: number, name_len: number): boolean {
+    const me = this.#coordination();
+    const decoder = new TextDecoder();
+    const codeId = decoder.decode(new Uint8Array(me.memory.buffer, module, module_len)).substring("starstream_utxo:".length);
+    const typeName = decoder.decode(new Uint8Array(me.memory.buffer, name, name_len));
+    const utxo = me.getUtxo(utxo_handle);
+    return utxo.codeId === codeId && utxo.typeName === typeName;
+  }
+
+  /** Write one handle per distinct UTXO matching `filter`, paginated like `starstream_find_utxos`. */
+  #txUtxos(filter: (utxo: Utxo) => boolean, cursor: number, out: number, out_len: number): number {
+    const me = this.#coordination();
+    const seen = new Map<Utxo, number>();
+    for (const [handle, utxo] of me.utxos) {
+      if (!seen.has(utxo) && filter(utxo)) {
+        seen.set(utxo, handle);
+      }
+    }
+    const page = [...seen.values()].slice(cursor, cursor + out_len);
+    new Uint32Array(me.memory.buffer, out, page.length).set(page);
+    return page.length;
+  }
+
+  starstream_tx_inputs(cursor: number, out: number, out_len: number): number {
+    // The universe's UTXO set isn't updated until the transaction ends.
+    return this.#txUtxos(utxo => this.me.universe.utxos.has(utxo), cursor, out, out_len);
+  }
+
+  starstream_tx_outputs(cursor: number, out: number, out_len: number): number {
+    return this.#txUtxos(utxo => !this.me.universe.utxos.has(utxo) && utxo.isAlive(), cursor, out, out_len);
+  }
+
+  starstream_tx_signer_count(): number {
+    return this.#coordination().signers.length;
+  }
+
+  starstream_call(
+    code: number,
+    entry: number,
+    entry_len: number,
+    args: number,
+    args_size: number,
+    ret: number,
+    ret_size: number,
+  ): number {
+    const me = this.#coordination();
+    return me.fallible(1, () => {
+      const hash = new Uint8Array(me.memory.buffer, code, 32);
+      const known = [...me.universe.contractCode.values()].find(c => {
+        const h = new Uint8Array(c.hash);
+        return h.every((b, i) => b === hash[i]);
+      });
+      if (!known) {
+        throw new HostFailure(HostError.NotFound, "no contract with that code hash");
+      } else if (coordinationStack.some(script => script.code === known)) {
+        throw new HostFailure(HostError.Reentered, "script is already running in this transaction");
+      } else if (coordinationStack.length >= MAX_CALL_DEPTH) {
+        throw new HostFailure(HostError.TooDeep, `calls nested deeper than ${MAX_CALL_DEPTH}`);
+      }
+      const callee = new CoordinationScriptInstance(me.universe, known, me.signers, me);
+      const name = new TextDecoder().decode(new Uint8Array(me.memory.buffer, entry, entry_len));
+      const f = callee.wasm.exports[name];
+      if (typeof f !== "function") {
+        throw new HostFailure(HostError.NotFound, `no entry point ${name}`);
+      }
+      callee.payload = new Uint8Array(me.memory.buffer).slice(args, args + args_size);
+      coordinationStack.push(callee);
+      me.universe.ranScripts.add(Buffer.from(known.hash).toString("hex"));
+      try {
+        // A trap in the callee fails the whole transaction.
+        f();
+      } finally {
+        coordinationStack.pop();
+      }
+      const returned = callee.result ?? new Uint8Array(0);
+      if (returned.byteLength !== ret_size) {
+        throw new HostFailure(HostError.SizeMismatch, "return value size mismatch");
+      }
+      new Uint8Array(me.memory.buffer, ret, ret_size).set(returned);
+      return 0;
+    });
+  }
+
+  starstream_tx_payload_len(): number {
+    return this.#coordination().payload.byteLength;
+  }
+
+  starstream_tx_payload(payload: number, payload_size: number) {
+    const me = this.#coordination();
+    if (me.payload.byteLength !== payload_size) {
+      throw new Error(`malformed payload: got ${me.payload.byteLength} bytes, but the script asked for ${payload_size}`);
+    }
+    new Uint8Array(me.memory.buffer, payload, payload_size).set(me.payload);
+  }
+
+  starstream_tx_result(result: number, result_size: number) {
+    const me = this.#coordination();
+    me.result = new Uint8Array(me.memory.buffer).slice(result, result + result_size);
+  }
+
+  starstream_tx_require_code(code: number) {
+    // Also available to UTXOs and tokens.
+    const hash = new Uint8Array(this.me.memory.buffer, code, 32);
+    this.me.universe.requiredScripts.add(Buffer.from(hash).toString("hex"));
+  }
+
+  // Events are available to UTXOs and tokens too.
+  starstream_emit_event(topic: number, topic_len: number, payload: number, payload_size: number) {
+    const { buffer } = this.me.memory;
+    const event = {
+      topic: new TextDecoder().decode(new Uint8Array(buffer, topic, topic_len)),
+      payload: new Uint8Array(buffer).slice(payload, payload + payload_size),
+    };
+    console.log('EVENT', event.topic, event.payload);
+    this.me.universe.events.push(event);
+  }
+
+  starstream_emit_event_parts(topic: number, topic_len: number, parts: number, count: number) {
+    const { buffer } = this.me.memory;
+    // struct Part { ptr: *const u8, len: usize }
+    const table = new Uint32Array(buffer, parts, count * 2);
+    const payload = Buffer.concat([...Array(count).keys()].map(i => new Uint8Array(buffer, table[2 * i], table[2 * i + 1])));
+    const event = {
+      topic: new TextDecoder().decode(new Uint8Array(buffer, topic, topic_len)),
+      payload: new Uint8Array(payload),
+    };
+    console.log('EVENT', event.topic, event.payload);
+    this.me.universe.events.push(event);
+  }
+
+  starstream_tx_event(topic: number, topic_len: number, index: number, payload: number, payload_size: number): boolean {
+    const { buffer } = this.me.memory;
+    const want = new TextDecoder().decode(new Uint8Array(buffer, topic, topic_len));
+    const event = this.me.universe.events.filter(event => event.topic === want)[index];
+    if (!event) {
+      return false;
+    } else if (event.payload.byteLength !== payload_size) {
+      throw new Error(`event payload size mismatch: got ${event.payload.byteLength} bytes, expected ${payload_size}`);
+    }
+    new Uint8Array(buffer, payload, payload_size).set(event.payload);
+    return true;
+  }
+
+  // Scratch storage is available to UTXOs and tokens too.
+  starstream_scratch_set(key: number, key_len: number, value: number, value_len: number) {
+    const { buffer } = this.me.memory;
+    const hex = Buffer.from(new Uint8Array(buffer, key, key_len)).toString("hex");
+    this.me.universe.scratch.set(hex, new Uint8Array(buffer).slice(value, value + value_len));
+  }
+
+  starstream_scratch_get(key: number, key_len: number, out: number, out_len: number, value_len: number): boolean {
+    const { buffer } = this.me.memory;
+    const hex = Buffer.from(new Uint8Array(buffer, key, key_len)).toString("hex");
+    const value = this.me.universe.scratch.get(hex);
+    if (!value) {
+      return false;
+    }
+    new Uint8Array(buffer, out, out_len).set(value.subarray(0, out_len));
+    new Uint32Array(buffer, value_len, 1)[0] = value.byteLength;
+    return true;
+  }
+
+  // Blobs too, and outlive `tx::atomic` rollbacks since they never change.
+  starstream_blob_new(ptr: number, len: number): number {
+    const { blobs } = this.me.universe;
+    blobs.push(new Uint8Array(this.me.memory.buffer).slice(ptr, ptr + len));
+    return blobs.length;
+  }
+
+  starstream_blob_len(blob: number): number {
+    return this.#blob(blob).byteLength;
+  }
+
+  starstream_blob_read(blob: number, offset: number, out: number, out_len: number): number {
+    const rest = this.#blob(blob).subarray(offset, offset + out_len);
+    new Uint8Array(this.me.memory.buffer, out, rest.byteLength).set(rest);
+    return rest.byteLength;
+  }
+
+  #blob(blob: number): Uint8Array {
+    const bytes = this.me.universe.blobs[blob - 1];
+    if (!bytes) {
+      throw new Error(`no blob with handle ${blob}`);
+    }
+    return bytes;
+  }
+
+  starstream_bridge_root(client: bigint, height: number, out: number): boolean {
+    const [revisionNumber, revisionHeight] = new BigUint64Array(this.me.memory.buffer, height, 2);
+    const root = this.me.universe.bridgeRoots.get(`${client}:${revisionNumber}:${revisionHeight}`);
+    if (!root) {
+      return false;
+    }
+    new Uint8Array(this.me.memory.buffer, out, 32).set(root);
+    return true;
+  }
+
+  starstream_checkpoint(): number {
+    const me = this.#coordination();
+    me.universe.checkpoints.push(me.universe.checkpoint(me));
+    return me.universe.checkpoints.length;
+  }
+
+  starstream_rollback(checkpoint: number) {
+    this.#endCheckpoint(checkpoint)();
+  }
+
+  starstream_release(checkpoint: number) {
+    this.#endCheckpoint(checkpoint);
+  }
+
+  /** Pop `checkpoint`, returning the function that restores it. */
+  #endCheckpoint(checkpoint: number): () => void {
+    const { checkpoints } = this.#coordination().universe;
+    if (checkpoint !== checkpoints.length) {
+      throw new Error(`checkpoint ${checkpoint} ended out of order; innermost is ${checkpoints.length}`);
+    }
+    return checkpoints.pop()!;
+  }
+
+  starstream_handle(effect: number, effect_len: number, handler: number): number {
+    const me = this.#coordination();
+    const name = new TextDecoder().decode(new Uint8Array(me.memory.buffer, effect, effect_len));
+    const old = me.effectHandlers.get(name) ?? 0;
+    if (handler === 0) {
+      me.effectHandlers.delete(name);
+    } else {
+      me.effectHandlers.set(name, handler);
+    }
+    return old;
+  }
+
+  #currentEffect() {
+    this.#coordination();
+    const current = effectStack[effectStack.length - 1];
+    if (!current) {
+      throw new Error("not handling an effect");
+    }
+    return current;
+  }
+
+  starstream_effect_payload(payload: number, payload_size: number) {
+    const current = this.#currentEffect();
+    if (current.payload.byteLength !== payload_size) {
+      throw new Error("effect payload size mismatch");
+    }
+    new Uint8Array(this.me.memory.buffer, payload, payload_size).set(current.payload);
+  }
+
+  starstream_effect_reply(reply: number, reply_size: number) {
+    const current = this.#currentEffect();
+    if (current.replySize !== reply_size) {
+      throw new Error("effect reply size mismatch");
+    }
+    current.reply = new Uint8Array(this.me.memory.buffer).slice(reply, reply + reply_size);
+  }
+
+  starstream_output_begin(code: number, entry: number, entry_len: number): number {
+    const me = this.#coordination();
+    const handle = randomU32();
+    me.pendingOutputs.set(handle, {
+      codeId: me.universe.getCodeIdByHash(new Uint8Array(me.memory.buffer, code, 32)),
+      entryPoint: new TextDecoder().decode(new Uint8Array(me.memory.buffer, entry, entry_len)),
+      initArgs: undefined,
+      tokens: [],
+    });
+    return handle;
+  }
+
+  #pendingOutput(output: number): PendingOutput {
+    const pending = this.#coordination().pendingOutputs.get(output);
+    if (!pending) {
+      throw new Error(`Invalid output handle: ${output}`);
+    }
+    return pending;
+  }
+
+  starstream_output_args(output: number, args: number, args_size: number) {
+    this.#pendingOutput(output).initArgs = new Uint8Array(this.me.memory.buffer).slice(args, args + args_size);
+  }
+
+  starstream_output_attach(output: number, token_handle: number) {
+    this.#pendingOutput(output).tokens.push(token_handle);
+  }
+
+  starstream_output_finish(output: number) {
+    const me = this.#coordination();
+    const pending = this.#pendingOutput(output);
+    me.pendingOutputs.delete(output);
+    // Taking the tokens now keeps them from counting as unattached.
+    me.outputs.push({ ...pending, tokens: pending.tokens.map(handle => me.takeToken(handle)) });
+  }
+
+  starstream_find_utxos(code: number, owner: number, cursor: number, out: number, out_len: number): number {
+    const me = this.#coordination();
+    const codeHash = code ? new Uint8Array(me.memory.buffer, code, 32) : undefined;
+    const page = [...me.universe.utxos]
+      .filter(utxo => utxo.isAlive())
+      .filter(utxo => !codeHash || utxo.universe.getCodeIdByHash(codeHash) === utxo.codeId)
+      // TODO: compare keys once PublicKey has a representation.
+      .filter(utxo => !owner || utxo.load().hasQuery("get_owner"))
+      .slice(cursor, cursor + out_len);
+    new Uint32Array(me.memory.buffer, out, page.length).set(page.map(utxo => me.setUtxo(utxo)));
+    return page.length;
+  }
+
+  starstream_lookup_utxo(id: number): number {
+    const me = this.#coordination();
+    const want = new Uint8Array(me.memory.buffer, id, 32);
+    const utxo = [...me.universe.utxos, ...me.utxos.values()]
+      .find(utxo => utxo.isAlive() && utxo.id.every((b, i) => b === want[i]));
+    if (!utxo) {
+      return INVALID_HANDLE;
+    }
+    return me.setUtxo(utxo);
+  }
+
+  starstream_utxo_attach(utxo_handle: number, token_handle: number) {
+    const me = this.#coordination();
+    const token = me.takeToken(token_handle);
+    me.getUtxo(utxo_handle).tokens.add(token);
+  }
+
+  /** The UTXO and token type a detach names, once the UTXO allows it. */
+  #detachable(utxo_handle: number, token_module: number, token_module_len: number, token_name: number, token_name_len: number) {
+    const me = this.#coordination();
+    const decoder = new TextDecoder();
+    const module = decoder.decode(new Uint8Array(me.memory.buffer, token_module, token_module_len));
+    const name = decoder.decode(new Uint8Array(me.memory.buffer, token_name, token_name_len));
+    const code = me.universe.getCodeSync(module.substring("starstream_token:".length));
+    const utxo = me.getUtxo(utxo_handle);
+    // The UTXO authorizes the script when it accepts its resume: otherwise
+    // any script could take what a UTXO holds.
+    if (!me.resumed.has(utxo)) {
+      throw new Error(`UTXO hasn't accepted a resume from this script in this transaction, so its tokens can't be detached`);
+    }
+    return { me, utxo, code, name };
+  }
+
+  starstream_utxo_detach(
+    utxo_handle: number,
+    token_module: number,
+    token_module_len: number,
+    token_name: number,
+    token_name_len: number,
+    amount: bigint,
+  ): number {
+    const { me, utxo, code, name } = this.#detachable(utxo_handle, token_module, token_module_len, token_name, token_name_len);
+    for (const token of utxo.tokens) {
+      if (token.is(code, name) && token.amount === amount) {
+        utxo.tokens.delete(token);
+        return me.setToken(token);
+      }
+    }
+    throw new Error(`UTXO carries no ${name} token with amount ${amount}`);
+  }
+
+  starstream_utxo_detach_amount(
+    utxo_handle: number,
+    token_module: number,
+    token_module_len: number,
+    token_name: number,
+    token_name_len: number,
+    amount: bigint,
+  ): number {
+    const { me, utxo, code, name } = this.#detachable(utxo_handle, token_module, token_module_len, token_name, token_name_len);
+    const held = [...utxo.tokens].filter(token => token.is(code, name));
+    const total = held.reduce((sum, token) => sum + token.amount, 0n);
+    if (amount === 0n) {
+      throw new Error(`Cannot detach 0 ${name}`);
+    } else if (total < amount) {
+      throw new Error(`UTXO carries ${total} ${name}, less than ${amount}`);
+    } else if (held.some(token => token.id !== held[0].id)) {
+      throw new Error(`UTXO carries ${name} tokens with different IDs, which can't be combined`);
+    }
+    // Take whole tokens while they fit, and split the rest off the next.
+    let detached: Token | undefined;
+    let rest = amount;
+    for (const token of held) {
+      if (rest === 0n) {
+        break;
+      }
+      let part = token;
+      if (token.amount <= rest) {
+        utxo.tokens.delete(token);
+      } else {
+        part = token.split(rest);
+      }
+      rest -= part.amount;
+      if (detached) {
+        detached.amount += part.amount;
+      } else {
+        detached = part;
+      }
+    }
+    return me.setToken(detached!);
+  }
+
+  starstream_utxo_tokens(utxo_handle: number, cursor: number, out: number, out_len: number): number {
+    const me = this.#coordination();
+    // struct AnyToken { code: [u8; 32], name: [u8; 32], id: u64, amount: u64 }
+    const SIZE = 80;
+    const page = [...me.getUtxo(utxo_handle).tokens].slice(cursor, cursor + out_len);
+    page.forEach((token, i) => {
+      const base = out + i * SIZE;
+      new Uint8Array(me.memory.buffer, base, 32).set(new Uint8Array(token.codeHash));
+      const name = new Uint8Array(me.memory.buffer, base + 32, 32);
+      name.fill(0);
+      name.set(new TextEncoder().encode(token.name).subarray(0, 32));
+      new BigUint64Array(me.memory.buffer, base + 64, 2).set([token.id, token.amount]);
+    });
+    return page.length;
+  }
 }
 
 /** Fulfiller of imports from `starstream_utxo_env` */
 class UtxoEnv {
+  /** Deadline declared for the next yield, if any. */
+  #deadline: bigint | undefined;
+  /** Where a small yield's resume argument waits to be returned on rewind. */
+  #resumeWord = new Uint8Array(8);
+
   constructor(
     private readonly me: UtxoInstance,
   ) {
     this.starstream_yield = this.starstream_yield.bind(this);
+    this.starstream_yield_mut = this.starstream_yield_mut.bind(this);
+    this.starstream_yield_small = this.starstream_yield_small.bind(this);
+    this.starstream_yield_mut_small = this.starstream_yield_mut_small.bind(this);
+    this.starstream_set_deadline = this.starstream_set_deadline.bind(this);
+    this.starstream_expired = this.starstream_expired.bind(this);
+    this.starstream_reject = this.starstream_reject.bind(this);
+    this.starstream_migrate = this.starstream_migrate.bind(this);
+    this.starstream_migrated_state = this.starstream_migrated_state.bind(this);
+    this.starstream_init_args = this.starstream_init_args.bind(this);
+    this.starstream_set_memo = this.starstream_set_memo.bind(this);
+    this.starstream_call_count = this.starstream_call_count.bind(this);
+    this.starstream_raise = this.starstream_raise.bind(this);
+    this.starstream_channel_send = this.starstream_channel_send.bind(this);
+    this.starstream_channel_recv = this.starstream_channel_recv.bind(this);
+  }
+
+  starstream_channel_send(to: number, message: number, message_size: number) {
+    const memory = this.me.exports.memory.buffer;
+    this.me.universe.inbox(new Uint8Array(memory, to, 32)).push(new Uint8Array(memory).slice(message, message + message_size));
+  }
+
+  starstream_channel_recv(message: number, message_size: number): boolean {
+    const next = this.me.universe.inbox(this.me.utxo.id).shift();
+    if (!next) {
+      return false;
+    } else if (next.byteLength !== message_size) {
+      throw new Error("channel message size mismatch");
+    }
+    new Uint8Array(this.me.exports.memory.buffer, message, message_size).set(next);
+    return true;
+  }
+
+  starstream_migrate(new_code: number, entry: number, entry_len: number, state: number, state_size: number) {
+    const memory = this.me.exports.memory.buffer;
+    this.me.migration = {
+      codeHash: new Uint8Array(memory).slice(new_code, new_code + 32),
+      entryPoint: new TextDecoder().decode(new Uint8Array(memory, entry, entry_len)),
+      state: new Uint8Array(memory).slice(state, state + state_size),
+    };
+  }
+
+  starstream_migrated_state(state: number, state_size: number) {
+    const migrated = this.me.migratedState;
+    if (!migrated) {
+      throw new Error("not started by a migration");
+    } else if (migrated.byteLength !== state_size) {
+      throw new Error("migrated state size mismatch");
+    }
+    new Uint8Array(this.me.exports.memory.buffer, state, state_size).set(migrated);
+  }
+
+  starstream_raise(
+    effect: number,
+    effect_len: number,
+    payload: number,
+    payload_size: number,
+    reply: number,
+    reply_size: number,
+  ) {
+    const memory = this.me.exports.memory.buffer;
+    const name = new TextDecoder().decode(new Uint8Array(memory, effect, effect_len));
+    const script = [...coordinationStack].reverse().find(script => script.effectHandlers.has(name));
+    if (!script) {
+      throw new Error(`unhandled effect: ${name}`);
+    }
+    effectStack.push({ payload: new Uint8Array(memory).slice(payload, payload + payload_size), replySize: reply_size });
+    let handled;
+    try {
+      script.getFunctionPointer(script.effectHandlers.get(name)!)();
+    } finally {
+      handled = effectStack.pop()!;
+    }
+    if (!handled.reply) {
+      throw new Error(`effect handler for ${name} did not reply`);
+    }
+    new Uint8Array(this.me.exports.memory.buffer, reply, reply_size).set(handled.reply);
+  }
+
+  starstream_set_memo(memo: number) {
+    this.me.utxo.memo.set(new Uint8Array(this.me.exports.memory.buffer, memo, 64));
+  }
+
+  starstream_call_count(): number {
+    return this.me.callCount;
+  }
+
+  starstream_init_args(args: number, args_size: number) {
+    const initArgs = this.me.utxo.initArgs;
+    if (!initArgs) {
+      throw new Error("not started by a spawn");
+    } else if (initArgs.byteLength !== args_size) {
+      throw new Error("init args size mismatch");
+    }
+    new Uint8Array(this.me.exports.memory.buffer, args, args_size).set(initArgs);
+  }
+
+  starstream_reject(code: number) {
+    this.me.rejection = code;
+    this.me.lastError = { kind: "rejected", code };
+  }
+
+  starstream_set_deadline(block: bigint) {
+    this.#deadline = block;
+  }
+
+  starstream_expired(): boolean {
+    return this.me.expired;
   }
 
   starstream_yield(
-    name: number,
-    name_len: number,
+    state: number,
     data: number,
     data_size: number,
     resume_arg: number,
     resume_arg_size: number,
+  ) {
+    this.#yield(false, state, data, data_size, this.#view(resume_arg, resume_arg_size));
+  }
+
+  /** Like `starstream_yield`, but the host may patch `data` before resuming. */
+  starstream_yield_mut(
+    state: number,
+    data: number,
+    data_size: number,
+    resume_arg: number,
+    resume_arg_size: number,
+  ) {
+    this.#yield(true, state, data, data_size, this.#view(resume_arg, resume_arg_size));
+  }
+
+  /** Like `starstream_yield`, but the resume argument (at most 8 bytes) is returned as a little-endian i64. */
+  starstream_yield_small(
+    state: number,
+    data: number,
+    data_size: number,
+    resume_arg_size: number,
+  ): bigint {
+    return this.#yieldSmall(false, state, data, data_size, resume_arg_size);
+  }
+
+  /** Like `starstream_yield_mut`, returning the resume argument as in `starstream_yield_small`. */
+  starstream_yield_mut_small(
+    state: number,
+    data: number,
+    data_size: number,
+    resume_arg_size: number,
+  ): bigint {
+    return this.#yieldSmall(true, state, data, data_size, resume_arg_size);
+  }
+
+  #view(ptr: number, size: number): Uint8Array {
+    return new Uint8Array(this.me.exports.memory.buffer, ptr, size);
+  }
+
+  #yieldSmall(
+    mutable: boolean,
+    state: number,
+    data: number,
+    data_size: number,
+    resume_arg_size: number,
+  ): bigint {
+    if (resume_arg_size > 8) {
+      throw new Error(`small yield with a ${resume_arg_size}-byte resume argument`);
+    }
+    if (this.me.exports.asyncify_get_state() == AsyncifyState.NORMAL) {
+      this.#resumeWord.fill(0);
+    }
+    this.#yield(mutable, state, data, data_size, this.#resumeWord.subarray(0, resume_arg_size));
+    return new DataView(this.#resumeWord.buffer).getBigUint64(0, true);
+  }
+
+  #yield(
+    mutable: boolean,
+    state: number,
+    data: number,
+    data_size: number,
+    resume_arg: Uint8Array,
   ) {
     const view = new Int32Array(this.me.exports.memory.buffer);
     if (this.me.exports.asyncify_get_state() == AsyncifyState.NORMAL) {
       this.me._setState({
         state: "yielded",
         yielded: {
-          type_name: new Uint8Array(this.me.exports.memory.buffer, name, name_len),
+          state: state >>> 0,
           data: new Uint8Array(this.me.exports.memory.buffer, data, data_size),
-          resume_arg: new Uint8Array(this.me.exports.memory.buffer, resume_arg, resume_arg_size),
+          resume_arg,
+          deadline: this.#deadline,
+          mutable,
         },
       });
+      this.#deadline = undefined;
       view[STACK_START >> 2] = STACK_START + 8;
       view[(STACK_START + 4) >> 2] = STACK_END;
       this.me.exports.asyncify_start_unwind(STACK_START);
@@ -201,13 +1253,27 @@ class UtxoImport {
       if (entry.kind === "function") {
         if (entry.name.startsWith("starstream_status_")) {
           this[entry.name] = (utxo_handle: number) => {
-            return me.getUtxo(utxo_handle).isAlive();
+            return me.getUtxo(utxo_handle).status();
           };
         } else if (entry.name.startsWith("starstream_resume_")) {
-          this[entry.name] = (utxo_handle: number, resume_arg: number, resume_arg_size: number) => {
+          this[entry.name] = (utxo_handle: number, resume_arg: number, resume_arg_size: number, yield_out: number, yield_out_size: number) => me.fallible(1, () => {
             const slice = new Uint8Array(me.memory.buffer).slice(resume_arg, resume_arg + resume_arg_size);
-            me.getUtxo(utxo_handle).load().resume(slice);
-          };
+            const utxo = me.getUtxo(utxo_handle);
+            const instance = utxo.load();
+            instance.resume(slice);
+            const rejected = instance.takeRejection();
+            if (rejected !== 0) {
+              me.callError = [1, rejected];
+              return 1;
+            } else if (instance.status() === UtxoStatus.Errored) {
+              me.callError = [2, 0];
+              return 1;
+            }
+            me.resumed.add(utxo);
+            // Copy what the UTXO yielded (or returned) back to the caller.
+            new Uint8Array(me.memory.buffer, yield_out, yield_out_size).set(instance.resumeResult(yield_out_size));
+            return 0;
+          });
         } else if (entry.name.startsWith("starstream_new_")) {
           this[entry.name] = (...args: unknown[]) => {
             console.log('NEW', entry.name, args);
@@ -215,6 +1281,30 @@ class UtxoImport {
             utxo.load().start(...args);
             return me.setUtxo(utxo);
           };
+        } else if (entry.name.startsWith("starstream_spawn_")) {
+          // Like starstream_new_, but the arguments are passed as one struct
+          // the entry point reads with `env::init_args`.
+          this[entry.name] = (args: number, args_size: number) => {
+            const initArgs = new Uint8Array(me.memory.buffer).slice(args, args + args_size);
+            const entryPoint = entry.name.replace(/^starstream_spawn_/, "starstream_new_");
+            const utxo = new Utxo(me.universe, targetCodeId, entryPoint, initArgs);
+            utxo.load().start();
+            return me.setUtxo(utxo);
+          };
+        } else if (entry.name.startsWith("starstream_state_")) {
+          this[entry.name] = (utxo_handle: number, state: number, data_out: number, data_out_size: number) => {
+            const data = me.getUtxo(utxo_handle).load().yieldedAs(state >>> 0);
+            if (!data) {
+              return 0;
+            }
+            if (data_out_size !== 0) {
+              if (data.byteLength !== data_out_size) {
+                throw new Error("state size mismatch");
+              }
+              new Uint8Array(me.memory.buffer, data_out, data_out_size).set(data);
+            }
+            return 1;
+          };
         } else if (entry.name.startsWith("starstream_query_")) {
           // query = &self
           this[entry.name] = (utxo_handle: number, ...args: unknown[]) => {
@@ -234,17 +1324,6 @@ class UtxoImport {
           this[entry.name] = (...args: unknown[]) => {
             console.log('EVENT', ...args);
           }
-        } else if (entry.name.startsWith("starstream_handle_")) {
-          this[entry.name] = (handler: number) => {
-            console.log('HANDLER =', handler);
-            if (handler == 0) {
-              effectHandlers.delete(entry.name);
-            } else {
-              effectHandlers.set(entry.name, (...args: unknown[]) => {
-                me.getFunctionPointer(handler)(...args);
-              });
-            }
-          }
         } else {
           throw new Error("bad import " + JSON.stringify(entry));
         }
@@ -266,26 +1345,67 @@ class TokenImport {
     targetCodeId: ContractCodeId,
     want: Record<string, WebAssembly.ModuleImportDescriptor>,
   ) {
+    const mint = (mintFn: string, args: unknown[]) => me.fallible(INVALID_HANDLE, () => {
+      const handle = randomU32();
+      let token;
+      try {
+        token = me.universe.tokenMint(targetCodeId, mintFn, args);
+      } catch (error) {
+        throw new HostFailure(HostError.Unauthorized, `${mintFn} refused: ${error}`);
+      }
+      this.#tokens.set(handle, token);
+      me.utxo.tokens.add(token);
+      return handle;
+    });
+    // TODO: the fallback assumes a single u64 intermediate, as StarNft has.
+    const burn = (burnFn: string, handle: number) => me.fallible<unknown>(0n, () => {
+      const token = this.#tokens.get(handle);
+      if (!token) {
+        throw new HostFailure(HostError.NotFound, "bad token handle to burn");
+      }
+      let intermediate;
+      try {
+        intermediate = token.burn(burnFn);
+      } catch (error) {
+        throw new HostFailure(HostError.Unauthorized, `${burnFn} refused: ${error}`);
+      }
+      this.#tokens.delete(handle);
+      me.utxo.tokens.delete(token);
+      return intermediate;
+    });
+
     for (const entry of Object.values(want)) {
       if (entry.kind === "function") {
-        if (entry.name.startsWith("starstream_mint_")) {
-          this[entry.name] = (...args: unknown[]) => {
-            const handle = randomU32();
-            const token = me.universe.tokenMint(targetCodeId, entry.name, args);
-            this.#tokens.set(handle, token);
-            me.utxo.tokens.add(token);
-            return handle;
+        // Batch forms stop at the first refusal and return how many succeeded.
+        // Like the single forms, they only pass intermediates as one i32 or i64.
+        if (entry.name.startsWith("starstream_mint_") && entry.name.endsWith("_batch")) {
+          const mintFn = entry.name.slice(0, -"_batch".length);
+          this[entry.name] = (intermediates: number, size: number, count: number, out: number) => {
+            for (let i = 0; i < count; ++i) {
+              const handle = mint(mintFn, [scalarAt(me.memory.buffer, intermediates + i * size, size)]);
+              if (handle === INVALID_HANDLE) {
+                return i;
+              }
+              new DataView(me.memory.buffer).setUint32(out + 4 * i, handle, true);
+            }
+            return count;
           };
-        } else if (entry.name.startsWith("starstream_burn_")) {
-          this[entry.name] = (handle: number) => {
-            const token = this.#tokens.get(handle);
-            if (!token) {
-              throw new Error("bad token handle to burn");
+        } else if (entry.name.startsWith("starstream_burn_") && entry.name.endsWith("_batch")) {
+          const burnFn = entry.name.slice(0, -"_batch".length);
+          this[entry.name] = (handles: number, count: number, out: number, size: number) => {
+            for (let i = 0; i < count; ++i) {
+              const intermediate = burn(burnFn, new DataView(me.memory.buffer).getUint32(handles + 4 * i, true));
+              if (me.callError) {
+                return i;
+              }
+              setScalarAt(me.memory.buffer, out + i * size, size, intermediate);
             }
-            const intermediate = token.burn(entry.name);
-            me.utxo.tokens.delete(token);
-            return intermediate;
+            return count;
           };
+        } else if (entry.name.startsWith("starstream_mint_")) {
+          this[entry.name] = (...args: unknown[]) => mint(entry.name, args);
+        } else if (entry.name.startsWith("starstream_burn_")) {
+          this[entry.name] = (handle: number) => burn(entry.name, handle);
         } else {
           throw new Error("bad import " + JSON.stringify(entry));
         }
@@ -296,6 +1416,25 @@ class TokenImport {
   }
 }
 
+/** The i32 or i64 intermediate of `size` bytes at `addr`. */
+function scalarAt(buffer: ArrayBufferLike, addr: number, size: number): number | bigint {
+  const view = new DataView(buffer);
+  switch (size) {
+    case 4: return view.getUint32(addr, true);
+    case 8: return view.getBigUint64(addr, true);
+    default: throw new Error(`batch token imports only pass 4- or 8-byte intermediates, not ${size}`);
+  }
+}
+
+function setScalarAt(buffer: ArrayBufferLike, addr: number, size: number, value: unknown) {
+  const view = new DataView(buffer);
+  switch (size) {
+    case 4: return view.setUint32(addr, Number(value), true);
+    case 8: return view.setBigUint64(addr, BigInt(value as bigint), true);
+    default: throw new Error(`batch token imports only pass 4- or 8-byte intermediates, not ${size}`);
+  }
+}
+
 // ----------------------------------------------------------------------------
 
 type ContractExports = MemoryExports & Partial<IndirectFunctionTableExports>;
@@ -344,6 +1483,23 @@ class ContractInstance {
     this.memory = this.exports.memory;
   }
 
+  /** Why this instance's most recent fallible host call failed, as `[kind, code]`. */
+  callError: [number, number] | undefined;
+
+  /** Run a fallible host call, reporting a `HostFailure` to the caller instead of trapping it. */
+  fallible<T>(fallback: T, call: () => T): T {
+    this.callError = undefined;
+    try {
+      return call();
+    } catch (error) {
+      if (error instanceof HostFailure) {
+        this.callError = [3, error.code];
+        return fallback;
+      }
+      throw error;
+    }
+  }
+
   getFunction(name: string): Function {
     const f = this.wasm.exports[name];
     if (!f) {
@@ -383,16 +1539,20 @@ class UtxoInstance extends ContractInstance {
   } | {
     state: "yielded",
     yielded: {
-      type_name: Uint8Array,
+      /** The `StateId` yielded in. */
+      state: number,
       data: Uint8Array,
       resume_arg: Uint8Array,
+      deadline?: bigint,
+      /** Yielded with `sleep_mut`, so `resume` may patch `data`. */
+      mutable: boolean,
     },
   } | {
     state: "returned",
     value: unknown,
   } | {
     state: "errored",
-    args: unknown[],
+    error: unknown,
   } | {
     state: "effect",
     effect: string,
@@ -403,6 +1563,29 @@ class UtxoInstance extends ContractInstance {
     state: "not_started"
   };
 
+  /** Whether the current resume is the expiry path of a `sleep_until`. */
+  expired = false;
+  /** Nonzero if the UTXO rejected the current resume with this code. */
+  rejection = 0;
+  /** Set by `migrate_to`; takes effect when the entry point returns. */
+  migration: { codeHash: Uint8Array, entryPoint: string, state: Uint8Array } | undefined;
+  /** State handed over by the migration that started this instance. */
+  migratedState: Uint8Array | undefined;
+  /** Why the most recent resume failed, if it did. */
+  lastError: { kind: "rejected", code: number } | { kind: "errored", error: unknown } | undefined;
+  /** Times entered in the transaction at `#callCountHeight`. Not rolled back, since the work was still done. */
+  callCount = 0;
+  #callCountHeight = -1n;
+
+  /** Count an entry into this UTXO's code, for `env::assert_budget`. */
+  #countCall() {
+    if (this.#callCountHeight !== this.universe.blockHeight) {
+      this.#callCountHeight = this.universe.blockHeight;
+      this.callCount = 0;
+    }
+    this.callCount += 1;
+  }
+
   constructor(
     public readonly utxo: Utxo,
     memory?: Uint8Array
@@ -424,14 +1607,52 @@ class UtxoInstance extends ContractInstance {
     this.#state = s;
   }
 
+  /** Capture everything running this instance can change, returning a function that restores it. */
+  snapshot(): () => void {
+    const memory = new Uint8Array(this.memory.buffer).slice();
+    // Yielded data and resume args are views of memory, or for small yields
+    // of a word every resume writes first, so restoring memory restores
+    // them. Copies would leave resume writing somewhere the UTXO never reads.
+    const state = this.#state;
+    const { expired, rejection, migration, lastError } = this;
+    return () => {
+      // TODO: Also restore globals, such as the stack pointer, if they can differ between yields.
+      new Uint8Array(this.memory.buffer).set(memory);
+      this.#state = state;
+      Object.assign(this, { expired, rejection, migration, lastError });
+    };
+  }
+
+  /** Whether the entry point is on the stack right now. */
+  #running = false;
+
+  /** Refuse to run the entry point again while it is already running. */
+  #checkNotRunning(what: string) {
+    if (this.#running) {
+      throw new HostFailure(HostError.Reentered, `Cannot ${what}() a UTXO from within its own execution`);
+    }
+  }
+
   #raw_resume() {
-    const returned = this.#entryPoint(...this.#start_args!);
+    let returned;
+    this.#running = true;
+    try {
+      returned = this.#entryPoint(...this.#start_args!);
+    } finally {
+      this.#running = false;
+    }
     if (this.exports.asyncify_get_state() == AsyncifyState.NORMAL) {
       // Normal exit; it's spent.
       this.#state = {
         state: "returned",
         value: returned,
       }
+      if (this.migration) {
+        // Same UTXO, new code.
+        const { codeHash, entryPoint, state } = this.migration;
+        this.migration = undefined;
+        return this.utxo.migrate(this.universe.getCodeIdByHash(codeHash), entryPoint, state);
+      }
       return false;
     }
     this.exports.asyncify_stop_unwind();
@@ -439,24 +1660,109 @@ class UtxoInstance extends ContractInstance {
   }
 
   start(...args: unknown[]): boolean {
+    this.#checkNotRunning("start");
     if (this.#state.state !== "not_started") {
       throw new Error("Cannot start() in state " + JSON.stringify(this.#state));
     }
     this.#start_args = args;
+    this.#countCall();
     return this.#raw_resume();
   }
 
-  resume(resume_data?: Uint8Array): boolean {
+  /** Resume, first overwriting the yielded data with `patch` if given. */
+  resume(resume_data?: Uint8Array, patch?: Uint8Array): boolean {
+    this.#checkNotRunning("resume");
     if (this.#state.state !== "yielded") {
-      throw new Error("Cannot resume() in state " + JSON.stringify(this.#state));
+      throw new HostFailure(HostError.AlreadyConsumed, "Cannot resume() in state " + JSON.stringify(this.#state));
+    }
+    const deadline = this.#state.yielded.deadline;
+    if (deadline !== undefined && this.universe.blockHeight > deadline) {
+      throw new Error(`Cannot resume() after deadline ${deadline}; only expire()`);
     }
     if (this.#state.yielded.resume_arg.byteLength !== (resume_data?.byteLength ?? 0)) {
-      throw new Error("resume_arg size mismatch");
+      throw new HostFailure(HostError.SizeMismatch, "resume_arg size mismatch");
     } else if (resume_data) {
       this.#state.yielded.resume_arg.set(resume_data);
     }
+    if (patch) {
+      if (!this.#state.yielded.mutable) {
+        throw new Error("Cannot patch a UTXO that did not yield with sleep_mut");
+      } else if (patch.byteLength !== this.#state.yielded.data.byteLength) {
+        throw new HostFailure(HostError.SizeMismatch, "patch size mismatch");
+      }
+      this.#state.yielded.data.set(patch);
+    }
+    this.expired = false;
+    this.lastError = undefined;
+    this.#countCall();
     this.exports.asyncify_start_rewind(STACK_START);
-    return this.#raw_resume();
+    return this.#guarded_resume();
+  }
+
+  /** Resume, recording a trap as the errored state rather than unwinding the caller. */
+  #guarded_resume(): boolean {
+    try {
+      return this.#raw_resume();
+    } catch (error) {
+      this.#state = { state: "errored", error };
+      this.lastError = { kind: "errored", error };
+      return false;
+    }
+  }
+
+  expire(): boolean {
+    this.#checkNotRunning("expire");
+    if (this.#state.state !== "yielded") {
+      throw new Error("Cannot expire() in state " + JSON.stringify(this.#state));
+    }
+    const deadline = this.#state.yielded.deadline;
+    if (deadline === undefined || this.universe.blockHeight <= deadline) {
+      throw new Error(`Cannot expire() before deadline ${deadline}`);
+    }
+    this.expired = true;
+    this.lastError = undefined;
+    this.#countCall();
+    this.exports.asyncify_start_rewind(STACK_START);
+    return this.#guarded_resume();
+  }
+
+  takeRejection(): number {
+    const code = this.rejection;
+    this.rejection = 0;
+    return code;
+  }
+
+  /** The payload of the most recent yield, or the return value if the UTXO has returned. */
+  resumeResult(size: number): Uint8Array {
+    if (this.#state.state === "yielded") {
+      if (this.#state.yielded.data.byteLength !== size) {
+        throw new Error("yield size mismatch");
+      }
+      return this.#state.yielded.data;
+    } else if (this.#state.state === "returned") {
+      const value = this.#state.value;
+      if (size === 0) {
+        return new Uint8Array(0);
+      } else if (size === 4 && typeof value === "number") {
+        return new Uint8Array(new Uint32Array([value]).buffer);
+      } else if (size === 8 && typeof value === "bigint") {
+        return new Uint8Array(new BigUint64Array([value]).buffer);
+      }
+      throw new Error("return value size mismatch");
+    }
+    throw new Error("Cannot resumeResult() in state " + JSON.stringify(this.#state));
+  }
+
+  /** The yielded payload if the UTXO is currently yielded in `state`. */
+  yieldedAs(state: number): Uint8Array | undefined {
+    if (this.#state.state !== "yielded" || this.#state.yielded.state !== state) {
+      return undefined;
+    }
+    return this.#state.yielded.data;
+  }
+
+  hasQuery(method: string): boolean {
+    return `starstream_query_${this.utxo.typeName}_${method}` in this.wasm.exports;
   }
 
   // &self
@@ -464,6 +1770,7 @@ class UtxoInstance extends ContractInstance {
     if (this.#state.state !== "yielded") {
       throw new Error("Cannot query() in state " + JSON.stringify(this.#state));
     }
+    this.#countCall();
     // TODO: enforce asyncify_get_state is NORMAL after this call
     return (this.wasm.exports[name] as Function)(this.#state.yielded.data.byteOffset, ...args);
   }
@@ -473,6 +1780,7 @@ class UtxoInstance extends ContractInstance {
     if (this.#state.state !== "yielded") {
       throw new Error("Cannot mutate() in state " + JSON.stringify(this.#state));
     }
+    this.#countCall();
     // TODO: enforce asyncify_get_state is NORMAL after this call
     return (this.wasm.exports[name] as Function)(this.#state.yielded.data.byteOffset, ...args);
   }
@@ -482,23 +1790,36 @@ class UtxoInstance extends ContractInstance {
     if (this.#state.state !== "yielded") {
       throw new Error("Cannot consume() in state " + JSON.stringify(this.#state));
     }
+    this.#countCall();
     // TODO: enforce asyncify_get_state is NORMAL after this call
     const r = (this.wasm.exports[name] as Function)(this.#state.yielded.data.byteOffset, ...args);
     this.#state = { state: "consumed" };
     return r;
   }
 
+  status(): UtxoStatus {
+    switch (this.#state.state) {
+      case "returned":
+        return UtxoStatus.Returned;
+      case "consumed":
+        return UtxoStatus.Consumed;
+      case "errored":
+        return UtxoStatus.Errored;
+      default:
+        return UtxoStatus.Yielded;
+    }
+  }
+
   isAlive(): boolean {
-    return this.#state.state !== "returned";
+    return this.status() === UtxoStatus.Yielded;
   }
 
   debug() {
     if (this.#state.state === "yielded") {
       const result: Record<string, any> = {};
-      const name = new TextDecoder().decode(this.#state.yielded.type_name);
-      result.__type = name;
-      const last_part = name.split("::").pop();
-      const prefix = `starstream_query_${last_part}_`;
+      result.__type = this.utxo.typeName;
+      result.__state = stateName(this.code, this.#state.yielded.state);
+      const prefix = `starstream_query_${this.utxo.typeName}_`;
       for (var key of Object.keys(this.wasm.exports)) {
         if (key.startsWith(prefix)) {
           try {
@@ -519,28 +1840,71 @@ class UtxoInstance extends ContractInstance {
 
 class Utxo {
   readonly universe: Universe;
-  readonly codeId: ContractCodeId;
-  readonly entryPoint: string;
+  codeId: ContractCodeId;
+  entryPoint: string;
+  /** Stable for the lifetime of the UTXO, unlike per-transaction handles. */
+  readonly id = crypto.getRandomValues(new Uint8Array(32));
   #loaded?: UtxoInstance;
 
   readonly tokens = new Set<Token>();
+  /** Set by the UTXO itself for display; opaque to the host. */
+  readonly memo = new Uint8Array(64);
+  /** The argument struct this UTXO was spawned with, if any. */
+  readonly initArgs: Uint8Array | undefined;
 
-  constructor(universe: Universe, codeId: ContractCodeId, entryPoint: string) {
+  constructor(universe: Universe, codeId: ContractCodeId, entryPoint: string, initArgs?: Uint8Array) {
     this.universe = universe;
     this.codeId = codeId;
     this.entryPoint = entryPoint;
+    this.initArgs = initArgs;
     this.universe.resolveCode(this.codeId);
   }
 
+  /** The UTXO type, from its `starstream_new_<Type>_<fn>` entry point. */
+  get typeName(): string {
+    return this.entryPoint.replace(/^starstream_new_/, "").split("_")[0];
+  }
+
   unload() {}
 
   load(): UtxoInstance {
     return (this.#loaded ??= new UtxoInstance(this));
   }
 
-  isAlive(): boolean {
+  /** Capture this UTXO's code, instance, tokens, and memo, returning a function that restores them. */
+  snapshot(): () => void {
+    const { codeId, entryPoint } = this;
+    const loaded = this.#loaded;
+    const restoreLoaded = loaded?.snapshot();
+    const tokens = [...this.tokens];
+    const memo = this.memo.slice();
+    return () => {
+      this.codeId = codeId;
+      this.entryPoint = entryPoint;
+      this.#loaded = loaded;
+      restoreLoaded?.();
+      this.tokens.clear();
+      tokens.forEach(token => this.tokens.add(token));
+      this.memo.set(memo);
+    };
+  }
+
+  /** Restart this UTXO at `entryPoint` of other code, keeping its id and tokens. */
+  migrate(codeId: ContractCodeId, entryPoint: string, state: Uint8Array): boolean {
+    this.codeId = codeId;
+    this.entryPoint = entryPoint;
+    this.#loaded = new UtxoInstance(this);
+    this.#loaded.migratedState = state;
+    return this.#loaded.start();
+  }
+
+  status(): UtxoStatus {
     // TODO: know state of UTXO without loading its code and memory
-    return this.#loaded ? this.#loaded.isAlive() : false;
+    return this.#loaded ? this.#loaded.status() : UtxoStatus.Returned;
+  }
+
+  isAlive(): boolean {
+    return this.status() === UtxoStatus.Yielded;
   }
 
   debug() {
@@ -566,11 +1930,16 @@ class Token {
   id: bigint;
   amount: bigint;
 
-  constructor(private universe: Universe, private code: ContractCode, mintFn: string, mintArgs: unknown[]) {
+  /** Mint a token with `mintFn`, or make one holding `storage` without minting. */
+  constructor(private universe: Universe, private code: ContractCode, mintFn: string, mintArgs: unknown[], storage?: [bigint, bigint]) {
     this.#burnFn = mintFn.replace(/^starstream_mint_/, "starstream_burn_");
     if (mintFn === this.#burnFn) {
       throw new Error(`bad mintFn: ${mintFn}`);
     }
+    if (storage) {
+      [this.id, this.amount] = storage;
+      return;
+    }
     const returnAddr = 16;
     const instance = new TokenInstance(universe, code);
     instance.getFunction(mintFn)(returnAddr, ...mintArgs);
@@ -584,6 +1953,28 @@ class Token {
     return new TokenInstance(this.universe, this.code).getFunction(burnFn)(this.id, this.amount);
   }
 
+  /** Take `amount` off this token, as a new token of the same type and ID. */
+  split(amount: bigint): Token {
+    if (amount <= 0n || amount >= this.amount) {
+      throw new Error(`cannot split ${amount} off a token of ${this.amount}`);
+    }
+    this.amount -= amount;
+    const mintFn = this.#burnFn.replace(/^starstream_burn_/, "starstream_mint_");
+    return new Token(this.universe, this.code, mintFn, [], [this.id, amount]);
+  }
+
+  get name(): string {
+    return this.#burnFn.replace(/^starstream_burn_/, "");
+  }
+
+  get codeHash(): ArrayBufferLike {
+    return this.code.hash;
+  }
+
+  is(code: ContractCode, name: string): boolean {
+    return this.code === code && this.#burnFn === `starstream_burn_${name}`;
+  }
+
   debug() {
     return {
       __type: this.#burnFn.replace(/^starstream_burn_/, ""),
@@ -595,11 +1986,44 @@ class Token {
 
 // ----------------------------------------------------------------------------
 
+interface PendingOutput {
+  codeId: ContractCodeId;
+  entryPoint: string;
+  initArgs: Uint8Array | undefined;
+  /** Token handles, until the output is finished. */
+  tokens: number[];
+}
+
 class CoordinationScriptInstance extends ContractInstance {
   utxos = new Map<number, Utxo>();
-
-  constructor(universe: Universe, code: ContractCode) {
+  /** Tokens detached from UTXOs and not yet attached to another. */
+  tokens = new Map<number, Token>();
+  /** Outputs being built with `tx::OutputBuilder`. */
+  pendingOutputs = new Map<number, PendingOutput>();
+  /** Finished outputs, created if the script returns successfully. */
+  outputs: { codeId: ContractCodeId, entryPoint: string, initArgs: Uint8Array | undefined, tokens: Token[] }[] = [];
+  /** Effect handlers registered by this script, as function pointers by effect name. */
+  readonly effectHandlers = new Map<string, number>();
+  /** The transaction payload, or the arguments when run by another script's `starstream_call`. */
+  payload = new Uint8Array(0);
+  /** Set by `starstream_tx_result`; recorded in the receipt, or returned to the calling script. */
+  result: Uint8Array | undefined;
+  /**
+   * UTXOs that accepted a resume or expiry from this script in the current
+   * transaction, and so let it detach their tokens. Not shared with called
+   * scripts, since a UTXO checks which script resumes it.
+   */
+  readonly resumed = new Set<Utxo>();
+
+  constructor(universe: Universe, code: ContractCode, readonly signers: Uint8Array[] = [], caller?: CoordinationScriptInstance) {
     super(universe, code);
+    if (caller) {
+      // A called script runs in the caller's transaction.
+      this.utxos = caller.utxos;
+      this.tokens = caller.tokens;
+      this.pendingOutputs = caller.pendingOutputs;
+      this.outputs = caller.outputs;
+    }
   }
 
   setUtxo(utxo: Utxo): number {
@@ -611,17 +2035,149 @@ class CoordinationScriptInstance extends ContractInstance {
   getUtxo(handle: number): Utxo {
     const utxo = this.utxos.get(handle);
     if (!utxo) {
-      throw new Error(`Invalid UTXO handle: ${handle}; known: ${JSON.stringify(this.utxos)}`);
+      throw new HostFailure(HostError.NotFound, `Invalid UTXO handle: ${handle}; known: ${JSON.stringify(this.utxos)}`);
     }
     return utxo;
   }
+
+  setToken(token: Token): number {
+    const handle = randomU32();
+    this.tokens.set(handle, token);
+    return handle;
+  }
+
+  takeToken(handle: number): Token {
+    const token = this.tokens.get(handle);
+    if (!token) {
+      throw new Error(`Invalid token handle: ${handle}`);
+    }
+    this.tokens.delete(handle);
+    return token;
+  }
 }
 
 // ----------------------------------------------------------------------------
 
+interface Receipt {
+  blockHeight: bigint;
+  entryPoint: string;
+  /** What the entry point passed to `tx::set_result`, if anything. */
+  result: Uint8Array | undefined;
+  feePaid: bigint;
+  events: TxEvent[];
+}
+
+/** Emitted with `tx::emit_event`. */
+interface TxEvent {
+  topic: string;
+  payload: Uint8Array;
+}
+
 class Universe {
   readonly contractCode = new Map<string, ContractCode>();
   readonly utxos = new Set<Utxo>();
+  readonly receipts: Receipt[] = [];
+
+  constructor(
+    /** Distinguishes networks running the same contract code, such as testnet and mainnet. */
+    readonly chainId = 0n,
+  ) {}
+
+  /** In this prototype, every transaction is its own block. */
+  blockHeight = 0n;
+  /** Unix seconds of the current block; never decreases. */
+  blockTime = 0n;
+  /** Flat fee every transaction owes, in any token. */
+  fee = 0n;
+  /** State roots `bridge::root` returns, keyed by `client:revisionNumber:revisionHeight`. */
+  readonly bridgeRoots = new Map<string, Uint8Array>();
+  /** Paid toward the fee of the current transaction so far. */
+  feePaid = 0n;
+  /** Whether the current transaction is a dry run. */
+  simulating = false;
+  /** Code hashes, in hex, of the coordination scripts run in the current transaction. */
+  readonly ranScripts = new Set<string>();
+  /** Code hashes, in hex, that must be in `ranScripts` by the end of the current transaction. */
+  readonly requiredScripts = new Set<string>();
+  /** Events emitted in the current transaction, oldest first. */
+  events: TxEvent[] = [];
+  /** Values stored with `tx::scratch_set` in the current transaction, by hex key. */
+  readonly scratch = new Map<string, Uint8Array>();
+  /** Payloads stored with `Blob::new` in the current transaction; handle `n` is `blobs[n - 1]`. Never rolled back. */
+  readonly blobs: Uint8Array[] = [];
+  /** Restore functions for the open `tx::atomic` sections of the current transaction, outermost first. */
+  readonly checkpoints: (() => void)[] = [];
+  /** Channel messages waiting for each UTXO, keyed by hex id. */
+  readonly #inboxes = new Map<string, Uint8Array[]>();
+
+  /**
+   * Capture the state of the transaction `script` is running, returning a
+   * function that restores it. Maps shared with called scripts are restored
+   * in place.
+   */
+  checkpoint(script: CoordinationScriptInstance): () => void {
+    const restoreShared = this.#snapshot(new Set([...this.utxos, ...script.utxos.values()]));
+    const handles = [...script.utxos];
+    const tokens = [...script.tokens];
+    const amounts = [...script.tokens.values()].map(token => [token, token.amount] as const);
+    const pendingOutputs = [...script.pendingOutputs].map(([handle, pending]) => [handle, { ...pending, tokens: [...pending.tokens] }] as const);
+    const outputCount = script.outputs.length;
+    const resumed = [...script.resumed];
+    return () => {
+      restoreShared();
+      script.utxos.clear();
+      handles.forEach(([handle, utxo]) => script.utxos.set(handle, utxo));
+      script.tokens.clear();
+      tokens.forEach(([handle, token]) => script.tokens.set(handle, token));
+      amounts.forEach(([token, amount]) => token.amount = amount);
+      script.pendingOutputs.clear();
+      pendingOutputs.forEach(([handle, pending]) => script.pendingOutputs.set(handle, pending));
+      script.outputs.length = outputCount;
+      script.resumed.clear();
+      resumed.forEach(utxo => script.resumed.add(utxo));
+    };
+  }
+
+  /**
+   * Capture `utxos`, the amounts of the tokens attached to them, and what
+   * every script of the current transaction shares, returning a function
+   * that restores them.
+   */
+  #snapshot(utxos: Set<Utxo>): () => void {
+    const restoreUtxos = [...utxos].map(utxo => utxo.snapshot());
+    const amounts = [...utxos].flatMap(utxo => [...utxo.tokens]).map(token => [token, token.amount] as const);
+    const inboxes = [...this.#inboxes].map(([key, inbox]) => [key, [...inbox]] as const);
+    const { feePaid } = this;
+    const ranScripts = [...this.ranScripts];
+    const requiredScripts = [...this.requiredScripts];
+    const scratch = [...this.scratch];
+    const { events } = this;
+    const eventCount = events.length;
+    return () => {
+      restoreUtxos.forEach(restore => restore());
+      amounts.forEach(([token, amount]) => token.amount = amount);
+      this.#inboxes.clear();
+      inboxes.forEach(([key, inbox]) => this.#inboxes.set(key, inbox));
+      this.feePaid = feePaid;
+      this.ranScripts.clear();
+      ranScripts.forEach(hash => this.ranScripts.add(hash));
+      this.requiredScripts.clear();
+      requiredScripts.forEach(hash => this.requiredScripts.add(hash));
+      this.scratch.clear();
+      scratch.forEach(([key, value]) => this.scratch.set(key, value));
+      this.events = events;
+      events.length = eventCount;
+    };
+  }
+
+  inbox(utxoId: Uint8Array): Uint8Array[] {
+    const key = Buffer.from(utxoId).toString("hex");
+    let inbox = this.#inboxes.get(key);
+    if (!inbox) {
+      this.#inboxes.set(key, inbox = []);
+    }
+    return inbox;
+  }
 
   getCodeSync(hash: ContractCodeId): ContractCode {
     let code = this.contractCode.get(hash);
@@ -631,6 +2187,16 @@ class Universe {
     return code;
   }
 
+  getCodeIdByHash(hash: Uint8Array): ContractCodeId {
+    for (const [id, code] of this.contractCode) {
+      const known = new Uint8Array(code.hash);
+      if (known.length === hash.length && known.every((b, i) => b === hash[i])) {
+        return id;
+      }
+    }
+    throw new Error("unknown code hash");
+  }
+
   async resolveCode(hash: ContractCodeId): Promise<ContractCode> {
     let code = this.contractCode.get(hash);
     if (!code) {
@@ -646,13 +2212,30 @@ class Universe {
     return new Token(this, code, mintFn, mintArgs);
   }
 
-  runTransaction(coordinationScript: ContractCode, entryPoint: string, inputs: unknown[] = []) {
+  runTransaction(
+    coordinationScript: ContractCode,
+    entryPoint: string,
+    inputs: unknown[] = [],
+    signers: Uint8Array[] = [],
+    payload: Uint8Array = new Uint8Array(0),
+  ) {
     // We aren't suspending this, we want to run it to completion always, so
     // we don't need to asyncify it.
     console.log('CALL', entryPoint, inputs);
+    this.blockHeight += 1n;
+    const now = BigInt(Math.floor(Date.now() / 1000));
+    this.blockTime = now > this.blockTime ? now : this.blockTime;
+    this.feePaid = 0n;
+    this.ranScripts.clear();
+    this.requiredScripts.clear();
+    this.checkpoints.length = 0;
+    this.scratch.clear();
+    this.blobs.length = 0;
+    this.events = [];
 
     // Fulfill imports and instantiate WASM
-    const instance = new CoordinationScriptInstance(this, coordinationScript);
+    const instance = new CoordinationScriptInstance(this, coordinationScript, signers);
+    instance.payload = payload;
 
     // Prepare inputs
     const inputs2 = [...inputs];
@@ -666,10 +2249,36 @@ class Universe {
     coordinationContext = {
       coordinationCode: coordinationScript,
     };
-    const result: unknown = instance.getFunction(entryPoint)(...inputs2);
+    coordinationStack.push(instance);
+    this.ranScripts.add(Buffer.from(coordinationScript.hash).toString("hex"));
+    let result: unknown;
+    try {
+      result = instance.getFunction(entryPoint)(...inputs2);
+    } finally {
+      coordinationStack.pop();
+    }
+    for (const output of instance.outputs) {
+      const utxo = new Utxo(this, output.codeId, output.entryPoint, output.initArgs);
+      output.tokens.forEach(token => utxo.tokens.add(token));
+      utxo.load().start();
+      instance.setUtxo(utxo);
+    }
     coordinationContext = null;
     console.log(' ->', result);
+    if (instance.tokens.size > 0) {
+      // Tokens must end the transaction attached to some UTXO.
+      throw new Error(`${instance.tokens.size} detached token(s) were not attached`);
+    }
+    for (const hash of this.requiredScripts) {
+      if (!this.ranScripts.has(hash)) {
+        throw new Error(`transaction requires coordination script ${hash}, which didn't run`);
+      }
+    }
+    if (this.feePaid < this.fee) {
+      throw new Error(`transaction paid ${this.feePaid} of its ${this.fee} fee`);
+    }
     // TODO: Rollback UTXO memories on error.
+    this.receipts.push({ blockHeight: this.blockHeight, entryPoint, result: instance.result, feePaid: this.feePaid, events: this.events });
 
     // Update UTXO set
     for (const utxo of instance.utxos.values()) {
@@ -688,6 +2297,29 @@ class Universe {
     return result;
   }
 
+  /**
+   * Run a transaction as a dry run, e.g. to show a wallet its result before
+   * it's submitted. Whether it succeeds or fails, everything it did is then
+   * undone, since contracts may behave differently in a simulation.
+   */
+  simulateTransaction(...args: Parameters<Universe["runTransaction"]>) {
+    const restoreShared = this.#snapshot(this.utxos);
+    const utxos = [...this.utxos];
+    const { blockHeight, blockTime } = this;
+    const receiptCount = this.receipts.length;
+    this.simulating = true;
+    try {
+      return this.runTransaction(...args);
+    } finally {
+      this.simulating = false;
+      restoreShared();
+      this.utxos.clear();
+      utxos.forEach(utxo => this.utxos.add(utxo));
+      Object.assign(this, { blockHeight, blockTime });
+      this.receipts.length = receiptCount;
+    }
+  }
+
   debug() {
     return inspect([...this.utxos].map(u => u.debug()), { depth: null, colors: true });
   }
@@ -710,6 +2342,25 @@ universe.runTransaction(
 );
 console.log(++n, '--', universe.debug());
 
+// A resume rolled back by `tx::atomic` leaves the UTXO to resume from where it was.
+assert.strictEqual(universe.runTransaction(exampleCoordination, "resume_after_rollback"), 2);
+console.log(++n, '--', universe.debug());
+
+// A dry run reports its result but commits nothing.
+const myMain: Utxo = universe.utxos.values().next().value!;
+const heightBeforeSimulation = universe.blockHeight;
+assert.strictEqual(universe.simulateTransaction(exampleCoordination, "bump", [myMain]), 2);
+assert.strictEqual(myMain.load().query("starstream_query_MyMain_get_supply"), 1);
+assert.strictEqual(universe.blockHeight, heightBeforeSimulation);
+assert.strictEqual(universe.runTransaction(exampleCoordination, "bump", [myMain]), 2);
+console.log(++n, '--', universe.debug());
+
+// Only a UTXO that accepted a resume from the script lets it detach tokens.
+assert.throws(
+  () => universe.simulateTransaction(exampleCoordination, "detach_unresumed", [myMain]),
+  /hasn't accepted a resume/,
+);
+
 /*
 universe.runTransaction(
   exampleCoordination,
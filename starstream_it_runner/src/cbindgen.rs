@@ -0,0 +1,316 @@
+//! C headers for a module, generated from its metadata section, so that C,
+//! C++, and Zig guests can implement or import the same tokens as a Rust
+//! module.
+//!
+//! As for `ts_bindgen`, build the contract with `starstream`'s `ts-bindgen`
+//! feature, which records the fields of every `#[derive(FfiSafe)]` type,
+//! token intermediates included. Call this from a build script:
+//!
+//! ```ignore
+//! let wasm = std::fs::read("target/wasm32-unknown-unknown/release/example_contract.wasm")?;
+//! let header = cbindgen::from_wasm(&wasm, "example_contract")?;
+//! std::fs::write("include/example_contract.h", header)?;
+//! ```
+//!
+//! The header defines `TokenStorage` and the recorded types, checking their
+//! sizes, and declares the token mint and burn imports from
+//! `starstream_token:<module>`. A guest implementing the same interface
+//! defines `STARSTREAM_IMPLEMENT_<MODULE>` before including it, and gets
+//! declarations of the exports to define instead: mint and burn, the UTXO
+//! entry points, and the coordination entry points.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use anyhow::{bail, Context, Result};
+
+use crate::layout::{layouts, Layouts, Ty};
+use crate::metadata::{compact, signature, split_param};
+
+/// The header for the module `wasm`, which importers load as `module`.
+pub fn from_wasm(wasm: &[u8], module: &str) -> Result<String> {
+    generate(&crate::metadata::lines(wasm)?, module)
+}
+
+/// The header for the metadata lines of a module, as from
+/// [`Runner::metadata`](crate::Runner::metadata), which importers load as
+/// `module`.
+pub fn generate(metadata: &[String], module: &str) -> Result<String> {
+    let layouts = layouts(metadata)?;
+    let upper: String = module
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// Generated by starstream_it_runner::cbindgen from the metadata"
+    )
+    .unwrap();
+    writeln!(out, "// of `{module}`. Don't edit; regenerate instead.\n").unwrap();
+    writeln!(out, "#ifndef STARSTREAM_{upper}_H").unwrap();
+    writeln!(out, "#define STARSTREAM_{upper}_H").unwrap();
+    out.push_str(PRELUDE);
+
+    let mut emitted = HashSet::new();
+    for name in layouts.keys() {
+        emit_layout(&mut out, &layouts, &mut emitted, name)?;
+    }
+
+    let mut imports = String::new();
+    let mut exports = String::new();
+    // Whether these are the lines of a `#[starstream::utxo]`, and its state.
+    let mut in_utxo = false;
+    let mut state = None;
+    // The intermediate of the `token_export!` whose lines these are.
+    let mut intermediate = None;
+    for line in metadata {
+        let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = compact(rest.trim());
+        match kind {
+            "utxo" => {
+                in_utxo = true;
+                state = None;
+            }
+            "state" if in_utxo && state.is_none() => {
+                let name = last_segment(&rest).to_owned();
+                if !layouts.contains_key(&name) && emitted.insert(name.clone()) {
+                    writeln!(out, "\ntypedef struct {name} {name};").unwrap();
+                }
+                state = Some(name);
+            }
+            "new" | "query" | "mutate" | "consume" => {
+                let this = match (kind, &state) {
+                    (_, _) if !in_utxo => bail!("`{line}` outside a UTXO"),
+                    ("new", _) => None,
+                    ("query", Some(state)) => Some(format!("const {state} *self")),
+                    (_, Some(state)) => Some(format!("{state} *self")),
+                    (_, None) => bail!("`{line}` before the UTXO's state"),
+                };
+                emit_export(&mut exports, &layouts, this, &rest)
+                    .with_context(|| format!("UTXO entry point `{rest}`"))?;
+            }
+            "token" => {
+                in_utxo = false;
+                let name = last_segment(&rest).to_owned();
+                if !layouts.contains_key(&name) {
+                    bail!("no layout for intermediate `{name}`; build with the ts-bindgen feature");
+                }
+                intermediate = Some(name);
+            }
+            "mint" | "burn" => {
+                let Some(intermediate) = &intermediate else {
+                    bail!("`{line}` outside a token export");
+                };
+                let (export, import) = if kind == "mint" {
+                    (
+                        format!("TokenStorage {rest}({intermediate} intermediate)"),
+                        format!("uint32_t {rest}({intermediate} intermediate)"),
+                    )
+                } else {
+                    (
+                        format!("{intermediate} {rest}(TokenStorage storage)"),
+                        format!("{intermediate} {rest}(uint32_t token)"),
+                    )
+                };
+                writeln!(exports, "\nSTARSTREAM_EXPORT(\"{rest}\")\n{export};").unwrap();
+                if kind == "mint" {
+                    writeln!(
+                        imports,
+                        "\n/** Returns 0 if the exporter refused the mint. */"
+                    )
+                    .unwrap();
+                } else {
+                    imports.push('\n');
+                }
+                writeln!(
+                    imports,
+                    "STARSTREAM_IMPORT(\"starstream_token:{module}\", \"{rest}\")\n{import};"
+                )
+                .unwrap();
+            }
+            "coordination" => {
+                in_utxo = false;
+                emit_export(&mut exports, &layouts, None, &rest)
+                    .with_context(|| format!("coordination entry point `{rest}`"))?;
+            }
+            _ => {}
+        }
+    }
+
+    writeln!(out, "\n#ifdef STARSTREAM_IMPLEMENT_{upper}").unwrap();
+    out.push_str(&exports);
+    writeln!(out, "\n#else").unwrap();
+    out.push_str(&imports);
+    writeln!(out, "\n#endif\n").unwrap();
+    writeln!(out, "#ifdef __cplusplus\n}}\n#endif\n").unwrap();
+    writeln!(out, "#endif").unwrap();
+    Ok(out)
+}
+
+fn last_segment(path: &str) -> &str {
+    path.rsplit("::").next().unwrap_or(path).trim()
+}
+
+/// The C type of a value of `ty`, as a parameter or return value.
+fn c_type(ty: &Ty) -> Result<String> {
+    Ok(match ty {
+        Ty::Unit => "void".into(),
+        Ty::Scalar(name) => match *name {
+            "f32" => "float".into(),
+            "f64" => "double".into(),
+            "u128" => "unsigned __int128".into(),
+            "i128" => "__int128".into(),
+            name if name.starts_with('u') => format!("uint{}_t", &name[1..]),
+            name => format!("int{}_t", &name[1..]),
+        },
+        // Unknown types are other modules' UTXOs, which the host passes as
+        // handles.
+        Ty::Handle | Ty::Unknown(_) => "uint32_t".into(),
+        Ty::Struct(name) => name.clone(),
+        Ty::Bytes(_) | Ty::Array(..) => bail!("C can't pass an array by value"),
+    })
+}
+
+/// A field declaration, with the array lengths after the name.
+fn c_field(name: &str, ty: &Ty) -> Result<String> {
+    Ok(match ty {
+        Ty::Bytes(len) => format!("uint8_t {name}[{len}]"),
+        Ty::Array(item, len) => c_field(&format!("{name}[{len}]"), item)?,
+        Ty::Unknown(source) => {
+            bail!("no layout for `{source}`; derive FfiSafe for it with the ts-bindgen feature")
+        }
+        ty => format!("{} {name}", c_type(ty)?),
+    })
+}
+
+/// Define `name` after the types its fields use.
+fn emit_layout(
+    out: &mut String,
+    layouts: &Layouts,
+    emitted: &mut HashSet<String>,
+    name: &str,
+) -> Result<()> {
+    if emitted.contains(name) {
+        return Ok(());
+    }
+    let mut fields = Vec::new();
+    for (field, ty) in &layouts[name] {
+        let ty = Ty::parse(ty, layouts)?;
+        for inner in ty.structs() {
+            emit_layout(out, layouts, emitted, inner)?;
+        }
+        let field = field.trim_start_matches("r#");
+        // C has no zero-sized fields, and the C ABI drops them anyway.
+        if ty.size(layouts) == 0 && !matches!(ty, Ty::Unknown(_)) {
+            fields.push(format!("/* `{field}` is zero-sized */"));
+            continue;
+        }
+        let decl = c_field(field, &ty).with_context(|| format!("field `{name}.{field}`"))?;
+        fields.push(format!("{decl};"));
+    }
+
+    let size = Ty::Struct(name.to_owned()).size(layouts);
+    // Another module's header may define the same type.
+    writeln!(out, "\n#ifndef STARSTREAM_TYPE_{name}").unwrap();
+    writeln!(out, "#define STARSTREAM_TYPE_{name}").unwrap();
+    emitted.insert(name.to_owned());
+    if size == 0 {
+        // C has no empty structs; this is only ever behind a pointer.
+        writeln!(out, "typedef struct {name} {name};\n#endif").unwrap();
+        return Ok(());
+    }
+    writeln!(out, "typedef struct {name} {{").unwrap();
+    for field in &fields {
+        writeln!(out, "    {field}").unwrap();
+    }
+    writeln!(out, "}} {name};").unwrap();
+    writeln!(
+        out,
+        "static_assert(sizeof({name}) == {size}, \"{name} is {size} bytes\");"
+    )
+    .unwrap();
+    writeln!(out, "#endif").unwrap();
+    Ok(())
+}
+
+/// Declare the export with signature `line`, such as `name(a: u64) -> u32`
+/// or, from `#[starstream::utxo]`, `name(u64) -> u32`, taking `this` first.
+fn emit_export(
+    out: &mut String,
+    layouts: &Layouts,
+    this: Option<String>,
+    line: &str,
+) -> Result<()> {
+    let (sig, ret) = match line.rsplit_once("->") {
+        Some((sig, ret)) => (sig.trim(), Ty::parse(ret, layouts)?),
+        None => (line.trim(), Ty::Unit),
+    };
+    let (name, params) = signature(sig)?;
+    let mut c_params: Vec<String> = this.into_iter().collect();
+    writeln!(out, "\n/** `{line}` */").unwrap();
+    for (i, param) in params.iter().enumerate() {
+        if let Some(param) = param.trim().strip_prefix("#[payload]") {
+            // The arguments are the transaction payload, not parameters.
+            let (_, ty) = split_param(param)?;
+            writeln!(
+                out,
+                "// Reads a `{}` from the CBOR payload, with `starstream_tx_payload`.",
+                ty.trim()
+            )
+            .unwrap();
+            continue;
+        }
+        let (arg, ty) = match split_param(param) {
+            Ok((arg, ty)) => (arg.trim_start_matches("r#").to_owned(), ty),
+            // `#[starstream::utxo]` lists types only.
+            Err(_) => (format!("arg{i}"), param.trim().to_owned()),
+        };
+        let ty = Ty::parse(&ty, layouts)?;
+        // The C ABI drops zero-sized parameters.
+        if ty.size(layouts) == 0 && !matches!(ty, Ty::Unknown(_)) {
+            continue;
+        }
+        let ty = c_type(&ty).with_context(|| format!("parameter `{arg}`"))?;
+        c_params.push(format!("{ty} {arg}"));
+    }
+    if c_params.is_empty() {
+        c_params.push("void".into());
+    }
+    writeln!(out, "STARSTREAM_EXPORT(\"{name}\")").unwrap();
+    writeln!(out, "{} {name}({});", c_type(&ret)?, c_params.join(", ")).unwrap();
+    Ok(())
+}
+
+const PRELUDE: &str = r#"
+#include <assert.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#ifndef STARSTREAM_EXPORT
+#define STARSTREAM_EXPORT(name) __attribute__((export_name(name)))
+#endif
+#ifndef STARSTREAM_IMPORT
+#define STARSTREAM_IMPORT(module, name) __attribute__((import_module(module), import_name(name)))
+#endif
+
+#ifndef STARSTREAM_TYPE_TokenStorage
+#define STARSTREAM_TYPE_TokenStorage
+/** What the host keeps for each token; `starstream::TokenStorage`. */
+typedef struct TokenStorage {
+    uint64_t id;
+    uint64_t amount;
+} TokenStorage;
+static_assert(sizeof(TokenStorage) == 16, "TokenStorage is 16 bytes");
+#endif
+"#;
@@ -0,0 +1,125 @@
+//! Differential testing against the `mock-host` emulation of the ABI: run a
+//! scenario natively with `starstream::testkit`, save its transcript, then
+//! replay it here on the compiled contracts and compare.
+//!
+//! ```ignore
+//! // In the contract's native tests:
+//! let receipt = Tx::new().call(MARKET, "list", &price).execute()?;
+//! std::fs::write("target/transcripts/list.txt", receipt.transcript())?;
+//!
+//! // In the integration tests:
+//! let payload = cbor::to_slice(&price, &mut [0; 16])?;
+//! let wasm = diff::replay(&mut runner, "market", &[("list", payload)])?;
+//! diff::assert_same(&Transcript::read("target/transcripts/list.txt")?, &wasm);
+//! ```
+//!
+//! A transcript lists what both hosts can observe, grouped by kind and in
+//! order within each kind: logs, events, tokens minted and burned, and the
+//! result of each call. The mock host sees intermediates where the runner
+//! sees `TokenStorage`, so tokens are compared by type only.
+
+use std::{fmt, path::Path};
+
+use anyhow::{Context, Result};
+
+use crate::Runner;
+
+/// What a scenario did, one observation per line, such as
+/// `event Listed 0a00000000000000`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub lines: Vec<String>,
+}
+
+impl Transcript {
+    pub fn parse(text: &str) -> Transcript {
+        Transcript {
+            lines: text.lines().map(str::to_owned).collect(),
+        }
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> Result<Transcript> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        Ok(Transcript::parse(&text))
+    }
+
+    /// The first line where `self` and `other` differ, as `(line number,
+    /// self's line, other's line)`, with `None` for a missing line.
+    pub fn first_difference<'a>(
+        &'a self,
+        other: &'a Transcript,
+    ) -> Option<(usize, Option<&'a str>, Option<&'a str>)> {
+        let len = self.lines.len().max(other.lines.len());
+        (0..len).find_map(|i| {
+            let (a, b) = (self.lines.get(i), other.lines.get(i));
+            (a != b).then(|| (i + 1, a.map(String::as_str), b.map(String::as_str)))
+        })
+    }
+}
+
+impl fmt::Display for Transcript {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            writeln!(f, "{line}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Run `calls`, each an entry point of `script` with the bytes for
+/// `tx::payload`, as `testkit::Tx` does, and record their transcript.
+/// Unlike `Tx`, each call is its own transaction here.
+pub fn replay(runner: &mut Runner, script: &str, calls: &[(&str, &[u8])]) -> Result<Transcript> {
+    let (logs_before, tokens_before) = {
+        let ledger = runner.ledger();
+        (ledger.logs.len(), ledger.tokens.clone())
+    };
+    let mut events = Vec::new();
+    let mut results = Vec::new();
+    for (entry, payload) in calls {
+        let receipt = runner.call_with_payload(script, entry, &[], payload)?;
+        events.extend(receipt.events);
+        results.push(receipt.result.unwrap_or_default());
+    }
+
+    let ledger = runner.ledger();
+    let mut lines = Vec::new();
+    for value in &ledger.logs[logs_before..] {
+        lines.push(format!("log {value}"));
+    }
+    for event in events {
+        lines.push(format!("event {} {}", event.topic, hex(&event.payload)));
+    }
+    for token in &ledger.tokens[tokens_before.len()..] {
+        lines.push(format!("mint {}", token.name));
+    }
+    for (i, token) in ledger.tokens.iter().enumerate() {
+        let was_burned = tokens_before.get(i).is_some_and(|before| before.burned);
+        if token.burned && !was_burned {
+            lines.push(format!("burn {}", token.name));
+        }
+    }
+    for result in results {
+        lines.push(format!("result {}", hex(&result)));
+    }
+    Ok(Transcript { lines })
+}
+
+/// Panic with the first divergence if the mock host's transcript differs
+/// from the runner's.
+#[track_caller]
+pub fn assert_same(mock: &Transcript, wasm: &Transcript) {
+    if let Some((line, expected, actual)) = mock.first_difference(wasm) {
+        panic!(
+            "mock host and runner diverge at line {line}:\n  mock: {}\n  wasm: {}\n\nmock transcript:\n{mock}\nwasm transcript:\n{wasm}",
+            expected.unwrap_or("(nothing)"),
+            actual.unwrap_or("(nothing)"),
+        );
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
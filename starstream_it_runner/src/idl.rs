@@ -0,0 +1,170 @@
+//! A module's interface as a standalone `.sidl` file, generated from its
+//! metadata section, for SDKs in other languages and for auditors.
+//!
+//! ```ignore
+//! let wasm = std::fs::read("target/wasm32-unknown-unknown/release/market.wasm")?;
+//! std::fs::write("market.sidl", idl::from_wasm(&wasm)?)?;
+//! ```
+//!
+//! The file lists, in this order: the fields of each type (with `starstream`'s
+//! `ts-bindgen` feature), the token exports, each `#[starstream::utxo]` with
+//! its state, resume argument, and exports, the other state names, and the
+//! coordination entry points:
+//!
+//! ```text
+//! sidl 1;
+//!
+//! type Listing {
+//!     price: u64,
+//!     seller: PublicKey,
+//! }
+//!
+//! token StarNftIntermediate {
+//!     mint starstream_mint_StarNft;
+//!     burn starstream_burn_StarNft;
+//! }
+//!
+//! utxo Market {
+//!     state Market;
+//!     resume ();
+//!     new starstream_new_Market_open(u64);
+//!     query starstream_query_Market_price() -> u64;
+//! }
+//!
+//! state Listed;
+//!
+//! coordination list(#[payload] listing: Listing) -> u64;
+//! ```
+//!
+//! Types are spelled as in the Rust source. Items are sorted only where the
+//! metadata doesn't order them, so regenerating an unchanged module gives the
+//! same file.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use anyhow::{bail, Context, Result};
+
+use crate::metadata::{compact, signature, split_param};
+
+/// The version on the first line, bumped with incompatible format changes.
+pub const IDL_VERSION: u32 = 1;
+
+/// The interface of the module `wasm`.
+pub fn from_wasm(wasm: &[u8]) -> Result<String> {
+    generate(&crate::metadata::lines(wasm)?)
+}
+
+/// The interface described by the metadata lines of a module, as from
+/// [`Runner::metadata`](crate::Runner::metadata).
+pub fn generate(metadata: &[String]) -> Result<String> {
+    let mut types = BTreeMap::new();
+    let mut tokens: Vec<Block> = Vec::new();
+    let mut utxos: Vec<Block> = Vec::new();
+    let mut states = BTreeSet::new();
+    let mut coordination = Vec::new();
+    // The block that `utxo` and `token` lines open, which takes the lines
+    // after it that belong to it.
+    let mut open: Option<&mut Block> = None;
+    for line in metadata {
+        let (kind, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = compact(rest.trim());
+        let belongs = match (kind, open.as_deref()) {
+            ("mint" | "burn", Some(block)) => block.kind == "token",
+            ("resume" | "new" | "query" | "mutate" | "consume", Some(block)) => {
+                block.kind == "utxo"
+            }
+            // `#[starstream::utxo]` lists its state once, after `utxo`.
+            ("state", Some(block)) => {
+                block.kind == "utxo" && !block.items.iter().any(|i| i.starts_with("state "))
+            }
+            _ => false,
+        };
+        if belongs {
+            open.as_mut().unwrap().items.push(format!("{kind} {rest}"));
+            continue;
+        }
+        open = None;
+        match kind {
+            "type" => {
+                let (name, fields) =
+                    signature(&rest).with_context(|| format!("bad line `{line}`"))?;
+                let fields = fields
+                    .iter()
+                    .map(|field| split_param(field).map(|(name, ty)| format!("{name}: {ty}")))
+                    .collect::<Result<Vec<_>>>()?;
+                if let Some(previous) = types.insert(name.clone(), fields.clone()) {
+                    if previous != fields {
+                        bail!("two different types are named `{name}`");
+                    }
+                }
+            }
+            "token" => {
+                tokens.push(Block::new("token", rest));
+                open = tokens.last_mut();
+            }
+            "utxo" => {
+                utxos.push(Block::new("utxo", rest));
+                open = utxos.last_mut();
+            }
+            "state" => {
+                states.insert(rest);
+            }
+            "coordination" => coordination.push(rest),
+            _ => {}
+        }
+    }
+    // `utxo` blocks list their own state.
+    for utxo in &utxos {
+        for item in &utxo.items {
+            if let Some(state) = item.strip_prefix("state ") {
+                states.remove(state);
+            }
+        }
+    }
+
+    let mut out = format!("sidl {IDL_VERSION};\n");
+    for (name, fields) in &types {
+        writeln!(out, "\ntype {name} {{").unwrap();
+        for field in fields {
+            writeln!(out, "    {field},").unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+    }
+    for block in tokens.iter().chain(&utxos) {
+        writeln!(out, "\n{} {} {{", block.kind, block.name).unwrap();
+        for item in &block.items {
+            writeln!(out, "    {item};").unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+    }
+    if !states.is_empty() {
+        out.push('\n');
+        for state in &states {
+            writeln!(out, "state {state};").unwrap();
+        }
+    }
+    if !coordination.is_empty() {
+        out.push('\n');
+        for entry in &coordination {
+            writeln!(out, "coordination {entry};").unwrap();
+        }
+    }
+    Ok(out)
+}
+
+struct Block {
+    kind: &'static str,
+    name: String,
+    items: Vec<String>,
+}
+
+impl Block {
+    fn new(kind: &'static str, name: String) -> Block {
+        Block {
+            kind,
+            name,
+            items: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! The layouts of `#[derive(FfiSafe)]` types, which `starstream`'s
+//! `ts-bindgen` feature records in the metadata section, for the generators
+//! that need more than the names of types.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Context, Result};
+
+use crate::metadata::{signature, split_param};
+
+/// The layouts in the `type` lines of `metadata`.
+pub(crate) fn layouts(metadata: &[String]) -> Result<Layouts> {
+    let mut layouts = BTreeMap::new();
+    for line in metadata {
+        let Some(rest) = line.strip_prefix("type ") else {
+            continue;
+        };
+        let (name, fields) = signature(rest).with_context(|| format!("bad line `{line}`"))?;
+        let fields = fields
+            .iter()
+            .map(|field| split_param(field))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(previous) = layouts.insert(name.clone(), fields.clone()) {
+            if previous != fields {
+                bail!("two different types are named `{name}`");
+            }
+        }
+    }
+    Ok(layouts)
+}
+
+/// How a Rust type is laid out, as far as the generators are concerned.
+pub(crate) enum Ty {
+    Unit,
+    /// A primitive, by its Rust name.
+    Scalar(&'static str),
+    /// An opaque handle, such as a UTXO, passed as a `u32`.
+    Handle,
+    Bytes(usize),
+    Array(Box<Ty>, usize),
+    /// A type with a recorded layout.
+    Struct(String),
+    /// Neither built in nor recorded.
+    Unknown(String),
+}
+
+impl Ty {
+    pub(crate) fn parse(source: &str, layouts: &Layouts) -> Result<Ty> {
+        let ty: String = source.chars().filter(|c| !c.is_whitespace()).collect();
+        if ty == "()" {
+            return Ok(Ty::Unit);
+        }
+        if let Some(inner) = ty.strip_prefix('[').and_then(|ty| ty.strip_suffix(']')) {
+            let (item, len) = inner.rsplit_once(';').context("bad array type")?;
+            let len = len
+                .parse()
+                .with_context(|| format!("array length `{len}`"))?;
+            return Ok(match Ty::parse(item, layouts)? {
+                Ty::Scalar("u8") => Ty::Bytes(len),
+                item => Ty::Array(Box::new(item), len),
+            });
+        }
+        let (path, generic) = match ty.split_once('<') {
+            Some((path, _)) => (path, true),
+            None => (&ty[..], false),
+        };
+        let name = path.rsplit("::").next().unwrap_or(path);
+        if let Some(scalar) = SCALARS.into_iter().find(|scalar| *scalar == name) {
+            return Ok(Ty::Scalar(scalar));
+        }
+        Ok(match name {
+            // wasm32
+            "usize" => Ty::Scalar("u32"),
+            "isize" => Ty::Scalar("i32"),
+            "CodeHash" | "UtxoId" | "Digest" | "Salt" => Ty::Bytes(32),
+            "PublicKey" => Ty::Bytes(0),
+            "StateId" | "Blob" => Ty::Scalar("u32"),
+            "UtxoHandle" | "TokenHandle" | "Option" if generic => Ty::Handle,
+            _ if !generic && layouts.contains_key(name) => Ty::Struct(name.to_owned()),
+            _ => Ty::Unknown(source.trim().to_owned()),
+        })
+    }
+
+    pub(crate) fn size(&self, layouts: &Layouts) -> usize {
+        match self {
+            Ty::Unit => 0,
+            Ty::Scalar(name) => match *name {
+                "u8" | "i8" => 1,
+                "u16" | "i16" => 2,
+                "u32" | "i32" | "f32" => 4,
+                "u128" | "i128" => 16,
+                _ => 8,
+            },
+            Ty::Handle => 4,
+            Ty::Bytes(len) => *len,
+            Ty::Array(item, len) => item.size(layouts) * len,
+            Ty::Struct(name) => layouts[name]
+                .iter()
+                .map(|(_, ty)| Ty::parse(ty, layouts).map_or(0, |ty| ty.size(layouts)))
+                .sum(),
+            Ty::Unknown(_) => 0,
+        }
+    }
+
+    /// Recorded types this one is made of.
+    pub(crate) fn structs(&self) -> Vec<&str> {
+        match self {
+            Ty::Array(item, _) => item.structs(),
+            Ty::Struct(name) => vec![name],
+            _ => Vec::new(),
+        }
+    }
+}
+
+const SCALARS: [&str; 12] = [
+    "u8", "u16", "u32", "u64", "u128", "i8", "i16", "i32", "i64", "i128", "f32", "f64",
+];
+
+/// Recorded layouts: `(field, type)` pairs by type name.
+pub(crate) type Layouts = BTreeMap<String, Vec<(String, String)>>;
@@ -0,0 +1,984 @@
+//! Runs compiled contracts under wasmtime, with the Starstream host functions
+//! and imports wired between modules, so integration tests exercise the real
+//! ABI rather than the `mock-host` emulation of it.
+//!
+//! ```ignore
+//! let mut runner = Runner::new();
+//! runner.load_file("example_contract", "target/wasm32-unknown-unknown/debug/example_contract.wasm")?;
+//! runner.load_file("example_coordination", "target/wasm32-unknown-unknown/debug/example_coordination.wasm")?;
+//! let receipt = runner.call("example_coordination", "star_mint", &[Val::I64(10)])?;
+//! ```
+//!
+//! Modules are named as their importers refer to them: a contract importing
+//! from `starstream_token:example_contract` is served by the module loaded as
+//! `example_contract`.
+//!
+//! Covered so far: coordination scripts, the common `env` imports, and token
+//! mint and burn. Imports the runner doesn't provide yet trap when called.
+//! TODO: UTXO lifecycles, which need `sleep` to suspend the instance, as the
+//! TypeScript host does with asyncify.
+//!
+//! [`diff`] compares a scenario's outcome here with its outcome on the mock
+//! host. [`idl`] writes a module's interface file from its metadata, and,
+//! with the `ts-bindgen` feature, `ts_bindgen` generates TypeScript bindings
+//! from it, and with the `cbindgen` feature, `cbindgen` generates C headers.
+//! [`manifest`] adds the list of a module's host imports to its metadata,
+//! which [`Runner::unsupported_imports`] checks against this host.
+
+mod blake2b;
+#[cfg(feature = "cbindgen")]
+pub mod cbindgen;
+pub mod diff;
+pub mod idl;
+#[cfg(any(feature = "ts-bindgen", feature = "cbindgen"))]
+mod layout;
+pub mod manifest;
+mod metadata;
+#[cfg(feature = "ts-bindgen")]
+pub mod ts_bindgen;
+
+use std::{cell::RefCell, collections::HashMap, path::Path, rc::Rc};
+
+use anyhow::{bail, ensure, Context, Result};
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use sha2::{Digest, Sha256};
+use wasmtime::{
+    Caller, Engine, Extern, ExternType, FuncType, Instance, Linker, Memory, Module, Store, Val,
+    ValType,
+};
+
+/// `HostError` discriminants, as in `starstream::HostError`.
+const NOT_FOUND: u32 = 1;
+const UNAUTHORIZED: u32 = 4;
+
+/// The handle returned in place of a token or UTXO, as `TokenHandle::INVALID`
+/// and `UtxoHandle::INVALID`. Handles are allocated from 1.
+const INVALID_HANDLE: u32 = 0;
+
+/// The `starstream_abort` code of a panic, as in `starstream::env::PANIC_CODE`.
+const PANIC_CODE: u32 = 0;
+
+/// The metadata section and the export that vouches for it, as in
+/// `starstream::METADATA_SECTION` and `starstream::METADATA_VERSION_EXPORT`.
+const METADATA_SECTION: &str = "starstream.metadata.v1";
+const METADATA_VERSION_EXPORT: &str = "starstream_metadata_version";
+
+/// The ABI this host implements, as in `starstream::ABI_VERSION`.
+const ABI_VERSION: u32 = 1;
+
+/// Where `TokenStorage` goes in the exporter's memory when minting and
+/// burning; the mint address is the TypeScript host's.
+const RETURN_ADDR: i32 = 16;
+
+/// A token minted during a run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenRecord {
+    /// The module that exports the token.
+    pub module: String,
+    pub name: String,
+    pub id: u64,
+    pub amount: u64,
+    pub burned: bool,
+}
+
+/// What an event-emitting contract recorded with `tx::emit_event`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub topic: String,
+    pub payload: Vec<u8>,
+}
+
+/// The chain state shared by every module of a run. Fields are public so
+/// tests can set up the environment and inspect the outcome.
+#[derive(Default)]
+pub struct Ledger {
+    pub block_height: u64,
+    pub block_time: u64,
+    pub chain_id: u64,
+    /// Values passed to `log`, oldest first.
+    pub logs: Vec<u32>,
+    /// Lines passed to `log::line`, oldest first.
+    pub log_lines: Vec<String>,
+    pub tokens: Vec<TokenRecord>,
+    /// State roots `bridge::root` returns, by light client, revision number,
+    /// and revision height.
+    pub bridge_roots: HashMap<(u64, u64, u64), [u8; 32]>,
+
+    // Reset for each transaction.
+    pub payload: Vec<u8>,
+    pub result: Option<Vec<u8>>,
+    pub events: Vec<Event>,
+    pub scratch: HashMap<Vec<u8>, Vec<u8>>,
+    /// Payloads stored with `Blob::new`; handle `n` is `blobs[n - 1]`.
+    pub blobs: Vec<Vec<u8>>,
+}
+
+/// The outcome of [`Runner::call`].
+#[derive(Debug)]
+pub struct Receipt {
+    /// What the entry point returned.
+    pub returned: Vec<Val>,
+    /// Set by `tx::set_result`.
+    pub result: Option<Vec<u8>>,
+    pub events: Vec<Event>,
+}
+
+struct Code {
+    module: Module,
+    hash: [u8; 32],
+    metadata: Vec<String>,
+}
+
+struct Inner {
+    engine: Engine,
+    codes: RefCell<HashMap<String, Code>>,
+    ledger: RefCell<Ledger>,
+}
+
+/// The store data of one contract instance.
+struct Ctx {
+    inner: Rc<Inner>,
+    code: [u8; 32],
+    coordination: bool,
+    last_error: (u32, u32),
+    /// Ledger indices of the tokens this instance holds, by handle.
+    tokens: HashMap<u32, usize>,
+    next_handle: u32,
+}
+
+/// Loads contract modules and runs transactions against them.
+pub struct Runner {
+    inner: Rc<Inner>,
+}
+
+impl Default for Runner {
+    fn default() -> Self {
+        Runner::new()
+    }
+}
+
+impl Runner {
+    pub fn new() -> Runner {
+        Runner {
+            inner: Rc::new(Inner {
+                engine: Engine::default(),
+                codes: RefCell::new(HashMap::new()),
+                ledger: RefCell::new(Ledger::default()),
+            }),
+        }
+    }
+
+    /// Load a compiled contract under `name`. Fails if the module was built
+    /// against `starstream` but its metadata section was stripped.
+    pub fn load(&mut self, name: &str, wasm: &[u8]) -> Result<()> {
+        let module =
+            Module::new(&self.inner.engine, wasm).with_context(|| format!("compiling {name}"))?;
+        let hash = Sha256::digest(wasm).into();
+        let section = custom_section(wasm, METADATA_SECTION)
+            .with_context(|| format!("reading the sections of {name}"))?;
+        let vouched = module
+            .exports()
+            .any(|export| export.name() == METADATA_VERSION_EXPORT);
+        let metadata = match section {
+            Some(section) => String::from_utf8(section)
+                .with_context(|| format!("{name}: {METADATA_SECTION} is not UTF-8"))?
+                .lines()
+                .map(str::to_owned)
+                .collect(),
+            None if vouched => bail!("{name}: {METADATA_SECTION} was stripped"),
+            None => Vec::new(),
+        };
+        let code = Code {
+            module,
+            hash,
+            metadata,
+        };
+        self.inner.codes.borrow_mut().insert(name.to_owned(), code);
+        Ok(())
+    }
+
+    pub fn load_file(&mut self, name: &str, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let wasm = std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        self.load(name, &wasm)
+    }
+
+    /// The SHA-256 of the module loaded as `name`, which contracts see as
+    /// its `CodeHash`.
+    pub fn code_hash(&self, name: &str) -> Option<[u8; 32]> {
+        self.inner.codes.borrow().get(name).map(|code| code.hash)
+    }
+
+    /// The lines of the metadata section of the module loaded as `name`,
+    /// such as `coordination star_mint(amount : u64)`.
+    pub fn metadata(&self, name: &str) -> Option<Vec<String>> {
+        (self.inner.codes.borrow().get(name)).map(|code| code.metadata.clone())
+    }
+
+    /// The imports listed in the manifest of the module loaded as `name`
+    /// that this host can't provide: those from a later ABI, those it
+    /// doesn't define or defines with another signature, and those from
+    /// modules not loaded. Fails if the module has no manifest, which
+    /// [`manifest::embed`] adds.
+    pub fn unsupported_imports(&self, name: &str) -> Result<Vec<manifest::Import>> {
+        let metadata = self
+            .metadata(name)
+            .with_context(|| format!("no module loaded as {name}"))?;
+        let imports = manifest::parse(&metadata)?
+            .with_context(|| format!("{name} has no import manifest"))?;
+        let mut store = self.inner.store([0; 32], false);
+        let mut linker = Linker::new(&self.inner.engine);
+        define_env(&mut linker)?;
+        let codes = self.inner.codes.borrow();
+        let supported = |import: &manifest::Import, store: &mut Store<Ctx>| {
+            if import.since > ABI_VERSION {
+                return false;
+            }
+            if let Some(exporter) = import.module.strip_prefix("starstream_token:") {
+                // Served by the exporter's function of the same name.
+                let export = import.name.strip_suffix("_batch").unwrap_or(&import.name);
+                return codes
+                    .get(exporter)
+                    .is_some_and(|code| code.module.get_export(export).is_some());
+            }
+            // TODO: `starstream_utxo_env` and `starstream_utxo:` imports.
+            match linker.get(&mut *store, &import.module, &import.name) {
+                Some(Extern::Func(func)) => {
+                    let ty = func.ty(&*store);
+                    let params: Vec<String> = ty.params().map(manifest::val_type).collect();
+                    let results: Vec<String> = ty.results().map(manifest::val_type).collect();
+                    params == import.params && results == import.results
+                }
+                _ => false,
+            }
+        };
+        Ok(imports
+            .into_iter()
+            .filter(|import| !supported(import, &mut store))
+            .collect())
+    }
+
+    pub fn ledger(&self) -> std::cell::RefMut<'_, Ledger> {
+        self.inner.ledger.borrow_mut()
+    }
+
+    /// Run the entry point `entry` of the coordination script loaded as
+    /// `script` as one transaction, with `payload` for `tx::payload`.
+    pub fn call_with_payload(
+        &mut self,
+        script: &str,
+        entry: &str,
+        args: &[Val],
+        payload: &[u8],
+    ) -> Result<Receipt> {
+        {
+            let mut ledger = self.ledger();
+            ledger.payload = payload.to_vec();
+            ledger.result = None;
+            ledger.events.clear();
+            ledger.scratch.clear();
+            ledger.blobs.clear();
+        }
+        let (mut store, instance) = self.inner.instantiate(script, true)?;
+        let func = instance
+            .get_func(&mut store, entry)
+            .with_context(|| format!("{script} has no entry point {entry}"))?;
+        let mut returned = vec![Val::I32(0); func.ty(&store).results().len()];
+        func.call(&mut store, args, &mut returned)
+            .with_context(|| format!("running {script}::{entry}"))?;
+        let mut ledger = self.ledger();
+        Ok(Receipt {
+            returned,
+            result: ledger.result.take(),
+            events: std::mem::take(&mut ledger.events),
+        })
+    }
+
+    /// Run the entry point `entry` of the coordination script loaded as
+    /// `script` as one transaction.
+    pub fn call(&mut self, script: &str, entry: &str, args: &[Val]) -> Result<Receipt> {
+        self.call_with_payload(script, entry, args, &[])
+    }
+}
+
+impl Inner {
+    fn store(self: &Rc<Self>, code: [u8; 32], coordination: bool) -> Store<Ctx> {
+        Store::new(
+            &self.engine,
+            Ctx {
+                inner: self.clone(),
+                code,
+                coordination,
+                last_error: (0, 0),
+                tokens: HashMap::new(),
+                next_handle: 1,
+            },
+        )
+    }
+
+    fn instantiate(
+        self: &Rc<Self>,
+        name: &str,
+        coordination: bool,
+    ) -> Result<(Store<Ctx>, Instance)> {
+        let (module, hash) = match self.codes.borrow().get(name) {
+            Some(code) => (code.module.clone(), code.hash),
+            None => bail!("no module loaded as {name}"),
+        };
+        let mut store = self.store(hash, coordination);
+        let mut linker = Linker::new(&self.engine);
+        define_env(&mut linker)?;
+        for import in module.imports() {
+            if let Some(exporter) = import.module().strip_prefix("starstream_token:") {
+                let ExternType::Func(ty) = import.ty() else {
+                    bail!("bad token import {} from {exporter}", import.name());
+                };
+                define_token_import(&mut linker, exporter, import.name(), ty)?;
+            }
+        }
+        // TODO: `starstream_utxo_env` and `starstream_utxo:` imports.
+        linker.define_unknown_imports_as_traps(&module)?;
+        let instance = linker.instantiate(&mut store, &module)?;
+        Ok((store, instance))
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Memory
+
+fn memory(caller: &mut Caller<'_, Ctx>) -> Result<Memory> {
+    match caller.get_export("memory") {
+        Some(Extern::Memory(memory)) => Ok(memory),
+        _ => bail!("contract exports no memory"),
+    }
+}
+
+fn read(caller: &mut Caller<'_, Ctx>, ptr: u32, len: u32) -> Result<Vec<u8>> {
+    let mut buf = vec![0; len as usize];
+    memory(caller)?.read(&*caller, ptr as usize, &mut buf)?;
+    Ok(buf)
+}
+
+fn write(caller: &mut Caller<'_, Ctx>, ptr: u32, bytes: &[u8]) -> Result<()> {
+    memory(caller)?.write(&mut *caller, ptr as usize, bytes)?;
+    Ok(())
+}
+
+fn read_str(caller: &mut Caller<'_, Ctx>, ptr: u32, len: u32) -> Result<String> {
+    Ok(String::from_utf8(read(caller, ptr, len)?)?)
+}
+
+/// The concatenation of the `count` guest slices described at `parts`.
+fn read_parts(caller: &mut Caller<'_, Ctx>, parts: u32, count: u32) -> Result<Vec<u8>> {
+    // struct Part { ptr: *const u8, len: usize }
+    let table = read(caller, parts, count * 8)?;
+    let mut bytes = Vec::new();
+    for part in table.chunks(8) {
+        let ptr = u32::from_le_bytes(part[..4].try_into().unwrap());
+        let len = u32::from_le_bytes(part[4..].try_into().unwrap());
+        bytes.extend(read(caller, ptr, len)?);
+    }
+    Ok(bytes)
+}
+
+/// The public key, uncompressed and without its tag, that made `signature`
+/// (`r`, `s`, and `v`) over `hash`, as Ethereum's `ecrecover` finds it.
+fn secp256k1_recover(hash: &[u8], signature: &[u8]) -> Option<[u8; 64]> {
+    let v = match signature[64] {
+        v @ (0 | 1) => v,
+        v @ (27 | 28) => v - 27,
+        _ => return None,
+    };
+    let signature = Signature::from_slice(&signature[..64]).ok()?;
+    // `ecrecover` accepts a high `s`, but k256 only verifies the low one,
+    // which recovers the same key from the other `v`.
+    let (signature, v) = match signature.normalize_s() {
+        Some(low) => (low, v ^ 1),
+        None => (signature, v),
+    };
+    let key =
+        VerifyingKey::recover_from_prehash(hash, &signature, RecoveryId::from_byte(v)?).ok()?;
+    key.to_encoded_point(false).as_bytes()[1..].try_into().ok()
+}
+
+/// Copy `bytes` to `ptr`, trapping like the real host if the guest asked
+/// for a different size.
+fn write_sized(
+    caller: &mut Caller<'_, Ctx>,
+    what: &str,
+    bytes: &[u8],
+    ptr: u32,
+    size: u32,
+) -> Result<()> {
+    if bytes.len() != size as usize {
+        bail!(
+            "{what} size mismatch: got {} bytes, expected {size}",
+            bytes.len()
+        );
+    }
+    write(caller, ptr, bytes)
+}
+
+/// The bytes of blob handle `blob`, trapping on a handle never handed out.
+fn ledger_blob(caller: &Caller<'_, Ctx>, blob: u32) -> Result<Vec<u8>> {
+    let ledger = caller.data().inner.ledger.borrow();
+    match ledger.blobs.get((blob as usize).wrapping_sub(1)) {
+        Some(bytes) => Ok(bytes.clone()),
+        None => bail!("no blob with handle {blob}"),
+    }
+}
+
+// ----------------------------------------------------------------------------
+// env
+
+fn define_env(linker: &mut Linker<Ctx>) -> Result<()> {
+    linker.func_wrap("env", "abort", || -> Result<()> { bail!("abort() called") })?;
+    linker.func_wrap(
+        "env",
+        "starstream_abort",
+        |mut caller: Caller<'_, Ctx>, code: u32, msg: u32, msg_len: u32| -> Result<()> {
+            let reason = read_str(&mut caller, msg, msg_len)?;
+            if code == PANIC_CODE {
+                bail!("contract panicked: {reason}")
+            }
+            bail!("contract aborted with code {code}: {reason}")
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_log",
+        |caller: Caller<'_, Ctx>, value: u32| {
+            caller.data().inner.ledger.borrow_mut().logs.push(value);
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_log_bytes",
+        |mut caller: Caller<'_, Ctx>, ptr: u32, len: u32| -> Result<()> {
+            let bytes = read(&mut caller, ptr, len)?;
+            let line = String::from_utf8_lossy(&bytes).into_owned();
+            caller.data().inner.ledger.borrow_mut().log_lines.push(line);
+            Ok(())
+        },
+    )?;
+    // The coordination script driving a run is the only one there is yet, so
+    // all three are the running module's hash, as in the TypeScript host.
+    for name in [
+        "starstream_coordination_code",
+        "starstream_this_code",
+        "starstream_caller_code",
+    ] {
+        linker.func_wrap("env", name, |mut caller: Caller<'_, Ctx>, ret: u32| {
+            let code = caller.data().code;
+            write(&mut caller, ret, &code)
+        })?;
+    }
+    linker.func_wrap(
+        "env",
+        "starstream_is_coordination",
+        |caller: Caller<'_, Ctx>| caller.data().coordination as u32,
+    )?;
+    linker.func_wrap("env", "starstream_is_simulation", || 0u32)?;
+    linker.func_wrap(
+        "env",
+        "starstream_block_height",
+        |caller: Caller<'_, Ctx>| caller.data().inner.ledger.borrow().block_height,
+    )?;
+    linker.func_wrap("env", "starstream_block_time", |caller: Caller<'_, Ctx>| {
+        caller.data().inner.ledger.borrow().block_time
+    })?;
+    linker.func_wrap("env", "starstream_chain_id", |caller: Caller<'_, Ctx>| {
+        caller.data().inner.ledger.borrow().chain_id
+    })?;
+    linker.func_wrap("env", "starstream_host_abi_version", || ABI_VERSION)?;
+    linker.func_wrap(
+        "env",
+        "starstream_last_error",
+        |mut caller: Caller<'_, Ctx>, ret: u32| {
+            let (kind, code) = caller.data().last_error;
+            let mut raw = [0; 8];
+            raw[..4].copy_from_slice(&kind.to_le_bytes());
+            raw[4..].copy_from_slice(&code.to_le_bytes());
+            write(&mut caller, ret, &raw)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_sha256",
+        |mut caller: Caller<'_, Ctx>, parts: u32, count: u32, out: u32| {
+            let message = read_parts(&mut caller, parts, count)?;
+            write(&mut caller, out, &Sha256::digest(message))
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_blake2b_256",
+        |mut caller: Caller<'_, Ctx>, parts: u32, count: u32, out: u32| {
+            let message = read_parts(&mut caller, parts, count)?;
+            write(&mut caller, out, &blake2b::blake2b_256(&message))
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_secp256k1_recover",
+        |mut caller: Caller<'_, Ctx>, hash: u32, signature: u32, out: u32| -> Result<u32> {
+            let hash = read(&mut caller, hash, 32)?;
+            let signature = read(&mut caller, signature, 65)?;
+            match secp256k1_recover(&hash, &signature) {
+                Some(key) => {
+                    write(&mut caller, out, &key)?;
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        },
+    )?;
+
+    // Transaction
+    linker.func_wrap("env", "starstream_tx_signer_count", || 0u32)?;
+    linker.func_wrap("env", "starstream_tx_fee", || 0u64)?;
+    linker.func_wrap(
+        "env",
+        "starstream_tx_payload_len",
+        |caller: Caller<'_, Ctx>| caller.data().inner.ledger.borrow().payload.len() as u32,
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_tx_payload",
+        |mut caller: Caller<'_, Ctx>, ptr: u32, size: u32| {
+            let payload = caller.data().inner.ledger.borrow().payload.clone();
+            write_sized(&mut caller, "payload", &payload, ptr, size)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_tx_result",
+        |mut caller: Caller<'_, Ctx>, ptr: u32, size: u32| {
+            let result = read(&mut caller, ptr, size)?;
+            caller.data().inner.ledger.borrow_mut().result = Some(result);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_emit_event",
+        |mut caller: Caller<'_, Ctx>, topic: u32, topic_len: u32, payload: u32, size: u32| {
+            let topic = read_str(&mut caller, topic, topic_len)?;
+            let payload = read(&mut caller, payload, size)?;
+            let event = Event { topic, payload };
+            caller.data().inner.ledger.borrow_mut().events.push(event);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_emit_event_parts",
+        |mut caller: Caller<'_, Ctx>, topic: u32, topic_len: u32, parts: u32, count: u32| {
+            let topic = read_str(&mut caller, topic, topic_len)?;
+            let payload = read_parts(&mut caller, parts, count)?;
+            let event = Event { topic, payload };
+            caller.data().inner.ledger.borrow_mut().events.push(event);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_tx_event",
+        |mut caller: Caller<'_, Ctx>,
+         topic: u32,
+         topic_len: u32,
+         index: u32,
+         payload: u32,
+         size: u32| {
+            let topic = read_str(&mut caller, topic, topic_len)?;
+            let event = (caller.data().inner.ledger.borrow().events.iter())
+                .filter(|event| event.topic == topic)
+                .nth(index as usize)
+                .map(|event| event.payload.clone());
+            match event {
+                Some(event) => {
+                    write_sized(&mut caller, "event payload", &event, payload, size)?;
+                    Ok(1u32)
+                }
+                None => Ok(0),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_blob_new",
+        |mut caller: Caller<'_, Ctx>, ptr: u32, len: u32| -> Result<u32> {
+            let bytes = read(&mut caller, ptr, len)?;
+            let mut ledger = caller.data().inner.ledger.borrow_mut();
+            ledger.blobs.push(bytes);
+            Ok(ledger.blobs.len() as u32)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_blob_len",
+        |caller: Caller<'_, Ctx>, blob: u32| -> Result<u32> {
+            Ok(ledger_blob(&caller, blob)?.len() as u32)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_blob_read",
+        |mut caller: Caller<'_, Ctx>, blob: u32, offset: u32, out: u32, out_len: u32| {
+            let blob = ledger_blob(&caller, blob)?;
+            let rest = blob.get(offset as usize..).unwrap_or_default();
+            let n = rest.len().min(out_len as usize);
+            write(&mut caller, out, &rest[..n])?;
+            Ok(n as u32)
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_bridge_root",
+        |mut caller: Caller<'_, Ctx>, client: u64, height: u32, out: u32| -> Result<u32> {
+            let height = read(&mut caller, height, 16)?;
+            let key = (
+                client,
+                u64::from_le_bytes(height[..8].try_into().unwrap()),
+                u64::from_le_bytes(height[8..].try_into().unwrap()),
+            );
+            let root = caller
+                .data()
+                .inner
+                .ledger
+                .borrow()
+                .bridge_roots
+                .get(&key)
+                .copied();
+            match root {
+                Some(root) => {
+                    write(&mut caller, out, &root)?;
+                    Ok(1)
+                }
+                None => Ok(0),
+            }
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_scratch_set",
+        |mut caller: Caller<'_, Ctx>, key: u32, key_len: u32, value: u32, value_len: u32| {
+            let key = read(&mut caller, key, key_len)?;
+            let value = read(&mut caller, value, value_len)?;
+            caller
+                .data()
+                .inner
+                .ledger
+                .borrow_mut()
+                .scratch
+                .insert(key, value);
+            Ok(())
+        },
+    )?;
+    linker.func_wrap(
+        "env",
+        "starstream_scratch_get",
+        |mut caller: Caller<'_, Ctx>,
+         key: u32,
+         key_len: u32,
+         out: u32,
+         out_len: u32,
+         value_len: u32| {
+            let key = read(&mut caller, key, key_len)?;
+            let value = caller
+                .data()
+                .inner
+                .ledger
+                .borrow()
+                .scratch
+                .get(&key)
+                .cloned();
+            match value {
+                Some(value) => {
+                    let n = value.len().min(out_len as usize);
+                    write(&mut caller, out, &value[..n])?;
+                    write(&mut caller, value_len, &(value.len() as u32).to_le_bytes())?;
+                    Ok(1u32)
+                }
+                None => Ok(0),
+            }
+        },
+    )?;
+    Ok(())
+}
+
+// ----------------------------------------------------------------------------
+// Tokens
+
+/// Serve `starstream_token:<exporter>` imports by running the exporter's
+/// mint and burn functions in fresh instances, as the TypeScript host does.
+fn define_token_import(
+    linker: &mut Linker<Ctx>,
+    exporter: &str,
+    name: &str,
+    ty: FuncType,
+) -> Result<()> {
+    let exporter = exporter.to_owned();
+    let import = format!("starstream_token:{exporter}");
+    if let Some(single) = name.strip_suffix("_batch") {
+        return define_token_batch_import(linker, &import, exporter, name, single, ty);
+    }
+    if let Some(token) = name.strip_prefix("starstream_mint_") {
+        let (token, mint_fn) = (token.to_owned(), name.to_owned());
+        linker.func_new(&import, name, ty, move |mut caller, params, results| {
+            caller.data_mut().last_error = (0, 0);
+            let handle = mint_token(&mut caller, &exporter, &token, &mint_fn, params);
+            results[0] = Val::I32(handle as i32);
+            Ok(())
+        })?;
+    } else if name.starts_with("starstream_burn_") {
+        let burn_fn = name.to_owned();
+        let result_types: Vec<ValType> = ty.results().collect();
+        linker.func_new(&import, name, ty, move |mut caller, params, results| {
+            caller.data_mut().last_error = (0, 0);
+            // Placeholders, in case the burn fails.
+            for (result, ty) in results.iter_mut().zip(&result_types) {
+                *result = zero(ty);
+            }
+            let handle = params[0].unwrap_i32() as u32;
+            burn_token(&mut caller, &exporter, &burn_fn, handle, results);
+            Ok(())
+        })?;
+    } else {
+        bail!("bad token import {name} from {exporter}");
+    }
+    Ok(())
+}
+
+/// Serve the `_batch` form of the mint or burn import `single`, which takes
+/// an array of intermediates or handles and stops at the first refusal,
+/// returning how many succeeded.
+///
+/// Like the single imports, this only handles intermediates passed as one
+/// `i32` or `i64`.
+fn define_token_batch_import(
+    linker: &mut Linker<Ctx>,
+    import: &str,
+    exporter: String,
+    name: &str,
+    single: &str,
+    ty: FuncType,
+) -> Result<()> {
+    fn scalar(size: u32) -> Result<ValType> {
+        match size {
+            4 => Ok(ValType::I32),
+            8 => Ok(ValType::I64),
+            _ => bail!("batch token imports only pass 4- or 8-byte intermediates, not {size}"),
+        }
+    }
+
+    if let Some(token) = single.strip_prefix("starstream_mint_") {
+        let (token, mint_fn) = (token.to_owned(), single.to_owned());
+        // (intermediates, intermediate_size, count, out) -> minted
+        linker.func_new(import, name, ty, move |mut caller, params, results| {
+            caller.data_mut().last_error = (0, 0);
+            let [intermediates, size, count, out] =
+                [0, 1, 2, 3].map(|i| params[i].unwrap_i32() as u32);
+            let ty = scalar(size)?;
+            let mut minted = 0;
+            while minted < count {
+                let bytes = read(&mut caller, intermediates + minted * size, size)?;
+                let arg = match ty {
+                    ValType::I32 => Val::I32(i32::from_le_bytes(bytes[..].try_into().unwrap())),
+                    _ => Val::I64(i64::from_le_bytes(bytes[..].try_into().unwrap())),
+                };
+                let handle = mint_token(&mut caller, &exporter, &token, &mint_fn, &[arg]);
+                if handle == INVALID_HANDLE {
+                    break;
+                }
+                write(&mut caller, out + minted * 4, &handle.to_le_bytes())?;
+                minted += 1;
+            }
+            results[0] = Val::I32(minted as i32);
+            Ok(())
+        })?;
+    } else if single.starts_with("starstream_burn_") {
+        let burn_fn = single.to_owned();
+        // (handles, count, out, intermediate_size) -> burned
+        linker.func_new(import, name, ty, move |mut caller, params, results| {
+            caller.data_mut().last_error = (0, 0);
+            let [handles, count, out, size] = [0, 1, 2, 3].map(|i| params[i].unwrap_i32() as u32);
+            let mut result = [zero(&scalar(size)?)];
+            let mut burned = 0;
+            while burned < count {
+                let handle = read(&mut caller, handles + burned * 4, 4)?;
+                let handle = u32::from_le_bytes(handle[..].try_into().unwrap());
+                if !burn_token(&mut caller, &exporter, &burn_fn, handle, &mut result) {
+                    break;
+                }
+                let bytes = match result[0] {
+                    Val::I32(value) => value.to_le_bytes().to_vec(),
+                    ref value => value.unwrap_i64().to_le_bytes().to_vec(),
+                };
+                write(&mut caller, out + burned * size, &bytes)?;
+                burned += 1;
+            }
+            results[0] = Val::I32(burned as i32);
+            Ok(())
+        })?;
+    } else {
+        bail!("bad token import {name} from {exporter}");
+    }
+    Ok(())
+}
+
+/// Mint a `token` with `mint_fn`, recording it in the ledger and returning
+/// its new handle, or record the failure and return [`INVALID_HANDLE`].
+fn mint_token(
+    caller: &mut Caller<'_, Ctx>,
+    exporter: &str,
+    token: &str,
+    mint_fn: &str,
+    params: &[Val],
+) -> u32 {
+    let inner = caller.data().inner.clone();
+    match mint(&inner, exporter, mint_fn, params) {
+        Ok((id, amount)) => {
+            let mut ledger = inner.ledger.borrow_mut();
+            ledger.tokens.push(TokenRecord {
+                module: exporter.to_owned(),
+                name: token.to_owned(),
+                id,
+                amount,
+                burned: false,
+            });
+            let index = ledger.tokens.len() - 1;
+            let ctx = caller.data_mut();
+            let handle = ctx.next_handle;
+            ctx.next_handle += 1;
+            ctx.tokens.insert(handle, index);
+            handle
+        }
+        Err(_) => {
+            caller.data_mut().last_error = (3, UNAUTHORIZED);
+            INVALID_HANDLE
+        }
+    }
+}
+
+/// Burn the token at `handle` with `burn_fn`, writing its intermediate to
+/// `results`, or record the failure and return false.
+fn burn_token(
+    caller: &mut Caller<'_, Ctx>,
+    exporter: &str,
+    burn_fn: &str,
+    handle: u32,
+    results: &mut [Val],
+) -> bool {
+    let Some(&index) = caller.data().tokens.get(&handle) else {
+        caller.data_mut().last_error = (3, NOT_FOUND);
+        return false;
+    };
+    let inner = caller.data().inner.clone();
+    let record = inner.ledger.borrow().tokens[index].clone();
+    match burn(&inner, exporter, burn_fn, &record, results) {
+        Ok(()) => {
+            inner.ledger.borrow_mut().tokens[index].burned = true;
+            caller.data_mut().tokens.remove(&handle);
+            true
+        }
+        Err(_) => {
+            caller.data_mut().last_error = (3, UNAUTHORIZED);
+            false
+        }
+    }
+}
+
+/// The contents of the custom sections of `wasm` named `name`, concatenated,
+/// or `None` if there are none.
+fn custom_section(wasm: &[u8], name: &str) -> Result<Option<Vec<u8>>> {
+    fn leb(bytes: &mut &[u8]) -> Result<usize> {
+        let mut value = 0;
+        for shift in (0..35).step_by(7) {
+            let (&byte, rest) = bytes.split_first().context("truncated")?;
+            *bytes = rest;
+            value |= ((byte & 0x7f) as usize) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("bad LEB128")
+    }
+    fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+        ensure!(bytes.len() >= len, "truncated");
+        let (head, rest) = bytes.split_at(len);
+        *bytes = rest;
+        Ok(head)
+    }
+
+    let mut bytes = wasm.strip_prefix(b"\0asm").context("not a wasm module")?;
+    take(&mut bytes, 4)?;
+    let mut found: Option<Vec<u8>> = None;
+    while !bytes.is_empty() {
+        let id = take(&mut bytes, 1)?[0];
+        let len = leb(&mut bytes)?;
+        let mut section = take(&mut bytes, len)?;
+        if id == 0 {
+            let name_len = leb(&mut section)?;
+            if take(&mut section, name_len)? == name.as_bytes() {
+                found.get_or_insert_with(Vec::new).extend_from_slice(section);
+            }
+        }
+    }
+    Ok(found)
+}
+
+fn zero(ty: &ValType) -> Val {
+    match ty {
+        ValType::I64 => Val::I64(0),
+        ValType::F32 => Val::F32(0),
+        ValType::F64 => Val::F64(0),
+        _ => Val::I32(0),
+    }
+}
+
+/// Run `mint_fn` in a fresh instance of `exporter`, returning the minted
+/// `TokenStorage`.
+fn mint(inner: &Rc<Inner>, exporter: &str, mint_fn: &str, params: &[Val]) -> Result<(u64, u64)> {
+    let (mut store, instance) = inner.instantiate(exporter, false)?;
+    let func = instance
+        .get_func(&mut store, mint_fn)
+        .with_context(|| format!("{exporter} has no export {mint_fn}"))?;
+    // TODO: intermediates passed by pointer point into the importer's memory.
+    let mut args = vec![Val::I32(RETURN_ADDR)];
+    args.extend_from_slice(params);
+    func.call(&mut store, &args, &mut [])?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .context("token exports no memory")?;
+    let mut storage = [0; 16];
+    memory.read(&store, RETURN_ADDR as usize, &mut storage)?;
+    let id = u64::from_le_bytes(storage[..8].try_into().unwrap());
+    let amount = u64::from_le_bytes(storage[8..].try_into().unwrap());
+    Ok((id, amount))
+}
+
+/// Run `burn_fn` in a fresh instance of `exporter` on the token's storage,
+/// writing the intermediate it returns to `results`.
+fn burn(
+    inner: &Rc<Inner>,
+    exporter: &str,
+    burn_fn: &str,
+    record: &TokenRecord,
+    results: &mut [Val],
+) -> Result<()> {
+    let (mut store, instance) = inner.instantiate(exporter, false)?;
+    let func = instance
+        .get_func(&mut store, burn_fn)
+        .with_context(|| format!("{exporter} has no export {burn_fn}"))?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .context("token exports no memory")?;
+    // `TokenStorage` is passed by pointer.
+    let mut storage = [0; 16];
+    storage[..8].copy_from_slice(&record.id.to_le_bytes());
+    storage[8..].copy_from_slice(&record.amount.to_le_bytes());
+    memory.write(&mut store, RETURN_ADDR as usize, &storage)?;
+    func.call(&mut store, &[Val::I32(RETURN_ADDR)], results)?;
+    Ok(())
+}
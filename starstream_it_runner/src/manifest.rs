@@ -0,0 +1,177 @@
+//! A manifest of the imports a module needs from its host, added to its
+//! metadata section after linking, so deployment tooling can check that a
+//! host provides every one of them before a transaction fails on a missing
+//! import.
+//!
+//! Only the linked module knows which imports it actually uses, so this is a
+//! build step rather than something `starstream` can emit. Run it on the
+//! final module, before computing its code hash:
+//!
+//! ```ignore
+//! let wasm = std::fs::read("target/wasm32-unknown-unknown/release/example_contract.wasm")?;
+//! std::fs::write("deploy/example_contract.wasm", manifest::embed(&wasm)?)?;
+//! ```
+//!
+//! Each import is an `import` line in the metadata section, with its wasm
+//! signature and the ABI version that introduced it:
+//!
+//! ```text
+//! import env starstream_sha256(i32, i32, i32) since 1
+//! import starstream_token:example_contract starstream_mint_StarNft(i64) -> i32 since 1
+//! ```
+
+use std::fmt;
+
+use anyhow::{bail, Context, Result};
+use wasmtime::{Engine, ExternType, Module, ValType};
+
+use crate::metadata::signature;
+use crate::METADATA_SECTION;
+
+/// One import of a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// The import module, such as `env` or `starstream_token:<module>`.
+    pub module: String,
+    pub name: String,
+    /// Wasm value types, such as `i32`.
+    pub params: Vec<String>,
+    pub results: Vec<String>,
+    /// The lowest host ABI version that can provide it.
+    pub since: u32,
+}
+
+impl fmt::Display for Import {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {}({})",
+            self.module,
+            self.name,
+            self.params.join(", ")
+        )?;
+        match self.results.as_slice() {
+            [] => {}
+            [result] => write!(f, " -> {result}")?,
+            results => write!(f, " -> ({})", results.join(", "))?,
+        }
+        write!(f, " since {}", self.since)
+    }
+}
+
+/// The ABI version that introduced the import `name` from `module`. Every
+/// import so far is from version 1; later additions go here.
+fn since(_module: &str, _name: &str) -> u32 {
+    1
+}
+
+pub(crate) fn val_type(ty: ValType) -> String {
+    match ty {
+        ValType::I32 => "i32".into(),
+        ValType::I64 => "i64".into(),
+        ValType::F32 => "f32".into(),
+        ValType::F64 => "f64".into(),
+        ValType::V128 => "v128".into(),
+        ty => format!("{ty}"),
+    }
+}
+
+/// The function imports of the module `wasm`.
+pub fn imports(wasm: &[u8]) -> Result<Vec<Import>> {
+    let module = Module::new(&Engine::default(), wasm).context("compiling the module")?;
+    let mut imports = Vec::new();
+    for import in module.imports() {
+        let ExternType::Func(ty) = import.ty() else {
+            bail!("{} {} isn't a function", import.module(), import.name());
+        };
+        imports.push(Import {
+            module: import.module().to_owned(),
+            name: import.name().to_owned(),
+            params: ty.params().map(val_type).collect(),
+            results: ty.results().map(val_type).collect(),
+            since: since(import.module(), import.name()),
+        });
+    }
+    Ok(imports)
+}
+
+/// The module `wasm` with a manifest of its imports added to its metadata
+/// section. Fails if it already has one.
+pub fn embed(wasm: &[u8]) -> Result<Vec<u8>> {
+    let metadata = crate::custom_section(wasm, METADATA_SECTION)?.unwrap_or_default();
+    let metadata = String::from_utf8(metadata).context("metadata is not UTF-8")?;
+    if metadata.lines().any(|line| line.starts_with("import ")) {
+        bail!("the module already has an import manifest");
+    }
+    let mut lines = String::new();
+    if !metadata.is_empty() && !metadata.ends_with('\n') {
+        lines.push('\n');
+    }
+    for import in imports(wasm)? {
+        lines.push_str(&format!("import {import}\n"));
+    }
+
+    // A second section of the same name, which readers concatenate with the
+    // first, as the linker does for the entries of each crate.
+    let mut section = Vec::new();
+    leb(&mut section, METADATA_SECTION.len());
+    section.extend_from_slice(METADATA_SECTION.as_bytes());
+    section.extend_from_slice(lines.as_bytes());
+    let mut out = wasm.to_vec();
+    out.push(0);
+    leb(&mut out, section.len());
+    out.extend(section);
+    Ok(out)
+}
+
+/// The imports listed in the metadata lines of a module, as from
+/// [`Runner::metadata`](crate::Runner::metadata), or `None` if it has no
+/// manifest.
+pub fn parse(metadata: &[String]) -> Result<Option<Vec<Import>>> {
+    let mut imports = None;
+    for line in metadata {
+        let Some(rest) = line.strip_prefix("import ") else {
+            continue;
+        };
+        let import = parse_line(rest).with_context(|| format!("bad manifest line `{line}`"))?;
+        imports.get_or_insert_with(Vec::new).push(import);
+    }
+    Ok(imports)
+}
+
+fn parse_line(line: &str) -> Result<Import> {
+    let (module, rest) = line.split_once(' ').context("expected a module")?;
+    let (rest, since) = rest.rsplit_once(" since ").context("expected `since`")?;
+    let (sig, results) = match rest.split_once("->") {
+        Some((sig, results)) => {
+            let results = results.trim();
+            let results = match results.strip_prefix('(') {
+                Some(_) => signature(&format!("_{results}"))?.1,
+                None => vec![results.to_owned()],
+            };
+            (sig, results)
+        }
+        None => (rest, Vec::new()),
+    };
+    let (name, params) = signature(sig)?;
+    let trim = |types: Vec<String>| types.iter().map(|ty| ty.trim().to_owned()).collect();
+    Ok(Import {
+        module: module.to_owned(),
+        name,
+        params: trim(params),
+        results: trim(results),
+        since: since.trim().parse().context("bad ABI version")?,
+    })
+}
+
+fn leb(out: &mut Vec<u8>, mut value: usize) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
@@ -0,0 +1,60 @@
+//! Parsing the lines of `starstream::METADATA_SECTION`, for the generators.
+
+use anyhow::{Context, Result};
+
+use crate::{custom_section, METADATA_SECTION};
+
+/// The metadata lines of the module `wasm`.
+pub(crate) fn lines(wasm: &[u8]) -> Result<Vec<String>> {
+    let section = custom_section(wasm, METADATA_SECTION)?
+        .with_context(|| format!("no {METADATA_SECTION} section"))?;
+    let text = String::from_utf8(section).context("metadata is not UTF-8")?;
+    Ok(text.lines().map(str::to_owned).collect())
+}
+
+/// Split `name(a, b)` into the name and the top-level comma-separated items.
+pub(crate) fn signature(source: &str) -> Result<(String, Vec<String>)> {
+    let (name, rest) = source.split_once('(').context("expected `(`")?;
+    let inner = rest.trim_end().strip_suffix(')').context("expected `)`")?;
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut depth = 0;
+    for c in inner.chars() {
+        match c {
+            '(' | '[' | '<' => depth += 1,
+            ')' | ']' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                items.push(std::mem::take(&mut item));
+                continue;
+            }
+            _ => {}
+        }
+        item.push(c);
+    }
+    if !item.trim().is_empty() {
+        items.push(item);
+    }
+    Ok((name.trim().to_owned(), items))
+}
+
+/// Split `name: Type` at its first lone `:`.
+pub(crate) fn split_param(param: &str) -> Result<(String, String)> {
+    let bytes = param.as_bytes();
+    let colon = (0..bytes.len())
+        .find(|&i| {
+            bytes[i] == b':' && bytes.get(i + 1) != Some(&b':') && (i == 0 || bytes[i - 1] != b':')
+        })
+        .with_context(|| format!("expected `name: Type`, got `{param}`"))?;
+    let name = param[..colon].trim().to_owned();
+    Ok((name, param[colon + 1..].trim().to_owned()))
+}
+
+/// `stringify!` spacing, such as `args : [u8 ; 4]`, tidied for docs.
+pub(crate) fn compact(source: &str) -> String {
+    source
+        .replace(" : ", ": ")
+        .replace(" ;", ";")
+        .replace(" :: ", "::")
+        .replace("[ ", "[")
+        .replace(" ]", "]")
+}
@@ -0,0 +1,301 @@
+//! TypeScript bindings for a module, generated from its metadata section so
+//! frontends building transactions stay in sync with the contract's types.
+//!
+//! The contract must be built with `starstream`'s `ts-bindgen` feature, which
+//! lists the fields of every `#[derive(FfiSafe)]` type in the section. Call
+//! this from a build script or an `xtask` after building the contract:
+//!
+//! ```ignore
+//! let wasm = std::fs::read("target/wasm32-unknown-unknown/release/market.wasm")?;
+//! std::fs::write("web/src/market.ts", ts_bindgen::from_wasm(&wasm)?)?;
+//! ```
+//!
+//! For each recorded type the output has an interface and a `Codec` of the
+//! same name, and for each coordination entry point a constant describing
+//! how to call it: `result()` for entry points taking a `#[payload]`, whose
+//! arguments are submitted as CBOR, and `args()` for the C-ABI parameters
+//! otherwise.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use anyhow::{bail, Context, Result};
+
+use crate::layout::{layouts, Layouts, Ty};
+use crate::metadata::{compact, signature, split_param};
+
+/// Bindings for the module `wasm`.
+pub fn from_wasm(wasm: &[u8]) -> Result<String> {
+    generate(&crate::metadata::lines(wasm)?)
+}
+
+/// Bindings for the metadata lines of a module, as from
+/// [`Runner::metadata`](crate::Runner::metadata).
+pub fn generate(metadata: &[String]) -> Result<String> {
+    let layouts = layouts(metadata)?;
+    let entry_points: Vec<&str> = metadata
+        .iter()
+        .filter_map(|line| line.strip_prefix("coordination "))
+        .collect();
+
+    let mut out = String::from(PRELUDE);
+    let mut emitted = HashSet::new();
+    for name in layouts.keys() {
+        emit_layout(&mut out, &layouts, &mut emitted, name)?;
+    }
+    for entry in entry_points {
+        emit_entry_point(&mut out, &layouts, entry)
+            .with_context(|| format!("coordination entry point `{entry}`"))?;
+    }
+    Ok(out)
+}
+
+impl Ty {
+    /// The TypeScript type of values.
+    fn ts(&self) -> String {
+        match self {
+            Ty::Unit => "null".into(),
+            Ty::Scalar(name) if matches!(*name, "u64" | "i64" | "u128" | "i128") => "bigint".into(),
+            Ty::Scalar(_) | Ty::Handle => "number".into(),
+            Ty::Bytes(_) => "Uint8Array".into(),
+            Ty::Array(item, _) => format!("{}[]", item.ts()),
+            Ty::Struct(name) => name.clone(),
+            Ty::Unknown(_) => "unknown".into(),
+        }
+    }
+
+    /// An expression for the `Codec`.
+    fn codec(&self) -> Result<String> {
+        Ok(match self {
+            Ty::Unit => "unit".into(),
+            Ty::Scalar(name) => (*name).into(),
+            Ty::Handle => "u32".into(),
+            Ty::Bytes(len) => format!("bytes({len})"),
+            Ty::Array(item, len) => format!("array({}, {len})", item.codec()?),
+            Ty::Struct(name) => name.clone(),
+            Ty::Unknown(source) => {
+                bail!("no layout for `{source}`; derive FfiSafe for it with the ts-bindgen feature")
+            }
+        })
+    }
+}
+
+/// Emit `name` after the types its fields use, since each `Codec` refers to
+/// those of its fields.
+fn emit_layout(
+    out: &mut String,
+    layouts: &Layouts,
+    emitted: &mut HashSet<String>,
+    name: &str,
+) -> Result<()> {
+    if emitted.contains(name) {
+        return Ok(());
+    }
+    let mut fields = Vec::new();
+    for (field, ty) in &layouts[name] {
+        let ty = Ty::parse(ty, layouts)?;
+        for inner in ty.structs() {
+            emit_layout(out, layouts, emitted, inner)?;
+        }
+        let codec = ty
+            .codec()
+            .with_context(|| format!("field `{name}.{field}`"))?;
+        fields.push((field.trim_start_matches("r#"), ty.ts(), codec));
+    }
+
+    let size = Ty::Struct(name.to_owned()).size(layouts);
+    writeln!(out, "\n/** `{name}`, {size} bytes. */").unwrap();
+    writeln!(out, "export interface {name} {{").unwrap();
+    for (field, ts, _) in &fields {
+        writeln!(out, "  {field}: {ts};").unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+    writeln!(
+        out,
+        "export const {name}: Codec<{name}> = struct<{name}>({{"
+    )
+    .unwrap();
+    for (field, _, codec) in &fields {
+        writeln!(out, "  {field}: {codec},").unwrap();
+    }
+    writeln!(out, "}});").unwrap();
+    emitted.insert(name.to_owned());
+    Ok(())
+}
+
+fn emit_entry_point(out: &mut String, layouts: &Layouts, line: &str) -> Result<()> {
+    let (sig, ret) = match line.rsplit_once("->") {
+        Some((sig, ret)) => (sig.trim(), Ty::parse(ret, layouts)?),
+        None => (line.trim(), Ty::Unit),
+    };
+    let (name, params) = signature(sig)?;
+    writeln!(out, "\n/** `{}` */", compact(line)).unwrap();
+
+    if let [param] = &params[..] {
+        if param.trim().starts_with("#[payload]") {
+            // The payload is CBOR, in whatever shape the parameter type's
+            // `Decode` impl reads, so only the result has a codec.
+            writeln!(
+                out,
+                "export const {name}: PayloadEntryPoint<{}> = payloadEntryPoint({name:?}, {});",
+                ret.ts(),
+                ret.codec()?,
+            )
+            .unwrap();
+            return Ok(());
+        }
+    }
+
+    let mut typed = Vec::new();
+    let mut passed = Vec::new();
+    for param in &params {
+        let (arg, ty) = split_param(param)?;
+        let ty = Ty::parse(&ty, layouts)?;
+        match &ty {
+            // The C ABI drops zero-sized parameters.
+            _ if ty.size(layouts) == 0 && !matches!(ty, Ty::Unknown(_)) => continue,
+            // Unknown types are other modules' UTXOs, which the host passes
+            // as handles.
+            Ty::Scalar(_) | Ty::Handle | Ty::Unknown(_) => passed.push(arg.clone()),
+            _ => {
+                writeln!(
+                    out,
+                    "// Not callable from TypeScript: `{arg}` is passed by pointer; use a #[payload] instead."
+                )
+                .unwrap();
+                return Ok(());
+            }
+        }
+        typed.push(format!("{arg}: {}", ty.ts()));
+    }
+    writeln!(out, "export const {name} = {{").unwrap();
+    writeln!(out, "  name: {name:?},").unwrap();
+    writeln!(
+        out,
+        "  args: ({}): unknown[] => [{}],",
+        typed.join(", "),
+        passed.join(", ")
+    )
+    .unwrap();
+    writeln!(out, "}};").unwrap();
+    Ok(())
+}
+
+const PRELUDE: &str = r#"// Generated by starstream_it_runner::ts_bindgen from a module's
+// `starstream.metadata.v1` section. Don't edit; regenerate instead.
+
+/** Reads and writes a type in its `FfiSafe` layout. */
+export interface Codec<T> {
+  readonly size: number;
+  read(view: DataView, offset: number): T;
+  write(view: DataView, offset: number, value: T): void;
+}
+
+function scalar<T>(
+  size: number,
+  read: (view: DataView, offset: number) => T,
+  write: (view: DataView, offset: number, value: T) => void,
+): Codec<T> {
+  return { size, read, write };
+}
+
+export const unit: Codec<null> = scalar(0, () => null, () => {});
+export const u8 = scalar(1, (v, o) => v.getUint8(o), (v, o, x: number) => v.setUint8(o, x));
+export const u16 = scalar(2, (v, o) => v.getUint16(o, true), (v, o, x: number) => v.setUint16(o, x, true));
+export const u32 = scalar(4, (v, o) => v.getUint32(o, true), (v, o, x: number) => v.setUint32(o, x, true));
+export const u64 = scalar(8, (v, o) => v.getBigUint64(o, true), (v, o, x: bigint) => v.setBigUint64(o, x, true));
+export const i8 = scalar(1, (v, o) => v.getInt8(o), (v, o, x: number) => v.setInt8(o, x));
+export const i16 = scalar(2, (v, o) => v.getInt16(o, true), (v, o, x: number) => v.setInt16(o, x, true));
+export const i32 = scalar(4, (v, o) => v.getInt32(o, true), (v, o, x: number) => v.setInt32(o, x, true));
+export const i64 = scalar(8, (v, o) => v.getBigInt64(o, true), (v, o, x: bigint) => v.setBigInt64(o, x, true));
+export const f32 = scalar(4, (v, o) => v.getFloat32(o, true), (v, o, x: number) => v.setFloat32(o, x, true));
+export const f64 = scalar(8, (v, o) => v.getFloat64(o, true), (v, o, x: number) => v.setFloat64(o, x, true));
+export const u128 = scalar(
+  16,
+  (v, o) => v.getBigUint64(o, true) | (v.getBigUint64(o + 8, true) << 64n),
+  (v, o, x: bigint) => {
+    v.setBigUint64(o, BigInt.asUintN(64, x), true);
+    v.setBigUint64(o + 8, BigInt.asUintN(64, x >> 64n), true);
+  },
+);
+export const i128 = scalar(
+  16,
+  (v, o) => BigInt.asIntN(128, u128.read(v, o)),
+  (v, o, x: bigint) => u128.write(v, o, BigInt.asUintN(128, x)),
+);
+
+export function bytes(len: number): Codec<Uint8Array> {
+  return {
+    size: len,
+    read: (view, offset) => new Uint8Array(view.buffer, view.byteOffset + offset, len).slice(),
+    write(view, offset, value) {
+      if (value.length !== len) {
+        throw new Error(`expected ${len} bytes, got ${value.length}`);
+      }
+      new Uint8Array(view.buffer, view.byteOffset + offset, len).set(value);
+    },
+  };
+}
+
+export function array<T>(item: Codec<T>, len: number): Codec<T[]> {
+  return {
+    size: item.size * len,
+    read: (view, offset) => Array.from({ length: len }, (_, i) => item.read(view, offset + i * item.size)),
+    write(view, offset, value) {
+      if (value.length !== len) {
+        throw new Error(`expected ${len} items, got ${value.length}`);
+      }
+      value.forEach((x, i) => item.write(view, offset + i * item.size, x));
+    },
+  };
+}
+
+/** A struct without padding: each field follows the last. */
+export function struct<T>(fields: { [K in keyof T]: Codec<T[K]> }): Codec<T> {
+  const entries = Object.entries(fields) as [keyof T, Codec<T[keyof T]>][];
+  return {
+    size: entries.reduce((size, [, codec]) => size + codec.size, 0),
+    read(view, offset) {
+      const value = {} as T;
+      for (const [name, codec] of entries) {
+        value[name] = codec.read(view, offset);
+        offset += codec.size;
+      }
+      return value;
+    },
+    write(view, offset, value) {
+      for (const [name, codec] of entries) {
+        codec.write(view, offset, value[name]);
+        offset += codec.size;
+      }
+    },
+  };
+}
+
+export function encode<T>(codec: Codec<T>, value: T): Uint8Array {
+  const bytes = new Uint8Array(codec.size);
+  codec.write(new DataView(bytes.buffer), 0, value);
+  return bytes;
+}
+
+export function decode<T>(codec: Codec<T>, bytes: Uint8Array): T {
+  if (bytes.length !== codec.size) {
+    throw new Error(`expected ${codec.size} bytes, got ${bytes.length}`);
+  }
+  return codec.read(new DataView(bytes.buffer, bytes.byteOffset, bytes.length), 0);
+}
+
+/**
+ * An entry point taking a `#[payload]`, submitted as CBOR in the shape the
+ * parameter type's `cbor::Decode` impl reads. Its return value is the
+ * transaction result.
+ */
+export interface PayloadEntryPoint<Ret> {
+  readonly name: string;
+  result(bytes: Uint8Array): Ret;
+}
+
+function payloadEntryPoint<Ret>(name: string, ret: Codec<Ret>): PayloadEntryPoint<Ret> {
+  return { name, result: (bytes) => decode(ret, bytes) };
+}
+"#;
@@ -0,0 +1,865 @@
+//! Attribute and derive macros for Starstream contracts, re-exported by
+//! `starstream`.
+//!
+//! This crate has no dependencies. It parses the small subset of Rust it
+//! needs (inherent impl blocks, inline modules, function signatures, and
+//! struct fields) with
+//! `proc_macro` directly, and generates code by formatting and re-parsing
+//! source text.
+
+use proc_macro::{Delimiter, Group, Literal, Spacing, TokenStream, TokenTree};
+
+/// Turn an inherent impl block into a full UTXO.
+///
+/// Each `pub fn` becomes an export, chosen by its receiver:
+///
+/// - no receiver, with a final `sleep: fn(&Self)` or `fn(&mut Self)`
+///   parameter: a constructor, `starstream_new_<Type>_<fn>`
+/// - `&self`: a query, `starstream_query_<Type>_<fn>`
+/// - `&mut self`: a mutation, `starstream_mutate_<Type>_<fn>`
+/// - `self`: a consumer, `starstream_consume_<Type>_<fn>`
+///
+/// The UTXO yields under the state name `<Type>`, and an entry in
+/// `starstream::METADATA_SECTION` lists the state, the `()` resume argument,
+/// and the generated exports with their parameter and return types.
+#[proc_macro_attribute]
+pub fn utxo(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return compile_error("#[starstream::utxo] takes no arguments");
+    }
+    match expand_utxo(item.clone()) {
+        Ok(generated) => {
+            let mut out = item;
+            out.extend(generated);
+            out
+        }
+        Err(message) => compile_error(&message),
+    }
+}
+
+/// Trap if this function is entered while any `#[non_reentrant]` function
+/// of the same UTXO is still running. Composes with `#[starstream::utxo]`.
+///
+/// Don't put this on a constructor: the guard would stay held while the UTXO
+/// sleeps, locking out every other `#[non_reentrant]` export.
+#[proc_macro_attribute]
+pub fn non_reentrant(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return compile_error("#[starstream::non_reentrant] takes no arguments");
+    }
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    if !tokens.iter().any(|tt| is_ident(tt, "fn")) {
+        return compile_error("#[starstream::non_reentrant] must be applied to a function");
+    }
+    let body = match tokens.pop() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g,
+        _ => return compile_error("#[starstream::non_reentrant]: expected function body"),
+    };
+    let guarded = match parse(format!(
+        "{{ let _guard = ::starstream::ReentrancyGuard::enter(); {} }}",
+        body.stream()
+    )) {
+        Ok(guarded) => guarded,
+        Err(message) => return compile_error(&message),
+    };
+    let mut out: TokenStream = tokens.into_iter().collect();
+    out.extend(guarded);
+    out
+}
+
+/// Export every `fn` in an inline module as a coordination script entry
+/// point, as with `coordination_export!`, and add a `client` submodule,
+/// behind `starstream`'s `client` feature, describing each one so off-chain
+/// code and tests can build calls without duplicating types.
+///
+/// For an entry point `fn swap(#[payload] args: SwapArgs) -> u64`, the client
+/// module has `#[repr(C)] pub struct Swap { pub args: SwapArgs }`, whose
+/// `ENTRY_POINT` constant gives the export name, the name, type, and size of
+/// each parameter, and the return type. Entry points taking a `#[payload]`
+/// also get `Swap::payload(buf)`, which encodes the bytes to submit with the
+/// transaction.
+#[proc_macro_attribute]
+pub fn coordination(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return compile_error("#[starstream::coordination] takes no arguments");
+    }
+    match expand_coordination(item) {
+        Ok(generated) => generated,
+        Err(message) => compile_error(&message),
+    }
+}
+
+/// Implement `starstream::FfiSafe` after checking, at compile time, that the
+/// struct is `#[repr(C)]` or `#[repr(transparent)]`, that every field is
+/// itself `FfiSafe` (which rules out references and `bool`), and that the
+/// layout has no padding.
+///
+/// With `starstream`'s `ts-bindgen` feature, the struct's fields are also
+/// listed in `starstream::METADATA_SECTION`, as `type Name(field: Type, ..)`,
+/// for generating bindings in other languages.
+#[proc_macro_derive(FfiSafe)]
+pub fn derive_ffi_safe(item: TokenStream) -> TokenStream {
+    match expand_ffi_safe(item) {
+        Ok(generated) => generated,
+        Err(message) => compile_error(&message),
+    }
+}
+
+fn compile_error(message: &str) -> TokenStream {
+    format!("compile_error!({:?});", message).parse().unwrap()
+}
+
+fn parse(source: String) -> Result<TokenStream, String> {
+    source
+        .parse()
+        .map_err(|e| format!("starstream macro generated invalid code: {e:?}"))
+}
+
+// ----------------------------------------------------------------------------
+// #[starstream::utxo]
+
+fn expand_utxo(item: TokenStream) -> Result<TokenStream, String> {
+    let imp = parse_impl(item)?;
+    let ty = &imp.self_ty;
+
+    let mut exports = String::new();
+    // Constructors sleep with a `()` resume argument.
+    let mut metadata = format!("utxo {ty}\nstate {ty}\nresume ()\n");
+    let mut constructors = Vec::new();
+
+    for f in imp.fns.iter().filter(|f| f.is_pub) {
+        if f.generic {
+            return Err(format!(
+                "#[starstream::utxo]: `{}` is generic and can't be exported; make it private",
+                f.name
+            ));
+        }
+        let ret = f.ret.as_deref().map(|r| format!("-> {r}")).unwrap_or_default();
+        let name = &f.name;
+        let args = arg_names(f.params.len());
+        let params = typed_params(&f.params);
+        let signature = |types: &[String]| {
+            let ret = f.ret.as_deref().map(|r| format!(" -> {}", r.trim())).unwrap_or_default();
+            let types: Vec<&str> = types.iter().map(|ty| ty.trim()).collect();
+            format!("({}){ret}", types.join(", "))
+        };
+        match f.receiver {
+            None => {
+                let Some((last, params)) = f.params.split_last() else {
+                    return Err(constructor_error(name));
+                };
+                let Some(sleep_mut) = sleep_param(last, ty) else {
+                    return Err(constructor_error(name));
+                };
+                let export = format!("starstream_new_{ty}_{name}");
+                metadata.push_str(&format!("new {export}{}\n", signature(params)));
+                constructors.push((name, export, params, sleep_mut, ret));
+                continue;
+            }
+            Some(Receiver::Ref) => {
+                let export = format!("starstream_query_{ty}_{name}");
+                metadata.push_str(&format!("query {export}{}\n", signature(&f.params)));
+                exports.push_str(&format!(
+                    "#[no_mangle]
+                    pub extern \"C\" fn {export}(this: &{ty}, {params}) {ret} {{
+                        ::starstream::__check_abi();
+                        this.{name}({args})
+                    }}"
+                ));
+                exports.push_str(&fuzz_method(&export, ty, "", name, &f.params));
+            }
+            Some(Receiver::RefMut) => {
+                let export = format!("starstream_mutate_{ty}_{name}");
+                metadata.push_str(&format!("mutate {export}{}\n", signature(&f.params)));
+                exports.push_str(&format!(
+                    "#[no_mangle]
+                    pub extern \"C\" fn {export}(this: &mut {ty}, {params}) {ret} {{
+                        ::starstream::__check_abi();
+                        this.{name}({args})
+                    }}"
+                ));
+                exports.push_str(&fuzz_method(&export, ty, "mut", name, &f.params));
+            }
+            Some(Receiver::Value) => {
+                let export = format!("starstream_consume_{ty}_{name}");
+                metadata.push_str(&format!("consume {export}{}\n", signature(&f.params)));
+                exports.push_str(&format!(
+                    "#[no_mangle]
+                    pub unsafe extern \"C\" fn {export}(this: *mut {ty}, {params}) {ret} {{
+                        ::starstream::__check_abi();
+                        ::core::ptr::read(this).{name}({args})
+                    }}"
+                ));
+                exports.push_str(&fuzz_method(&export, ty, "", name, &f.params));
+            }
+        }
+    }
+
+    if constructors.is_empty() {
+        return Err(format!(
+            "#[starstream::utxo]: `{ty}` needs a `pub fn` constructor taking `sleep: fn(&mut Self)` last"
+        ));
+    }
+    let metadata = Literal::byte_string(metadata.as_bytes());
+    for (i, (name, export, types, sleep_mut, ret)) in constructors.into_iter().enumerate() {
+        let params = typed_params(types);
+        let args = arg_names(types.len());
+        let (this, sleep_fn) = if sleep_mut {
+            (format!("&mut {ty}"), "sleep_mut_state")
+        } else {
+            (format!("&{ty}"), "sleep_state")
+        };
+        // The entry is `#[used]`, so it can go with any constructor, once.
+        let metadata = if i == 0 {
+            format!("::starstream::metadata!({metadata});")
+        } else {
+            String::new()
+        };
+        exports.push_str(&format!(
+            "#[no_mangle]
+            pub extern \"C\" fn {export}({params}) {ret} {{
+                fn sleep(this: {this}) {{
+                    ::starstream::{sleep_fn}::<(), {ty}>(::starstream::__state_id!({ty:?}), this)
+                }}
+                {metadata}
+                ::starstream::__check_abi();
+                <{ty}>::{name}({args} sleep)
+            }}"
+        ));
+
+        let decls = fuzz_decls(types);
+        let sizes: String = types.iter().map(|t| format!(" + ::core::mem::size_of::<{t}>()")).collect();
+        exports.push_str(&format!(
+            "::starstream::__if_fuzzing! {{{{
+                #[allow(non_snake_case)]
+                pub fn fuzz_{export}(data: &[u8]) -> ::starstream::fuzz::Outcome {{
+                    fn body(input: &mut ::starstream::fuzz::Input) -> ::core::option::Option<()> {{
+                        fn sleep(this: {this}) {{
+                            ::starstream::{sleep_fn}::<(), {ty}>(::starstream::__state_id!({ty:?}), this)
+                        }}
+                        {decls}
+                        let _ = <{ty}>::{name}({args} sleep);
+                        ::core::option::Option::Some(())
+                    }}
+                    ::starstream::fuzz::resume::<{ty}, ()>(data, 0{sizes}, body)
+                }}
+            }}}}"
+        ));
+    }
+
+    parse(exports)
+}
+
+// ----------------------------------------------------------------------------
+// #[starstream::coordination]
+
+fn expand_coordination(item: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens: Vec<TokenTree> = item.into_iter().collect();
+    if !tokens.iter().any(|tt| is_ident(tt, "mod")) {
+        return Err("#[starstream::coordination] must be applied to an inline module".into());
+    }
+    let body = match tokens.pop() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => g,
+        _ => return Err("#[starstream::coordination]: expected `mod name { ... }`".into()),
+    };
+
+    let mut others = TokenStream::new();
+    let mut entry_fns = TokenStream::new();
+    let mut client = String::new();
+    for item in split_items(body.stream()) {
+        let Some(fn_pos) = item.iter().position(|tt| is_ident(tt, "fn")) else {
+            others.extend(item);
+            continue;
+        };
+        let entry = parse_entry_point(&item[fn_pos + 1..])?;
+        client.push_str(&client_struct(&entry));
+        // `coordination_export!` takes bare `fn`s; the exports are public anyway.
+        let mut exported = Vec::new();
+        let mut rest = item.into_iter().enumerate().peekable();
+        while let Some((i, tt)) = rest.next() {
+            if i < fn_pos && is_ident(&tt, "pub") {
+                if matches!(rest.peek(), Some((_, TokenTree::Group(g))) if g.delimiter() == Delimiter::Parenthesis) {
+                    rest.next();
+                }
+                continue;
+            }
+            exported.push(tt);
+        }
+        entry_fns.extend(exported);
+    }
+
+    let mut inner = others;
+    inner.extend(parse("::starstream::coordination_export!".into())?);
+    inner.extend([TokenTree::Group(Group::new(Delimiter::Brace, entry_fns))]);
+    inner.extend(parse(format!(
+        "::starstream::__client! {{
+            pub mod client {{
+                #[allow(unused_imports)]
+                use super::*;
+                {client}
+            }}
+        }}"
+    ))?);
+    let mut out: TokenStream = tokens.into_iter().collect();
+    out.extend([TokenTree::Group(Group::new(Delimiter::Brace, inner))]);
+    Ok(out)
+}
+
+struct EntryPoint {
+    name: String,
+    payload: bool,
+    /// `(name, type)` pairs.
+    params: Vec<(String, String)>,
+    ret: Option<String>,
+}
+
+/// Parse an entry point from the tokens after `fn`.
+fn parse_entry_point(tokens: &[TokenTree]) -> Result<EntryPoint, String> {
+    let name = match tokens.first() {
+        Some(TokenTree::Ident(name)) => name.to_string(),
+        _ => return Err("expected function name".into()),
+    };
+    let params = match tokens.get(1) {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => g.stream(),
+        _ => return Err(format!("#[starstream::coordination]: `{name}` can't be generic")),
+    };
+    // `-> Type` up to the body or a `where` clause.
+    let ret: Option<Vec<TokenTree>> = tokens.get(2).filter(|tt| is_punct(tt, '-')).map(|_| {
+        tokens[4..].iter()
+            .take_while(|tt| {
+                !is_ident(tt, "where")
+                    && !matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace)
+            })
+            .cloned()
+            .collect()
+    });
+
+    let mut payload = false;
+    let mut parsed = Vec::new();
+    for param in split_commas(params) {
+        if let [first, TokenTree::Group(attr), ..] = &param[..] {
+            let attr_is = |name| is_punct(first, '#') && attr.stream().into_iter().any(|tt| is_ident(&tt, name));
+            if attr_is("payload") {
+                payload = true;
+            }
+            // Supplied by the generated export, not the caller.
+            if attr_is("entry") {
+                continue;
+            }
+        }
+        let param = strip_attrs(param);
+        let ty = substitute_self(param_type(param.clone())?, "Self");
+        let Some(TokenTree::Ident(arg)) = param.into_iter().find(|tt| !is_ident(tt, "mut")) else {
+            return Err(format!("#[starstream::coordination]: `{name}` parameters must be plain names"));
+        };
+        parsed.push((arg.to_string(), ty));
+    }
+    Ok(EntryPoint {
+        name,
+        payload,
+        params: parsed,
+        ret: ret.map(|ret| substitute_self(ret, "Self")),
+    })
+}
+
+fn client_struct(entry: &EntryPoint) -> String {
+    let export = &entry.name;
+    let ty: String = export
+        .split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect();
+    let fields: String = entry
+        .params
+        .iter()
+        .map(|(name, ty)| format!("pub {name}: {ty},"))
+        .collect();
+    let params: String = entry
+        .params
+        .iter()
+        .map(|(name, ty)| {
+            format!(
+                "::starstream::coordination::Param {{
+                    name: {name:?},
+                    ty: {:?},
+                    size: ::core::mem::size_of::<{ty}>(),
+                }},",
+                ty.trim()
+            )
+        })
+        .collect();
+    let ret = entry.ret.as_deref().map_or("()", str::trim);
+    let payload = entry.payload;
+    let payload_fn = match (payload, entry.params.first()) {
+        (true, Some((name, _))) => format!(
+            "/// Encode the transaction payload into `buf`, returning the bytes
+            /// to submit.
+            pub fn payload<'b>(&self, buf: &'b mut [u8]) -> Result<&'b [u8], ::starstream::cbor::Error> {{
+                ::starstream::cbor::to_slice(&self.{name}, buf)
+            }}"
+        ),
+        _ => String::new(),
+    };
+    format!(
+        "#[doc = concat!(\"Arguments of the `\", {export:?}, \"` entry point.\")]
+        #[repr(C)]
+        pub struct {ty} {{
+            {fields}
+        }}
+
+        impl {ty} {{
+            pub const ENTRY_POINT: ::starstream::coordination::EntryPoint =
+                ::starstream::coordination::EntryPoint {{
+                    name: {export:?},
+                    params: &[{params}],
+                    ret: {ret:?},
+                    payload: {payload},
+                }};
+
+            {payload_fn}
+        }}"
+    )
+}
+
+/// Split a module body into items, each with its attributes.
+fn split_items(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut items = Vec::new();
+    let mut item = Vec::new();
+    let mut tokens = stream.into_iter().peekable();
+    while let Some(tt) = tokens.next() {
+        let is_attr = is_punct(&tt, '#');
+        let ends_item = is_punct(&tt, ';')
+            || matches!(&tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace);
+        item.push(tt);
+        if is_attr {
+            // Keep `#[...]` with the item it belongs to.
+            item.extend(tokens.next());
+        } else if ends_item {
+            // `use a::{b, c};` continues past the braces.
+            if tokens.peek().is_some_and(|tt| is_punct(tt, ';')) {
+                item.extend(tokens.next());
+            }
+            items.push(core::mem::take(&mut item));
+        }
+    }
+    if !item.is_empty() {
+        items.push(item);
+    }
+    items
+}
+
+// ----------------------------------------------------------------------------
+// #[derive(FfiSafe)]
+
+fn expand_ffi_safe(item: TokenStream) -> Result<TokenStream, String> {
+    let tokens: Vec<TokenTree> = item.into_iter().collect();
+    let mut repr_ok = false;
+    let mut rest = tokens.iter().peekable();
+    while let Some(tt) = rest.next() {
+        if is_punct(tt, '#') {
+            if let Some(TokenTree::Group(g)) = rest.next() {
+                let attr: Vec<TokenTree> = g.stream().into_iter().collect();
+                if let [name, TokenTree::Group(args)] = &attr[..] {
+                    if is_ident(name, "repr")
+                        && args
+                            .stream()
+                            .into_iter()
+                            .any(|tt| is_ident(&tt, "C") || is_ident(&tt, "transparent"))
+                    {
+                        repr_ok = true;
+                    }
+                }
+            }
+        } else if is_ident(tt, "enum") || is_ident(tt, "union") {
+            return Err(format!(
+                "#[derive(FfiSafe)] only supports structs; not every bit pattern is a valid `{tt}`"
+            ));
+        } else if is_ident(tt, "struct") {
+            break;
+        }
+    }
+    let name = match rest.next() {
+        Some(TokenTree::Ident(name)) => name.to_string(),
+        _ => return Err("#[derive(FfiSafe)]: expected struct name".into()),
+    };
+    if !repr_ok {
+        return Err(format!(
+            "#[derive(FfiSafe)]: `{name}` must be #[repr(C)] or #[repr(transparent)]"
+        ));
+    }
+    let fields = match rest.next() {
+        Some(tt) if is_punct(tt, '<') => {
+            return Err(format!("#[derive(FfiSafe)]: `{name}` must not be generic"));
+        }
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => split_commas(g.stream())
+            .into_iter()
+            .map(|field| {
+                let field = strip_visibility(strip_attrs(field));
+                let name = field.first().map(|tt| tt.to_string()).unwrap_or_default();
+                Ok((name, param_type(field)?))
+            })
+            .collect::<Result<Vec<_>, String>>()?,
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => split_commas(g.stream())
+            .into_iter()
+            .enumerate()
+            .map(|(i, field)| (i.to_string(), strip_visibility(strip_attrs(field))))
+            .collect(),
+        // Unit struct.
+        _ => Vec::new(),
+    };
+
+    let mut bounds = String::new();
+    let mut sizes = String::from("0");
+    let mut layout = Vec::new();
+    for (field_name, field) in fields {
+        if field.first().is_some_and(|tt| is_punct(tt, '&')) {
+            return Err(format!(
+                "#[derive(FfiSafe)]: `{name}` has a reference field, which is meaningless on the other side of the host boundary"
+            ));
+        }
+        if let [tt] = &field[..] {
+            if is_ident(tt, "bool") {
+                return Err(format!(
+                    "#[derive(FfiSafe)]: `{name}` has a `bool` field; use `u8` or `u32`, since the host may write any byte"
+                ));
+            }
+        }
+        let ty: TokenStream = field.into_iter().collect();
+        bounds.push_str(&format!("{ty}: ::starstream::FfiSafe, "));
+        sizes.push_str(&format!(" + ::core::mem::size_of::<{ty}>()"));
+        layout.push(format!("{field_name}: {ty}"));
+    }
+    // With no padding, the field types alone give the layout.
+    let layout = Literal::byte_string(format!("type {name}({})\n", layout.join(", ")).as_bytes());
+    parse(format!(
+        "unsafe impl ::starstream::FfiSafe for {name} where {bounds} {{}}
+        const _: () = {{
+            assert!(
+                ::core::mem::size_of::<{name}>() == {sizes},
+                \"`{name}` has padding; reorder or add explicit padding fields to derive FfiSafe\",
+            );
+        }};
+        ::starstream::__layout_metadata!({layout});"
+    ))
+}
+
+/// Drop leading `#[...]` attributes from a field.
+fn strip_attrs(mut field: Vec<TokenTree>) -> Vec<TokenTree> {
+    while field.first().is_some_and(|tt| is_punct(tt, '#')) {
+        field.drain(..2.min(field.len()));
+    }
+    field
+}
+
+/// Drop a leading `pub` or `pub(...)` from a tuple struct field.
+fn strip_visibility(mut field: Vec<TokenTree>) -> Vec<TokenTree> {
+    if field.first().is_some_and(|tt| is_ident(tt, "pub")) {
+        field.remove(0);
+        if matches!(field.first(), Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis) {
+            field.remove(0);
+        }
+    }
+    field
+}
+
+fn constructor_error(name: &str) -> String {
+    format!(
+        "#[starstream::utxo]: constructor `{name}` must take `sleep: fn(&Self)` or `sleep: fn(&mut Self)` as its last parameter"
+    )
+}
+
+/// If `ty` is `fn(&Self)` or `fn(&mut Self)`, whether it is the latter.
+fn sleep_param(ty: &str, self_ty: &str) -> Option<bool> {
+    let compact: String = ty.chars().filter(|c| !c.is_whitespace()).collect();
+    if compact == format!("fn(&{self_ty})") {
+        Some(false)
+    } else if compact == format!("fn(&mut{self_ty})") {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// A bytes-in wrapper around an export, for `cargo fuzz`; see
+/// `starstream::fuzz`. `this` and the parameters are decoded in order.
+fn fuzz_method(export: &str, ty: &str, binding: &str, name: &str, types: &[String]) -> String {
+    let decls = fuzz_decls(types);
+    let args = arg_names(types.len());
+    format!(
+        "::starstream::__if_fuzzing! {{{{
+            #[allow(non_snake_case)]
+            pub fn fuzz_{export}(data: &[u8]) -> ::starstream::fuzz::Outcome {{
+                ::starstream::fuzz::run(data, |input| {{
+                    let {binding} this: {ty} = input.take()?;
+                    {decls}
+                    let _ = this.{name}({args});
+                    ::core::option::Option::Some(())
+                }})
+            }}
+        }}}}"
+    )
+}
+
+fn fuzz_decls(types: &[String]) -> String {
+    types
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| format!("let arg{i}: {ty} = input.take()?; "))
+        .collect()
+}
+
+/// `arg0, arg1, ...,` with a trailing comma when non-empty.
+fn arg_names(count: usize) -> String {
+    (0..count).map(|i| format!("arg{i}, ")).collect()
+}
+
+fn typed_params(types: &[String]) -> String {
+    types
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| format!("arg{i}: {ty}, "))
+        .collect()
+}
+
+// ----------------------------------------------------------------------------
+// Parsing
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Receiver {
+    Ref,
+    RefMut,
+    Value,
+}
+
+struct Fn {
+    name: String,
+    is_pub: bool,
+    generic: bool,
+    receiver: Option<Receiver>,
+    /// Parameter types, excluding the receiver, with `Self` substituted.
+    params: Vec<String>,
+    /// Return type, with `Self` substituted.
+    ret: Option<String>,
+}
+
+struct Impl {
+    self_ty: String,
+    fns: Vec<Fn>,
+}
+
+fn is_ident(tt: &TokenTree, name: &str) -> bool {
+    matches!(tt, TokenTree::Ident(i) if i.to_string() == name)
+}
+
+fn is_punct(tt: &TokenTree, ch: char) -> bool {
+    matches!(tt, TokenTree::Punct(p) if p.as_char() == ch)
+}
+
+fn parse_impl(item: TokenStream) -> Result<Impl, String> {
+    let tokens: Vec<TokenTree> = item.into_iter().collect();
+    let start = tokens
+        .iter()
+        .position(|tt| is_ident(tt, "impl"))
+        .ok_or("#[starstream::utxo] must be applied to an impl block")?;
+    let body = tokens
+        .iter()
+        .position(|tt| matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace))
+        .ok_or("#[starstream::utxo]: expected impl body")?;
+    let header = &tokens[start + 1..body];
+    if header.iter().any(|tt| is_ident(tt, "for")) {
+        return Err("#[starstream::utxo] must be applied to an inherent impl, not a trait impl".into());
+    }
+    if header.iter().any(|tt| is_punct(tt, '<')) {
+        return Err("#[starstream::utxo] does not support generic UTXOs".into());
+    }
+    let self_ty = match header {
+        [TokenTree::Ident(name)] => name.to_string(),
+        _ => return Err("#[starstream::utxo]: expected `impl TypeName`".into()),
+    };
+    let TokenTree::Group(body) = &tokens[body] else {
+        unreachable!()
+    };
+    let fns = parse_fns(body.stream(), &self_ty)?;
+    Ok(Impl { self_ty, fns })
+}
+
+/// Parse the `fn` items out of an impl body, skipping everything else.
+fn parse_fns(body: TokenStream, self_ty: &str) -> Result<Vec<Fn>, String> {
+    let mut fns = Vec::new();
+    let mut tokens = body.into_iter().peekable();
+    let mut is_pub = false;
+    while let Some(tt) = tokens.next() {
+        if is_punct(&tt, '#') {
+            // Attribute.
+            tokens.next();
+        } else if is_ident(&tt, "pub") {
+            is_pub = true;
+            if let Some(TokenTree::Group(g)) = tokens.peek() {
+                if g.delimiter() == Delimiter::Parenthesis {
+                    // pub(crate) and friends aren't exported.
+                    is_pub = false;
+                    tokens.next();
+                }
+            }
+        } else if is_ident(&tt, "fn") {
+            let name = match tokens.next() {
+                Some(TokenTree::Ident(name)) => name.to_string(),
+                _ => return Err("expected function name".into()),
+            };
+            let mut generic = false;
+            if tokens.peek().is_some_and(|tt| is_punct(tt, '<')) {
+                generic = true;
+                skip_angle_brackets(&mut tokens);
+            }
+            let params = match tokens.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => g.stream(),
+                _ => return Err(format!("expected parameters for `{name}`")),
+            };
+            let mut ret = Vec::new();
+            let mut in_ret = false;
+            loop {
+                match tokens.next() {
+                    Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => break,
+                    Some(tt) if is_punct(&tt, ';') => break,
+                    Some(tt) if is_ident(&tt, "where") => {
+                        generic = true;
+                        in_ret = false;
+                    }
+                    Some(tt) if !in_ret && ret.is_empty() && is_punct(&tt, '-') => {
+                        // `->`
+                        tokens.next();
+                        in_ret = true;
+                    }
+                    Some(tt) if in_ret => ret.push(tt),
+                    Some(_) => {}
+                    None => return Err(format!("expected body for `{name}`")),
+                }
+            }
+            let (receiver, params) = parse_params(params, self_ty)?;
+            fns.push(Fn {
+                name,
+                is_pub,
+                generic,
+                receiver,
+                params,
+                ret: (!ret.is_empty()).then(|| substitute_self(ret, self_ty)),
+            });
+            is_pub = false;
+        } else if is_punct(&tt, ';') {
+            // End of some non-fn item.
+            is_pub = false;
+        }
+    }
+    Ok(fns)
+}
+
+fn skip_angle_brackets(tokens: &mut core::iter::Peekable<proc_macro::token_stream::IntoIter>) {
+    let mut depth = 0;
+    let mut prev_dash = false;
+    for tt in tokens.by_ref() {
+        if is_punct(&tt, '<') {
+            depth += 1;
+        } else if is_punct(&tt, '>') && !prev_dash {
+            depth -= 1;
+            if depth == 0 {
+                return;
+            }
+        }
+        prev_dash = matches!(&tt, TokenTree::Punct(p) if p.as_char() == '-' && p.spacing() == Spacing::Joint);
+    }
+}
+
+/// Split a parameter list at top-level commas.
+fn split_commas(stream: TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut out = vec![Vec::new()];
+    let mut depth = 0i32;
+    let mut prev_dash = false;
+    for tt in stream {
+        if is_punct(&tt, '<') {
+            depth += 1;
+        } else if is_punct(&tt, '>') && !prev_dash {
+            depth -= 1;
+        }
+        prev_dash = matches!(&tt, TokenTree::Punct(p) if p.as_char() == '-' && p.spacing() == Spacing::Joint);
+        if depth == 0 && is_punct(&tt, ',') {
+            out.push(Vec::new());
+        } else {
+            out.last_mut().unwrap().push(tt);
+        }
+    }
+    out.retain(|p| !p.is_empty());
+    out
+}
+
+fn parse_params(stream: TokenStream, self_ty: &str) -> Result<(Option<Receiver>, Vec<String>), String> {
+    let mut receiver = None;
+    let mut params = Vec::new();
+    for (i, param) in split_commas(stream).into_iter().enumerate() {
+        if i == 0 && param.iter().any(|tt| is_ident(tt, "self")) {
+            let by_ref = param.first().is_some_and(|tt| is_punct(tt, '&'));
+            let by_mut = param.iter().any(|tt| is_ident(tt, "mut"));
+            if param.iter().any(|tt| is_punct(tt, ':')) {
+                return Err("typed `self` receivers are not supported".into());
+            }
+            receiver = Some(match (by_ref, by_mut) {
+                (true, true) => Receiver::RefMut,
+                (true, false) => Receiver::Ref,
+                (false, _) => Receiver::Value,
+            });
+            continue;
+        }
+        params.push(substitute_self(param_type(param)?, self_ty));
+    }
+    Ok((receiver, params))
+}
+
+/// The type of a `pattern: Type` parameter.
+fn param_type(param: Vec<TokenTree>) -> Result<Vec<TokenTree>, String> {
+    let mut prev_colon = false;
+    for (i, tt) in param.iter().enumerate() {
+        if let TokenTree::Punct(p) = tt {
+            if p.as_char() == ':' && p.spacing() == Spacing::Alone && !prev_colon {
+                return Ok(param[i + 1..].to_vec());
+            }
+            prev_colon = p.as_char() == ':' && p.spacing() == Spacing::Joint;
+        } else {
+            prev_colon = false;
+        }
+    }
+    Err("expected `name: Type` parameter".into())
+}
+
+/// Render tokens as source, replacing `Self` with the concrete type so the
+/// result can be used outside the impl block.
+fn substitute_self(tokens: Vec<TokenTree>, self_ty: &str) -> String {
+    let mut out = String::new();
+    for tt in tokens {
+        match tt {
+            TokenTree::Ident(i) if i.to_string() == "Self" => out.push_str(self_ty),
+            TokenTree::Group(g) => {
+                let inner = substitute_self(g.stream().into_iter().collect(), self_ty);
+                let (open, close) = match g.delimiter() {
+                    Delimiter::Parenthesis => ("(", ")"),
+                    Delimiter::Brace => ("{", "}"),
+                    Delimiter::Bracket => ("[", "]"),
+                    Delimiter::None => ("", ""),
+                };
+                out.push_str(&format!("{open}{inner}{close}"));
+            }
+            TokenTree::Punct(p) => {
+                out.push(p.as_char());
+                if p.spacing() == Spacing::Joint {
+                    continue;
+                }
+            }
+            other => out.push_str(&other.to_string()),
+        }
+        out.push(' ');
+    }
+    out
+}
@@ -0,0 +1,210 @@
+//! The token and UTXO import ABI as plain declarations, for code generators
+//! and other-language frontends that target it without expanding
+//! [`token_import!`](crate::token_import) or
+//! [`utxo_import!`](crate::utxo_import).
+//!
+//! The importer declares the exporter's imports in an ordinary `extern`
+//! block, with the signatures of [`MintFn`], [`BurnFn`], [`StatusFn`], and
+//! [`ResumeFn`], and wraps them in a [`TokenImport`] or [`UtxoImport`]. These
+//! return typed handles and do the same error handling as the macros: host
+//! errors come back as `Err`, and a handle whose operation failed fatally is
+//! [poisoned](crate::poison).
+//!
+//! ```ignore
+//! #[link(wasm_import_module = "starstream_token:example_contract")]
+//! unsafe extern "C" {
+//!     fn starstream_mint_StarNft(intermediate: StarNftIntermediate) -> u32;
+//!     fn starstream_burn_StarNft(token: u32) -> StarNftIntermediate;
+//! }
+//!
+//! pub enum StarNft {}
+//!
+//! pub const STAR_NFT: TokenImport<StarNft, StarNftIntermediate> =
+//!     unsafe { TokenImport::new(starstream_mint_StarNft, starstream_burn_StarNft) };
+//!
+//! let nft: TokenHandle<StarNft> = STAR_NFT.mint(StarNftIntermediate { id: 7 });
+//! ```
+//!
+//! Handles are tagged with a marker type `T` of the importer's choosing, so
+//! that handles of different imports don't mix. Unlike the macros, these
+//! always call the declared imports, so under the mock host they only work if
+//! the importer provides the functions itself.
+
+use core::{marker::PhantomData, mem::MaybeUninit};
+
+use crate::{
+    __last_resume_error, last_host_error, poison, FfiSafe, HostError, TokenHandle, UtxoError,
+    UtxoHandle, UtxoStatus,
+};
+
+/// A token's mint import, `<mint fn>` in `starstream_token:<module>`: mints
+/// a token from `intermediate` and returns its raw handle, or
+/// [`TokenHandle::INVALID`] if the exporter refused.
+pub type MintFn<I> = unsafe extern "C" fn(intermediate: I) -> u32;
+
+/// A token's burn import, `<burn fn>` in `starstream_token:<module>`: burns
+/// the token with raw handle `token` and returns its intermediate. If the
+/// burn fails, the return value is a placeholder and
+/// [`last_host_error`] says why.
+pub type BurnFn<I> = unsafe extern "C" fn(token: u32) -> I;
+
+/// A UTXO type's status import, `starstream_status_<Name>` in
+/// `starstream_utxo:<module>`.
+pub type StatusFn = unsafe extern "C" fn(utxo: u32) -> UtxoStatus;
+
+/// A UTXO type's resume import, `starstream_resume_<Name>` in
+/// `starstream_utxo:<module>`: resumes the UTXO with the
+/// `resume_arg_size` bytes at `resume_arg` and writes the
+/// `yield_out_size` bytes it yields, or returns, to `yield_out`. Returns
+/// nonzero if the resume failed, leaving `yield_out` unwritten.
+pub type ResumeFn = unsafe extern "C" fn(
+    utxo: u32,
+    resume_arg: *const (),
+    resume_arg_size: usize,
+    yield_out: *mut (),
+    yield_out_size: usize,
+) -> u32;
+
+/// The mint and burn imports of a token type whose intermediate is `I`,
+/// handing out [`TokenHandle<T>`]s.
+pub struct TokenImport<T: ?Sized, I> {
+    mint: MintFn<I>,
+    burn: BurnFn<I>,
+    _phantom: PhantomData<*mut T>,
+}
+
+impl<T: ?Sized, I: FfiSafe> TokenImport<T, I> {
+    /// # Safety
+    ///
+    /// `mint` and `burn` must be the imports of one token type, and `I` its
+    /// intermediate, with the layout the exporter declares.
+    pub const unsafe fn new(mint: MintFn<I>, burn: BurnFn<I>) -> Self {
+        TokenImport {
+            mint,
+            burn,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Traps if the mint is refused.
+    pub fn mint(&self, i: I) -> TokenHandle<T> {
+        match self.try_mint(i) {
+            Ok(token) => token,
+            Err(e) => trap!("mint failed: {}", e),
+        }
+    }
+
+    /// Traps if the burn is refused.
+    pub fn burn(&self, token: TokenHandle<T>) -> I {
+        match self.try_burn(token) {
+            Ok(i) => i,
+            Err(e) => trap!("burn failed: {}", e),
+        }
+    }
+
+    pub fn try_mint(&self, i: I) -> Result<TokenHandle<T>, HostError> {
+        match TokenHandle::from_raw(unsafe { (self.mint)(i) }) {
+            Some(token) => Ok(token),
+            None => Err(last_host_error().unwrap_or(HostError::Unauthorized)),
+        }
+    }
+
+    pub fn try_burn(&self, token: TokenHandle<T>) -> Result<I, HostError> {
+        let raw = token.raw();
+        poison::check(poison::Kind::Token, raw)?;
+        let i = unsafe { (self.burn)(raw) };
+        match last_host_error() {
+            Some(e) => {
+                // Placeholder the host returned in place of the real value.
+                core::mem::forget(i);
+                Err(poison::record(poison::Kind::Token, raw, e))
+            }
+            None => Ok(i),
+        }
+    }
+}
+
+impl<T: ?Sized, I> Clone for TokenImport<T, I> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized, I> Copy for TokenImport<T, I> {}
+
+/// The status and resume imports of a UTXO type that takes `R` when resumed
+/// and yields `Y`, handing out [`UtxoHandle<T>`]s.
+pub struct UtxoImport<T: ?Sized, R, Y> {
+    status: StatusFn,
+    resume: ResumeFn,
+    _phantom: PhantomData<*mut T>,
+    _types: PhantomData<fn(R) -> Y>,
+}
+
+impl<T: ?Sized, R: FfiSafe, Y: FfiSafe> UtxoImport<T, R, Y> {
+    /// # Safety
+    ///
+    /// `status` and `resume` must be the imports of one UTXO type, and `R`
+    /// and `Y` the types it is resumed with and yields, with the layouts the
+    /// exporter declares.
+    pub const unsafe fn new(status: StatusFn, resume: ResumeFn) -> Self {
+        UtxoImport {
+            status,
+            resume,
+            _phantom: PhantomData,
+            _types: PhantomData,
+        }
+    }
+
+    /// The handle with raw value `raw`, as received from the host, or
+    /// `None` if it is [`UtxoHandle::INVALID`].
+    pub const fn handle(&self, raw: u32) -> Option<UtxoHandle<T>> {
+        UtxoHandle::from_raw(raw)
+    }
+
+    pub fn status(&self, utxo: UtxoHandle<T>) -> UtxoStatus {
+        unsafe { (self.status)(utxo.raw()) }
+    }
+
+    /// Traps if the UTXO or the host rejects the resume.
+    pub fn resume(&self, utxo: UtxoHandle<T>, arg: R) -> Y {
+        match self.try_resume(utxo, arg) {
+            Ok(yielded) => yielded,
+            Err(e) => trap!("resume rejected: {}", e),
+        }
+    }
+
+    /// Resume the UTXO and return what it yields next, or its return value
+    /// if it returns instead.
+    pub fn try_resume(&self, utxo: UtxoHandle<T>, arg: R) -> Result<Y, UtxoError> {
+        let raw = utxo.raw();
+        poison::check(poison::Kind::Utxo, raw).map_err(UtxoError::Host)?;
+        let mut yielded = MaybeUninit::<Y>::uninit();
+        let failed = unsafe {
+            (self.resume)(
+                raw,
+                &raw const arg as *const (),
+                size_of::<R>(),
+                yielded.as_mut_ptr() as *mut (),
+                size_of::<Y>(),
+            )
+        };
+        if failed != 0 {
+            return Err(match __last_resume_error() {
+                UtxoError::Host(e) => UtxoError::Host(poison::record(poison::Kind::Utxo, raw, e)),
+                e => e,
+            });
+        }
+        // SAFETY TODO: same caveat as `sleep`, the host must have written a
+        // valid instance of Y.
+        Ok(unsafe { yielded.assume_init() })
+    }
+}
+
+impl<T: ?Sized, R, Y> Clone for UtxoImport<T, R, Y> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized, R, Y> Copy for UtxoImport<T, R, Y> {}
@@ -0,0 +1,224 @@
+//! Cost measurements for tracking the impact of a change, such as moving an
+//! interface from copying structs to serialized arguments. Enabled by the
+//! `bench` feature, which implies `mock-host`.
+//!
+//! [`measure`] counts the host calls a scenario makes on the mock host and
+//! the bytes they copy; [`export_sizes`] reads the code size of each export
+//! from a compiled contract.
+//!
+//! ```ignore
+//! let (_, report) = bench::measure(|| {
+//!     Tx::new().call(MARKET, "buy", &listing).execute().unwrap();
+//! });
+//! println!("{report}");
+//! for export in bench::export_sizes(&std::fs::read("market.wasm")?) {
+//!     println!("{} {}", export.name, export.size);
+//! }
+//! ```
+
+extern crate std;
+
+use core::fmt;
+use std::{collections::BTreeMap, string::String, vec::Vec};
+
+use crate::mock::{self, HostCallStats};
+
+// ----------------------------------------------------------------------------
+// Host calls
+
+/// The host calls made while running a scenario.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Calls and bytes copied, by import.
+    pub host_calls: BTreeMap<&'static str, HostCallStats>,
+    /// Fuel charged under [`MockHost::fuel_costs`](crate::mock::MockHost::fuel_costs).
+    pub fuel: u64,
+}
+
+impl Report {
+    /// Host calls made, over all imports.
+    pub fn calls(&self) -> u64 {
+        self.host_calls.values().map(|stats| stats.calls).sum()
+    }
+
+    /// Bytes copied across the boundary, over all imports.
+    pub fn bytes(&self) -> u64 {
+        self.host_calls.values().map(|stats| stats.bytes).sum()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{:<40} {:>8} {:>10}", "import", "calls", "bytes")?;
+        for (import, stats) in &self.host_calls {
+            writeln!(f, "{import:<40} {:>8} {:>10}", stats.calls, stats.bytes)?;
+        }
+        writeln!(
+            f,
+            "{:<40} {:>8} {:>10}",
+            "total",
+            self.calls(),
+            self.bytes()
+        )?;
+        writeln!(f, "fuel: {}", self.fuel)
+    }
+}
+
+/// Run `f` on this thread's mock host and report the host calls it made.
+pub fn measure<R>(f: impl FnOnce() -> R) -> (R, Report) {
+    let (calls_before, fuel_before) = mock::with(|host| (host.host_calls.clone(), host.fuel_used));
+    let result = f();
+    let report = mock::with(|host| {
+        let mut host_calls = BTreeMap::new();
+        for (import, after) in &host.host_calls {
+            let before = calls_before.get(import).copied().unwrap_or_default();
+            let stats = HostCallStats {
+                calls: after.calls - before.calls,
+                bytes: after.bytes - before.bytes,
+            };
+            if stats != HostCallStats::default() {
+                host_calls.insert(*import, stats);
+            }
+        }
+        Report {
+            host_calls,
+            fuel: host.fuel_used - fuel_before,
+        }
+    });
+    (result, report)
+}
+
+// ----------------------------------------------------------------------------
+// Code size
+
+/// The code size of an exported function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportSize {
+    pub name: String,
+    /// Bytes of the function's own body. Functions it calls aren't counted,
+    /// so compare an export with itself across builds at the same
+    /// optimization level.
+    pub size: usize,
+}
+
+/// The exported functions of the wasm module `wasm` and their code sizes, in
+/// export order. Panics if `wasm` isn't a well-formed module.
+#[track_caller]
+pub fn export_sizes(wasm: &[u8]) -> Vec<ExportSize> {
+    match parse_export_sizes(wasm) {
+        Some(sizes) => sizes,
+        None => panic!("not a well-formed wasm module"),
+    }
+}
+
+fn parse_export_sizes(wasm: &[u8]) -> Option<Vec<ExportSize>> {
+    let mut reader = Reader(wasm.strip_prefix(b"\0asm\x01\0\0\0")?);
+    let mut imported_fns = 0;
+    let mut exports = Vec::new();
+    let mut bodies = Vec::new();
+    while !reader.0.is_empty() {
+        let id = reader.byte()?;
+        let size = reader.leb()? as usize;
+        let mut section = Reader(reader.take(size)?);
+        match id {
+            // Imports: function indices start after the imported functions.
+            2 => {
+                for _ in 0..section.leb()? {
+                    section.name()?;
+                    section.name()?;
+                    match section.byte()? {
+                        0 => {
+                            section.leb()?;
+                            imported_fns += 1;
+                        }
+                        // Table: reftype, then limits.
+                        1 => {
+                            section.byte()?;
+                            section.limits()?;
+                        }
+                        2 => section.limits()?,
+                        // Global: valtype, mutability.
+                        3 => {
+                            section.take(2)?;
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            7 => {
+                for _ in 0..section.leb()? {
+                    let name = section.name()?;
+                    let kind = section.byte()?;
+                    let index = section.leb()?;
+                    if kind == 0 {
+                        exports.push((name, index));
+                    }
+                }
+            }
+            10 => {
+                for _ in 0..section.leb()? {
+                    let size = section.leb()? as usize;
+                    section.take(size)?;
+                    bodies.push(size);
+                }
+            }
+            _ => {}
+        }
+    }
+    (exports.into_iter())
+        .map(|(name, index)| {
+            // An export of an imported function has no body here.
+            let size = match index.checked_sub(imported_fns) {
+                Some(local) => *bodies.get(local as usize)?,
+                None => 0,
+            };
+            Some(ExportSize { name, size })
+        })
+        .collect()
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(head)
+    }
+
+    fn byte(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn leb(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+        for shift in (0..35).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u32) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let len = self.leb()? as usize;
+        Some(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn limits(&mut self) -> Option<()> {
+        match self.byte()? {
+            0 => self.leb()?,
+            1 => {
+                self.leb()?;
+                self.leb()?
+            }
+            _ => return None,
+        };
+        Some(())
+    }
+}
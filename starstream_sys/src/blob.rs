@@ -0,0 +1,72 @@
+//! Large payloads, such as NFT metadata or proofs, passed between modules by
+//! reference.
+//!
+//! [`Blob::new`] copies bytes into a region the host keeps for the rest of
+//! the transaction and returns a 4-byte handle. Yielding, resuming, or
+//! calling with the handle instead of the bytes means the payload is copied
+//! in once, however many modules it passes through, and each reader copies
+//! out only the range it needs with [`Blob::read`].
+//!
+//! ```ignore
+//! let proof = Blob::new(&proof_bytes);
+//! verifier.resume(Check { proof });
+//!
+//! // In the verifier:
+//! let mut header = [0; 64];
+//! check.proof.read(0, &mut header);
+//! ```
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_blob_new(ptr: *const u8, len: usize) -> u32;
+    safe fn starstream_blob_len(blob: u32) -> usize;
+    unsafe fn starstream_blob_read(blob: u32, offset: usize, out: *mut u8, out_len: usize)
+        -> usize;
+}
+
+/// A handle to bytes stored with the host for the current transaction.
+///
+/// Every script, UTXO, and token in the transaction can read a blob whose
+/// handle it's given. Blobs are immutable, so `tx::atomic` rollbacks leave
+/// them alone. Like other handles, a blob is meaningless in a later
+/// transaction, and the host traps on a handle it didn't hand out.
+#[repr(transparent)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Blob(u32);
+
+unsafe impl crate::FfiSafe for Blob {}
+
+impl Blob {
+    /// Store a copy of `bytes`.
+    pub fn new(bytes: &[u8]) -> Blob {
+        Blob(unsafe { starstream_blob_new(bytes.as_ptr(), bytes.len()) })
+    }
+
+    /// Length of the stored bytes.
+    #[inline]
+    pub fn len(self) -> usize {
+        starstream_blob_len(self.0)
+    }
+
+    #[inline]
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copy the stored bytes from `offset` into `out`, returning how many
+    /// were copied: fewer than `out.len()` at the end of the blob, and none
+    /// past it.
+    pub fn read(self, offset: usize, out: &mut [u8]) -> usize {
+        unsafe { starstream_blob_read(self.0, offset, out.as_mut_ptr(), out.len()) }
+    }
+
+    /// Copy the whole blob into `out`, which must be exactly [`len`](Self::len)
+    /// bytes long.
+    #[track_caller]
+    pub fn read_all(self, out: &mut [u8]) {
+        if out.len() != self.len() {
+            trap!("blob is {} bytes, not {}", self.len(), out.len());
+        }
+        self.read(0, out);
+    }
+}
@@ -0,0 +1,312 @@
+//! Cross-chain packets in the style of IBC, as a common foundation for
+//! bridged asset contracts.
+//!
+//! The sending chain commits to each outgoing [`Packet`] under
+//! [`packet_key`]; a relayer carries the packet to the receiving chain with a
+//! Merkle [`Proof`] of that commitment, which [`verify_packet`] checks
+//! against the state root of the sender that the host's light client
+//! vouches for. The receiver's [`Acknowledgement`] travels back the same way,
+//! checked with [`verify_acknowledgement`]. A channel end keeps its
+//! [`Sequences`] in the state of the UTXO that owns it, so that each packet
+//! is sent, received, and acknowledged exactly once and in order.
+//!
+//! ```ignore
+//! let packet = Packet::new(channel.sequences.send(), channel.id, channel.counterparty, timeout, &data);
+//! tx::emit_event("bridge.send", &packet);
+//!
+//! // On the receiving chain:
+//! assert!(bridge::verify_packet(client, proof_height, &packet, &data, &proof));
+//! assert!(channel.sequences.recv(&packet));
+//! ```
+
+use crate::{hash, FfiSafe};
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_bridge_root(client: u64, height: *const Height, out: *mut Root) -> bool;
+}
+
+/// A light client the host runs, tracking one counterparty chain.
+pub type ClientId = u64;
+
+/// One end of a channel, numbered by the chain it's on.
+pub type ChannelId = u64;
+
+/// A counterparty chain's state root at some height.
+pub type Root = [u8; 32];
+
+/// What a chain stores under a key to commit to a packet or acknowledgement.
+pub type Commitment = [u8; 32];
+
+/// The key a commitment is stored under: a kind byte, the channel, and the
+/// sequence, both big-endian.
+pub type Key = [u8; 17];
+
+/// A height on the counterparty chain. The zero height means "none", as for
+/// a packet with only a timestamp timeout.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub struct Height {
+    /// Bumped when the chain restarts from zero, e.g. after an upgrade.
+    pub revision_number: u64,
+    pub revision_height: u64,
+}
+
+unsafe impl FfiSafe for Height {}
+
+crate::assert_abi_layout!(Height, 16, 8, revision_number: 0, revision_height: 8);
+
+impl Height {
+    pub const ZERO: Height = Height {
+        revision_number: 0,
+        revision_height: 0,
+    };
+
+    pub const fn new(revision_number: u64, revision_height: u64) -> Height {
+        Height {
+            revision_number,
+            revision_height,
+        }
+    }
+}
+
+/// A packet's header. The data travels alongside and is bound to the header
+/// by its hash.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Packet {
+    pub sequence: u64,
+    pub source_channel: ChannelId,
+    pub destination_channel: ChannelId,
+    /// The packet can't be received at or after this height; zero for none.
+    pub timeout_height: Height,
+    /// The packet can't be received at or after this time, in seconds; zero
+    /// for none.
+    pub timeout_timestamp: u64,
+    /// SHA-256 of the data.
+    pub data_hash: [u8; 32],
+}
+
+unsafe impl FfiSafe for Packet {}
+
+crate::assert_abi_layout!(Packet, 80, 8, sequence: 0, timeout_height: 24, data_hash: 48);
+
+impl Packet {
+    pub fn new(
+        sequence: u64,
+        source_channel: ChannelId,
+        destination_channel: ChannelId,
+        timeout_height: Height,
+        timeout_timestamp: u64,
+        data: &[u8],
+    ) -> Packet {
+        Packet {
+            sequence,
+            source_channel,
+            destination_channel,
+            timeout_height,
+            timeout_timestamp,
+            data_hash: hash::sha256(data),
+        }
+    }
+
+    /// Whether `data` is this packet's data.
+    pub fn carries(&self, data: &[u8]) -> bool {
+        hash::sha256(data) == self.data_hash
+    }
+
+    /// What the sending chain stores under [`packet_key`]: the SHA-256 of
+    /// the timeout timestamp, the timeout height, and the data hash, as in
+    /// IBC.
+    pub fn commitment(&self) -> Commitment {
+        hash::sha256_parts([
+            &self.timeout_timestamp.to_be_bytes(),
+            &self.timeout_height.revision_number.to_be_bytes(),
+            &self.timeout_height.revision_height.to_be_bytes(),
+            &self.data_hash,
+        ])
+    }
+
+    /// Whether the packet has timed out at `height` and `timestamp` on the
+    /// receiving chain, after which it can only be refunded on the sender.
+    pub fn timed_out(&self, height: Height, timestamp: u64) -> bool {
+        (self.timeout_height != Height::ZERO && height >= self.timeout_height)
+            || (self.timeout_timestamp != 0 && timestamp >= self.timeout_timestamp)
+    }
+}
+
+/// The receiving chain's answer to a packet.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Acknowledgement<'a> {
+    Success(&'a [u8]),
+    /// The packet was refused, so the sender should refund it.
+    Error(&'a [u8]),
+}
+
+impl Acknowledgement<'_> {
+    /// What the receiving chain stores under [`ack_key`]: the SHA-256 of a
+    /// tag byte, 1 for success and 0 for an error, and the result.
+    pub fn commitment(&self) -> Commitment {
+        match self {
+            Acknowledgement::Success(result) => hash::sha256_parts([&[1], result]),
+            Acknowledgement::Error(result) => hash::sha256_parts([&[0], result]),
+        }
+    }
+}
+
+/// The key a packet's commitment is stored under on its source chain.
+pub fn packet_key(source_channel: ChannelId, sequence: u64) -> Key {
+    key(1, source_channel, sequence)
+}
+
+/// The key an acknowledgement's commitment is stored under on the chain
+/// that received the packet.
+pub fn ack_key(destination_channel: ChannelId, sequence: u64) -> Key {
+    key(2, destination_channel, sequence)
+}
+
+fn key(kind: u8, channel: ChannelId, sequence: u64) -> Key {
+    let mut key = [0; 17];
+    key[0] = kind;
+    key[1..9].copy_from_slice(&channel.to_be_bytes());
+    key[9..].copy_from_slice(&sequence.to_be_bytes());
+    key
+}
+
+/// A Merkle proof that a key holds a value under a state root.
+///
+/// Leaves are `sha256(0x00 ‖ key ‖ value)` and inner nodes
+/// `sha256(0x01 ‖ left ‖ right)`, so a leaf can't pass for a node. Bit `i`
+/// of `index` says whether the `i`th sibling, counting from the leaf, is on
+/// the left.
+#[derive(Clone, Copy, Debug)]
+pub struct Proof<'a> {
+    pub index: u64,
+    pub siblings: &'a [[u8; 32]],
+}
+
+impl Proof<'_> {
+    /// The root this proof leads to from `key` holding `value`.
+    pub fn root(&self, key: &[u8], value: &[u8]) -> Root {
+        let mut node = hash::sha256_parts([&[0], key, value]);
+        for (i, sibling) in self.siblings.iter().enumerate() {
+            node = if i < 64 && self.index >> i & 1 == 1 {
+                hash::sha256_parts([&[1], sibling, &node])
+            } else {
+                hash::sha256_parts([&[1], &node, sibling])
+            };
+        }
+        node
+    }
+}
+
+/// The state root of the chain tracked by `client` at `height`, or `None`
+/// if the light client hasn't verified that height.
+pub fn root(client: ClientId, height: Height) -> Option<Root> {
+    let mut root = [0; 32];
+    unsafe { starstream_bridge_root(client, &height, &mut root) }.then_some(root)
+}
+
+/// Whether `proof` shows that `key` held `value` on the chain tracked by
+/// `client`, at a `height` its light client has verified.
+pub fn verify_membership(
+    client: ClientId,
+    height: Height,
+    key: &[u8],
+    value: &[u8],
+    proof: &Proof,
+) -> bool {
+    root(client, height) == Some(proof.root(key, value))
+}
+
+/// Whether `packet` was sent, with `data`, on the chain tracked by `client`.
+pub fn verify_packet(
+    client: ClientId,
+    height: Height,
+    packet: &Packet,
+    data: &[u8],
+    proof: &Proof,
+) -> bool {
+    packet.carries(data)
+        && verify_membership(
+            client,
+            height,
+            &packet_key(packet.source_channel, packet.sequence),
+            &packet.commitment(),
+            proof,
+        )
+}
+
+/// Whether the chain tracked by `client` answered `packet` with `ack`.
+pub fn verify_acknowledgement(
+    client: ClientId,
+    height: Height,
+    packet: &Packet,
+    ack: &Acknowledgement,
+    proof: &Proof,
+) -> bool {
+    verify_membership(
+        client,
+        height,
+        &ack_key(packet.destination_channel, packet.sequence),
+        &ack.commitment(),
+        proof,
+    )
+}
+
+/// The next sequence numbers of an ordered channel end, kept in the state of
+/// the UTXO that owns it. Sequences start at 1.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Sequences {
+    pub next_send: u64,
+    pub next_recv: u64,
+    pub next_ack: u64,
+}
+
+unsafe impl FfiSafe for Sequences {}
+
+impl Sequences {
+    pub const fn new() -> Sequences {
+        Sequences {
+            next_send: 1,
+            next_recv: 1,
+            next_ack: 1,
+        }
+    }
+
+    /// The sequence for the next outgoing packet.
+    pub fn send(&mut self) -> u64 {
+        let sequence = self.next_send;
+        self.next_send += 1;
+        sequence
+    }
+
+    /// Accept `packet` if it's the next one due, returning `false` for a
+    /// replayed or out-of-order packet.
+    #[must_use]
+    pub fn recv(&mut self, packet: &Packet) -> bool {
+        Self::advance(&mut self.next_recv, packet.sequence)
+    }
+
+    /// Accept the acknowledgement of the packet `sequence` if it's the next
+    /// one due, as for [`recv`](Self::recv).
+    #[must_use]
+    pub fn acknowledge(&mut self, sequence: u64) -> bool {
+        Self::advance(&mut self.next_ack, sequence)
+    }
+
+    fn advance(next: &mut u64, sequence: u64) -> bool {
+        if sequence != *next {
+            return false;
+        }
+        *next += 1;
+        true
+    }
+}
+
+impl Default for Sequences {
+    fn default() -> Self {
+        Sequences::new()
+    }
+}
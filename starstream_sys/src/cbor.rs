@@ -0,0 +1,1028 @@
+//! A small CBOR (RFC 8949) codec, the common encoding of the UTXO chains
+//! contracts interoperate with, for metadata, event payloads, and
+//! coordination arguments.
+//!
+//! Neither side allocates: [`Encoder`] writes into a buffer the caller
+//! provides, and [`Decoder`] borrows byte and text strings from its input.
+//!
+//! ```ignore
+//! let mut buf = [0; 64];
+//! let mut e = Encoder::new(&mut buf);
+//! e.map(2).str("amount").uint(amount).str("to").bytes(&to.0);
+//! tx::emit_event_parts("transfer", [e.finish()?]);
+//!
+//! let mut d = Decoder::new(payload);
+//! for _ in 0..d.map()?.ok_or(Error::UnexpectedType)? {
+//!     match d.str()? {
+//!         "amount" => amount = d.uint()?,
+//!         _ => d.skip()?,
+//!     }
+//! }
+//! ```
+//!
+//! Floats aren't supported, and byte and text strings must have a definite
+//! length, since indefinite ones can't be borrowed in one piece; only
+//! [`Decoder::bytes_into`], which copies, reads byte strings of indefinite
+//! length. Arrays and maps may have either.
+
+use core::fmt;
+
+/// Why encoding or decoding failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The input ended in the middle of an item.
+    Eof,
+    /// The encoder's buffer is too small for what was written to it.
+    BufferFull,
+    /// The next item isn't of the type asked for.
+    UnexpectedType,
+    /// The integer or length doesn't fit the type asked for.
+    Overflow,
+    /// A text string isn't UTF-8.
+    InvalidUtf8,
+    /// The item is malformed or unsupported, e.g. a float or a reserved
+    /// length encoding.
+    Malformed,
+    /// Arrays, maps, and tags are nested more than [`MAX_DEPTH`] deep.
+    TooDeep,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Error::Eof => "unexpected end of CBOR input",
+            Error::BufferFull => "CBOR buffer full",
+            Error::UnexpectedType => "unexpected CBOR type",
+            Error::Overflow => "CBOR integer or length out of range",
+            Error::InvalidUtf8 => "CBOR text isn't UTF-8",
+            Error::Malformed => "malformed or unsupported CBOR",
+            Error::TooDeep => "CBOR nested too deep",
+        })
+    }
+}
+
+/// How deep [`Decoder::skip`] follows nested items.
+pub const MAX_DEPTH: usize = 32;
+
+/// The type of a CBOR item, by its major type and, for simple values, its
+/// value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    UInt,
+    /// A negative integer.
+    NInt,
+    Bytes,
+    Str,
+    Array,
+    Map,
+    Tag,
+    Bool,
+    Null,
+    Undefined,
+    /// The end of an array or map of indefinite length.
+    Break,
+    /// A float or an unassigned simple value.
+    Other,
+}
+
+const UINT: u8 = 0;
+const NINT: u8 = 1;
+const BYTES: u8 = 2;
+const STR: u8 = 3;
+const ARRAY: u8 = 4;
+const MAP: u8 = 5;
+const TAG: u8 = 6;
+
+const FALSE: u8 = 0xf4;
+const TRUE: u8 = 0xf5;
+const NULL: u8 = 0xf6;
+const UNDEFINED: u8 = 0xf7;
+const BREAK: u8 = 0xff;
+/// The additional information for an indefinite length.
+const INDEFINITE: u8 = 31;
+
+/// Writes CBOR items into a buffer.
+///
+/// Writes chain, and the first one that doesn't fit is reported by
+/// [`finish`](Encoder::finish); the items written so far are left in the
+/// buffer. Arrays and maps are written as their header followed by their
+/// items, so the caller writes the right number of items after it.
+pub struct Encoder<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    full: bool,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Encoder<'a> {
+        Encoder {
+            buf,
+            len: 0,
+            full: false,
+        }
+    }
+
+    /// The bytes written, or [`Error::BufferFull`] if something didn't fit.
+    pub fn finish(self) -> Result<&'a [u8], Error> {
+        if self.full {
+            return Err(Error::BufferFull);
+        }
+        Ok(&self.buf[..self.len])
+    }
+
+    /// How many bytes have been written.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Write already encoded items as they are.
+    pub fn raw(&mut self, bytes: &[u8]) -> &mut Self {
+        match self.buf.get_mut(self.len..self.len + bytes.len()) {
+            Some(out) if !self.full => {
+                out.copy_from_slice(bytes);
+                self.len += bytes.len();
+            }
+            _ => self.full = true,
+        }
+        self
+    }
+
+    /// Write a header: the major type and the argument in the fewest bytes.
+    fn head(&mut self, major: u8, argument: u64) -> &mut Self {
+        let major = major << 5;
+        let bytes = argument.to_be_bytes();
+        match argument {
+            0..=23 => self.raw(&[major | argument as u8]),
+            24..=0xff => self.raw(&[major | 24]).raw(&bytes[7..]),
+            0x100..=0xffff => self.raw(&[major | 25]).raw(&bytes[6..]),
+            0x1_0000..=0xffff_ffff => self.raw(&[major | 26]).raw(&bytes[4..]),
+            _ => self.raw(&[major | 27]).raw(&bytes),
+        }
+    }
+
+    pub fn uint(&mut self, value: u64) -> &mut Self {
+        self.head(UINT, value)
+    }
+
+    pub fn int(&mut self, value: i64) -> &mut Self {
+        if value < 0 {
+            // -1 - value, without overflowing for i64::MIN.
+            self.head(NINT, !value as u64)
+        } else {
+            self.head(UINT, value as u64)
+        }
+    }
+
+    pub fn bytes(&mut self, value: &[u8]) -> &mut Self {
+        self.head(BYTES, value.len() as u64).raw(value)
+    }
+
+    pub fn str(&mut self, value: &str) -> &mut Self {
+        self.head(STR, value.len() as u64).raw(value.as_bytes())
+    }
+
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.raw(&[if value { TRUE } else { FALSE }])
+    }
+
+    pub fn null(&mut self) -> &mut Self {
+        self.raw(&[NULL])
+    }
+
+    /// Start an array of `len` items.
+    pub fn array(&mut self, len: u64) -> &mut Self {
+        self.head(ARRAY, len)
+    }
+
+    /// Start a map of `len` key-value pairs.
+    pub fn map(&mut self, len: u64) -> &mut Self {
+        self.head(MAP, len)
+    }
+
+    /// Start an array of indefinite length, to be ended with
+    /// [`end`](Encoder::end).
+    pub fn begin_array(&mut self) -> &mut Self {
+        self.raw(&[ARRAY << 5 | INDEFINITE])
+    }
+
+    /// Start a map of indefinite length, to be ended with
+    /// [`end`](Encoder::end).
+    pub fn begin_map(&mut self) -> &mut Self {
+        self.raw(&[MAP << 5 | INDEFINITE])
+    }
+
+    /// Start a byte string of indefinite length, to be written as byte
+    /// string chunks and ended with [`end`](Encoder::end).
+    pub fn begin_bytes(&mut self) -> &mut Self {
+        self.raw(&[BYTES << 5 | INDEFINITE])
+    }
+
+    /// End the innermost array, map, or byte string of indefinite length.
+    pub fn end(&mut self) -> &mut Self {
+        self.raw(&[BREAK])
+    }
+
+    /// Tag the next item, e.g. with 2 for a big unsigned integer.
+    pub fn tag(&mut self, tag: u64) -> &mut Self {
+        self.head(TAG, tag)
+    }
+
+    /// Write `value` with its [`Encode`] impl.
+    pub fn encode<T: Encode + ?Sized>(&mut self, value: &T) -> &mut Self {
+        value.encode(self);
+        self
+    }
+}
+
+/// Reads CBOR items from bytes, in order.
+///
+/// A method that fails leaves the decoder where it was, so the caller can
+/// try reading the item as another type.
+#[derive(Clone)]
+pub struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(data: &'a [u8]) -> Decoder<'a> {
+        Decoder { data, pos: 0 }
+    }
+
+    /// How many bytes have been read.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// The bytes not yet read.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    /// Whether every item has been read.
+    pub fn is_done(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    /// The type of the next item, without reading it.
+    pub fn peek(&self) -> Result<Type, Error> {
+        let initial = *self.data.get(self.pos).ok_or(Error::Eof)?;
+        Ok(match (initial >> 5, initial) {
+            (UINT, _) => Type::UInt,
+            (NINT, _) => Type::NInt,
+            (BYTES, _) => Type::Bytes,
+            (STR, _) => Type::Str,
+            (ARRAY, _) => Type::Array,
+            (MAP, _) => Type::Map,
+            (TAG, _) => Type::Tag,
+            (_, FALSE | TRUE) => Type::Bool,
+            (_, NULL) => Type::Null,
+            (_, UNDEFINED) => Type::Undefined,
+            (_, BREAK) => Type::Break,
+            _ => Type::Other,
+        })
+    }
+
+    /// Read a header of major type `major`, returning its argument, or
+    /// `None` for an indefinite length.
+    fn head(&mut self, major: u8) -> Result<Option<u64>, Error> {
+        let initial = *self.data.get(self.pos).ok_or(Error::Eof)?;
+        if initial >> 5 != major {
+            return Err(Error::UnexpectedType);
+        }
+        let (argument, size) = match initial & 0x1f {
+            info @ 0..=23 => (Some(info as u64), 0),
+            24 => (None, 1),
+            25 => (None, 2),
+            26 => (None, 4),
+            27 => (None, 8),
+            INDEFINITE if matches!(major, ARRAY | MAP) => {
+                self.pos += 1;
+                return Ok(None);
+            }
+            _ => return Err(Error::Malformed),
+        };
+        let start = self.pos + 1;
+        let bytes = self.data.get(start..start + size).ok_or(Error::Eof)?;
+        self.pos = start + size;
+        Ok(Some(argument.unwrap_or_else(|| {
+            let mut be = [0; 8];
+            be[8 - size..].copy_from_slice(bytes);
+            u64::from_be_bytes(be)
+        })))
+    }
+
+    /// Read a header whose length must be definite.
+    fn definite(&mut self, major: u8) -> Result<u64, Error> {
+        self.head(major)?.ok_or(Error::Malformed)
+    }
+
+    /// Read `len` bytes of a string's content, restoring the position to
+    /// `start` on failure.
+    fn content(&mut self, start: usize, len: u64) -> Result<&'a [u8], Error> {
+        let content = usize::try_from(len)
+            .ok()
+            .and_then(|len| self.data.get(self.pos..self.pos.checked_add(len)?));
+        match content {
+            Some(content) => {
+                self.pos += content.len();
+                Ok(content)
+            }
+            None => {
+                self.pos = start;
+                Err(Error::Eof)
+            }
+        }
+    }
+
+    pub fn uint(&mut self) -> Result<u64, Error> {
+        self.definite(UINT)
+    }
+
+    /// Read an integer of either sign that fits an `i64`.
+    pub fn int(&mut self) -> Result<i64, Error> {
+        let start = self.pos;
+        let value = match self.peek()? {
+            Type::UInt => i64::try_from(self.definite(UINT)?).ok(),
+            Type::NInt => i64::try_from(self.definite(NINT)?).ok().map(|n| !n),
+            _ => return Err(Error::UnexpectedType),
+        };
+        value.ok_or_else(|| {
+            self.pos = start;
+            Error::Overflow
+        })
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let start = self.pos;
+        let len = self.definite(BYTES)?;
+        self.content(start, len)
+    }
+
+    /// Copy a byte string of definite or indefinite length into the start of
+    /// `out`, returning its length, or fail with [`Error::Overflow`] if it is
+    /// longer than `out`.
+    pub fn bytes_into(&mut self, out: &mut [u8]) -> Result<usize, Error> {
+        let start = self.pos;
+        self.bytes_into_unrestored(out)
+            .inspect_err(|_| self.pos = start)
+    }
+
+    fn bytes_into_unrestored(&mut self, out: &mut [u8]) -> Result<usize, Error> {
+        fn copy(bytes: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+            let out = out.get_mut(..bytes.len()).ok_or(Error::Overflow)?;
+            out.copy_from_slice(bytes);
+            Ok(bytes.len())
+        }
+
+        if self.data.get(self.pos) != Some(&(BYTES << 5 | INDEFINITE)) {
+            return copy(self.bytes()?, out);
+        }
+        self.pos += 1;
+        let mut len = 0;
+        // The chunks have definite lengths.
+        while !self.end() {
+            len += copy(self.bytes()?, &mut out[len..])?;
+        }
+        Ok(len)
+    }
+
+    pub fn str(&mut self) -> Result<&'a str, Error> {
+        let start = self.pos;
+        let len = self.definite(STR)?;
+        let content = self.content(start, len)?;
+        core::str::from_utf8(content).map_err(|_| {
+            self.pos = start;
+            Error::InvalidUtf8
+        })
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        let value = match self.data.get(self.pos) {
+            Some(&FALSE) => false,
+            Some(&TRUE) => true,
+            Some(_) => return Err(Error::UnexpectedType),
+            None => return Err(Error::Eof),
+        };
+        self.pos += 1;
+        Ok(value)
+    }
+
+    /// Read a null, or leave the decoder where it was and return `false`.
+    pub fn null(&mut self) -> bool {
+        let null = self.data.get(self.pos) == Some(&NULL);
+        self.pos += null as usize;
+        null
+    }
+
+    /// Read the start of an array, returning its number of items, or `None`
+    /// if it has an indefinite length, in which case it ends at the next
+    /// [`end`](Decoder::end).
+    pub fn array(&mut self) -> Result<Option<u64>, Error> {
+        self.head(ARRAY)
+    }
+
+    /// Read the start of a map, returning its number of key-value pairs, or
+    /// `None` as for [`array`](Decoder::array).
+    pub fn map(&mut self) -> Result<Option<u64>, Error> {
+        self.head(MAP)
+    }
+
+    /// Read the end of an array or map of indefinite length, or leave the
+    /// decoder where it was and return `false` if there are more items.
+    pub fn end(&mut self) -> bool {
+        let end = self.data.get(self.pos) == Some(&BREAK);
+        self.pos += end as usize;
+        end
+    }
+
+    pub fn tag(&mut self) -> Result<u64, Error> {
+        self.definite(TAG)
+    }
+
+    /// Read a `T` with its [`Decode`] impl.
+    pub fn decode<T: Decode<'a>>(&mut self) -> Result<T, Error> {
+        T::decode(self)
+    }
+
+    /// Read the next item, including everything nested in it, without
+    /// decoding it, e.g. the value of a map key the caller doesn't know.
+    pub fn skip(&mut self) -> Result<(), Error> {
+        let start = self.pos;
+        self.skip_nested(0).inspect_err(|_| self.pos = start)
+    }
+
+    fn skip_nested(&mut self, depth: usize) -> Result<(), Error> {
+        if depth > MAX_DEPTH {
+            return Err(Error::TooDeep);
+        }
+        let initial = *self.data.get(self.pos).ok_or(Error::Eof)?;
+        match initial >> 5 {
+            UINT | NINT => {
+                self.definite(initial >> 5)?;
+            }
+            BYTES if initial & 0x1f == INDEFINITE => {
+                self.pos += 1;
+                while !self.end() {
+                    self.bytes()?;
+                }
+            }
+            BYTES => {
+                self.bytes()?;
+            }
+            STR => {
+                let start = self.pos;
+                let len = self.definite(STR)?;
+                self.content(start, len)?;
+            }
+            TAG => {
+                self.tag()?;
+                self.skip_nested(depth + 1)?;
+            }
+            major @ (ARRAY | MAP) => {
+                let per_entry = if major == MAP { 2 } else { 1 };
+                match self.head(major)? {
+                    Some(len) => {
+                        for _ in 0..len.checked_mul(per_entry).ok_or(Error::Overflow)? {
+                            self.skip_nested(depth + 1)?;
+                        }
+                    }
+                    None => {
+                        while !self.end() {
+                            self.skip_nested(depth + 1)?;
+                        }
+                    }
+                }
+            }
+            _ => match initial {
+                FALSE | TRUE | NULL | UNDEFINED => self.pos += 1,
+                _ => return Err(Error::Malformed),
+            },
+        }
+        Ok(())
+    }
+}
+
+/// A type with a CBOR encoding.
+pub trait Encode {
+    fn encode(&self, e: &mut Encoder);
+}
+
+/// A type that can be read from CBOR, possibly borrowing from the input.
+pub trait Decode<'a>: Sized {
+    fn decode(d: &mut Decoder<'a>) -> Result<Self, Error>;
+}
+
+macro_rules! impl_uint {
+    ($($ty:ty),*) => {$(
+        impl Encode for $ty {
+            fn encode(&self, e: &mut Encoder) {
+                e.uint(*self as u64);
+            }
+        }
+
+        impl Decode<'_> for $ty {
+            fn decode(d: &mut Decoder) -> Result<Self, Error> {
+                let start = d.pos;
+                <$ty>::try_from(d.uint()?).map_err(|_| {
+                    d.pos = start;
+                    Error::Overflow
+                })
+            }
+        }
+    )*};
+}
+
+macro_rules! impl_int {
+    ($($ty:ty),*) => {$(
+        impl Encode for $ty {
+            fn encode(&self, e: &mut Encoder) {
+                e.int(*self as i64);
+            }
+        }
+
+        impl Decode<'_> for $ty {
+            fn decode(d: &mut Decoder) -> Result<Self, Error> {
+                let start = d.pos;
+                <$ty>::try_from(d.int()?).map_err(|_| {
+                    d.pos = start;
+                    Error::Overflow
+                })
+            }
+        }
+    )*};
+}
+
+impl_uint!(u8, u16, u32, u64);
+impl_int!(i8, i16, i32, i64);
+
+impl Encode for bool {
+    fn encode(&self, e: &mut Encoder) {
+        e.bool(*self);
+    }
+}
+
+impl Decode<'_> for bool {
+    fn decode(d: &mut Decoder) -> Result<Self, Error> {
+        d.bool()
+    }
+}
+
+impl Encode for [u8] {
+    fn encode(&self, e: &mut Encoder) {
+        e.bytes(self);
+    }
+}
+
+impl<'a> Decode<'a> for &'a [u8] {
+    fn decode(d: &mut Decoder<'a>) -> Result<Self, Error> {
+        d.bytes()
+    }
+}
+
+/// Fixed-size byte arrays, such as hashes and keys, are byte strings.
+impl<const N: usize> Encode for [u8; N] {
+    fn encode(&self, e: &mut Encoder) {
+        e.bytes(self);
+    }
+}
+
+impl<const N: usize> Decode<'_> for [u8; N] {
+    fn decode(d: &mut Decoder) -> Result<Self, Error> {
+        let start = d.pos;
+        d.bytes()?.try_into().map_err(|_| {
+            d.pos = start;
+            Error::UnexpectedType
+        })
+    }
+}
+
+impl Encode for str {
+    fn encode(&self, e: &mut Encoder) {
+        e.str(self);
+    }
+}
+
+impl<'a> Decode<'a> for &'a str {
+    fn decode(d: &mut Decoder<'a>) -> Result<Self, Error> {
+        d.str()
+    }
+}
+
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self, e: &mut Encoder) {
+        (**self).encode(e);
+    }
+}
+
+/// `None` is null.
+impl<T: Encode> Encode for Option<T> {
+    fn encode(&self, e: &mut Encoder) {
+        match self {
+            Some(value) => value.encode(e),
+            None => {
+                e.null();
+            }
+        }
+    }
+}
+
+impl<'a, T: Decode<'a>> Decode<'a> for Option<T> {
+    fn decode(d: &mut Decoder<'a>) -> Result<Self, Error> {
+        if d.null() {
+            return Ok(None);
+        }
+        T::decode(d).map(Some)
+    }
+}
+
+impl Encode for crate::UtxoId {
+    fn encode(&self, e: &mut Encoder) {
+        e.bytes(&self.0);
+    }
+}
+
+impl Decode<'_> for crate::UtxoId {
+    fn decode(d: &mut Decoder) -> Result<Self, Error> {
+        d.decode().map(crate::UtxoId)
+    }
+}
+
+/// Encode `value` into `buf`, returning the bytes written.
+pub fn to_slice<'a, T: Encode + ?Sized>(value: &T, buf: &'a mut [u8]) -> Result<&'a [u8], Error> {
+    let mut e = Encoder::new(buf);
+    e.encode(value);
+    e.finish()
+}
+
+/// Decode a `T` that must make up all of `data`.
+pub fn from_slice<'a, T: Decode<'a>>(data: &'a [u8]) -> Result<T, Error> {
+    let mut d = Decoder::new(data);
+    let value = d.decode()?;
+    if !d.is_done() {
+        return Err(Error::Malformed);
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Encode with `write` and check the bytes are `expected`.
+    fn encodes(expected: &str, write: impl FnOnce(&mut Encoder)) {
+        let mut buf = [0; 64];
+        let mut e = Encoder::new(&mut buf);
+        write(&mut e);
+        assert_eq!(e.finish().unwrap(), hex(expected), "{expected}");
+    }
+
+    /// Check that `skip` reads all of `data`, as one well-formed item.
+    fn skips(data: &str) {
+        let data = hex(data);
+        let mut d = Decoder::new(&data);
+        d.skip().unwrap();
+        assert!(d.is_done());
+    }
+
+    // The vectors below are RFC 8949, appendix A, less floats and bignums.
+
+    #[test]
+    fn unsigned_integers() {
+        let vectors = [
+            (0, "00"),
+            (1, "01"),
+            (10, "0a"),
+            (23, "17"),
+            (24, "1818"),
+            (25, "1819"),
+            (100, "1864"),
+            (1000, "1903e8"),
+            (1000000, "1a000f4240"),
+            (1000000000000, "1b000000e8d4a51000"),
+            (18446744073709551615, "1bffffffffffffffff"),
+        ];
+        for (value, data) in vectors {
+            encodes(data, |e| {
+                e.uint(value);
+            });
+            assert_eq!(from_slice::<u64>(&hex(data)), Ok(value));
+        }
+    }
+
+    #[test]
+    fn negative_integers() {
+        let vectors = [
+            (-1, "20"),
+            (-10, "29"),
+            (-100, "3863"),
+            (-1000, "3903e7"),
+            (i64::MIN, "3b7fffffffffffffff"),
+        ];
+        for (value, data) in vectors {
+            encodes(data, |e| {
+                e.int(value);
+            });
+            assert_eq!(from_slice::<i64>(&hex(data)), Ok(value));
+        }
+        // -18446744073709551616 is well-formed but doesn't fit an `i64`.
+        let data = hex("3bffffffffffffffff");
+        let mut d = Decoder::new(&data);
+        assert_eq!(d.int(), Err(Error::Overflow));
+        assert_eq!(d.position(), 0);
+        d.skip().unwrap();
+    }
+
+    #[test]
+    fn simple_values() {
+        encodes("f4", |e| {
+            e.bool(false);
+        });
+        encodes("f5", |e| {
+            e.bool(true);
+        });
+        encodes("f6", |e| {
+            e.null();
+        });
+        assert_eq!(from_slice::<bool>(&hex("f4")), Ok(false));
+        assert_eq!(from_slice::<bool>(&hex("f5")), Ok(true));
+        assert_eq!(from_slice::<Option<u8>>(&hex("f6")), Ok(None));
+        let undefined = hex("f7");
+        assert_eq!(Decoder::new(&undefined).peek(), Ok(Type::Undefined));
+        skips("f7");
+    }
+
+    #[test]
+    fn strings() {
+        let bytes: [(&[u8], &str); 2] = [(b"", "40"), (&[1, 2, 3, 4], "4401020304")];
+        for (value, data) in bytes {
+            encodes(data, |e| {
+                e.bytes(value);
+            });
+            assert_eq!(from_slice::<&[u8]>(&hex(data)), Ok(value));
+        }
+        let text = [
+            ("", "60"),
+            ("a", "6161"),
+            ("IETF", "6449455446"),
+            ("\"\\", "62225c"),
+            ("\u{00fc}", "62c3bc"),
+            ("\u{6c34}", "63e6b0b4"),
+            ("\u{10151}", "64f0908591"),
+        ];
+        for (value, data) in text {
+            encodes(data, |e| {
+                e.str(value);
+            });
+            assert_eq!(from_slice::<&str>(&hex(data)), Ok(value));
+        }
+    }
+
+    #[test]
+    fn arrays_and_maps() {
+        encodes("80", |e| {
+            e.array(0);
+        });
+        encodes("83010203", |e| {
+            e.array(3).uint(1).uint(2).uint(3);
+        });
+        encodes("8301820203820405", |e| {
+            e.array(3)
+                .uint(1)
+                .array(2)
+                .uint(2)
+                .uint(3)
+                .array(2)
+                .uint(4)
+                .uint(5);
+        });
+        encodes(
+            "98190102030405060708090a0b0c0d0e0f101112131415161718181819",
+            |e| {
+                e.array(25);
+                for i in 1..=25 {
+                    e.uint(i);
+                }
+            },
+        );
+        encodes("a0", |e| {
+            e.map(0);
+        });
+        encodes("a201020304", |e| {
+            e.map(2).uint(1).uint(2).uint(3).uint(4);
+        });
+        encodes("a26161016162820203", |e| {
+            e.map(2).str("a").uint(1).str("b").array(2).uint(2).uint(3);
+        });
+        encodes("826161a161626163", |e| {
+            e.array(2).str("a").map(1).str("b").str("c");
+        });
+        encodes("a56161614161626142616361436164614461656145", |e| {
+            e.map(5);
+            for (key, value) in [("a", "A"), ("b", "B"), ("c", "C"), ("d", "D"), ("e", "E")] {
+                e.str(key).str(value);
+            }
+        });
+
+        let data = hex("a26161016162820203");
+        let mut d = Decoder::new(&data);
+        assert_eq!(d.map(), Ok(Some(2)));
+        assert_eq!((d.str(), d.uint()), (Ok("a"), Ok(1)));
+        assert_eq!((d.str(), d.array()), (Ok("b"), Ok(Some(2))));
+        assert_eq!((d.uint(), d.uint()), (Ok(2), Ok(3)));
+        assert!(d.is_done());
+        for data in ["8301820203820405", "826161a161626163"] {
+            skips(data);
+        }
+    }
+
+    #[test]
+    fn tags() {
+        encodes("c11a514b67b0", |e| {
+            e.tag(1).uint(1363896240);
+        });
+        encodes("d74401020304", |e| {
+            e.tag(23).bytes(&[1, 2, 3, 4]);
+        });
+        encodes("d818456449455446", |e| {
+            e.tag(24).bytes(&hex("6449455446"));
+        });
+        encodes("d82076687474703a2f2f7777772e6578616d706c652e636f6d", |e| {
+            e.tag(32).str("http://www.example.com");
+        });
+        let data = hex("c074323031332d30332d32315432303a30343a30305a");
+        let mut d = Decoder::new(&data);
+        assert_eq!(d.tag(), Ok(0));
+        assert_eq!(d.str(), Ok("2013-03-21T20:04:00Z"));
+        assert!(d.is_done());
+    }
+
+    #[test]
+    fn indefinite_lengths() {
+        encodes("9fff", |e| {
+            e.begin_array().end();
+        });
+        encodes("9f018202039f0405ffff", |e| {
+            e.begin_array().uint(1).array(2).uint(2).uint(3);
+            e.begin_array().uint(4).uint(5).end().end();
+        });
+        encodes("bf61610161629f0203ffff", |e| {
+            e.begin_map().str("a").uint(1).str("b");
+            e.begin_array().uint(2).uint(3).end().end();
+        });
+        encodes("bf6346756ef563416d7421ff", |e| {
+            e.begin_map().str("Fun").bool(true).str("Amt").int(-2).end();
+        });
+        encodes("5f42010243030405ff", |e| {
+            e.begin_bytes().bytes(&[1, 2]).bytes(&[3, 4, 5]).end();
+        });
+
+        let data = hex("83018202039f0405ff");
+        let mut d = Decoder::new(&data);
+        assert_eq!(d.array(), Ok(Some(3)));
+        d.skip().unwrap();
+        d.skip().unwrap();
+        assert_eq!(d.array(), Ok(None));
+        assert_eq!((d.uint(), d.uint()), (Ok(4), Ok(5)));
+        assert!(d.end());
+        assert!(d.is_done());
+
+        let data = hex("5f42010243030405ff");
+        let mut out = [0; 8];
+        let mut d = Decoder::new(&data);
+        assert_eq!(d.bytes_into(&mut out), Ok(5));
+        assert_eq!(out[..5], [1, 2, 3, 4, 5]);
+        assert!(d.is_done());
+        assert_eq!(
+            Decoder::new(&data).bytes_into(&mut [0; 4]),
+            Err(Error::Overflow)
+        );
+
+        for data in [
+            "9f0102030405060708090a0b0c0d0e0f101112131415161718181819ff",
+            "826161bf61626163ff",
+            "5f42010243030405ff",
+        ] {
+            skips(data);
+        }
+    }
+
+    #[test]
+    fn integers_round_trip_in_the_fewest_bytes() {
+        let uints = [
+            (0, 1),
+            (23, 1),
+            (24, 2),
+            (0xff, 2),
+            (0x100, 3),
+            (0xffff, 3),
+            (0x1_0000, 5),
+            (0xffff_ffff, 5),
+            (0x1_0000_0000, 9),
+            (u64::MAX, 9),
+        ];
+        for (value, len) in uints {
+            let mut buf = [0; 9];
+            let data = to_slice(&value, &mut buf).unwrap();
+            assert_eq!(data.len(), len, "{value}");
+            assert_eq!(from_slice::<u64>(data), Ok(value));
+        }
+        for value in [
+            i64::MIN,
+            -0x1_0000_0001,
+            -257,
+            -256,
+            -25,
+            -24,
+            -1,
+            0,
+            i64::MAX,
+        ] {
+            let mut buf = [0; 9];
+            assert_eq!(
+                from_slice::<i64>(to_slice(&value, &mut buf).unwrap()),
+                Ok(value)
+            );
+        }
+        let mut buf = [0; 9];
+        let data = to_slice(&256u64, &mut buf).unwrap();
+        assert_eq!(from_slice::<u8>(data), Err(Error::Overflow));
+    }
+
+    #[test]
+    fn other_types_round_trip() {
+        let mut buf = [0; 64];
+        let hash = [7; 32];
+        assert_eq!(
+            from_slice::<[u8; 32]>(to_slice(&hash, &mut buf).unwrap()),
+            Ok(hash)
+        );
+        let mut buf = [0; 64];
+        let data = to_slice(&Some("starstream"), &mut buf).unwrap();
+        assert_eq!(from_slice::<Option<&str>>(data), Ok(Some("starstream")));
+        assert_eq!(from_slice::<[u8; 31]>(data), Err(Error::UnexpectedType));
+        assert_eq!(to_slice(&hash, &mut [0; 32]), Err(Error::BufferFull));
+    }
+
+    #[test]
+    fn rejects_indefinite_length_strings() {
+        let bytes = hex("5f42010243030405ff");
+        let mut d = Decoder::new(&bytes);
+        assert_eq!(d.bytes(), Err(Error::Malformed));
+        assert_eq!(d.position(), 0);
+        let text = hex("7f657374726561646d696e67ff");
+        let mut d = Decoder::new(&text);
+        assert_eq!(d.str(), Err(Error::Malformed));
+        assert_eq!(d.skip(), Err(Error::Malformed));
+        assert_eq!(d.position(), 0);
+    }
+
+    #[test]
+    fn rejects_nesting_deeper_than_max_depth() {
+        // `MAX_DEPTH` arrays of one item around a 0, then one more.
+        let mut data = [0x81; MAX_DEPTH + 2];
+        data[MAX_DEPTH + 1] = 0;
+        let mut d = Decoder::new(&data[1..]);
+        d.skip().unwrap();
+        assert!(d.is_done());
+        let mut d = Decoder::new(&data);
+        assert_eq!(d.skip(), Err(Error::TooDeep));
+        assert_eq!(d.position(), 0);
+        // Tags nest too.
+        data[..MAX_DEPTH + 1].fill(0xc1);
+        assert_eq!(Decoder::new(&data).skip(), Err(Error::TooDeep));
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        assert_eq!(from_slice::<u8>(&hex("01")), Ok(1));
+        assert_eq!(from_slice::<u8>(&hex("0102")), Err(Error::Malformed));
+        assert_eq!(from_slice::<&str>(&hex("6161f6")), Err(Error::Malformed));
+    }
+
+    #[test]
+    fn rejects_floats_and_reserved_lengths() {
+        // 1.0 as a half-precision float.
+        let float = hex("f93c00");
+        assert_eq!(Decoder::new(&float).uint(), Err(Error::UnexpectedType));
+        assert_eq!(Decoder::new(&float).skip(), Err(Error::Malformed));
+        // Additional information 28 to 30 is reserved.
+        assert_eq!(from_slice::<u64>(&hex("1c")), Err(Error::Malformed));
+        assert_eq!(from_slice::<u64>(&hex("19ff")), Err(Error::Eof));
+    }
+}
@@ -0,0 +1,40 @@
+//! Typed messages between UTXOs, buffered by the host.
+//!
+//! A UTXO sends to another by id; the message waits in the receiver's inbox
+//! until the receiver drains it, typically on its next resume. Only UTXOs
+//! can send and receive.
+
+use core::mem::{size_of, MaybeUninit};
+
+use crate::UtxoId;
+
+#[link(wasm_import_module = "starstream_utxo_env")]
+unsafe extern "C" {
+    unsafe fn starstream_channel_send(to: *const UtxoId, message: *const (), message_size: usize);
+    unsafe fn starstream_channel_recv(message: *mut (), message_size: usize) -> bool;
+}
+
+/// Queue `message` for the UTXO `to`.
+pub fn send<T>(to: UtxoId, message: &T) {
+    unsafe { starstream_channel_send(&to, message as *const T as *const (), size_of::<T>()) }
+}
+
+/// Take the oldest message from this UTXO's inbox. The host traps if it
+/// isn't the size of a `T`.
+pub fn recv<T>() -> Option<T> {
+    let mut message = MaybeUninit::<T>::uninit();
+    unsafe {
+        if starstream_channel_recv(message.as_mut_ptr() as *mut (), size_of::<T>()) {
+            // SAFETY TODO: as in `sleep`, relies on sender and receiver
+            // agreeing on T.
+            Some(message.assume_init())
+        } else {
+            None
+        }
+    }
+}
+
+/// Take every message from this UTXO's inbox.
+pub fn drain<T>() -> impl Iterator<Item = T> {
+    core::iter::from_fn(recv)
+}
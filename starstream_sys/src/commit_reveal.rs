@@ -0,0 +1,172 @@
+//! Commit-reveal, for sealed bids, fair games, and anything else where
+//! participants must choose before they can see each other's choices.
+//!
+//! Phase one: a participant computes [`digest`] of their value and a secret
+//! random salt off-chain, and a transaction stores only the digest in a
+//! commitment UTXO. Phase two: once everyone has committed, each participant
+//! submits the value and salt; the helper checks them against the digest,
+//! consumes the UTXO, and hands back the value.
+//!
+//! A contract exports the commitment UTXO with
+//! [`commitment_export!`](crate::commitment_export), and coordination scripts
+//! import it with [`commitment_import!`](crate::commitment_import). Only the
+//! coordination script that created a commitment can consume it, so a script
+//! that opens commitments only through `reveal` ensures every opening was
+//! checked.
+
+use crate::{env, hash, sleep_state, CodeHash, FfiSafe, StateId};
+
+pub type Digest = [u8; 32];
+
+/// Should be fresh random bytes for every commitment, or small values like
+/// bids can be guessed by hashing every candidate.
+pub type Salt = [u8; 32];
+
+/// `sha256(value ‖ salt)`, with `value` in its `FfiSafe` layout.
+pub fn digest<T: FfiSafe>(value: &T, salt: &Salt) -> Digest {
+    hash::sha256_parts([hash::bytes_of(value), salt])
+}
+
+/// The state a commitment UTXO yields in until it is revealed.
+#[repr(C)]
+pub struct Sealed {
+    pub digest: Digest,
+    /// The coordination script that created it, and so may reveal it.
+    pub script: CodeHash,
+}
+
+unsafe impl FfiSafe for Sealed {}
+
+crate::assert_abi_layout!(Sealed, 64, 1, digest: 0, script: 32);
+
+/// The state name commitment UTXOs yield under.
+pub const SEALED: &str = "sealed";
+
+#[doc(hidden)]
+pub fn __commitment_main(state: StateId) {
+    let sealed = Sealed {
+        digest: env::init_args(),
+        script: env::caller_code(),
+    };
+    let () = sleep_state(state, &sealed);
+    // Consumed by the reveal, which checked the value on the script's side.
+    env::assert_called_by(sealed.script);
+}
+
+/// Export a commitment UTXO type from a contract. It is spawned with a
+/// [`Digest`](crate::commit_reveal::Digest) and resumed once, to reveal it.
+///
+/// ```ignore
+/// commitment_export! {
+///     new fn starstream_new_Commitment_new;
+/// }
+/// ```
+#[macro_export]
+macro_rules! commitment_export {
+    (
+        new fn $new_fn:ident;
+    ) => {
+        #[no_mangle]
+        pub extern "C" fn $new_fn() {
+            $crate::__check_abi();
+            // `state!` needs a literal: this is `commit_reveal::SEALED`.
+            $crate::commit_reveal::__commitment_main($crate::state!("sealed"))
+        }
+    };
+}
+
+/// Import a commitment UTXO type exported with `commitment_export!`.
+///
+/// ```ignore
+/// commitment_import! {
+///     from "starstream_utxo:example_contract";
+///     type Commitment;
+///     spawn fn starstream_spawn_Commitment_new;
+///     status fn starstream_status_Commitment;
+///     resume fn starstream_resume_Commitment;
+///     state fn starstream_state_Commitment;
+/// }
+///
+/// let bid = Commitment::commit(sealed_bid);
+/// // ... in a later transaction, by the same script:
+/// let amount: u64 = bid.reveal(amount, &salt);
+/// ```
+#[macro_export]
+macro_rules! commitment_import {
+    (
+        from $module:expr;
+        type $name:ident;
+        spawn fn $spawn_fn:ident;
+        status fn $status_fn:ident;
+        resume fn $resume_fn:ident;
+        state fn $state_fn:ident;
+    ) => {
+        $crate::utxo_import! {
+            $module;
+            $name;
+            $status_fn;
+            $resume_fn;
+            ();
+            ();
+        }
+
+        $crate::__state_import!($module; $name; $state_fn);
+        $crate::__spawn_import!($module; $name; $spawn_fn($crate::commit_reveal::Digest));
+
+        impl $name {
+            /// Phase one: store `digest`, computed off-chain with
+            /// `commit_reveal::digest` so the value itself stays secret.
+            #[inline]
+            pub fn commit(digest: $crate::commit_reveal::Digest) -> Self {
+                unsafe {
+                    $spawn_fn(
+                        &digest,
+                        core::mem::size_of::<$crate::commit_reveal::Digest>(),
+                    )
+                }
+            }
+
+            /// The stored digest, or `None` once revealed.
+            pub fn digest(self) -> Option<$crate::commit_reveal::Digest> {
+                let mut sealed = core::mem::MaybeUninit::<$crate::commit_reveal::Sealed>::uninit();
+                unsafe {
+                    if $state_fn(
+                        self,
+                        const { $crate::StateId::of($crate::commit_reveal::SEALED) },
+                        sealed.as_mut_ptr() as *mut (),
+                        core::mem::size_of::<$crate::commit_reveal::Sealed>(),
+                    ) != 0
+                    {
+                        Some(sealed.assume_init().digest)
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            /// Whether `value` and `salt` are what was committed to.
+            pub fn matches<T: $crate::FfiSafe>(
+                self,
+                value: &T,
+                salt: &$crate::commit_reveal::Salt,
+            ) -> bool {
+                self.digest() == Some($crate::commit_reveal::digest(value, salt))
+            }
+
+            /// Phase two: check `value` and `salt` against the commitment,
+            /// consume it, and return `value`. Traps if they don't match.
+            pub fn reveal<T: $crate::FfiSafe>(
+                self,
+                value: T,
+                salt: &$crate::commit_reveal::Salt,
+            ) -> T {
+                assert!(
+                    self.matches(&value, salt),
+                    "reveal doesn't match commitment"
+                );
+                <Self as $crate::Utxo>::resume(self, ());
+                value
+            }
+        }
+    };
+}
@@ -0,0 +1,87 @@
+//! Calling other coordination scripts within the same transaction, so that
+//! scripts can be composed, e.g. a router calling an AMM script, and
+//! describing entry points for off-chain callers.
+//!
+//! The callee shares the caller's UTXO and token handles. A script can't be
+//! called while it is already running, and calls may be nested at most a
+//! few levels deep. A trap in the callee fails the whole transaction.
+
+use core::mem::{size_of, MaybeUninit};
+
+use crate::{cbor, last_host_error, tx::MAX_PAYLOAD, CodeHash, FfiSafe, HostError};
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_call(
+        code: *const CodeHash,
+        entry: *const u8,
+        entry_len: usize,
+        args: *const (),
+        args_size: usize,
+        ret: *mut (),
+        ret_size: usize,
+    ) -> u32;
+}
+
+/// Run the entry point `entry` of the coordination script with hash `code`,
+/// which receives `args`, encoded as CBOR, as its payload and answers with
+/// its result; see [`tx::payload`] and [`tx::set_result`]. Traps if the
+/// encoding is longer than [`MAX_PAYLOAD`].
+///
+/// [`tx::payload`]: crate::tx::payload
+/// [`tx::set_result`]: crate::tx::set_result
+#[track_caller]
+pub fn call<Args: cbor::Encode + ?Sized, Ret: FfiSafe>(
+    code: CodeHash,
+    entry: &str,
+    args: &Args,
+) -> Result<Ret, HostError> {
+    let mut buf = [0; MAX_PAYLOAD];
+    let Ok(args) = cbor::to_slice(args, &mut buf) else {
+        trap!(
+            "arguments to {} are longer than {} bytes",
+            entry,
+            MAX_PAYLOAD
+        );
+    };
+    let mut ret = MaybeUninit::<Ret>::uninit();
+    let failed = unsafe {
+        starstream_call(
+            &code,
+            entry.as_ptr(),
+            entry.len(),
+            args.as_ptr() as *const (),
+            args.len(),
+            ret.as_mut_ptr() as *mut (),
+            size_of::<Ret>(),
+        )
+    };
+    if failed != 0 {
+        return Err(last_host_error().unwrap_or(HostError::NotFound));
+    }
+    // SAFETY TODO: as in `sleep`, relies on both scripts agreeing on Ret.
+    Ok(unsafe { ret.assume_init() })
+}
+
+/// A coordination script entry point, as described in the `client` module
+/// generated by [`#[coordination]`](macro@crate::coordination).
+#[derive(Clone, Copy, Debug)]
+pub struct EntryPoint {
+    /// The export to run.
+    pub name: &'static str,
+    pub params: &'static [Param],
+    /// The return type as written, or `()`.
+    pub ret: &'static str,
+    /// Whether the arguments are read from the transaction payload, as CBOR,
+    /// rather than passed as C-ABI parameters.
+    pub payload: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Param {
+    pub name: &'static str,
+    /// The type as written.
+    pub ty: &'static str,
+    /// Size in bytes, in the `FfiSafe` layout.
+    pub size: usize,
+}
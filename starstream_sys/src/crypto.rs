@@ -0,0 +1,79 @@
+//! Signatures made by other chains' wallets, so users can authorize
+//! Starstream actions with the keys they already have, such as a
+//! MetaMask-signed message during onboarding.
+//!
+//! ```ignore
+//! let hash = crypto::personal_sign_hash(b"Link my account");
+//! match crypto::eth_recover(&hash, &signature) {
+//!     Some(address) if address == expected => link(address),
+//!     _ => env::abort_with(BAD_SIGNATURE, "not signed by the account"),
+//! }
+//! ```
+//!
+//! The host recovers the public key, as Ethereum's `ecrecover` precompile
+//! does, and the address is derived from it in the guest.
+
+use crate::{hash::keccak, FfiSafe};
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_secp256k1_recover(
+        hash: *const [u8; 32],
+        signature: *const [u8; 65],
+        out: *mut [u8; 64],
+    ) -> bool;
+}
+
+/// An Ethereum account address: the last 20 bytes of the Keccak-256 of the
+/// account's public key.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(transparent)]
+pub struct EthAddress(pub [u8; 20]);
+
+unsafe impl FfiSafe for EthAddress {}
+
+impl EthAddress {
+    /// The address of the uncompressed secp256k1 public key `key`, its `x`
+    /// and `y` big-endian, without the `0x04` tag.
+    pub fn from_public_key(key: &[u8; 64]) -> Self {
+        let hash = keccak::keccak256(&[key]);
+        let mut address = [0; 20];
+        address.copy_from_slice(&hash[12..]);
+        EthAddress(address)
+    }
+}
+
+/// What `personal_sign` and `eth_sign` prepend to a message before hashing
+/// it, followed by the message's length in decimal, so that a signed
+/// message can't be a signed transaction.
+pub const PERSONAL_SIGN_PREFIX: &[u8] = b"\x19Ethereum Signed Message:\n";
+
+/// The hash a wallet signs for `personal_sign(message)`.
+pub fn personal_sign_hash(message: &[u8]) -> [u8; 32] {
+    let mut digits = [0; 20];
+    let mut start = digits.len();
+    let mut rest = message.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    keccak::keccak256(&[PERSONAL_SIGN_PREFIX, &digits[start..], message])
+}
+
+/// The address whose key made `signature` over `msg_hash`, like
+/// Ethereum's `ecrecover`, or `None` if the signature is malformed.
+///
+/// `signature` is `r`, `s`, and `v`, as wallets return it; `v` is 27 or 28,
+/// or the recovery id itself, 0 or 1. Any valid signature recovers some
+/// address, so compare it with the one expected.
+pub fn eth_recover(msg_hash: &[u8; 32], signature: &[u8; 65]) -> Option<EthAddress> {
+    let mut key = [0; 64];
+    match unsafe { starstream_secp256k1_recover(msg_hash, signature, &mut key) } {
+        true => Some(EthAddress::from_public_key(&key)),
+        false => None,
+    }
+}
@@ -0,0 +1,114 @@
+//! Effects: a UTXO raises a typed request that the running coordination
+//! script must handle, such as "give me 100 of token X", and gets a typed
+//! reply back.
+//!
+//! Handlers are found by effect name, innermost first, among those the
+//! coordination script has registered with [`handle!`](crate::handle). An
+//! effect with no handler traps the transaction.
+
+use core::mem::{size_of, MaybeUninit};
+
+use crate::FfiSafe;
+
+#[link(wasm_import_module = "starstream_utxo_env")]
+unsafe extern "C" {
+    unsafe fn starstream_raise(
+        effect: *const u8,
+        effect_len: usize,
+        payload: *const (),
+        payload_size: usize,
+        reply: *mut (),
+        reply_size: usize,
+    );
+}
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_handle(
+        effect: *const u8,
+        effect_len: usize,
+        handler: Option<extern "C" fn()>,
+    ) -> Option<extern "C" fn()>;
+    unsafe fn starstream_effect_payload(payload: *mut (), payload_size: usize);
+    unsafe fn starstream_effect_reply(reply: *const (), reply_size: usize);
+}
+
+/// Raise `effect` from a UTXO and wait for the handler's reply. Usually
+/// written with [`raise!`](crate::raise).
+pub fn raise<Payload: FfiSafe, Reply: FfiSafe>(effect: &str, payload: &Payload) -> Reply {
+    let mut reply = MaybeUninit::<Reply>::uninit();
+    unsafe {
+        starstream_raise(
+            effect.as_ptr(),
+            effect.len(),
+            payload as *const Payload as *const (),
+            size_of::<Payload>(),
+            reply.as_mut_ptr() as *mut (),
+            size_of::<Reply>(),
+        );
+        // SAFETY TODO: as in `sleep`, relies on the UTXO and the handler
+        // agreeing on Reply.
+        reply.assume_init()
+    }
+}
+
+/// Run `scope` with `handler` registered for `effect`, restoring whatever
+/// handler was registered before afterwards. Usually written with
+/// [`handle!`](crate::handle), which generates `handler`.
+pub fn with_handler<R>(effect: &str, handler: extern "C" fn(), scope: impl FnOnce() -> R) -> R {
+    let old = unsafe { starstream_handle(effect.as_ptr(), effect.len(), Some(handler)) };
+    let r = scope();
+    unsafe { starstream_handle(effect.as_ptr(), effect.len(), old) };
+    r
+}
+
+/// Inside a handler, read the payload of the effect being handled.
+pub fn payload<Payload: FfiSafe>() -> Payload {
+    let mut payload = MaybeUninit::<Payload>::uninit();
+    unsafe {
+        starstream_effect_payload(payload.as_mut_ptr() as *mut (), size_of::<Payload>());
+        payload.assume_init()
+    }
+}
+
+/// Inside a handler, set the reply to the effect being handled.
+pub fn reply<Reply: FfiSafe>(reply: &Reply) {
+    unsafe { starstream_effect_reply(reply as *const Reply as *const (), size_of::<Reply>()) }
+}
+
+/// Raise an effect from a UTXO: `let coins: u64 = raise!(want_coins(&100u64));`
+#[macro_export]
+macro_rules! raise {
+    ($effect:ident($payload:expr)) => {
+        $crate::effect::raise(stringify!($effect), $payload)
+    };
+}
+
+/// Handle an effect raised by any UTXO while `scope` runs:
+///
+/// ```ignore
+/// let r = handle! {
+///     fn my_effect(supply: u32) -> () {
+///         starstream::log(100 + supply);
+///     }
+///     in {
+///         MyMain::new()
+///     }
+/// };
+/// ```
+///
+/// The handler body can't capture local variables.
+#[macro_export]
+macro_rules! handle {
+    (
+        fn $effect:ident($arg:ident: $payload_ty:ty) -> $reply_ty:ty $body:block
+        in $scope:block
+    ) => {{
+        extern "C" fn $effect() {
+            fn body($arg: $payload_ty) -> $reply_ty $body
+            let reply = body($crate::effect::payload::<$payload_ty>());
+            $crate::effect::reply::<$reply_ty>(&reply);
+        }
+        $crate::effect::with_handler(stringify!($effect), $effect, || $scope)
+    }};
+}
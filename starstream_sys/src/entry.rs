@@ -0,0 +1,162 @@
+//! Handles scoped to one entry point call.
+//!
+//! Handles are only meaningful during the host call that produced them: the
+//! next call into the contract may find the number reused or dangling. An
+//! entry point that takes an `#[entry]` parameter receives an
+//! [`EntryToken`] whose lifetime ends with the call, and handles wrapped in
+//! [`Scoped`] borrow it, so the compiler refuses to stash them in a `static`
+//! for a later call.
+//!
+//! ```ignore
+//! coordination_export! {
+//!     fn settle(#[entry] entry: EntryToken, #[payload] id: UtxoId) {
+//!         let order = entry.lookup(id).and_then(|utxo| utxo.downcast::<Order>());
+//!         order.expect("no such order").resume(Fill::Now);
+//!     }
+//! }
+//! ```
+
+use core::marker::PhantomData;
+
+use crate::{
+    utxo::{self, AnyUtxo},
+    CodeHash, HostError, PublicKey, Token, Utxo, UtxoError, UtxoId, UtxoStatus,
+};
+
+/// Proof of being inside an entry point call, valid for `'entry`.
+///
+/// The lifetime is invariant and chosen by the generated entry point, so it
+/// can't be stretched to `'static`. Not `Send`, like the handles it scopes.
+#[derive(Clone, Copy)]
+pub struct EntryToken<'entry> {
+    _scope: PhantomData<*mut &'entry ()>,
+}
+
+impl<'entry> EntryToken<'entry> {
+    /// # Safety
+    ///
+    /// Only the code generated for an entry point creates a token, once per
+    /// call, and lets it go when the call returns.
+    #[doc(hidden)]
+    #[inline]
+    pub unsafe fn new() -> EntryToken<'entry> {
+        EntryToken {
+            _scope: PhantomData,
+        }
+    }
+
+    /// Tie `handle` to this call.
+    #[inline]
+    pub fn scope<H: Copy>(self, handle: H) -> Scoped<'entry, H> {
+        Scoped {
+            handle,
+            _entry: self,
+        }
+    }
+
+    /// [`utxo::lookup`], scoped to this call.
+    pub fn lookup(self, id: UtxoId) -> Option<Scoped<'entry, AnyUtxo>> {
+        utxo::lookup(id).map(|utxo| self.scope(utxo))
+    }
+
+    /// [`utxo::find`], scoped to this call.
+    pub fn find(
+        self,
+        code: Option<CodeHash>,
+        owner: Option<PublicKey>,
+    ) -> impl Iterator<Item = Scoped<'entry, AnyUtxo>> {
+        utxo::find(code, owner).map(move |utxo| self.scope(utxo))
+    }
+}
+
+/// A handle that can only be used during the entry point call it was
+/// obtained in.
+#[derive(Clone, Copy)]
+pub struct Scoped<'entry, H> {
+    handle: H,
+    _entry: EntryToken<'entry>,
+}
+
+impl<'entry, H> Scoped<'entry, H> {
+    /// The bare handle, which the compiler no longer ties to this call.
+    ///
+    /// # Safety
+    ///
+    /// The handle must not be used after the entry point call returns.
+    #[inline]
+    pub unsafe fn into_unscoped(self) -> H {
+        self.handle
+    }
+}
+
+impl<'entry> Scoped<'entry, AnyUtxo> {
+    #[inline]
+    pub fn id(&self) -> UtxoId {
+        self.handle.id()
+    }
+
+    /// Whether this is a UTXO of type `T`.
+    #[inline]
+    pub fn is<T: Utxo>(&self) -> bool {
+        self.handle.is::<T>()
+    }
+
+    pub fn downcast<T: Utxo + Copy>(self) -> Option<Scoped<'entry, T>> {
+        (self.handle.downcast::<T>()).map(|handle| self._entry.scope(handle))
+    }
+}
+
+impl<'entry, T: Utxo + Copy> Scoped<'entry, T> {
+    #[inline]
+    pub fn status(&self) -> UtxoStatus {
+        self.handle.status()
+    }
+
+    #[inline]
+    pub fn id(&self) -> UtxoId {
+        self.handle.id()
+    }
+
+    #[inline]
+    pub fn can_resume(&self) -> bool {
+        self.handle.can_resume()
+    }
+
+    #[inline]
+    #[track_caller]
+    pub fn resume(&self, arg: T::Resume) -> T::Yield {
+        self.handle.resume(arg)
+    }
+
+    #[inline]
+    pub fn try_resume(&self, arg: T::Resume) -> Result<T::Yield, UtxoError> {
+        self.handle.try_resume(arg)
+    }
+
+    #[inline]
+    pub fn last_error(&self) -> Option<UtxoError> {
+        self.handle.last_error()
+    }
+
+    #[inline]
+    pub fn attach<K: Token>(&self, token: K) {
+        self.handle.attach(token)
+    }
+
+    #[inline]
+    pub fn detach<K: Token + Copy>(&self, amount: u64) -> Scoped<'entry, K> {
+        self._entry.scope(self.handle.detach::<K>(amount))
+    }
+}
+
+impl<'entry, T: Token + Copy> Scoped<'entry, T> {
+    #[inline]
+    pub fn burn(self) -> T::Intermediate {
+        self.handle.burn()
+    }
+
+    #[inline]
+    pub fn try_burn(self) -> Result<T::Intermediate, HostError> {
+        self.handle.try_burn()
+    }
+}
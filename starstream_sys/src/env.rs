@@ -0,0 +1,182 @@
+//! What the running contract can learn about its environment: the network
+//! and current block, who is driving it, and how it was started.
+
+#[cfg(not(all(feature = "min-size", not(any(feature = "mock-host", miri)))))]
+use core::fmt;
+use core::mem::{size_of, MaybeUninit};
+
+use crate::{CodeHash, FfiSafe, HostCache};
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    safe fn starstream_is_coordination() -> bool;
+    safe fn starstream_is_simulation() -> bool;
+    safe fn starstream_caller_code() -> CodeHash;
+    safe fn starstream_block_height() -> u64;
+    safe fn starstream_block_time() -> u64;
+    safe fn starstream_chain_id() -> u64;
+    safe fn starstream_host_abi_version() -> u32;
+    #[cfg(not(any(feature = "mock-host", miri)))]
+    unsafe fn starstream_abort(code: u32, msg: *const u8, msg_len: usize) -> !;
+}
+
+/// The [`abort_with`] code the panic handler uses, so hosts and off-chain
+/// tools can tell an internal panic from a rejection the contract chose.
+/// Application codes should be nonzero.
+pub const PANIC_CODE: u32 = 0;
+
+/// The [`abort_with`] code of an entry point that found the host implements
+/// a different [`ABI_VERSION`](crate::ABI_VERSION) than the contract was
+/// built against. Application codes shouldn't use it.
+pub const ABI_MISMATCH_CODE: u32 = u32::MAX;
+
+/// Stop the contract, failing the transaction, with an application-defined
+/// `code` and a message for whoever reads the failure, such as
+/// `abort_with(42, "listing expired")`. Hosts report the code separately
+/// from the message, so tools can match on it rather than on text.
+#[cold]
+pub fn abort_with(code: u32, msg: &str) -> ! {
+    #[cfg(any(feature = "mock-host", miri))]
+    crate::mock::__abort_with(code, msg);
+    #[cfg(not(any(feature = "mock-host", miri)))]
+    unsafe {
+        starstream_abort(code, msg.as_ptr(), msg.len())
+    }
+}
+
+/// Trap with a formatted message. Unlike `panic!`, which only passes literal
+/// messages on to the host, this formats on wasm too, cutting the message to
+/// fit a small buffer. Called through `trap!`, which skips it with
+/// `min-size`.
+#[cfg(not(all(feature = "min-size", not(any(feature = "mock-host", miri)))))]
+#[cold]
+pub(crate) fn trap(args: fmt::Arguments) -> ! {
+    #[cfg(any(feature = "mock-host", miri))]
+    panic!("{args}");
+    #[cfg(not(any(feature = "mock-host", miri)))]
+    {
+        if let Some(message) = args.as_str() {
+            abort_with(PANIC_CODE, message);
+        }
+        let mut message = TrapMessage {
+            bytes: [0; 256],
+            len: 0,
+        };
+        let _ = fmt::write(&mut message, args);
+        // Only whole characters are copied in.
+        abort_with(PANIC_CODE, unsafe {
+            core::str::from_utf8_unchecked(&message.bytes[..message.len])
+        })
+    }
+}
+
+#[cfg(not(any(feature = "mock-host", miri, feature = "min-size")))]
+struct TrapMessage {
+    bytes: [u8; 256],
+    len: usize,
+}
+
+#[cfg(not(any(feature = "mock-host", miri, feature = "min-size")))]
+impl fmt::Write for TrapMessage {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut n = s.len().min(self.bytes.len() - self.len);
+        while !s.is_char_boundary(n) {
+            n -= 1;
+        }
+        self.bytes[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Identifies the network this is running on. Include it in anything signed
+/// off-chain, so that a signature made for one network can't be replayed on
+/// another running the same contract code.
+pub fn chain_id() -> u64 {
+    starstream_chain_id()
+}
+
+/// The [`ABI_VERSION`](crate::ABI_VERSION) the host implements.
+pub fn host_abi_version() -> u32 {
+    starstream_host_abi_version()
+}
+
+/// The height of the block this transaction is in. Deadlines such as
+/// `sleep_until`'s are measured in block heights.
+pub fn block_height() -> u64 {
+    starstream_block_height()
+}
+
+/// The timestamp of the block this transaction is in, in Unix seconds.
+///
+/// This is chosen by the block producer, not read from a clock, so every
+/// call within a transaction sees the same value and replays agree. It
+/// never decreases from one block to the next, but may stay the same, and
+/// is only as accurate as the producer is honest; prefer [`block_height`]
+/// where exact ordering matters.
+pub fn block_time() -> u64 {
+    starstream_block_time()
+}
+
+/// Whether this code is running as a coordination script, rather than as a
+/// UTXO or token.
+pub fn is_coordination_context() -> bool {
+    static IS_COORDINATION: HostCache<bool> = HostCache::new();
+    IS_COORDINATION.get_or(|| starstream_is_coordination())
+}
+
+/// Whether this is a dry run, such as a wallet estimating a transaction's
+/// result before submitting it. Results should still be computed exactly,
+/// but side effects outside the transaction, like notifying an external
+/// service, can be skipped.
+pub fn is_simulation() -> bool {
+    starstream_is_simulation()
+}
+
+/// The code hash of the coordination script driving this execution. For
+/// nested `coordination::call`s, this is the innermost script.
+pub fn caller_code() -> CodeHash {
+    starstream_caller_code()
+}
+
+/// Trap unless the coordination script driving this execution has hash
+/// `code`, so a UTXO or token can restrict who may mint, resume, or burn it.
+pub fn assert_called_by(code: CodeHash) {
+    if caller_code() != code {
+        trap!("called by an unexpected coordination script");
+    }
+}
+
+#[link(wasm_import_module = "starstream_utxo_env")]
+unsafe extern "C" {
+    unsafe fn starstream_init_args(args: *mut (), args_size: usize);
+    safe fn starstream_call_count() -> u32;
+}
+
+/// Read the argument struct this UTXO was spawned with through a
+/// `utxo_spawn!` import. The host traps if the UTXO was started some other
+/// way, or with arguments that aren't the size of a `T`.
+pub fn init_args<T: FfiSafe>() -> T {
+    let mut args = MaybeUninit::<T>::uninit();
+    unsafe {
+        starstream_init_args(args.as_mut_ptr() as *mut (), size_of::<T>());
+        args.assume_init()
+    }
+}
+
+/// How many times this UTXO has been entered in the current transaction,
+/// including this time: starts, resumes, expiries, queries, mutations, and
+/// consumption all count. Work undone by `tx::atomic` still counts.
+pub fn call_count() -> u32 {
+    starstream_call_count()
+}
+
+/// Trap if this UTXO has been entered more than `calls` times in the current
+/// transaction, so a script can't burn the owner's execution budget by
+/// resuming it in a loop. Call it on every path that may be resumed
+/// repeatedly, typically right after `sleep`.
+pub fn assert_budget(calls: u32) {
+    if call_count() > calls {
+        trap!("UTXO call budget exceeded");
+    }
+}
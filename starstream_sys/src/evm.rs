@@ -0,0 +1,85 @@
+//! Events laid out as Ethereum logs, so EVM indexing infrastructure can
+//! ingest them during a migration. Enabled by the `evm-events` feature.
+//!
+//! A log's first topic is the Keccak-256 of the event's Solidity signature,
+//! followed by up to three indexed parameters; the rest of the parameters are
+//! ABI-encoded, one 32-byte word each, as its data:
+//!
+//! ```ignore
+//! // event Transfer(address indexed from, address indexed to, uint256 value);
+//! evm::emit_log(
+//!     "Transfer(address,address,uint256)",
+//!     [evm::address(from), evm::address(to)],
+//!     [evm::uint(amount as u128)],
+//! );
+//! ```
+//!
+//! Each log is an ordinary event under [`LOG_TOPIC`], whose payload is the
+//! number of topics as one byte, then the topics, then the data. Only static
+//! ABI types fit in a word; index a string or byte array by its [`hashed`]
+//! value, as Solidity does.
+
+use crate::{hash, tx};
+
+/// The event topic every log is emitted under.
+pub const LOG_TOPIC: &str = "evm.log";
+
+/// An ABI-encoded word: a topic, or one parameter of the data.
+pub type Word = [u8; 32];
+
+/// The first topic of logs of the event with `signature`, such as
+/// `Transfer(address,address,uint256)`.
+pub fn topic(signature: &str) -> Word {
+    hash::keccak256(signature.as_bytes())
+}
+
+/// A `uint<N>`, big-endian and zero-padded.
+pub fn uint(value: u128) -> Word {
+    let mut word = [0; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// An `int<N>`, big-endian and sign-extended.
+pub fn int(value: i128) -> Word {
+    let mut word = [if value < 0 { 0xff } else { 0 }; 32];
+    word[16..].copy_from_slice(&value.to_be_bytes());
+    word
+}
+
+/// A `bool`.
+pub fn bool(value: bool) -> Word {
+    uint(value as u128)
+}
+
+/// An `address`, right-aligned.
+pub fn address(value: [u8; 20]) -> Word {
+    let mut word = [0; 32];
+    word[12..].copy_from_slice(&value);
+    word
+}
+
+/// An indexed `string` or `bytes`, which a topic holds as its Keccak-256.
+pub fn hashed(value: &[u8]) -> Word {
+    hash::keccak256(value)
+}
+
+/// Emit a log of the event with `signature`, with up to three `indexed`
+/// topics after the signature's, and `data` words.
+pub fn emit_log<const T: usize, const D: usize>(
+    signature: &str,
+    indexed: [Word; T],
+    data: [Word; D],
+) {
+    const { assert!(T <= 3, "an EVM log has at most three indexed topics") };
+    let topic = topic(signature);
+    tx::emit_event_parts(
+        LOG_TOPIC,
+        [
+            &[1 + T as u8],
+            &topic,
+            indexed.as_flattened(),
+            data.as_flattened(),
+        ],
+    );
+}
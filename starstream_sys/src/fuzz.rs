@@ -0,0 +1,256 @@
+//! Harnesses for fuzzing contract exports with `cargo fuzz`, which builds
+//! with `--cfg fuzzing`. The fuzz crate depends on the contract with the
+//! `mock-host` feature, so the exports run against [`mock`] natively.
+//!
+//! `#[starstream::utxo]` generates a bytes-in `fuzz_<export>` function for
+//! each export, and [`mint`] and [`burn`] wrap the functions generated by
+//! `token_export!`. A fuzz target calls one of them:
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     starstream::fuzz::mint(data, starstream_mint_StarNft).check();
+//! });
+//! ```
+//!
+//! Inputs are split into the export's parameters in order, each taking the
+//! bytes of its [`FfiSafe`] layout. A contract rejecting an input by
+//! trapping is expected, so harnesses report it as
+//! [`Outcome::Rejected`] rather than crashing; [`Outcome::check`] crashes
+//! only when the trap isn't one the contract raised deliberately. Use
+//! [`reproduce`] to triage a crash file afterwards.
+
+extern crate std;
+
+use core::{
+    fmt,
+    mem::{size_of, MaybeUninit},
+};
+use std::{
+    format,
+    path::Path,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::{
+    hash::bytes_of,
+    mock::{self, HostCall, MockStatus},
+    testkit::{catch_abort, Aborted},
+    FfiSafe, TokenStorage,
+};
+
+/// Fuzzer bytes being decoded into parameters.
+pub struct Input<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    pub fn new(data: &'a [u8]) -> Input<'a> {
+        Input { data }
+    }
+
+    /// Decode the next `T` from its raw bytes, or `None` if too few are left.
+    pub fn take<T: FfiSafe>(&mut self) -> Option<T> {
+        if self.data.len() < size_of::<T>() {
+            return None;
+        }
+        let (head, rest) = self.data.split_at(size_of::<T>());
+        self.data = rest;
+        let mut value = MaybeUninit::<T>::uninit();
+        // Every bit pattern is a valid FfiSafe value.
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                head.as_ptr(),
+                value.as_mut_ptr() as *mut u8,
+                head.len(),
+            );
+            Some(value.assume_init())
+        }
+    }
+
+    /// The bytes not yet decoded.
+    pub fn rest(&self) -> &'a [u8] {
+        self.data
+    }
+}
+
+/// How a harness run ended.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Accepted,
+    /// The contract trapped; a normal way to refuse an input.
+    Rejected(Aborted),
+    /// The input was too short for the export's parameters.
+    TooShort,
+}
+
+impl Outcome {
+    /// Panic, crashing the fuzzer, if the contract trapped in a way it
+    /// didn't mean to: arithmetic overflow, an out-of-bounds index, or an
+    /// `unwrap` on `None` or `Err`. Failed `assert!`s, host errors, and
+    /// `env::abort_with` are the contract refusing the input.
+    #[track_caller]
+    pub fn check(&self) {
+        if let Outcome::Rejected(aborted) = self {
+            if aborted.is_panic() && is_bug(&aborted.message) {
+                panic!("contract bug: {}", aborted.message);
+            }
+        }
+    }
+}
+
+fn is_bug(message: &str) -> bool {
+    const BUGS: &[&str] = &[
+        "attempt to add with overflow",
+        "attempt to subtract with overflow",
+        "attempt to multiply with overflow",
+        "attempt to divide by zero",
+        "attempt to calculate the remainder with a divisor of zero",
+        "attempt to negate with overflow",
+        "attempt to shift",
+        "index out of bounds",
+        "called `Option::unwrap()` on a `None` value",
+        "called `Result::unwrap()` on an `Err` value",
+        "unreachable",
+    ];
+    BUGS.iter().any(|bug| message.contains(bug))
+}
+
+/// Run `f` on `data` against a fresh mock host, catching traps.
+/// `f` returns `None` when the input is too short.
+pub fn run(data: &[u8], f: impl FnOnce(&mut Input) -> Option<()>) -> Outcome {
+    mock::reset();
+    let mut input = Input::new(data);
+    match catch_abort(|| f(&mut input)) {
+        Ok(Some(())) => Outcome::Accepted,
+        Ok(None) => Outcome::TooShort,
+        Err(aborted) => Outcome::Rejected(aborted),
+    }
+}
+
+/// Fuzz a mint export generated by `token_export!` with intermediates
+/// decoded from `data`.
+pub fn mint<I: FfiSafe>(data: &[u8], mint: extern "C-unwind" fn(I) -> TokenStorage) -> Outcome {
+    run(data, |input| {
+        mint(input.take()?);
+        Some(())
+    })
+}
+
+/// Fuzz a burn export generated by `token_export!` with storage decoded
+/// from `data`.
+pub fn burn<I: FfiSafe>(data: &[u8], burn: extern "C-unwind" fn(TokenStorage) -> I) -> Outcome {
+    run(data, |input| {
+        burn(input.take()?);
+        Some(())
+    })
+}
+
+/// Fuzz a UTXO's lifecycle: `body` decodes the constructor's parameters
+/// from the first `params_size` bytes and runs it, then each following
+/// chunk of `size_of::<Resume>()` bytes resumes it, until the bytes run out
+/// or the UTXO ends. A tag byte precedes each chunk, so a zero-sized
+/// `Resume` still takes input. `fuzz_starstream_new_*` functions generated
+/// by `#[starstream::utxo]` call this.
+pub fn resume<Yield: FfiSafe, Resume: FfiSafe>(
+    data: &[u8],
+    params_size: usize,
+    body: fn(&mut Input) -> Option<()>,
+) -> Outcome {
+    if data.len() < params_size {
+        return Outcome::TooShort;
+    }
+    mock::reset();
+    let (params, resumes) = data.split_at(params_size);
+    let params = params.to_vec();
+    let mut utxo = mock::spawn::<Yield, Resume>(move || {
+        body(&mut Input::new(&params));
+    });
+    let mut resumes = Input::new(resumes);
+    while utxo.is_alive() && resumes.take::<u8>().is_some() {
+        let Some(arg) = resumes.take::<Resume>() else {
+            break;
+        };
+        utxo.resume(arg);
+    }
+    match utxo.status() {
+        MockStatus::Errored => Outcome::Rejected(Aborted::from_message(
+            utxo.error().unwrap_or("contract aborted").to_string(),
+        )),
+        _ => Outcome::Accepted,
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Triage
+
+/// A crash input re-run under a harness, with what the contract did.
+pub struct Report {
+    pub input: Vec<u8>,
+    pub outcome: Outcome,
+    /// The host calls made before the run ended.
+    pub trace: Vec<HostCall>,
+}
+
+/// Re-run `harness` on `data` and record what happened.
+pub fn triage(data: &[u8], harness: impl FnOnce(&[u8]) -> Outcome) -> Report {
+    let outcome = harness(data);
+    Report {
+        input: data.to_vec(),
+        outcome,
+        trace: mock::with(|host| host.trace.clone()),
+    }
+}
+
+/// [`triage`] a crash file written by `cargo fuzz`, such as
+/// `fuzz/artifacts/mint/crash-<hash>`.
+pub fn reproduce(path: impl AsRef<Path>, harness: impl FnOnce(&[u8]) -> Outcome) -> Report {
+    let path = path.as_ref();
+    let data = std::fs::read(path).unwrap_or_else(|e| panic!("reading {}: {e}", path.display()));
+    triage(&data, harness)
+}
+
+impl Report {
+    /// A key for grouping crashes with the same cause: the trap message,
+    /// followed by the last host call made before it.
+    pub fn signature(&self) -> String {
+        let cause = match &self.outcome {
+            Outcome::Rejected(aborted) => aborted.message.clone(),
+            outcome => format!("{outcome:?}"),
+        };
+        match self.trace.last() {
+            Some(HostCall::Log(_) | HostCall::LogLine(_)) => format!("{cause} after log"),
+            Some(HostCall::Yield { state, .. }) => format!("{cause} after yield {state}"),
+            Some(HostCall::Mint { token, .. }) => format!("{cause} after mint {token}"),
+            Some(HostCall::Burn { token, .. }) => format!("{cause} after burn {token}"),
+            Some(HostCall::Event { topic, .. }) => format!("{cause} after event {topic}"),
+            Some(HostCall::Raise { effect, .. }) => format!("{cause} after raise {effect}"),
+            Some(HostCall::Call { entry, .. }) => format!("{cause} after call {entry}"),
+            None => cause,
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "input ({} bytes):", self.input.len())?;
+        for (i, line) in self.input.chunks(16).enumerate() {
+            write!(f, "  {:04x}:", i * 16)?;
+            for byte in line {
+                write!(f, " {byte:02x}")?;
+            }
+            writeln!(f)?;
+        }
+        writeln!(f, "outcome: {:?}", self.outcome)?;
+        writeln!(f, "host calls:")?;
+        for call in &self.trace {
+            writeln!(f, "  {call:?}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The raw bytes of `value`, for writing seed corpus files.
+pub fn corpus_entry<T: FfiSafe>(value: &T) -> Vec<u8> {
+    bytes_of(value).to_vec()
+}
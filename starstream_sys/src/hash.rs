@@ -0,0 +1,108 @@
+//! Hash functions computed by the host, or in the guest with the
+//! `software-hash` feature, for hosts without hash imports.
+
+use core::mem::size_of;
+
+use crate::FfiSafe;
+
+pub(crate) mod keccak;
+#[cfg(any(feature = "software-hash", feature = "mock-host", miri))]
+pub(crate) mod soft;
+
+/// One piece of a message to hash or emit, laid out as the host reads it.
+#[repr(C)]
+pub(crate) struct Part {
+    pub(crate) ptr: *const u8,
+    pub(crate) len: usize,
+}
+
+#[cfg(not(feature = "software-hash"))]
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_sha256(parts: *const Part, count: usize, out: *mut [u8; 32]);
+    unsafe fn starstream_blake2b_256(parts: *const Part, count: usize, out: *mut [u8; 32]);
+}
+
+/// SHA-256 of `data`.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    sha256_parts([data])
+}
+
+/// SHA-256 of the concatenation of `parts`, without copying them into one
+/// buffer first.
+pub fn sha256_parts<const N: usize>(parts: [&[u8]; N]) -> [u8; 32] {
+    #[cfg(feature = "software-hash")]
+    return soft::sha256(&parts);
+    #[cfg(not(feature = "software-hash"))]
+    {
+        let parts = parts.map(|part| Part {
+            ptr: part.as_ptr(),
+            len: part.len(),
+        });
+        let mut out = [0; 32];
+        unsafe { starstream_sha256(parts.as_ptr(), N, &mut out) };
+        out
+    }
+}
+
+/// SHA-256 of four messages of the same length, such as four sibling pairs
+/// from one level of a Merkle tree.
+///
+/// With the `simd` feature, on a build with the `simd128` target feature,
+/// the four are hashed side by side in wasm SIMD lanes for about the cost of
+/// one; otherwise this is four [`sha256`]s.
+pub fn sha256_x4(messages: [&[u8]; 4]) -> [[u8; 32]; 4] {
+    let len = messages[0].len();
+    if messages.iter().any(|message| message.len() != len) {
+        trap!("sha256_x4 messages differ in length");
+    }
+    #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+    return soft::sha256_x4(messages);
+    #[cfg(not(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128")))]
+    messages.map(sha256)
+}
+
+/// BLAKE2b with a 32-byte digest, unkeyed, of `data`, as Cardano hashes
+/// transactions and datums.
+pub fn blake2b_256(data: &[u8]) -> [u8; 32] {
+    blake2b_256_parts([data])
+}
+
+/// BLAKE2b-256 of the concatenation of `parts`, like [`sha256_parts`].
+pub fn blake2b_256_parts<const N: usize>(parts: [&[u8]; N]) -> [u8; 32] {
+    #[cfg(feature = "software-hash")]
+    return soft::blake2b_256(&parts);
+    #[cfg(not(feature = "software-hash"))]
+    {
+        let parts = parts.map(|part| Part {
+            ptr: part.as_ptr(),
+            len: part.len(),
+        });
+        let mut out = [0; 32];
+        unsafe { starstream_blake2b_256(parts.as_ptr(), N, &mut out) };
+        out
+    }
+}
+
+/// BLAKE2b-256 of two messages of the same length, side by side in wasm
+/// SIMD lanes like [`sha256_x4`]; BLAKE2b's 64-bit words fit two to a lane.
+pub fn blake2b_256_x2(messages: [&[u8]; 2]) -> [[u8; 32]; 2] {
+    if messages[0].len() != messages[1].len() {
+        trap!("blake2b_256_x2 messages differ in length");
+    }
+    #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+    return soft::blake2b_256_x2(messages);
+    #[cfg(not(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128")))]
+    messages.map(blake2b_256)
+}
+
+/// Keccak-256 of `data`, as Ethereum uses it. Always computed in the guest.
+pub fn keccak256(data: &[u8]) -> [u8; 32] {
+    keccak::keccak256(&[data])
+}
+
+/// The raw bytes of `value`, as hashed by helpers that commit to structs.
+pub fn bytes_of<T: FfiSafe>(value: &T) -> &[u8] {
+    // FfiSafe types have no padding, so every byte is initialized.
+    unsafe { core::slice::from_raw_parts(value as *const T as *const u8, size_of::<T>()) }
+}
@@ -0,0 +1,173 @@
+//! Keccak-256 as Ethereum uses it: the original Keccak padding, not SHA-3's.
+//! No host provides it, so it's always computed in the guest.
+
+const ROUND_CONSTANTS: [u64; 24] = [
+    0x0000000000000001,
+    0x0000000000008082,
+    0x800000000000808a,
+    0x8000000080008000,
+    0x000000000000808b,
+    0x0000000080000001,
+    0x8000000080008081,
+    0x8000000000008009,
+    0x000000000000008a,
+    0x0000000000000088,
+    0x0000000080008009,
+    0x000000008000000a,
+    0x000000008000808b,
+    0x800000000000008b,
+    0x8000000000008089,
+    0x8000000000008003,
+    0x8000000000008002,
+    0x8000000000000080,
+    0x000000000000800a,
+    0x800000008000000a,
+    0x8000000080008081,
+    0x8000000000008080,
+    0x0000000080000001,
+    0x8000000080008008,
+];
+
+/// Rotation of each lane visited by the combined rho and pi steps.
+const ROTATIONS: [u32; 24] = [
+    1, 3, 6, 10, 15, 21, 28, 36, 45, 55, 2, 14, 27, 41, 56, 8, 25, 43, 62, 18, 39, 61, 20, 44,
+];
+
+/// The order the combined rho and pi steps visit lanes in, from lane 1.
+const PI: [usize; 24] = [
+    10, 7, 11, 17, 18, 3, 5, 16, 8, 21, 24, 4, 15, 23, 19, 13, 12, 2, 20, 14, 22, 9, 6, 1,
+];
+
+/// Bytes absorbed per permutation for a 256-bit output.
+const RATE: usize = 136;
+
+fn keccak_f(state: &mut [u64; 25]) {
+    for round_constant in ROUND_CONSTANTS {
+        // Theta
+        let mut columns = [0; 5];
+        for (x, column) in columns.iter_mut().enumerate() {
+            *column = state[x] ^ state[x + 5] ^ state[x + 10] ^ state[x + 15] ^ state[x + 20];
+        }
+        for x in 0..5 {
+            let d = columns[(x + 4) % 5] ^ columns[(x + 1) % 5].rotate_left(1);
+            for y in (0..25).step_by(5) {
+                state[y + x] ^= d;
+            }
+        }
+        // Rho and pi
+        let mut carried = state[1];
+        for (&lane, &rotation) in PI.iter().zip(&ROTATIONS) {
+            let next = state[lane];
+            state[lane] = carried.rotate_left(rotation);
+            carried = next;
+        }
+        // Chi
+        for y in (0..25).step_by(5) {
+            let row: [u64; 5] = state[y..y + 5].try_into().unwrap();
+            for x in 0..5 {
+                state[y + x] = row[x] ^ (!row[(x + 1) % 5] & row[(x + 2) % 5]);
+            }
+        }
+        // Iota
+        state[0] ^= round_constant;
+    }
+}
+
+fn absorb(state: &mut [u64; 25], block: &[u8; RATE]) {
+    for (lane, bytes) in state.iter_mut().zip(block.chunks_exact(8)) {
+        *lane ^= u64::from_le_bytes(bytes.try_into().unwrap());
+    }
+    keccak_f(state);
+}
+
+/// Keccak-256 of the concatenation of `parts`.
+pub(crate) fn keccak256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut state = [0; 25];
+    let mut block = [0; RATE];
+    let mut filled = 0;
+    for part in parts {
+        let mut part = *part;
+        while !part.is_empty() {
+            let n = part.len().min(RATE - filled);
+            block[filled..filled + n].copy_from_slice(&part[..n]);
+            filled += n;
+            part = &part[n..];
+            if filled == RATE {
+                absorb(&mut state, &block);
+                filled = 0;
+            }
+        }
+    }
+    block[filled..].fill(0);
+    block[filled] = 0x01;
+    block[RATE - 1] |= 0x80;
+    absorb(&mut state, &block);
+
+    let mut out = [0; 32];
+    for (chunk, lane) in out.chunks_exact_mut(8).zip(state) {
+        chunk.copy_from_slice(&lane.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: &str) -> [u8; 32] {
+        let mut out = [0; 32];
+        for (byte, i) in out.iter_mut().zip((0..64).step_by(2)) {
+            *byte = u8::from_str_radix(&digest[i..i + 2], 16).unwrap();
+        }
+        out
+    }
+
+    // The Keccak team's known answers for the original padding, which
+    // Ethereum uses, rather than FIPS 202's SHA3-256.
+    #[test]
+    fn keccak256_matches_known_answers() {
+        let vectors: [(&[u8], &str); 3] = [
+            (
+                b"",
+                "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+            ),
+            (
+                b"abc",
+                "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45",
+            ),
+            (
+                &[0xa3; 200],
+                "3a57666b048777f2c953dc4456f45a2588e1cb6f2da760122d530ac2ce607d4a",
+            ),
+        ];
+        for (message, digest) in vectors {
+            assert_eq!(keccak256(&[message]), hex(digest));
+        }
+    }
+
+    #[test]
+    fn keccak256_around_the_rate() {
+        // One byte short of a full block, so the padding fits in it, and a
+        // full block, so the padding takes another.
+        assert_eq!(
+            keccak256(&[&[b'a'; RATE - 1]]),
+            hex("34367dc248bbd832f4e3e69dfaac2f92638bd0bbd18f2912ba4ef454919cf446"),
+        );
+        assert_eq!(
+            keccak256(&[&[b'a'; RATE]]),
+            hex("a6c4d403279fe3e0af03729caada8374b5ca54d8065329a3ebcaeb4b60aa386e"),
+        );
+    }
+
+    #[test]
+    fn keccak256_parts_hash_as_their_concatenation() {
+        let message = [0x3c; 280];
+        for len in [1, RATE - 1, RATE, RATE + 1, 2 * RATE, 280] {
+            let whole = keccak256(&[&message[..len]]);
+            for split in [0, 1, len / 2, len - 1, len] {
+                let (a, b) = message[..len].split_at(split);
+                assert_eq!(keccak256(&[a, b]), whole, "{len} split at {split}");
+            }
+        }
+    }
+}
@@ -0,0 +1,517 @@
+//! SHA-256 and BLAKE2b-256 computed in the guest, for hosts without hash
+//! imports and for the mock host.
+//!
+//! The compression functions are generic over [`Word`] and [`Word64`], so
+//! the same code hashes one message with `u32`s or `u64`s or, with the
+//! `simd` feature on a `simd128` build, four SHA-256 or two BLAKE2b messages
+//! at once with one `v128` lane each.
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const H: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// The 32-bit operations SHA-256 needs, on one message's words or on
+/// several messages' words side by side.
+trait Word: Copy {
+    fn splat(x: u32) -> Self;
+    fn add(self, other: Self) -> Self;
+    fn xor(self, other: Self) -> Self;
+    fn and(self, other: Self) -> Self;
+    /// `!self & other`
+    fn and_not(self, other: Self) -> Self;
+    fn rotr(self, n: u32) -> Self;
+    fn shr(self, n: u32) -> Self;
+}
+
+impl Word for u32 {
+    #[inline(always)]
+    fn splat(x: u32) -> Self {
+        x
+    }
+
+    #[inline(always)]
+    fn add(self, other: Self) -> Self {
+        self.wrapping_add(other)
+    }
+
+    #[inline(always)]
+    fn xor(self, other: Self) -> Self {
+        self ^ other
+    }
+
+    #[inline(always)]
+    fn and(self, other: Self) -> Self {
+        self & other
+    }
+
+    #[inline(always)]
+    fn and_not(self, other: Self) -> Self {
+        !self & other
+    }
+
+    #[inline(always)]
+    fn rotr(self, n: u32) -> Self {
+        self.rotate_right(n)
+    }
+
+    #[inline(always)]
+    fn shr(self, n: u32) -> Self {
+        self >> n
+    }
+}
+
+fn compress<W: Word>(h: &mut [W; 8], block: [W; 16]) {
+    let mut w = [W::splat(0); 64];
+    w[..16].copy_from_slice(&block);
+    for i in 16..64 {
+        let (x, y) = (w[i - 15], w[i - 2]);
+        let s0 = x.rotr(7).xor(x.rotr(18)).xor(x.shr(3));
+        let s1 = y.rotr(17).xor(y.rotr(19)).xor(y.shr(10));
+        w[i] = w[i - 16].add(s0).add(w[i - 7]).add(s1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = *h;
+    for i in 0..64 {
+        let s1 = e.rotr(6).xor(e.rotr(11)).xor(e.rotr(25));
+        let ch = e.and(f).xor(e.and_not(g));
+        let t1 = hh.add(s1).add(ch).add(W::splat(K[i])).add(w[i]);
+        let s0 = a.rotr(2).xor(a.rotr(13)).xor(a.rotr(22));
+        let maj = a.and(b).xor(a.and(c)).xor(b.and(c));
+        let t2 = s0.add(maj);
+        hh = g;
+        g = f;
+        f = e;
+        e = d.add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.add(t2);
+    }
+    for (state, value) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+        *state = state.add(value);
+    }
+}
+
+/// How many 64-byte blocks a message of `len` bytes pads to.
+fn block_count(len: usize) -> usize {
+    (len + 9).div_ceil(64)
+}
+
+/// Block `i` of the padded message made of `parts`, `len` bytes in all, as
+/// big-endian words.
+fn block(parts: &[&[u8]], len: usize, i: usize) -> [u32; 16] {
+    let start = i * 64;
+    let mut bytes = [0; 64];
+    let mut offset = 0;
+    for part in parts {
+        let from = start.max(offset);
+        let to = (start + 64).min(offset + part.len());
+        if from < to {
+            bytes[from - start..to - start].copy_from_slice(&part[from - offset..to - offset]);
+        }
+        offset += part.len();
+    }
+    if (start..start + 64).contains(&len) {
+        bytes[len - start] = 0x80;
+    }
+    if i + 1 == block_count(len) {
+        bytes[56..].copy_from_slice(&(len as u64 * 8).to_be_bytes());
+    }
+    let mut words = [0; 16];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    words
+}
+
+/// SHA-256 of the concatenation of `parts`.
+pub(crate) fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    let len = parts.iter().map(|part| part.len()).sum();
+    let mut h = H;
+    for i in 0..block_count(len) {
+        compress(&mut h, block(parts, len, i));
+    }
+    let mut out = [0; 32];
+    for (chunk, word) in out.chunks_exact_mut(4).zip(h) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+const BLAKE2B_IV: [u64; 8] = [
+    0x6a09e667f3bcc908,
+    0xbb67ae8584caa73b,
+    0x3c6ef372fe94f82b,
+    0xa54ff53a5f1d36f1,
+    0x510e527fade682d1,
+    0x9b05688c2b3e6c1f,
+    0x1f83d9abfb41bd6b,
+    0x5be0cd19137e2179,
+];
+
+/// The message word order of each BLAKE2b round; rounds 10 and 11 repeat
+/// the first two.
+const SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// The 64-bit operations BLAKE2b needs, like [`Word`].
+trait Word64: Copy {
+    fn splat(x: u64) -> Self;
+    fn add(self, other: Self) -> Self;
+    fn xor(self, other: Self) -> Self;
+    fn rotr(self, n: u32) -> Self;
+}
+
+impl Word64 for u64 {
+    #[inline(always)]
+    fn splat(x: u64) -> Self {
+        x
+    }
+
+    #[inline(always)]
+    fn add(self, other: Self) -> Self {
+        self.wrapping_add(other)
+    }
+
+    #[inline(always)]
+    fn xor(self, other: Self) -> Self {
+        self ^ other
+    }
+
+    #[inline(always)]
+    fn rotr(self, n: u32) -> Self {
+        self.rotate_right(n)
+    }
+}
+
+#[inline(always)]
+fn mix<W: Word64>(v: &mut [W; 16], [a, b, c, d]: [usize; 4], x: W, y: W) {
+    v[a] = v[a].add(v[b]).add(x);
+    v[d] = v[d].xor(v[a]).rotr(32);
+    v[c] = v[c].add(v[d]);
+    v[b] = v[b].xor(v[c]).rotr(24);
+    v[a] = v[a].add(v[b]).add(y);
+    v[d] = v[d].xor(v[a]).rotr(16);
+    v[c] = v[c].add(v[d]);
+    v[b] = v[b].xor(v[c]).rotr(63);
+}
+
+/// Compress `block` into `h`, with `bytes` hashed so far including it.
+fn blake2b_compress<W: Word64>(h: &mut [W; 8], block: [W; 16], bytes: u128, last: bool) {
+    let mut v = [W::splat(0); 16];
+    v[..8].copy_from_slice(h);
+    for (v, iv) in v[8..].iter_mut().zip(BLAKE2B_IV) {
+        *v = W::splat(iv);
+    }
+    v[12] = v[12].xor(W::splat(bytes as u64));
+    v[13] = v[13].xor(W::splat((bytes >> 64) as u64));
+    if last {
+        v[14] = v[14].xor(W::splat(u64::MAX));
+    }
+    for round in 0..12 {
+        let s = &SIGMA[round % 10];
+        mix(&mut v, [0, 4, 8, 12], block[s[0]], block[s[1]]);
+        mix(&mut v, [1, 5, 9, 13], block[s[2]], block[s[3]]);
+        mix(&mut v, [2, 6, 10, 14], block[s[4]], block[s[5]]);
+        mix(&mut v, [3, 7, 11, 15], block[s[6]], block[s[7]]);
+        mix(&mut v, [0, 5, 10, 15], block[s[8]], block[s[9]]);
+        mix(&mut v, [1, 6, 11, 12], block[s[10]], block[s[11]]);
+        mix(&mut v, [2, 7, 8, 13], block[s[12]], block[s[13]]);
+        mix(&mut v, [3, 4, 9, 14], block[s[14]], block[s[15]]);
+    }
+    for (i, state) in h.iter_mut().enumerate() {
+        *state = state.xor(v[i]).xor(v[i + 8]);
+    }
+}
+
+/// The state BLAKE2b starts from for an unkeyed 32-byte digest.
+fn blake2b_256_h() -> [u64; 8] {
+    let mut h = BLAKE2B_IV;
+    h[0] ^= 0x0101_0020;
+    h
+}
+
+/// How many 128-byte blocks a message of `len` bytes fills; an empty
+/// message is one block of zeros.
+fn blake2b_block_count(len: usize) -> usize {
+    len.div_ceil(128).max(1)
+}
+
+/// Block `i` of the message made of `parts`, zero-padded, as little-endian
+/// words.
+fn blake2b_block(parts: &[&[u8]], i: usize) -> [u64; 16] {
+    let start = i * 128;
+    let mut bytes = [0; 128];
+    let mut offset = 0;
+    for part in parts {
+        let from = start.max(offset);
+        let to = (start + 128).min(offset + part.len());
+        if from < to {
+            bytes[from - start..to - start].copy_from_slice(&part[from - offset..to - offset]);
+        }
+        offset += part.len();
+    }
+    let mut words = [0; 16];
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(8)) {
+        *word = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    words
+}
+
+/// Bytes hashed once block `i` of a `len`-byte message is compressed.
+fn blake2b_counter(len: usize, i: usize) -> u128 {
+    len.min((i + 1) * 128) as u128
+}
+
+/// BLAKE2b-256 of the concatenation of `parts`.
+pub(crate) fn blake2b_256(parts: &[&[u8]]) -> [u8; 32] {
+    let len = parts.iter().map(|part| part.len()).sum();
+    let count = blake2b_block_count(len);
+    let mut h = blake2b_256_h();
+    for i in 0..count {
+        let block = blake2b_block(parts, i);
+        blake2b_compress(&mut h, block, blake2b_counter(len, i), i + 1 == count);
+    }
+    let mut out = [0; 32];
+    for (chunk, word) in out.chunks_exact_mut(8).zip(h) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    out
+}
+
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+mod simd {
+    use core::arch::wasm32::*;
+
+    use super::{
+        blake2b_256_h, blake2b_block, blake2b_block_count, blake2b_compress, blake2b_counter,
+        block, block_count, compress, Word, Word64, H,
+    };
+
+    impl Word for v128 {
+        #[inline(always)]
+        fn splat(x: u32) -> Self {
+            u32x4_splat(x)
+        }
+
+        #[inline(always)]
+        fn add(self, other: Self) -> Self {
+            u32x4_add(self, other)
+        }
+
+        #[inline(always)]
+        fn xor(self, other: Self) -> Self {
+            v128_xor(self, other)
+        }
+
+        #[inline(always)]
+        fn and(self, other: Self) -> Self {
+            v128_and(self, other)
+        }
+
+        #[inline(always)]
+        fn and_not(self, other: Self) -> Self {
+            v128_andnot(other, self)
+        }
+
+        #[inline(always)]
+        fn rotr(self, n: u32) -> Self {
+            v128_or(u32x4_shr(self, n), u32x4_shl(self, 32 - n))
+        }
+
+        #[inline(always)]
+        fn shr(self, n: u32) -> Self {
+            u32x4_shr(self, n)
+        }
+    }
+
+    /// SHA-256 of four messages of the same length, one per lane.
+    pub(crate) fn sha256_x4(messages: [&[u8]; 4]) -> [[u8; 32]; 4] {
+        let len = messages[0].len();
+        let mut h: [v128; 8] = H.map(Word::splat);
+        for i in 0..block_count(len) {
+            let [b0, b1, b2, b3] = messages.map(|message| block(&[message], len, i));
+            let mut lanes = [u32x4_splat(0); 16];
+            for (j, lane) in lanes.iter_mut().enumerate() {
+                *lane = u32x4(b0[j], b1[j], b2[j], b3[j]);
+            }
+            compress(&mut h, lanes);
+        }
+        let mut out = [[0; 32]; 4];
+        for (j, word) in h.into_iter().enumerate() {
+            let words = [
+                u32x4_extract_lane::<0>(word),
+                u32x4_extract_lane::<1>(word),
+                u32x4_extract_lane::<2>(word),
+                u32x4_extract_lane::<3>(word),
+            ];
+            for (digest, word) in out.iter_mut().zip(words) {
+                digest[j * 4..j * 4 + 4].copy_from_slice(&word.to_be_bytes());
+            }
+        }
+        out
+    }
+
+    impl Word64 for v128 {
+        #[inline(always)]
+        fn splat(x: u64) -> Self {
+            u64x2_splat(x)
+        }
+
+        #[inline(always)]
+        fn add(self, other: Self) -> Self {
+            u64x2_add(self, other)
+        }
+
+        #[inline(always)]
+        fn xor(self, other: Self) -> Self {
+            v128_xor(self, other)
+        }
+
+        #[inline(always)]
+        fn rotr(self, n: u32) -> Self {
+            v128_or(u64x2_shr(self, n), u64x2_shl(self, 64 - n))
+        }
+    }
+
+    /// BLAKE2b-256 of two messages of the same length, one per lane.
+    pub(crate) fn blake2b_256_x2(messages: [&[u8]; 2]) -> [[u8; 32]; 2] {
+        let len = messages[0].len();
+        let count = blake2b_block_count(len);
+        let mut h: [v128; 8] = blake2b_256_h().map(Word64::splat);
+        for i in 0..count {
+            let [b0, b1] = messages.map(|message| blake2b_block(&[message], i));
+            let mut lanes = [u64x2_splat(0); 16];
+            for (j, lane) in lanes.iter_mut().enumerate() {
+                *lane = u64x2(b0[j], b1[j]);
+            }
+            blake2b_compress(&mut h, lanes, blake2b_counter(len, i), i + 1 == count);
+        }
+        let mut out = [[0; 32]; 2];
+        for (j, word) in h[..4].iter().enumerate() {
+            let words = [
+                u64x2_extract_lane::<0>(*word),
+                u64x2_extract_lane::<1>(*word),
+            ];
+            for (digest, word) in out.iter_mut().zip(words) {
+                digest[j * 8..j * 8 + 8].copy_from_slice(&word.to_le_bytes());
+            }
+        }
+        out
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+pub(crate) use simd::{blake2b_256_x2, sha256_x4};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(digest: &str) -> [u8; 32] {
+        let mut out = [0; 32];
+        for (byte, i) in out.iter_mut().zip((0..64).step_by(2)) {
+            *byte = u8::from_str_radix(&digest[i..i + 2], 16).unwrap();
+        }
+        out
+    }
+
+    // FIPS 180-2, appendix B, and the NIST CSRC examples.
+    #[test]
+    fn sha256_matches_nist_vectors() {
+        let vectors: [(&[u8], &str); 4] = [
+            (b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+            (
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+                "cf5b16a778af8380036ce59e7b0492370b249b11e8f07a51afac45037afee9d1",
+            ),
+        ];
+        for (message, digest) in vectors {
+            assert_eq!(sha256(&[message]), hex(digest));
+        }
+    }
+
+    #[test]
+    fn sha256_of_a_million_as() {
+        let thousand = [b'a'; 1000];
+        assert_eq!(
+            sha256(&[&thousand[..]; 1000]),
+            hex("cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"),
+        );
+    }
+
+    #[test]
+    fn sha256_parts_hash_as_their_concatenation() {
+        let message = [0x5a; 130];
+        // Around the lengths where the padding spills into another block.
+        for len in [55, 56, 63, 64, 65, 119, 120, 128, 130] {
+            let whole = sha256(&[&message[..len]]);
+            for split in [0, 1, len / 2, len - 1, len] {
+                let (a, b) = message[..len].split_at(split);
+                assert_eq!(sha256(&[a, b]), whole, "{len} split at {split}");
+            }
+        }
+    }
+
+    #[test]
+    fn blake2b_256_matches_reference_vectors() {
+        let mut counting = [0; 256];
+        for (i, byte) in counting.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let vectors: [(&[u8], &str); 3] = [
+            (
+                b"",
+                "0e5751c026e543b2e8ab2eb06099daa1d1e5df47778f7787faab45cdf12fe3a8",
+            ),
+            (
+                b"abc",
+                "bddd813c634239723171ef3fee98579b94964e3bb1cb3e427262c8c068d52319",
+            ),
+            (
+                &counting,
+                "39a7eb9fedc19aabc83425c6755dd90e6f9d0c804964a1f4aaeea3b9fb599835",
+            ),
+        ];
+        for (message, digest) in vectors {
+            assert_eq!(blake2b_256(&[message]), hex(digest));
+        }
+    }
+
+    #[test]
+    fn blake2b_256_parts_hash_as_their_concatenation() {
+        let message = [0xa5; 260];
+        for len in [1, 127, 128, 129, 255, 256, 257, 260] {
+            let whole = blake2b_256(&[&message[..len]]);
+            for split in [0, 1, len / 2, len - 1, len] {
+                let (a, b) = message[..len].split_at(split);
+                assert_eq!(blake2b_256(&[a, b]), whole, "{len} split at {split}");
+            }
+        }
+    }
+}
@@ -1,37 +1,503 @@
 #![no_std]
 
-use core::{marker::PhantomData, mem::MaybeUninit, panic::PanicInfo};
+// `#[derive(FfiSafe)]` and the export macros name this crate `::starstream`,
+// including when used from inside it.
+extern crate self as starstream;
 
+use core::{
+    future::Future,
+    marker::PhantomData,
+    mem::MaybeUninit,
+    num::NonZeroU32,
+    pin::{pin, Pin},
+    sync::atomic::{AtomicBool, Ordering},
+    task::{self, Poll, Waker},
+};
+
+pub use blob::Blob;
+pub use entry::EntryToken;
+pub use starstream_macros::{coordination, non_reentrant, utxo, FfiSafe};
+
+/// Trap with a formatted message, through [`env::trap`].
+///
+/// With `min-size` on wasm, nothing is formatted: the arguments are dropped
+/// and the format string is the message, so no formatting code is linked.
+#[cfg(not(all(feature = "min-size", not(any(feature = "mock-host", miri)))))]
+macro_rules! trap {
+    ($($arg:tt)*) => {
+        $crate::env::trap(format_args!($($arg)*))
+    };
+}
+
+#[cfg(all(feature = "min-size", not(any(feature = "mock-host", miri))))]
+macro_rules! trap {
+    ($fmt:literal $(, $arg:expr)* $(,)?) => {{
+        let _ = ($(&$arg,)*);
+        $crate::env::abort_with($crate::env::PANIC_CODE, $fmt)
+    }};
+}
+
+/// Declare a `bool` that belongs to this contract instance, read and written
+/// with `get`, `set`, and `replace`. On wasm each instance has its own
+/// memory, so it is a static. Under the mock host a test and each UTXO it
+/// spawns run on their own threads, as do parallel tests, so it is a
+/// thread-local.
+#[cfg(not(any(feature = "mock-host", miri)))]
+macro_rules! instance_flag {
+    ($(#[$attr:meta])* static $name:ident;) => {
+        $(#[$attr])*
+        static $name: $crate::InstanceFlag = $crate::InstanceFlag(AtomicBool::new(false));
+    };
+}
+
+#[cfg(any(feature = "mock-host", miri))]
+macro_rules! instance_flag {
+    ($(#[$attr:meta])* static $name:ident;) => {
+        std::thread_local! {
+            $(#[$attr])*
+            static $name: core::cell::Cell<bool> = const { core::cell::Cell::new(false) };
+        }
+    };
+}
+
+/// The wasm side of [`instance_flag!`], with the methods of the
+/// `LocalKey<Cell<bool>>` it is under the mock host.
+#[cfg(not(any(feature = "mock-host", miri)))]
+struct InstanceFlag(AtomicBool);
+
+#[cfg(not(any(feature = "mock-host", miri)))]
+impl InstanceFlag {
+    fn get(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, value: bool) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+
+    fn replace(&self, value: bool) -> bool {
+        self.0.swap(value, Ordering::Relaxed)
+    }
+}
+
+// For `instance_flag!`; the mock host runs on std.
+#[cfg(any(feature = "mock-host", miri))]
+extern crate std;
+
+pub mod abi;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod blob;
+pub mod bridge;
+pub mod cbor;
+pub mod channel;
+pub mod commit_reveal;
+pub mod coordination;
+pub mod crypto;
+pub mod effect;
+pub mod entry;
+pub mod env;
+#[cfg(feature = "evm-events")]
+pub mod evm;
+#[cfg(all(fuzzing, any(feature = "mock-host", miri)))]
+pub mod fuzz;
+pub mod hash;
+pub mod log;
+// Miri can't call wasm imports, so it always runs against the mock host.
+#[cfg(any(feature = "mock-host", miri))]
+pub mod mock;
+#[cfg(feature = "paima")]
+pub mod paima;
+pub mod plutus;
+#[doc(hidden)]
+pub mod poison;
+pub mod policy;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod standards;
+#[cfg(any(feature = "mock-host", miri))]
+pub mod testkit;
+pub mod tx;
+pub mod utxo;
+
+/// The custom section that [`metadata!`] entries go in. The linker
+/// concatenates the entries of every crate; each is a block of lines ending
+/// in `\n`, each line starting with its kind:
+///
+/// - `utxo`, `state`, `resume`, `new`, `query`, `mutate`, and `consume`, from
+///   `#[starstream::utxo]`
+/// - `state`, from [`state!`]
+/// - `token`, `mint`, and `burn`, from [`token_export!`]
+/// - `coordination`, from [`coordination_export!`]
+/// - `type`, from `#[derive(FfiSafe)]` with the `ts-bindgen` feature
+/// - `import`, the module's host imports, added after linking by
+///   `starstream_it_runner::manifest`
+///
+/// Readers should skip lines of kinds they don't know.
+///
+/// The name is part of the ABI: the version suffix changes whenever the
+/// entry format does, and it's outside the names that stripping passes drop
+/// (`name`, `producers`, `target_features`, `.debug*`), so `wasm-opt` and
+/// `wasm-ld --strip-debug` keep it. Build pipelines must not strip custom
+/// sections wholesale (`wasm-strip`, `wasm-opt --strip-all`); a host can
+/// tell they did by [`METADATA_VERSION_EXPORT`].
+pub const METADATA_SECTION: &str = "starstream.metadata.v1";
+
+/// The version in [`METADATA_SECTION`].
+pub const METADATA_VERSION: u32 = 1;
+
+/// An export returning [`METADATA_VERSION`], present in every module linked
+/// with this crate. Exports survive every optimization pass, so a module
+/// that has this export but no [`METADATA_SECTION`] had its metadata
+/// stripped, and a host should refuse it rather than treat it as having no
+/// entry points.
+pub const METADATA_VERSION_EXPORT: &str = "starstream_metadata_version";
+
+#[no_mangle]
+extern "C" fn starstream_metadata_version() -> u32 {
+    METADATA_VERSION
+}
+
+/// The version of the ABI between contracts and the host that this crate
+/// implements: the imports, the exports, and the layouts of the types that
+/// cross the boundary. Bumped with the crate version whenever any of them
+/// change incompatibly.
+pub const ABI_VERSION: u32 = 1;
+
+/// An export returning [`ABI_VERSION`], present in every module linked with
+/// this crate, so a host can refuse a module built for another ABI before
+/// running it. The other way around, every entry point generated by this
+/// crate's macros first compares it with [`env::host_abi_version`], aborting
+/// with [`env::ABI_MISMATCH_CODE`] if they differ.
+pub const ABI_VERSION_EXPORT: &str = "starstream_abi_version";
+
+#[no_mangle]
+extern "C" fn starstream_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+/// Whether [`__check_abi`] has run in this instance.
+static ABI_CHECKED: AtomicBool = AtomicBool::new(false);
+
+/// Abort unless the host implements [`ABI_VERSION`]. Called first by
+/// generated entry points; only the first call asks the host.
+#[doc(hidden)]
+pub fn __check_abi() {
+    if !ABI_CHECKED.swap(true, Ordering::Relaxed) && env::host_abi_version() != ABI_VERSION {
+        env::abort_with(
+            env::ABI_MISMATCH_CODE,
+            "the host implements a different starstream ABI version than the contract",
+        );
+    }
+}
+
+/// Add an entry to the [`METADATA_SECTION`] of the module.
+///
+/// The entry is `#[used]`, so it's kept even if nothing reads it, through
+/// LTO and dead code elimination; the section name is spelled out here
+/// because `link_section` only takes a literal.
 #[macro_export]
 macro_rules! metadata {
-    ($x:expr) => {{
-        #[link_section = "starstream"]
-        static FOO: [u8; $x.len()] = *$x;
-        core::hint::black_box(FOO);
-    }};
+    ($x:expr) => {
+        #[used]
+        #[link_section = "starstream.metadata.v1"]
+        static ENTRY: [u8; $x.len()] = $crate::__metadata_bytes($x);
+    };
+}
+
+/// Record a `#[derive(FfiSafe)]` layout with the `ts-bindgen` feature. A
+/// `cfg` in the derive's output would check the deriving crate's features.
+#[cfg(feature = "ts-bindgen")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __layout_metadata {
+    ($x:expr) => {
+        const _: () = {
+            $crate::metadata!($x);
+        };
+    };
+}
+
+#[cfg(not(feature = "ts-bindgen"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __layout_metadata {
+    ($x:expr) => {};
+}
+
+/// Derive `Serialize` and `Deserialize` for a struct a macro declares, with
+/// the `serde` feature, through this crate's `serde`.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __serde {
+    ($item:item) => {
+        #[derive($crate::__serde::Serialize, $crate::__serde::Deserialize)]
+        #[serde(crate = "starstream::__serde")]
+        $item
+    };
+}
+
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __serde {
+    ($item:item) => {
+        $item
+    };
+}
+
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+pub use serde as __serde;
+
+/// Expand to the `client` module `#[coordination]` generates with the
+/// `client` feature. A `cfg` in the attribute's output would check the
+/// invoking crate's features.
+#[cfg(feature = "client")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __client {
+    ($($item:tt)*) => {
+        $($item)*
+    };
+}
+
+#[cfg(not(feature = "client"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __client {
+    ($($item:tt)*) => {};
+}
+
+#[doc(hidden)]
+pub const fn __metadata_bytes<const N: usize>(bytes: &[u8]) -> [u8; N] {
+    let mut out = [0; N];
+    let mut i = 0;
+    while i < N {
+        out[i] = bytes[i];
+        i += 1;
+    }
+    out
 }
 
 // ----------------------------------------------------------------------------
 // Model types
 
+/// Types whose bytes are copied across the host boundary as-is, such as
+/// yield, resume, and token intermediate types. Use `#[derive(FfiSafe)]`,
+/// which checks the requirements below at compile time.
+///
+/// # Safety
+///
+/// The type must have a stable layout (`repr(C)` or `repr(transparent)`)
+/// with no padding, contain no references, and every bit pattern of its size
+/// must be a valid value, since the host may write any bytes into it.
+pub unsafe trait FfiSafe {}
+
+macro_rules! ffi_safe {
+    ($($ty:ty),*) => {
+        $(unsafe impl FfiSafe for $ty {})*
+    };
+}
+
+ffi_safe!(u8, u16, u32, u64, u128, usize, i8, i16, i32, i64, i128, isize, f32, f64, ());
+
+unsafe impl<T: FfiSafe, const N: usize> FfiSafe for [T; N] {}
+
+/// Types `coordination_export!` entry points take and return: every
+/// [`FfiSafe`] type as itself, and UTXO types imported with
+/// [`utxo_import!`] as their raw handle, since not every `u32` is one.
+pub trait EntryValue: Sized {
+    /// What crosses the host boundary.
+    type Raw: FfiSafe;
+
+    /// Take a value the host passed. Traps if it isn't valid.
+    fn from_raw(raw: Self::Raw) -> Self;
+
+    /// The value to pass to the host.
+    fn into_raw(self) -> Self::Raw;
+}
+
+impl<T: FfiSafe> EntryValue for T {
+    type Raw = T;
+
+    #[inline]
+    fn from_raw(raw: T) -> T {
+        raw
+    }
+
+    #[inline]
+    fn into_raw(self) -> T {
+        self
+    }
+}
+
+/// Trap on a UTXO argument of type `name` that is
+/// [`UtxoHandle::INVALID`].
+#[doc(hidden)]
+#[cold]
+pub fn __invalid_handle(name: &str) -> ! {
+    trap!("the host passed an invalid {} handle", name)
+}
+
+/// Fail the build if a type's size, alignment, or field offsets change, so
+/// an edit can't silently break the ABI that deployed contracts and the host
+/// agree on. Pin every yield, resume, and intermediate type:
+///
+/// ```ignore
+/// assert_abi_layout!(TokenStorage, 16, 8, id: 0, amount: 8);
+/// ```
+#[macro_export]
+macro_rules! assert_abi_layout {
+    ($ty:ty, $size:expr, $align:expr $(, $field:tt: $offset:expr)* $(,)?) => {
+        const _: () = {
+            assert!(
+                core::mem::size_of::<$ty>() == $size,
+                concat!("size of ", stringify!($ty), " changed"),
+            );
+            assert!(
+                core::mem::align_of::<$ty>() == $align,
+                concat!("alignment of ", stringify!($ty), " changed"),
+            );
+            $(
+                assert!(
+                    core::mem::offset_of!($ty, $field) == $offset,
+                    concat!("offset of ", stringify!($ty), ".", stringify!($field), " changed"),
+                );
+            )*
+        };
+    };
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(transparent))]
 #[repr(C)]
 pub struct CodeHash {
     raw: [u8; 32],
 }
 
+unsafe impl FfiSafe for CodeHash {}
+
+assert_abi_layout!(CodeHash, 32, 1, raw: 0);
+
 impl CodeHash {
     pub const fn zero() -> Self {
         CodeHash { raw: [0; 32] }
     }
 }
 
+/// Identifies a UTXO across transactions, unlike its handle, which is only
+/// meaningful within the running execution.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[repr(C)]
+pub struct UtxoId(pub [u8; 32]);
+
+unsafe impl FfiSafe for UtxoId {}
+
+assert_abi_layout!(UtxoId, 32, 1, 0: 0);
+
+/// Formats as 64 lowercase hex digits.
+impl core::fmt::Display for UtxoId {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        for byte in &self.0 {
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Identifies a yield state across the boundary: the 32-bit FNV-1a hash of
+/// its name, so a yield passes four bytes rather than the name. Hosts learn
+/// names from the `state` lines that [`state!`] adds to the metadata.
+///
+/// Only the last `::` segment of the name is hashed, so a state named after
+/// a type matches however the importer's crate spells the path.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[repr(transparent)]
+pub struct StateId(u32);
+
+unsafe impl FfiSafe for StateId {}
+
+impl StateId {
+    /// The ID of the state called `name`. Usable in constants; [`state!`]
+    /// also registers the name.
+    pub const fn of(name: &str) -> StateId {
+        let name = name.as_bytes();
+        let mut start = name.len();
+        while start >= 2 && !(name[start - 2] == b':' && name[start - 1] == b':') {
+            start -= 1;
+        }
+        if start < 2 {
+            start = 0;
+        }
+        let mut hash: u32 = 0x811c_9dc5;
+        while start < name.len() {
+            hash ^= name[start] as u32;
+            hash = hash.wrapping_mul(0x0100_0193);
+            start += 1;
+        }
+        StateId(hash)
+    }
+
+    #[inline]
+    pub const fn from_raw(raw: u32) -> StateId {
+        StateId(raw)
+    }
+
+    #[inline]
+    pub const fn raw(self) -> u32 {
+        self.0
+    }
+}
+
+/// Formats as `#` and 8 lowercase hex digits.
+impl core::fmt::Display for StateId {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "#{:08x}", self.0)
+    }
+}
+
+/// The [`StateId`] of the state called `$name`, computed at compile time
+/// and registered as `state $name` in the [`METADATA_SECTION`].
+///
+/// ```ignore
+/// let price: u64 = sleep_state(state!("Listed"), &listing);
+/// ```
+#[macro_export]
+macro_rules! state {
+    ($name:literal) => {{
+        $crate::metadata!(concat!("state ", $name, "\n").as_bytes());
+        $crate::__state_id!($name)
+    }};
+}
+
+/// [`state!`] for code that has registered the name some other way.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __state_id {
+    ($name:literal) => {{
+        const ID: $crate::StateId = $crate::StateId::of($name);
+        $crate::__mock_host! {
+            mock { $crate::mock::__name_state(ID, $name); }
+            wasm {}
+        }
+        ID
+    }};
+}
+
 #[derive(Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct PublicKey {
+    #[cfg_attr(feature = "serde", serde(skip))]
     _0: (),
 }
 
+unsafe impl FfiSafe for PublicKey {}
+
 #[derive(Clone, Copy)]
 pub struct PrivateKey;
 
@@ -50,15 +516,24 @@ impl PrivateKey {
 
 impl SignedMessage {
     pub fn is_valid(&self, _message: &[u8]) -> bool {
-        true
+        #[cfg(any(feature = "mock-host", miri))]
+        let valid = mock::__signature_valid(_message);
+        #[cfg(not(any(feature = "mock-host", miri)))]
+        let valid = true;
+        valid
     }
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub enum UtxoStatus {
+    /// Finished normally.
     Returned = 0,
     Yielded = 1,
+    /// Spent earlier in this transaction.
+    Consumed = 2,
+    /// Trapped while running; see `Utxo::last_error`.
+    Errored = 3,
 }
 
 impl UtxoStatus {
@@ -68,47 +543,273 @@ impl UtxoStatus {
     }
 }
 
+/// Why a UTXO refused a resume.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UtxoError {
+    /// The exporter rejected the resume argument with its own code, and is
+    /// still yielded in the state it was in before.
+    Rejected(NonZeroU32),
+    /// The UTXO trapped while handling the resume.
+    Errored,
+    /// The host refused the resume before the UTXO ran.
+    Host(HostError),
+}
+
+/// A failure detected by the host itself, rather than by the contract on the
+/// other end of the call.
+///
+/// This is the one error type of the ABI: every `try_*` API returns it (or
+/// wraps it, as [`UtxoError::Host`]), traps name it through its `Display`,
+/// and events that report a failure carry its [`code`](HostError::code).
+/// Discriminants are stable; new variants only ever get new codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum HostError {
+    /// The handle does not refer to anything this contract holds.
+    NotFound = 1,
+    /// The UTXO has already returned, been consumed, or errored.
+    AlreadyConsumed = 2,
+    /// An argument, return value, or patch is not the size the other side
+    /// expects.
+    SizeMismatch = 3,
+    /// The callee's own checks refused the call, e.g. an invalid mint.
+    Unauthorized = 4,
+    /// The UTXO or script is already running further up the call stack.
+    Reentered = 5,
+    /// Too many nested coordination script calls.
+    TooDeep = 6,
+    /// The token holds less than the amount asked for.
+    InsufficientFunds = 7,
+    /// The bytes are the right size but don't decode, e.g. a bad enum tag
+    /// or a name that isn't UTF-8.
+    BadEncoding = 8,
+    /// The call would exceed an execution or call budget.
+    BudgetExceeded = 9,
+    /// An earlier `try_*` operation on this handle failed fatally, so it may
+    /// refer to half-written state. Reported by this library, not the host.
+    Poisoned = 10,
+}
+
+impl HostError {
+    /// The error for a stable code, or `None` if the code is unknown.
+    pub const fn from_code(code: u32) -> Option<HostError> {
+        match code {
+            1 => Some(HostError::NotFound),
+            2 => Some(HostError::AlreadyConsumed),
+            3 => Some(HostError::SizeMismatch),
+            4 => Some(HostError::Unauthorized),
+            5 => Some(HostError::Reentered),
+            6 => Some(HostError::TooDeep),
+            7 => Some(HostError::InsufficientFunds),
+            8 => Some(HostError::BadEncoding),
+            9 => Some(HostError::BudgetExceeded),
+            10 => Some(HostError::Poisoned),
+            _ => None,
+        }
+    }
+
+    /// The stable code hosts report this error with.
+    pub const fn code(self) -> u32 {
+        self as u32
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            HostError::NotFound => "not found",
+            HostError::AlreadyConsumed => "already consumed",
+            HostError::SizeMismatch => "size mismatch",
+            HostError::Unauthorized => "unauthorized",
+            HostError::Reentered => "reentered",
+            HostError::TooDeep => "calls nested too deep",
+            HostError::InsufficientFunds => "insufficient funds",
+            HostError::BadEncoding => "bad encoding",
+            HostError::BudgetExceeded => "budget exceeded",
+            HostError::Poisoned => "poisoned",
+        }
+    }
+
+    /// Whether the two sides of the call disagreed about a layout, so the
+    /// handle involved may refer to half-written state. Fatal failures
+    /// poison the handle; later `try_*` operations on it fail with
+    /// [`Poisoned`](HostError::Poisoned).
+    pub const fn is_fatal(self) -> bool {
+        matches!(self, HostError::SizeMismatch | HostError::BadEncoding)
+    }
+}
+
+/// Formats as `not found (host error 1)`.
+impl core::fmt::Display for HostError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        write!(f, "{} (host error {})", self.as_str(), self.code())
+    }
+}
+
+impl core::fmt::Display for UtxoError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            UtxoError::Rejected(code) => write!(f, "rejected with code {code}"),
+            UtxoError::Errored => f.write_str("errored"),
+            UtxoError::Host(e) => e.fmt(f),
+        }
+    }
+}
+
 // ----------------------------------------------------------------------------
 // Common environment
 
 #[link(wasm_import_module = "env")]
 unsafe extern "C" {
-    unsafe fn abort();
-
     // Debug log
     #[link_name = "starstream_log"]
     pub safe fn log(value: u32);
 
-    #[link_name = "starstream_coordination_code"]
-    pub safe fn coordination_code() -> CodeHash;
+    safe fn starstream_coordination_code() -> CodeHash;
+    safe fn starstream_this_code() -> CodeHash;
+
+    safe fn starstream_last_error() -> RawError;
+}
+
+/// The code hash of the coordination script driving this execution.
+///
+/// Coordination scripts are instantiated afresh for each transaction, so
+/// they ask the host once and keep the answer. UTXOs and tokens outlive the
+/// transaction and may next be driven by a different script, so they ask
+/// every time.
+pub fn coordination_code() -> CodeHash {
+    static COORDINATION_CODE: HostCache<CodeHash> = HostCache::new();
+    match env::is_coordination_context() {
+        true => COORDINATION_CODE.get_or(|| starstream_coordination_code()),
+        false => starstream_coordination_code(),
+    }
+}
+
+/// The code hash of this contract, asked of the host once per instance.
+pub fn this_code() -> CodeHash {
+    static THIS_CODE: HostCache<CodeHash> = HostCache::new();
+    THIS_CODE.get_or(|| starstream_this_code())
+}
+
+/// A host result that can't change during the life of this instance,
+/// fetched on first use so hot checks don't pay for a host call each time.
+#[cfg(not(any(feature = "mock-host", miri)))]
+pub(crate) struct HostCache<T>(core::cell::UnsafeCell<Option<T>>);
+
+// Wasm contracts are single-threaded.
+#[cfg(not(any(feature = "mock-host", miri)))]
+unsafe impl<T> Sync for HostCache<T> {}
+
+#[cfg(not(any(feature = "mock-host", miri)))]
+impl<T: Copy> HostCache<T> {
+    pub(crate) const fn new() -> Self {
+        HostCache(core::cell::UnsafeCell::new(None))
+    }
+
+    #[inline]
+    pub(crate) fn get_or(&self, fetch: impl FnOnce() -> T) -> T {
+        let cached = unsafe { &mut *self.0.get() };
+        *cached.get_or_insert_with(fetch)
+    }
+}
+
+/// The mock host runs many tests per process, each free to change what the
+/// host answers, so nothing is cached.
+#[cfg(any(feature = "mock-host", miri))]
+pub(crate) struct HostCache<T>(PhantomData<T>);
+
+#[cfg(any(feature = "mock-host", miri))]
+impl<T> HostCache<T> {
+    pub(crate) const fn new() -> Self {
+        HostCache(PhantomData)
+    }
+
+    #[inline]
+    pub(crate) fn get_or(&self, fetch: impl FnOnce() -> T) -> T {
+        fetch()
+    }
+}
+
+#[repr(C)]
+struct RawError {
+    /// 0 = none, 1 = rejected, 2 = errored, 3 = host.
+    kind: u32,
+    code: u32,
+}
+
+assert_abi_layout!(RawError, 8, 4, kind: 0, code: 4);
+
+impl RawError {
+    fn into_error(self) -> Option<UtxoError> {
+        match (self.kind, NonZeroU32::new(self.code)) {
+            (1, Some(code)) => Some(UtxoError::Rejected(code)),
+            (2, _) => Some(UtxoError::Errored),
+            (3, _) => HostError::from_code(self.code).map(UtxoError::Host),
+            _ => None,
+        }
+    }
+}
+
+/// Why the most recent fallible host call made by this contract failed, if
+/// it failed in the host rather than in the callee.
+pub fn last_host_error() -> Option<HostError> {
+    match starstream_last_error().into_error() {
+        Some(UtxoError::Host(e)) => Some(e),
+        _ => None,
+    }
+}
 
-    #[link_name = "starstream_this_code"]
-    pub safe fn this_code() -> CodeHash;
+#[doc(hidden)]
+pub fn __last_resume_error() -> UtxoError {
+    starstream_last_error()
+        .into_error()
+        .unwrap_or(UtxoError::Errored)
 }
 
-#[cfg_attr(not(test), panic_handler)]
+/// Aborts with [`env::PANIC_CODE`]. Only messages without arguments are
+/// passed on; formatting the rest would link `core::fmt` into every contract.
+///
+/// Behind the default `panic-handler` feature, so a binary can define its
+/// own handler and still link this crate.
+#[cfg(feature = "panic-handler")]
+#[cfg_attr(not(any(test, feature = "mock-host", miri)), panic_handler)]
 #[allow(dead_code)]
-fn panic_handler(_: &PanicInfo) -> ! {
-    unsafe {
-        abort();
-        // abort() is meant to not return, but just in case:
-        loop {}
+fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    env::abort_with(
+        env::PANIC_CODE,
+        info.message().as_str().unwrap_or("panicked"),
+    )
+}
+
+pub fn assert_tx_signed_by(key: PublicKey) {
+    if !tx_signed_by(key) {
+        trap!("transaction not signed by the required key");
     }
 }
 
-pub fn assert_tx_signed_by(_key: PublicKey) {
-    // TODO: assert that this coordination-script-call is signed by `key`
+/// Whether this coordination-script-call is signed by `key`. See
+/// [`policy`] for rules over several keys.
+pub fn tx_signed_by(_key: PublicKey) -> bool {
+    #[cfg(any(feature = "mock-host", miri))]
+    let signed = mock::__tx_signed_by(_key);
+    // TODO: check the signature once PublicKey has a representation.
+    #[cfg(not(any(feature = "mock-host", miri)))]
+    let signed = true;
+    signed
 }
 
 // ----------------------------------------------------------------------------
 // Token export environment
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[repr(C)]
 pub struct TokenStorage {
     pub id: u64,
     pub amount: u64,
 }
 
+unsafe impl FfiSafe for TokenStorage {}
+
+assert_abi_layout!(TokenStorage, 16, 8, id: 0, amount: 8);
+
 /*
 pub trait TokenIntermediate {
     /// Called when the token is minted. Panics if the mint is invalid.
@@ -125,23 +826,126 @@ macro_rules! token_export {
         mint fn $mint_fn:ident($self:ident: Self) -> TokenStorage $mint_body:block
         burn fn $burn_fn:ident($storage:ident: TokenStorage) -> Self $burn_body:block
     ) => {
-        #[no_mangle]
-        pub extern "C" fn $mint_fn($self: $intermediate) -> $crate::TokenStorage $mint_body
+        $crate::__if_fuzzing! {
+            {
+                // Under `cargo fuzz`, a trap unwinds to the harness; see `starstream::fuzz`.
+                #[no_mangle]
+                pub extern "C-unwind" fn $mint_fn($self: $intermediate) -> $crate::TokenStorage {
+                    $crate::__check_abi();
+                    let _operation = $crate::__TokenOperation::enter();
+                    $mint_body
+                }
 
-        #[no_mangle]
-        pub extern "C" fn $burn_fn($storage: $crate::TokenStorage) -> $intermediate $burn_body
+                #[no_mangle]
+                pub extern "C-unwind" fn $burn_fn($storage: $crate::TokenStorage) -> $intermediate {
+                    $crate::__check_abi();
+                    let _operation = $crate::__TokenOperation::enter();
+                    $burn_body
+                }
+            } else {
+                #[no_mangle]
+                pub extern "C" fn $mint_fn($self: $intermediate) -> $crate::TokenStorage {
+                    $crate::__check_abi();
+                    let _operation = $crate::__TokenOperation::enter();
+                    $mint_body
+                }
+
+                #[no_mangle]
+                pub extern "C" fn $burn_fn($storage: $crate::TokenStorage) -> $intermediate {
+                    $crate::__check_abi();
+                    let _operation = $crate::__TokenOperation::enter();
+                    $burn_body
+                }
+            }
+        }
+
+        const _: () = {
+            $crate::metadata!(concat!(
+                "token ",
+                stringify!($intermediate),
+                "\nmint ",
+                stringify!($mint_fn),
+                "\nburn ",
+                stringify!($burn_fn),
+                "\n",
+            ).as_bytes());
+        };
+    }
+}
+
+instance_flag! {
+    /// Set while a mint or burn generated by [`token_export!`] runs. A token
+    /// operation runs to completion inside its caller's host call, so it has
+    /// no execution of its own to suspend, and [`sleep`] traps instead.
+    static IN_TOKEN_OPERATION;
+}
+
+/// Marks a token operation until dropped. Operations nest, as when a mint
+/// mints another token of the same contract, so leaving one restores the
+/// flag as the enclosing operation had it.
+#[doc(hidden)]
+pub struct __TokenOperation {
+    was_in_operation: bool,
+}
+
+impl __TokenOperation {
+    pub fn enter() -> __TokenOperation {
+        __TokenOperation {
+            was_in_operation: IN_TOKEN_OPERATION.replace(true),
+        }
+    }
+}
+
+impl Drop for __TokenOperation {
+    fn drop(&mut self) {
+        IN_TOKEN_OPERATION.set(self.was_in_operation);
+    }
+}
+
+fn assert_not_in_token_operation() {
+    if IN_TOKEN_OPERATION.get() {
+        trap!("cannot yield inside token operation");
     }
 }
 
 // ----------------------------------------------------------------------------
 // Token import environment
 
-#[repr(C)]
+/// A handle to a token of type `T`. Never [`INVALID`](Self::INVALID), so
+/// `Option<TokenHandle<T>>` is the same size and is what imports that may
+/// fail return. A handle isn't [`FfiSafe`] itself, since not every bit
+/// pattern is one, but the `Option` is.
+#[repr(transparent)]
 pub struct TokenHandle<T: ?Sized> {
-    ptr: u32,
+    ptr: NonZeroU32,
     _phantom: PhantomData<*mut T>,
 }
 
+impl<T: ?Sized> TokenHandle<T> {
+    /// The raw value hosts use for "no token", such as the result of a
+    /// refused mint.
+    pub const INVALID: u32 = 0;
+
+    /// The handle with raw value `raw`, or `None` if it is
+    /// [`INVALID`](Self::INVALID).
+    #[inline]
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        match NonZeroU32::new(raw) {
+            Some(ptr) => Some(TokenHandle {
+                ptr,
+                _phantom: PhantomData,
+            }),
+            None => None,
+        }
+    }
+
+    /// The raw value passed to host imports.
+    #[inline]
+    pub const fn raw(self) -> u32 {
+        self.ptr.get()
+    }
+}
+
 impl<T: ?Sized> Clone for TokenHandle<T> {
     fn clone(&self) -> Self {
         *self
@@ -150,56 +954,324 @@ impl<T: ?Sized> Clone for TokenHandle<T> {
 
 impl<T: ?Sized> Copy for TokenHandle<T> {}
 
+unsafe impl<T: ?Sized> FfiSafe for Option<TokenHandle<T>> {}
+
 pub trait Token {
-    type Intermediate;
-    fn mint(i: Self::Intermediate) -> Self;
-    fn burn(self) -> Self::Intermediate;
-}
+    /// Import module and name the host identifies this token type by.
+    const MODULE: &'static str;
+    const NAME: &'static str;
 
-#[macro_export]
-macro_rules! token_import {
-    (
-        from $module:expr;
-        type $handle_name:ident;
-        intermediate struct $intermediate_name:ident {
-            $($contents:tt)*
-        }
-        mint fn $mint_fn:ident;
-        burn fn $burn_fn:ident;
-    ) => {
-        #[repr(C)]
-        pub struct $intermediate_name {
-            $($contents)*
-        }
+    type Intermediate: FfiSafe;
 
-        impl $intermediate_name {
-            #[inline]
-            pub fn mint(self) -> $handle_name {
-                <$handle_name as $crate::Token>::mint(self)
-            }
+    /// Traps if the mint is refused.
+    fn mint(i: Self::Intermediate) -> Self
+    where
+        Self: Sized,
+    {
+        match Self::try_mint(i) {
+            Ok(token) => token,
+            Err(e) => trap!("mint failed: {}", e),
         }
+    }
 
-        #[link(wasm_import_module = $module)]
-        unsafe extern "C" {
-            safe fn $mint_fn(intermediate: $intermediate_name) -> $crate::TokenHandle<$handle_name>;
-            safe fn $burn_fn(handle: $crate::TokenHandle<$handle_name>) -> $intermediate_name;
+    /// Traps if the burn is refused.
+    fn burn(self) -> Self::Intermediate
+    where
+        Self: Sized,
+    {
+        match self.try_burn() {
+            Ok(i) => i,
+            Err(e) => trap!("burn failed: {}", e),
         }
+    }
 
-        #[derive(Clone, Copy)]
-        #[repr(transparent)]
-        pub struct $handle_name($crate::TokenHandle<$handle_name>);
+    fn try_mint(i: Self::Intermediate) -> Result<Self, HostError>
+    where
+        Self: Sized;
+    fn try_burn(self) -> Result<Self::Intermediate, HostError>;
+
+    /// Mint a token from each of `intermediates` in one host call, such as
+    /// for an airdrop, putting each handle in the same place in `out`, which
+    /// must be at least as long. A refused mint stops the batch: the error
+    /// comes back with its index, and the tokens before it are minted.
+    fn try_mint_batch(
+        intermediates: &[Self::Intermediate],
+        out: &mut [Option<Self>],
+    ) -> Result<(), (usize, HostError)>
+    where
+        Self: Sized,
+    {
+        if out.len() < intermediates.len() {
+            trap!(
+                "{} intermediates to mint into {} handles",
+                intermediates.len(),
+                out.len()
+            );
+        }
+        let count = intermediates.len();
+        match unsafe { Self::__mint_batch(intermediates.as_ptr(), count, out.as_mut_ptr()) } {
+            minted if minted == count => Ok(()),
+            minted => Err((minted, last_host_error().unwrap_or(HostError::Unauthorized))),
+        }
+    }
+
+    /// Burn each of `tokens` in one host call, writing each intermediate to
+    /// the same place in `out`, which must be at least as long. A refused
+    /// burn stops the batch, poisoning that token if the error is fatal: the
+    /// error comes back with its index, and only the intermediates before it
+    /// are written.
+    fn try_burn_batch(
+        tokens: &[Self],
+        out: &mut [MaybeUninit<Self::Intermediate>],
+    ) -> Result<(), (usize, HostError)>
+    where
+        Self: Sized + Copy,
+    {
+        if out.len() < tokens.len() {
+            trap!(
+                "{} tokens to burn into {} intermediates",
+                tokens.len(),
+                out.len()
+            );
+        }
+        for (index, token) in tokens.iter().enumerate() {
+            poison::check(poison::Kind::Token, token.handle().raw()).map_err(|e| (index, e))?;
+        }
+        let count = tokens.len();
+        match unsafe { Self::__burn_batch(tokens.as_ptr(), count, out.as_mut_ptr() as *mut _) } {
+            burned if burned == count => Ok(()),
+            burned => {
+                let error = last_host_error().unwrap_or(HostError::Unauthorized);
+                let raw = tokens[burned].handle().raw();
+                Err((burned, poison::record(poison::Kind::Token, raw, error)))
+            }
+        }
+    }
+
+    fn handle(self) -> TokenHandle<Self>;
+    fn from_handle(handle: TokenHandle<Self>) -> Self;
+
+    /// The batch mint import: mints up to `count`, writing handles to `out`,
+    /// and returns how many were minted.
+    #[doc(hidden)]
+    unsafe fn __mint_batch(
+        intermediates: *const Self::Intermediate,
+        count: usize,
+        out: *mut Option<Self>,
+    ) -> usize
+    where
+        Self: Sized;
+
+    /// The batch burn import: burns up to `count`, writing intermediates to
+    /// `out`, and returns how many were burned.
+    #[doc(hidden)]
+    unsafe fn __burn_batch(
+        tokens: *const Self,
+        count: usize,
+        out: *mut Self::Intermediate,
+    ) -> usize
+    where
+        Self: Sized;
+}
+
+/// A type-erased description of a token attached to a UTXO.
+#[repr(C)]
+pub struct AnyToken {
+    pub code: CodeHash,
+    name: [u8; 32],
+    pub storage: TokenStorage,
+}
+
+assert_abi_layout!(AnyToken, 80, 8, code: 0, name: 32, storage: 64);
+
+impl AnyToken {
+    /// The token's import name, such as `StarNft`.
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&b| b == 0).unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+
+    #[inline]
+    pub fn is<T: Token>(&self) -> bool {
+        self.name() == T::NAME
+    }
+}
+
+/// Expands to the `mock` items when built with the `mock-host` feature or
+/// under Miri, and to the `wasm` items otherwise. A `cfg` in an exported macro would check
+/// the calling crate's features rather than this crate's.
+#[cfg(any(feature = "mock-host", miri))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __mock_host {
+    (mock { $($mock:tt)* } wasm { $($wasm:tt)* }) => { $($mock)* };
+}
+
+#[cfg(not(any(feature = "mock-host", miri)))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __mock_host {
+    (mock { $($mock:tt)* } wasm { $($wasm:tt)* }) => { $($wasm)* };
+}
+
+/// Expands to the first items under `cargo fuzz`, which builds with `--cfg
+/// fuzzing`, and to the `else` items otherwise. Checking the cfg here spares
+/// contracts declaring it in their own `check-cfg`.
+#[cfg(fuzzing)]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_fuzzing {
+    ({ $($fuzzing:tt)* } $(else { $($other:tt)* })?) => { $($fuzzing)* };
+}
+
+#[cfg(not(fuzzing))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_fuzzing {
+    ({ $($fuzzing:tt)* } $(else { $($other:tt)* })?) => { $($($other)*)? };
+}
+
+#[macro_export]
+macro_rules! token_import {
+    (
+        from $module:expr;
+        type $handle_name:ident;
+        intermediate struct $intermediate_name:ident {
+            $($contents:tt)*
+        }
+        mint fn $mint_fn:ident;
+        burn fn $burn_fn:ident;
+    ) => {
+        $crate::__serde! {
+            #[derive($crate::FfiSafe)]
+            #[repr(C)]
+            pub struct $intermediate_name {
+                $($contents)*
+            }
+        }
+
+        impl $intermediate_name {
+            #[inline]
+            pub fn mint(self) -> $handle_name {
+                <$handle_name as $crate::Token>::mint(self)
+            }
+        }
+
+        $crate::__mock_host! {
+            mock {
+                #[allow(non_snake_case)]
+                fn $mint_fn(intermediate: $intermediate_name) -> Option<$crate::TokenHandle<$handle_name>> {
+                    $crate::mock::__mint(intermediate)
+                }
+                #[allow(non_snake_case)]
+                fn $burn_fn(handle: $crate::TokenHandle<$handle_name>) -> $intermediate_name {
+                    $crate::mock::__burn(handle)
+                }
+            }
+            wasm {
+                #[link(wasm_import_module = $module)]
+                unsafe extern "C" {
+                    safe fn $mint_fn(intermediate: $intermediate_name) -> Option<$crate::TokenHandle<$handle_name>>;
+                    safe fn $burn_fn(handle: $crate::TokenHandle<$handle_name>) -> $intermediate_name;
+                }
+            }
+        }
+
+        #[derive(Clone, Copy)]
+        #[repr(transparent)]
+        pub struct $handle_name($crate::TokenHandle<$handle_name>);
 
         impl $crate::Token for $handle_name {
+            const MODULE: &'static str = $module;
+            const NAME: &'static str = stringify!($handle_name);
+
             type Intermediate = $intermediate_name;
 
             #[inline]
-            fn mint(i: Self::Intermediate) -> Self {
-                Self($mint_fn(i))
+            fn try_mint(i: Self::Intermediate) -> Result<Self, $crate::HostError> {
+                match $mint_fn(i) {
+                    Some(handle) => Ok(Self(handle)),
+                    // The host returned `TokenHandle::INVALID`.
+                    None => Err($crate::last_host_error().unwrap_or($crate::HostError::Unauthorized)),
+                }
             }
 
             #[inline]
-            fn burn(self) -> Self::Intermediate {
-                $burn_fn(self.0)
+            fn try_burn(self) -> Result<Self::Intermediate, $crate::HostError> {
+                let raw = self.0.raw();
+                $crate::poison::check($crate::poison::Kind::Token, raw)?;
+                let i = $burn_fn(self.0);
+                match $crate::last_host_error() {
+                    Some(e) => {
+                        // Placeholder the host returned in place of the real value.
+                        let _ = i;
+                        Err($crate::poison::record($crate::poison::Kind::Token, raw, e))
+                    }
+                    None => Ok(i),
+                }
+            }
+
+            #[inline]
+            fn handle(self) -> $crate::TokenHandle<Self> {
+                self.0
+            }
+
+            #[inline]
+            fn from_handle(handle: $crate::TokenHandle<Self>) -> Self {
+                Self(handle)
+            }
+
+            // `Self` is a transparent `TokenHandle`, so `Option<Self>` is a
+            // raw handle as well.
+            $crate::__mock_host! {
+                mock {
+                    unsafe fn __mint_batch(
+                        intermediates: *const $intermediate_name,
+                        count: usize,
+                        out: *mut Option<Self>,
+                    ) -> usize {
+                        let intermediates = core::slice::from_raw_parts(intermediates, count);
+                        $crate::mock::__mint_batch(intermediates, out as *mut Option<$crate::TokenHandle<Self>>)
+                    }
+
+                    unsafe fn __burn_batch(tokens: *const Self, count: usize, out: *mut $intermediate_name) -> usize {
+                        let handles = core::slice::from_raw_parts(tokens as *const $crate::TokenHandle<Self>, count);
+                        $crate::mock::__burn_batch(handles, out)
+                    }
+                }
+                wasm {
+                    unsafe fn __mint_batch(
+                        intermediates: *const $intermediate_name,
+                        count: usize,
+                        out: *mut Option<Self>,
+                    ) -> usize {
+                        #[link(wasm_import_module = $module)]
+                        unsafe extern "C" {
+                            #[link_name = concat!(stringify!($mint_fn), "_batch")]
+                            unsafe fn mint_batch(
+                                intermediates: *const $intermediate_name,
+                                intermediate_size: usize,
+                                count: usize,
+                                out: *mut Option<$handle_name>,
+                            ) -> usize;
+                        }
+                        mint_batch(intermediates, core::mem::size_of::<$intermediate_name>(), count, out)
+                    }
+
+                    unsafe fn __burn_batch(tokens: *const Self, count: usize, out: *mut $intermediate_name) -> usize {
+                        #[link(wasm_import_module = $module)]
+                        unsafe extern "C" {
+                            #[link_name = concat!(stringify!($burn_fn), "_batch")]
+                            unsafe fn burn_batch(
+                                tokens: *const $handle_name,
+                                count: usize,
+                                out: *mut $intermediate_name,
+                                intermediate_size: usize,
+                            ) -> usize;
+                        }
+                        burn_batch(tokens, count, out, core::mem::size_of::<$intermediate_name>())
+                    }
+                }
             }
         }
     };
@@ -208,53 +1280,435 @@ macro_rules! token_import {
 // ----------------------------------------------------------------------------
 // UTXO export (main/implementation) environment
 
+// "C-unwind" so that the mock host can end a UTXO body under test by
+// panicking out of a `sleep`; on wasm it is the same as "C".
 #[link(wasm_import_module = "starstream_utxo_env")]
-unsafe extern "C" {
+unsafe extern "C-unwind" {
     unsafe fn starstream_yield(
-        name: *const u8,
-        name_len: usize,
+        state: StateId,
         data: *const (),
         data_size: usize,
         resume_arg: *mut (),
         resume_arg_size: usize,
     );
+    unsafe fn starstream_yield_mut(
+        state: StateId,
+        data: *mut (),
+        data_size: usize,
+        resume_arg: *mut (),
+        resume_arg_size: usize,
+    );
+    // As above, for resume arguments of at most `SMALL_RESUME` bytes, which
+    // come back in the low bytes of the result instead of through memory.
+    unsafe fn starstream_yield_small(
+        state: StateId,
+        data: *const (),
+        data_size: usize,
+        resume_arg_size: usize,
+    ) -> u64;
+    unsafe fn starstream_yield_mut_small(
+        state: StateId,
+        data: *mut (),
+        data_size: usize,
+        resume_arg_size: usize,
+    ) -> u64;
+    safe fn starstream_set_deadline(block: u64);
+    safe fn starstream_expired() -> bool;
+    safe fn starstream_reject(code: NonZeroU32);
+    unsafe fn starstream_migrate(
+        new_code: *const CodeHash,
+        entry: *const u8,
+        entry_len: usize,
+        state: *const (),
+        state_size: usize,
+    );
+    unsafe fn starstream_migrated_state(state: *mut (), state_size: usize);
+    unsafe fn starstream_set_memo(memo: *const [u8; 64]);
 }
 
 // yield = fn(a...) -> (b...)
 // resume = (b...) -> (a...)
 
-pub fn sleep<Resume, Yield>(data: &Yield) -> Resume {
-    let name = core::any::type_name::<Yield>();
+/// The largest resume argument returned by value from the yield imports.
+/// Stable Rust's wasm32 C ABI returns up to 8 bytes as an `i64` and anything
+/// bigger (even a `u128`) through a pointer, so larger arguments keep the
+/// out-pointer imports.
+const SMALL_RESUME: usize = 8;
+
+/// Read a resume argument of at most [`SMALL_RESUME`] bytes out of the
+/// little-endian `word` a small yield import returned.
+///
+/// # Safety
+///
+/// As for reading the argument from memory: the host must have resumed with
+/// a valid `T`.
+unsafe fn from_word<T>(word: u64) -> T {
+    let mut value = MaybeUninit::<T>::uninit();
+    let bytes = word.to_le_bytes();
+    core::ptr::copy_nonoverlapping(
+        bytes.as_ptr(),
+        value.as_mut_ptr() as *mut u8,
+        size_of::<T>(),
+    );
+    value.assume_init()
+}
 
-    let mut resume_arg = MaybeUninit::<Resume>::uninit();
+/// The ID of a state named at runtime. The mock host can't read metadata,
+/// so it's told the name here, as [`state!`] does.
+fn name_state(name: &str) -> StateId {
+    let state = StateId::of(name);
+    #[cfg(any(feature = "mock-host", miri))]
+    mock::__name_state(state, name);
+    state
+}
+
+/// Yield using `Yield`'s type name as the state name. Prefer [`sleep_state`]
+/// for anything on-chain, since type names can change between compiler
+/// versions, and aren't registered in the metadata.
+pub fn sleep<Resume: FfiSafe, Yield: FfiSafe>(data: &Yield) -> Resume {
+    sleep_named(core::any::type_name::<Yield>(), data)
+}
+
+/// Yield in the state called `name`. The ID is hashed at runtime and the
+/// name isn't in the metadata, so hosts only know it by [`StateId`]; prefer
+/// [`sleep_state`] with [`state!`].
+pub fn sleep_named<Resume: FfiSafe, Yield: FfiSafe>(name: &str, data: &Yield) -> Resume {
+    sleep_state(name_state(name), data)
+}
+
+/// Yield in `state`, which importers use to identify it. Traps inside a
+/// token mint or burn, which can't be suspended.
+pub fn sleep_state<Resume: FfiSafe, Yield: FfiSafe>(state: StateId, data: &Yield) -> Resume {
+    assert_not_in_token_operation();
     unsafe {
+        // SAFETY TODO: unsound if we're resumed with a value that isn't
+        // actually a valid instance of Resume due to ABI trouble.
+        if size_of::<Resume>() <= SMALL_RESUME {
+            return from_word(starstream_yield_small(
+                state,
+                data as *const Yield as *const (),
+                size_of::<Yield>(),
+                size_of::<Resume>(),
+            ));
+        }
+        let mut resume_arg = MaybeUninit::<Resume>::uninit();
         starstream_yield(
-            name.as_ptr(),
-            name.len(),
+            state,
             data as *const Yield as *const (),
             size_of::<Yield>(),
             resume_arg.as_mut_ptr() as *mut (),
             size_of::<Resume>(),
         );
-        // SAFETY TODO: unsound if we're resumed with a value that isn't
-        // actually a valid instance of Resume due to ABI trouble.
         resume_arg.assume_init()
     }
 }
 
-pub fn sleep_mut<Resume, Yield>(data: &mut Yield) -> Resume {
-    sleep(data)
+/// Like [`sleep`], but the host may write an updated `Yield` into `data`
+/// while the UTXO is asleep, e.g. through `mutate` exports. Changes are
+/// visible in `data` once this returns.
+pub fn sleep_mut<Resume: FfiSafe, Yield: FfiSafe>(data: &mut Yield) -> Resume {
+    sleep_mut_named(core::any::type_name::<Yield>(), data)
+}
+
+/// Like [`sleep_named`], but with [`sleep_mut`]'s write-back.
+pub fn sleep_mut_named<Resume: FfiSafe, Yield: FfiSafe>(name: &str, data: &mut Yield) -> Resume {
+    sleep_mut_state(name_state(name), data)
+}
+
+/// Like [`sleep_state`], but with [`sleep_mut`]'s write-back.
+pub fn sleep_mut_state<Resume: FfiSafe, Yield: FfiSafe>(state: StateId, data: &mut Yield) -> Resume {
+    assert_not_in_token_operation();
+    unsafe {
+        // SAFETY TODO: as in `sleep_state`, and the host must likewise have
+        // written only a valid Yield into `data`.
+        if size_of::<Resume>() <= SMALL_RESUME {
+            return from_word(starstream_yield_mut_small(
+                state,
+                data as *mut Yield as *mut (),
+                size_of::<Yield>(),
+                size_of::<Resume>(),
+            ));
+        }
+        let mut resume_arg = MaybeUninit::<Resume>::uninit();
+        starstream_yield_mut(
+            state,
+            data as *mut Yield as *mut (),
+            size_of::<Yield>(),
+            resume_arg.as_mut_ptr() as *mut (),
+            size_of::<Resume>(),
+        );
+        resume_arg.assume_init()
+    }
+}
+
+/// Like [`sleep_named`], but resume arguments that fail `check` are rejected
+/// back to the caller's `try_resume` instead of trapping the transaction.
+/// The UTXO then stays yielded with the same data until a valid resume.
+pub fn sleep_checked<Resume: FfiSafe, Yield: FfiSafe>(
+    name: &str,
+    data: &Yield,
+    check: impl Fn(&Resume) -> Result<(), NonZeroU32>,
+) -> Resume {
+    loop {
+        let arg = sleep_named(name, data);
+        match check(&arg) {
+            Ok(()) => return arg,
+            Err(code) => starstream_reject(code),
+        }
+    }
+}
+
+/// How a [`sleep_until`] ended.
+pub enum Expiry<Resume> {
+    /// Resumed normally before the deadline.
+    Resumed(Resume),
+    /// The deadline passed and someone triggered the expiry path.
+    Expired,
+}
+
+/// Like [`sleep`], but the UTXO is only resumable until block `deadline`.
+/// After that, normal resumes are refused and anyone may call `expire()` on
+/// the UTXO, which continues execution here with [`Expiry::Expired`].
+pub fn sleep_until<Resume: FfiSafe, Yield: FfiSafe>(data: &Yield, deadline: u64) -> Expiry<Resume> {
+    sleep_until_named(core::any::type_name::<Yield>(), data, deadline)
+}
+
+/// [`sleep_until`] with an explicit state name, as in [`sleep_named`].
+pub fn sleep_until_named<Resume: FfiSafe, Yield: FfiSafe>(
+    name: &str,
+    data: &Yield,
+    deadline: u64,
+) -> Expiry<Resume> {
+    sleep_until_state(name_state(name), data, deadline)
+}
+
+/// [`sleep_until`] in `state`, as in [`sleep_state`].
+pub fn sleep_until_state<Resume: FfiSafe, Yield: FfiSafe>(
+    state: StateId,
+    data: &Yield,
+    deadline: u64,
+) -> Expiry<Resume> {
+    assert_not_in_token_operation();
+    starstream_set_deadline(deadline);
+    unsafe {
+        if size_of::<Resume>() <= SMALL_RESUME {
+            let word = starstream_yield_small(
+                state,
+                data as *const Yield as *const (),
+                size_of::<Yield>(),
+                size_of::<Resume>(),
+            );
+            return match starstream_expired() {
+                // The word holds no resume arg.
+                true => Expiry::Expired,
+                false => Expiry::Resumed(from_word(word)),
+            };
+        }
+        let mut resume_arg = MaybeUninit::<Resume>::uninit();
+        starstream_yield(
+            state,
+            data as *const Yield as *const (),
+            size_of::<Yield>(),
+            resume_arg.as_mut_ptr() as *mut (),
+            size_of::<Resume>(),
+        );
+        if starstream_expired() {
+            // The resume arg was never written.
+            Expiry::Expired
+        } else {
+            Expiry::Resumed(resume_arg.assume_init())
+        }
+    }
+}
+
+/// Move this UTXO to new contract code. `transform` converts the current
+/// state, then once the calling entry point returns, the host restarts the
+/// same UTXO (keeping its id and tokens) at export `entry` of `new_code`,
+/// which reads the converted state with [`migrated_state`].
+///
+/// Return from the entry point right after calling this.
+pub fn migrate_to<Old, New: FfiSafe>(new_code: CodeHash, entry: &str, state: Old, transform: fn(Old) -> New) {
+    let new = transform(state);
+    unsafe {
+        starstream_migrate(
+            &new_code,
+            entry.as_ptr(),
+            entry.len(),
+            &new as *const New as *const (),
+            size_of::<New>(),
+        );
+    }
+}
+
+/// Set this UTXO's memo, a short label for wallets and explorers to show. It
+/// is not interpreted by the host and survives migrations. By convention it
+/// holds UTF-8 text padded with zeros.
+pub fn set_memo(memo: &[u8; 64]) {
+    unsafe { starstream_set_memo(memo) }
+}
+
+/// Read the state handed over by [`migrate_to`]. Only valid in the entry
+/// point a migration started.
+pub fn migrated_state<New: FfiSafe>() -> New {
+    let mut state = MaybeUninit::<New>::uninit();
+    unsafe {
+        starstream_migrated_state(state.as_mut_ptr() as *mut (), size_of::<New>());
+        // SAFETY TODO: as in `sleep`, relies on both codes agreeing on New.
+        state.assume_init()
+    }
+}
+
+/// Handed to an async UTXO body by [`run_async`] to express yield points as
+/// `let price: u64 = ctx.yield_state(&Open { .. }).await;`.
+pub struct YieldContext {
+    _private: (),
+}
+
+impl YieldContext {
+    /// Yield `data` like [`sleep`], resolving to the resume argument.
+    pub fn yield_state<'a, Resume: FfiSafe, Yield: FfiSafe>(&'a mut self, data: &'a Yield) -> YieldState<'a, Resume, Yield> {
+        self.yield_named(core::any::type_name::<Yield>(), data)
+    }
+
+    /// Yield `data` like [`sleep_named`], resolving to the resume argument.
+    pub fn yield_named<'a, Resume: FfiSafe, Yield: FfiSafe>(
+        &'a mut self,
+        name: &'a str,
+        data: &'a Yield,
+    ) -> YieldState<'a, Resume, Yield> {
+        YieldState {
+            name,
+            data,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// A yield point. Since `sleep` suspends the whole instance, this completes
+/// on its first poll, once the UTXO has been resumed.
+pub struct YieldState<'a, Resume, Yield> {
+    name: &'a str,
+    data: &'a Yield,
+    _phantom: PhantomData<fn() -> Resume>,
+}
+
+impl<Resume: FfiSafe, Yield: FfiSafe> Future for YieldState<'_, Resume, Yield> {
+    type Output = Resume;
+
+    fn poll(self: Pin<&mut Self>, _: &mut task::Context<'_>) -> Poll<Resume> {
+        Poll::Ready(sleep_named(self.name, self.data))
+    }
+}
+
+/// Run an async UTXO body to completion. This is a minimal single-task
+/// executor: the body may only await yield points from its [`YieldContext`],
+/// and awaiting anything that returns `Pending` traps.
+pub fn run_async<F: Future>(body: impl FnOnce(YieldContext) -> F) -> F::Output {
+    let future = pin!(body(YieldContext { _private: () }));
+    match future.poll(&mut task::Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => trap!("UTXO futures may only await yield points"),
+    }
+}
+
+/// Implemented by UTXOs that follow the ownership convention, where a
+/// `starstream_query_<Name>_get_owner` export reports who owns the UTXO.
+pub trait Owned {
+    fn owner(&self) -> PublicKey;
+}
+
+/// Held for the duration of a `#[non_reentrant]` export. Entering any
+/// `#[non_reentrant]` export of this UTXO while another is still running
+/// traps, like Solidity's `nonReentrant` modifier.
+///
+/// The host already refuses to resume a UTXO from within its own execution;
+/// this additionally covers queries and mutations.
+pub struct ReentrancyGuard {
+    _private: (),
+}
+
+instance_flag! {
+    static ENTERED;
+}
+
+impl ReentrancyGuard {
+    pub fn try_enter() -> Option<ReentrancyGuard> {
+        if ENTERED.replace(true) {
+            None
+        } else {
+            Some(ReentrancyGuard { _private: () })
+        }
+    }
+
+    pub fn enter() -> ReentrancyGuard {
+        match ReentrancyGuard::try_enter() {
+            Some(guard) => guard,
+            None => trap!("re-entered a #[non_reentrant] export"),
+        }
+    }
+}
+
+impl Drop for ReentrancyGuard {
+    fn drop(&mut self) {
+        ENTERED.set(false);
+    }
+}
+
+#[macro_export]
+macro_rules! owner_export {
+    (
+        for $ty:ty;
+        query fn $query_fn:ident;
+    ) => {
+        #[no_mangle]
+        pub extern "C" fn $query_fn(this: &$ty) -> $crate::PublicKey {
+            $crate::__check_abi();
+            <$ty as $crate::Owned>::owner(this)
+        }
+    };
 }
 
 // ----------------------------------------------------------------------------
 // UTXO import (lib) interface
 
-#[repr(C)]
-pub struct UtxoHandle<T: ?Sized> {
-    ptr: u32,
-    _phantom: PhantomData<*mut T>,
+/// A handle to a UTXO of type `T`. `S` optionally tracks its status at the
+/// type level; see [`utxo::state`].
+///
+/// Like [`TokenHandle`], never [`INVALID`](Self::INVALID), so
+/// `Option<UtxoHandle<T>>` is [`FfiSafe`] where the handle isn't.
+#[repr(transparent)]
+pub struct UtxoHandle<T: ?Sized, S = utxo::state::Any> {
+    ptr: NonZeroU32,
+    _phantom: PhantomData<(*mut T, S)>,
+}
+
+impl<T: ?Sized, S> UtxoHandle<T, S> {
+    /// The raw value hosts use for "no UTXO", such as the result of a
+    /// failed lookup.
+    pub const INVALID: u32 = 0;
+
+    /// The handle with raw value `raw`, or `None` if it is
+    /// [`INVALID`](Self::INVALID).
+    #[inline]
+    pub const fn from_raw(raw: u32) -> Option<Self> {
+        match NonZeroU32::new(raw) {
+            Some(ptr) => Some(UtxoHandle {
+                ptr,
+                _phantom: PhantomData,
+            }),
+            None => None,
+        }
+    }
+
+    /// The raw value passed to host imports.
+    #[inline]
+    pub const fn raw(self) -> u32 {
+        self.ptr.get()
+    }
 }
 
+// Only handles of unknown status are copied: a handle in a known state is
+// used up by the transition out of it.
 impl<T: ?Sized> Clone for UtxoHandle<T> {
     fn clone(&self) -> Self {
         *self
@@ -263,11 +1717,156 @@ impl<T: ?Sized> Clone for UtxoHandle<T> {
 
 impl<T: ?Sized> Copy for UtxoHandle<T> {}
 
+unsafe impl<T: ?Sized, S> FfiSafe for Option<UtxoHandle<T, S>> {}
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    safe fn starstream_utxo_id(utxo: u32) -> UtxoId;
+    safe fn starstream_utxo_expire(utxo: u32);
+    safe fn starstream_utxo_last_error(utxo: u32) -> RawError;
+    unsafe fn starstream_utxo_memo(utxo: u32, out: *mut [u8; 64]);
+    unsafe fn starstream_utxo_attach(utxo: u32, token: u32);
+    unsafe fn starstream_utxo_detach(
+        utxo: u32,
+        token_module: *const u8,
+        token_module_len: usize,
+        token_name: *const u8,
+        token_name_len: usize,
+        amount: u64,
+    ) -> u32;
+    unsafe fn starstream_utxo_detach_amount(
+        utxo: u32,
+        token_module: *const u8,
+        token_module_len: usize,
+        token_name: *const u8,
+        token_name_len: usize,
+        amount: u64,
+    ) -> u32;
+    unsafe fn starstream_utxo_tokens(
+        utxo: u32,
+        cursor: u32,
+        out: *mut AnyToken,
+        out_len: usize,
+    ) -> usize;
+}
+
+/// Pages through the tokens attached to a UTXO, a few per host call.
+struct UtxoTokens {
+    utxo: u32,
+    cursor: u32,
+    page: [MaybeUninit<AnyToken>; UtxoTokens::PAGE_LEN],
+    len: usize,
+    pos: usize,
+}
+
+impl UtxoTokens {
+    const PAGE_LEN: usize = 8;
+
+    fn new(utxo: u32) -> Self {
+        UtxoTokens {
+            utxo,
+            cursor: 0,
+            page: [const { MaybeUninit::uninit() }; UtxoTokens::PAGE_LEN],
+            len: 0,
+            pos: 0,
+        }
+    }
+}
+
+impl Iterator for UtxoTokens {
+    type Item = AnyToken;
+
+    fn next(&mut self) -> Option<AnyToken> {
+        if self.pos == self.len {
+            // A short page means the previous one was the last.
+            if self.cursor != 0 && self.len < UtxoTokens::PAGE_LEN {
+                return None;
+            }
+            self.len = unsafe {
+                starstream_utxo_tokens(
+                    self.utxo,
+                    self.cursor,
+                    self.page.as_mut_ptr() as *mut AnyToken,
+                    UtxoTokens::PAGE_LEN,
+                )
+            };
+            self.cursor += self.len as u32;
+            self.pos = 0;
+            if self.len == 0 {
+                return None;
+            }
+        }
+        let token = unsafe { self.page[self.pos].assume_init_read() };
+        self.pos += 1;
+        Some(token)
+    }
+}
+
 pub trait Utxo {
-    type Resume;
+    /// Import module and name the host identifies this UTXO type by.
+    const MODULE: &'static str;
+    const NAME: &'static str;
+
+    type Resume: FfiSafe;
+    type Yield: FfiSafe;
 
+    fn handle(self) -> UtxoHandle<Self>;
+    fn from_handle(handle: UtxoHandle<Self>) -> Self
+    where
+        Self: Sized;
     fn status(self) -> UtxoStatus;
-    fn resume(self, arg: Self::Resume);
+
+    fn id(self) -> UtxoId
+    where
+        Self: Sized,
+    {
+        starstream_utxo_id(self.handle().raw())
+    }
+
+    /// Resume the UTXO and return what it yields next. If it returns instead
+    /// of yielding, its return value is written in place of the yield.
+    /// Traps if the UTXO rejects the resume. A resume after the UTXO has
+    /// ended traps naming the UTXO, this call site, and the site that last
+    /// resumed it.
+    #[track_caller]
+    fn resume(self, arg: Self::Resume) -> Self::Yield
+    where
+        Self: Sized,
+    {
+        utxo::resume_or_trap(self.handle(), arg)
+    }
+
+    /// Like `resume`, but a rejection by the UTXO or the host is returned as
+    /// an error instead of trapping.
+    fn try_resume(self, arg: Self::Resume) -> Result<Self::Yield, UtxoError>;
+
+    /// Why this UTXO's most recent resume in this transaction failed, if it
+    /// did.
+    fn last_error(self) -> Option<UtxoError>
+    where
+        Self: Sized,
+    {
+        starstream_utxo_last_error(self.handle().raw()).into_error()
+    }
+
+    /// The label the UTXO set with [`set_memo`], or all zeros.
+    fn memo(self) -> [u8; 64]
+    where
+        Self: Sized,
+    {
+        let mut memo = [0; 64];
+        unsafe { starstream_utxo_memo(self.handle().raw(), &mut memo) };
+        memo
+    }
+
+    /// Trigger the expiry path of a UTXO that yielded with `sleep_until`
+    /// and whose deadline has passed. Anyone may call this.
+    fn expire(self)
+    where
+        Self: Sized,
+    {
+        starstream_utxo_expire(self.handle().raw())
+    }
 
     fn can_resume(self) -> bool
     where
@@ -276,39 +1875,248 @@ pub trait Utxo {
         self.status().can_resume()
     }
 
-    fn next(self)
+    #[track_caller]
+    fn next(self) -> Self::Yield
     where
         Self: Sized + Utxo<Resume = ()>,
     {
         self.resume(())
     }
+
+    /// Bind a token to this UTXO, so it is carried as part of its value.
+    fn attach<T: Token>(self, token: T)
+    where
+        Self: Sized,
+    {
+        unsafe { starstream_utxo_attach(self.handle().raw(), token.handle().raw()) }
+    }
+
+    /// Enumerate the tokens this UTXO carries.
+    fn tokens(self) -> impl Iterator<Item = AnyToken>
+    where
+        Self: Sized,
+    {
+        UtxoTokens::new(self.handle().raw())
+    }
+
+    /// The total amount of the tokens of type `T` this UTXO carries.
+    fn amount_of<T: Token>(self) -> u64
+    where
+        Self: Sized,
+    {
+        self.tokens()
+            .filter(AnyToken::is::<T>)
+            .fold(0, |total, token| total.saturating_add(token.storage.amount))
+    }
+
+    /// Unbind a token of type `T` and exactly `amount` from this UTXO. The
+    /// host traps if the UTXO carries no such token, or if it hasn't accepted
+    /// a resume from this script in this transaction, or been expired by it.
+    /// A UTXO that ended in this transaction can still be detached from.
+    fn detach<T: Token>(self, amount: u64) -> T
+    where
+        Self: Sized,
+    {
+        let raw = unsafe {
+            starstream_utxo_detach(
+                self.handle().raw(),
+                T::MODULE.as_ptr(),
+                T::MODULE.len(),
+                T::NAME.as_ptr(),
+                T::NAME.len(),
+                amount,
+            )
+        };
+        match TokenHandle::from_raw(raw) {
+            Some(handle) => T::from_handle(handle),
+            None => trap!("detach returned an invalid token handle"),
+        }
+    }
+
+    /// Unbind `amount` of `T` from this UTXO as one token, combining and
+    /// splitting the tokens of type `T` it carries as needed, such as for
+    /// part of a fungible balance deposited in several tokens. The host
+    /// traps if `amount` is zero, if they carry less in all or have
+    /// different IDs, or, as for [`detach`](Self::detach), if the UTXO
+    /// hasn't accepted a resume from this script in this transaction.
+    fn detach_amount<T: Token>(self, amount: u64) -> T
+    where
+        Self: Sized,
+    {
+        let raw = unsafe {
+            starstream_utxo_detach_amount(
+                self.handle().raw(),
+                T::MODULE.as_ptr(),
+                T::MODULE.len(),
+                T::NAME.as_ptr(),
+                T::NAME.len(),
+                amount,
+            )
+        };
+        match TokenHandle::from_raw(raw) {
+            Some(handle) => T::from_handle(handle),
+            None => trap!("detach returned an invalid token handle"),
+        }
+    }
 }
 
 #[macro_export]
 macro_rules! utxo_import {
+    // Several resume entry points, each with its own argument type. They
+    // share the resume import as variants of a generated enum, sent as its
+    // `RawResume` wire form, which the exporter receives from `sleep` and
+    // decodes with the enum's `from_raw`:
+    //
+    //     match AuctionResume::from_raw(sleep(&state)) { .. }
+    (
+        $module:expr;
+        $name:ident;
+        $status_fn:ident;
+        $resume_fn:ident;
+        enum $resume_name:ident {
+            $($variant:ident($arg_ty:ty) => $method:ident,)*
+        }
+        $yield_ty:ty;
+    ) => {
+        pub enum $resume_name {
+            $($variant($arg_ty),)*
+        }
+
+        const _: () = {
+            // The variants' tags, in order.
+            enum Tag {
+                $($variant,)*
+            }
+
+            impl $resume_name {
+                /// The payload words of the wire form.
+                pub const WORDS: usize =
+                    $crate::utxo::__resume_words(&[$(core::mem::size_of::<$arg_ty>(),)*]);
+
+                /// The wire form, as the resume import takes it.
+                pub fn into_raw(self) -> $crate::utxo::RawResume<{ $resume_name::WORDS }> {
+                    match self {
+                        $($resume_name::$variant(arg) => {
+                            $crate::utxo::RawResume::new(Tag::$variant as u32, arg)
+                        })*
+                    }
+                }
+
+                /// Decode the wire form, as the exporter's `sleep` returns
+                /// it. Traps on a tag that names no variant.
+                pub fn from_raw(raw: $crate::utxo::RawResume<{ $resume_name::WORDS }>) -> Self {
+                    $(
+                        if raw.tag == Tag::$variant as u32 {
+                            return $resume_name::$variant(raw.payload());
+                        }
+                    )*
+                    $crate::utxo::__unknown_tag(stringify!($resume_name), raw.tag)
+                }
+            }
+        };
+
+        $crate::utxo_import! {
+            $module;
+            $name;
+            $status_fn;
+            $resume_fn;
+            $crate::utxo::RawResume<{ $resume_name::WORDS }>;
+            $yield_ty;
+        }
+
+        impl $name {
+            $(
+                #[inline]
+                #[track_caller]
+                pub fn $method(self, arg: $arg_ty) -> $yield_ty {
+                    <Self as $crate::Utxo>::resume(self, $resume_name::$variant(arg).into_raw())
+                }
+            )*
+        }
+    };
     (
         $module:expr;
         $name:ident;
         $status_fn:ident;
         $resume_fn:ident;
         $resume_ty:ty;
+        $yield_ty:ty;
     ) => {
-        #[link(wasm_import_module = $module)]
-        unsafe extern "C" {
-            safe fn $status_fn(utxo: $name) -> $crate::UtxoStatus;
-            unsafe fn $resume_fn(
-                utxo: $name,
-                resume_arg: *const (),
-                resume_arg_size: usize,
-            );
+        $crate::__mock_host! {
+            mock {
+                #[allow(non_snake_case)]
+                fn $status_fn(utxo: $name) -> $crate::UtxoStatus {
+                    $crate::mock::__utxo_status(<$name as $crate::Utxo>::handle(utxo))
+                }
+                #[allow(non_snake_case)]
+                unsafe fn $resume_fn(
+                    utxo: $name,
+                    resume_arg: *const (),
+                    resume_arg_size: usize,
+                    yield_out: *mut (),
+                    yield_out_size: usize,
+                ) -> u32 {
+                    $crate::mock::__resume(
+                        <$name as $crate::Utxo>::handle(utxo),
+                        resume_arg,
+                        resume_arg_size,
+                        yield_out,
+                        yield_out_size,
+                    )
+                }
+            }
+            wasm {
+                #[link(wasm_import_module = $module)]
+                unsafe extern "C" {
+                    safe fn $status_fn(utxo: $name) -> $crate::UtxoStatus;
+                    unsafe fn $resume_fn(
+                        utxo: $name,
+                        resume_arg: *const (),
+                        resume_arg_size: usize,
+                        yield_out: *mut (),
+                        yield_out_size: usize,
+                    ) -> u32;
+                }
+            }
         }
 
         #[derive(Clone, Copy)]
         #[repr(transparent)]
         pub struct $name($crate::UtxoHandle<$name>);
 
+        impl $crate::EntryValue for $name {
+            type Raw = u32;
+
+            #[inline]
+            fn from_raw(raw: u32) -> Self {
+                match $crate::UtxoHandle::from_raw(raw) {
+                    Some(handle) => $name(handle),
+                    None => $crate::__invalid_handle(stringify!($name)),
+                }
+            }
+
+            #[inline]
+            fn into_raw(self) -> u32 {
+                self.0.raw()
+            }
+        }
+
         impl $crate::Utxo for $name {
+            const MODULE: &'static str = $module;
+            const NAME: &'static str = stringify!($name);
+
             type Resume = $resume_ty;
+            type Yield = $yield_ty;
+
+            #[inline]
+            fn handle(self) -> $crate::UtxoHandle<Self> {
+                self.0
+            }
+
+            #[inline]
+            fn from_handle(handle: $crate::UtxoHandle<Self>) -> Self {
+                Self(handle)
+            }
 
             #[inline]
             fn status(self) -> $crate::UtxoStatus {
@@ -316,18 +2124,366 @@ macro_rules! utxo_import {
             }
 
             #[inline]
-            fn resume(self, arg: Self::Resume) {
+            fn try_resume(self, arg: Self::Resume) -> Result<Self::Yield, $crate::UtxoError> {
+                let raw = self.0.raw();
+                $crate::poison::check($crate::poison::Kind::Utxo, raw)
+                    .map_err($crate::UtxoError::Host)?;
+                let mut yielded = core::mem::MaybeUninit::<Self::Yield>::uninit();
                 unsafe {
-                    $resume_fn(
+                    let failed = $resume_fn(
                         self,
                         &raw const arg as *const (),
                         core::mem::size_of_val(&arg),
+                        yielded.as_mut_ptr() as *mut (),
+                        core::mem::size_of::<Self::Yield>(),
                     );
+                    if failed != 0 {
+                        Err(match $crate::__last_resume_error() {
+                            $crate::UtxoError::Host(e) => $crate::UtxoError::Host(
+                                $crate::poison::record($crate::poison::Kind::Utxo, raw, e),
+                            ),
+                            e => e,
+                        })
+                    } else {
+                        // SAFETY TODO: same caveat as `sleep`, the host must
+                        // have written a valid instance of Yield.
+                        Ok(yielded.assume_init())
+                    }
                 }
             }
         }
     };
 }
 
+/// Declare a UTXO type's state import, which returns 1 if the UTXO is
+/// yielded in `state`, copying its data to `data_out` unless
+/// `data_out_size` is 0, and 0 otherwise. For the import macros.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __state_import {
+    ($module:expr; $name:ident; $state_fn:ident) => {
+        $crate::__mock_host! {
+            mock {
+                #[allow(non_snake_case)]
+                unsafe fn $state_fn(
+                    utxo: $name,
+                    state: $crate::StateId,
+                    data_out: *mut (),
+                    data_out_size: usize,
+                ) -> u32 {
+                    $crate::mock::__utxo_state(
+                        <$name as $crate::Utxo>::handle(utxo),
+                        state,
+                        data_out,
+                        data_out_size,
+                    )
+                }
+            }
+            wasm {
+                #[link(wasm_import_module = $module)]
+                unsafe extern "C" {
+                    unsafe fn $state_fn(
+                        utxo: $name,
+                        state: $crate::StateId,
+                        data_out: *mut (),
+                        data_out_size: usize,
+                    ) -> u32;
+                }
+            }
+        }
+    };
+}
+
+/// Declare a UTXO type's spawn import, which starts one with `args` for its
+/// entry point to read with [`env::init_args`]. For the import macros.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __spawn_import {
+    ($module:expr; $name:ident; $spawn_fn:ident($args_ty:ty)) => {
+        $crate::__mock_host! {
+            mock {
+                #[allow(non_snake_case)]
+                unsafe fn $spawn_fn(args: *const $args_ty, args_size: usize) -> $name {
+                    <$name as $crate::Utxo>::from_handle($crate::mock::__spawn(
+                        stringify!($spawn_fn),
+                        args as *const (),
+                        args_size,
+                    ))
+                }
+            }
+            wasm {
+                #[link(wasm_import_module = $module)]
+                unsafe extern "C" {
+                    unsafe fn $spawn_fn(args: *const $args_ty, args_size: usize) -> $name;
+                }
+            }
+        }
+    };
+}
+
+/// Declare the distinct states a UTXO can yield in, generating a `state()`
+/// method returning a discriminant enum plus a typed accessor per state.
+///
+/// States are matched by [`StateId`], so `$state_name` is the name the
+/// exporter passes to [`state!`] or [`sleep_named`].
+#[macro_export]
+macro_rules! utxo_states {
+    (
+        from $module:expr;
+        for $name:ident;
+        state fn $state_fn:ident;
+        enum $enum_name:ident {
+            $($variant:ident($state_ty:ty) = $state_name:literal => $accessor:ident,)*
+        }
+    ) => {
+        $crate::__state_import!($module; $name; $state_fn);
+
+        #[derive(Clone, Copy, PartialEq, Eq, Debug)]
+        #[repr(u32)]
+        pub enum $enum_name {
+            $($variant,)*
+        }
+
+        impl $name {
+            /// The state this UTXO is currently yielded in, or `None` if it
+            /// is not yielded in any of the declared states.
+            pub fn state(self) -> Option<$enum_name> {
+                $(
+                    if unsafe { $state_fn(self, const { $crate::StateId::of($state_name) }, core::ptr::null_mut(), 0) } != 0 {
+                        return Some($enum_name::$variant);
+                    }
+                )*
+                None
+            }
+
+            $(
+                pub fn $accessor(self) -> Option<$state_ty> {
+                    let mut data = core::mem::MaybeUninit::<$state_ty>::uninit();
+                    unsafe {
+                        if $state_fn(
+                            self,
+                            const { $crate::StateId::of($state_name) },
+                            data.as_mut_ptr() as *mut (),
+                            core::mem::size_of::<$state_ty>(),
+                        ) != 0
+                        {
+                            Some(data.assume_init())
+                        } else {
+                            None
+                        }
+                    }
+                }
+            )*
+        }
+    };
+}
+
+/// A UTXO import whose exporter follows the ownership convention.
+pub trait OwnedUtxo: Utxo {
+    fn owner(self) -> PublicKey;
+}
+
+#[macro_export]
+macro_rules! utxo_owner {
+    (
+        from $module:expr;
+        for $name:ident;
+        query fn $query_fn:ident;
+    ) => {
+        $crate::__mock_host! {
+            mock {
+                #[allow(non_snake_case)]
+                fn $query_fn(utxo: $name) -> $crate::PublicKey {
+                    $crate::mock::__utxo_owner(<$name as $crate::Utxo>::handle(utxo))
+                }
+            }
+            wasm {
+                #[link(wasm_import_module = $module)]
+                unsafe extern "C" {
+                    safe fn $query_fn(utxo: $name) -> $crate::PublicKey;
+                }
+            }
+        }
+
+        impl $crate::OwnedUtxo for $name {
+            #[inline]
+            fn owner(self) -> $crate::PublicKey {
+                $query_fn(self)
+            }
+        }
+    };
+}
+
+/// Declare a constructor that passes its arguments as one struct, which the
+/// UTXO's entry point reads with [`env::init_args`] rather than as C-ABI
+/// parameters.
+///
+/// The exporter's entry point is `starstream_new_<Type>_<fn>`, taking no
+/// parameters; the import is named `starstream_spawn_<Type>_<fn>`.
+#[macro_export]
+macro_rules! utxo_spawn {
+    (
+        from $module:expr;
+        for $name:ident;
+        spawn fn $spawn_fn:ident($args_ty:ty) => $method:ident;
+    ) => {
+        $crate::__spawn_import!($module; $name; $spawn_fn($args_ty));
+
+        impl $name {
+            #[inline]
+            pub fn $method(args: $args_ty) -> Self
+            where
+                $args_ty: $crate::FfiSafe,
+            {
+                unsafe { $spawn_fn(&args, core::mem::size_of::<$args_ty>()) }
+            }
+        }
+    };
+}
+
 // ----------------------------------------------------------------------------
 // Coordination script environment
+
+/// Declare coordination script entry points. Each becomes an export the host
+/// can run as a transaction, and is listed in the [`METADATA_SECTION`]
+/// with its signature.
+///
+/// ```ignore
+/// coordination_export! {
+///     fn star_mint(owner: PublicKey, amount: u64) -> StarToken {
+///         StarToken::new(owner, amount)
+///     }
+///
+///     fn swap(#[payload] args: SwapArgs) -> u64 {
+///         ...
+///     }
+/// }
+/// ```
+///
+/// By convention, entry point names don't start with `starstream_`, which is
+/// reserved for UTXO and token exports. Arguments and the return value are
+/// passed as C-ABI values and so must be [`EntryValue`]s: the host passes
+/// UTXOs as handles, and a call with an invalid one traps. Alternatively, a
+/// single `#[payload]` parameter, of a type implementing [`cbor::Decode`], is
+/// decoded from the transaction payload with [`tx::payload`], trapping with
+/// what was wrong if it is malformed, and the return value becomes the
+/// transaction result with [`tx::set_result`].
+///
+/// A first parameter marked `#[entry]`, typed [`EntryToken`], isn't passed
+/// by the host; it receives a token scoped to the call, for the handles of
+/// [`entry::Scoped`].
+///
+/// Entry points are `extern "C-unwind"`, so that a trap in a script run on
+/// the mock host unwinds to the test; on wasm it is the same as "C".
+#[macro_export]
+macro_rules! coordination_export {
+    () => {};
+    (
+        $(#[$attr:meta])*
+        fn $name:ident(#[entry] $entry:ident: $entry_ty:ty, #[payload] $arg:ident: $arg_ty:ty $(,)?) $(-> $ret:ty)? $body:block
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        #[no_mangle]
+        pub extern "C-unwind" fn $name() {
+            $crate::metadata!(concat!(
+                "coordination ",
+                stringify!($name),
+                "(#[payload] ",
+                stringify!($arg: $arg_ty),
+                ")",
+                $(" -> ", stringify!($ret),)?
+                "\n",
+            ).as_bytes());
+            $crate::__check_abi();
+            fn body($entry: $entry_ty, $arg: $arg_ty) $(-> $ret)? $body
+            // SAFETY: this is the entry point, and the token ends with it.
+            let entry = unsafe { $crate::EntryToken::new() };
+            let ret = body(entry, $crate::tx::payload());
+            $crate::tx::set_result(&$crate::EntryValue::into_raw(ret));
+        }
+
+        $crate::coordination_export! { $($rest)* }
+    };
+    (
+        $(#[$attr:meta])*
+        fn $name:ident(#[entry] $entry:ident: $entry_ty:ty $(, $arg:ident: $arg_ty:ty)* $(,)?) $(-> $ret:ty)? $body:block
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        #[no_mangle]
+        pub extern "C-unwind" fn $name(
+            $($arg: <$arg_ty as $crate::EntryValue>::Raw),*
+        ) $(-> <$ret as $crate::EntryValue>::Raw)? {
+            $crate::metadata!(concat!(
+                "coordination ",
+                stringify!($name),
+                "(",
+                stringify!($($arg: $arg_ty),*),
+                ")",
+                $(" -> ", stringify!($ret),)?
+                "\n",
+            ).as_bytes());
+            $crate::__check_abi();
+            fn body($entry: $entry_ty $(, $arg: $arg_ty)*) $(-> $ret)? $body
+            // SAFETY: this is the entry point, and the token ends with it.
+            let entry = unsafe { $crate::EntryToken::new() };
+            let ret = body(entry $(, <$arg_ty as $crate::EntryValue>::from_raw($arg))*);
+            $crate::EntryValue::into_raw(ret)
+        }
+
+        $crate::coordination_export! { $($rest)* }
+    };
+    (
+        $(#[$attr:meta])*
+        fn $name:ident(#[payload] $arg:ident: $arg_ty:ty $(,)?) $(-> $ret:ty)? $body:block
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        #[no_mangle]
+        pub extern "C-unwind" fn $name() {
+            $crate::metadata!(concat!(
+                "coordination ",
+                stringify!($name),
+                "(#[payload] ",
+                stringify!($arg: $arg_ty),
+                ")",
+                $(" -> ", stringify!($ret),)?
+                "\n",
+            ).as_bytes());
+            $crate::__check_abi();
+            fn body($arg: $arg_ty) $(-> $ret)? $body
+            let ret = body($crate::tx::payload());
+            $crate::tx::set_result(&$crate::EntryValue::into_raw(ret));
+        }
+
+        $crate::coordination_export! { $($rest)* }
+    };
+    (
+        $(#[$attr:meta])*
+        fn $name:ident($($arg:ident: $arg_ty:ty),* $(,)?) $(-> $ret:ty)? $body:block
+        $($rest:tt)*
+    ) => {
+        $(#[$attr])*
+        #[no_mangle]
+        pub extern "C-unwind" fn $name(
+            $($arg: <$arg_ty as $crate::EntryValue>::Raw),*
+        ) $(-> <$ret as $crate::EntryValue>::Raw)? {
+            $crate::metadata!(concat!(
+                "coordination ",
+                stringify!($name),
+                "(",
+                stringify!($($arg: $arg_ty),*),
+                ")",
+                $(" -> ", stringify!($ret),)?
+                "\n",
+            ).as_bytes());
+            $crate::__check_abi();
+            fn body($($arg: $arg_ty),*) $(-> $ret)? $body
+            let ret = body($(<$arg_ty as $crate::EntryValue>::from_raw($arg)),*);
+            $crate::EntryValue::into_raw(ret)
+        }
+
+        $crate::coordination_export! { $($rest)* }
+    };
+}
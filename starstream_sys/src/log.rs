@@ -0,0 +1,92 @@
+//! Text logging for debugging, batched into one host call per line rather
+//! than one [`log`](crate::log()) call per value.
+//!
+//! ```ignore
+//! use core::fmt::Write;
+//!
+//! let mut log = log::Writer::new();
+//! writeln!(log, "filled {amount} at {price}").ok();
+//! ```
+
+use core::fmt;
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_log_bytes(bytes: *const u8, len: usize);
+}
+
+/// Log one line of text. Hosts show it as UTF-8, replacing invalid bytes.
+pub fn line(text: &str) {
+    unsafe { starstream_log_bytes(text.as_ptr(), text.len()) }
+}
+
+/// A [`fmt::Write`] that buffers text and logs each line with [`line`].
+///
+/// A line longer than [`Writer::CAPACITY`] bytes is logged in pieces, which
+/// may split a character. Whatever is buffered when the writer is dropped is
+/// logged as a last line.
+pub struct Writer {
+    buf: [u8; Writer::CAPACITY],
+    len: usize,
+}
+
+impl Writer {
+    pub const CAPACITY: usize = 128;
+
+    pub const fn new() -> Writer {
+        Writer {
+            buf: [0; Writer::CAPACITY],
+            len: 0,
+        }
+    }
+
+    /// Log the buffered text, if any, as a line.
+    pub fn flush(&mut self) {
+        if self.len > 0 {
+            self.end_line();
+        }
+    }
+
+    fn end_line(&mut self) {
+        unsafe { starstream_log_bytes(self.buf.as_ptr(), self.len) };
+        self.len = 0;
+    }
+
+    fn push(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            if self.len == Writer::CAPACITY {
+                self.end_line();
+            }
+            let n = bytes.len().min(Writer::CAPACITY - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+        }
+    }
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Writer::new()
+    }
+}
+
+impl fmt::Write for Writer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.push(first.as_bytes());
+        }
+        for rest in lines {
+            self.end_line();
+            self.push(rest.as_bytes());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
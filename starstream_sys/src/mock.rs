@@ -0,0 +1,2032 @@
+//! A native stand-in for the Starstream host, so contract crates can run
+//! `cargo test` without a wasm runtime. Enabled by the `mock-host` feature,
+//! for builds targeting the host rather than wasm32; crates with a
+//! `forced-target` need to drop it for native tests.
+//!
+//! Miri can't call wasm imports either, so these implementations are always
+//! used under Miri, where `cargo miri test` checks this crate's unsafe code
+//! and the contract's for undefined behavior.
+//!
+//! Every fixed-name import is implemented here against a per-thread
+//! [`MockHost`], which tests configure and inspect with [`with`]. Token
+//! imports declared with `token_import!` mint into and burn from the mock
+//! ledger instead of calling the token's exporter. The mock host has no UTXOs
+//! of its own: resumes of imported UTXO types reach stubs in
+//! [`MockHost::utxos`], their states and owners are read from
+//! [`MockHost::utxo_states`] and [`MockHost::utxo_owners`], spawns are
+//! recorded in [`MockHost::spawned`], and other imports taking UTXO handles
+//! panic.
+//!
+//! Tests can make fallible operations fail on purpose with
+//! [`MockHost::faults`], to exercise the `try_*` error paths.
+//!
+//! A `sleep` with no resume queued in [`MockHost::resumes`] panics, which is
+//! how a test ends a UTXO body that would otherwise run forever. To drive a
+//! whole lifecycle instead, start the body with [`spawn`], which runs it on
+//! its own thread so `sleep` suspends it until the test resumes it. Other
+//! failures the real host would trap on, like size mismatches, abort the test
+//! process.
+//!
+//! ```ignore
+//! use starstream::mock;
+//!
+//! mock::with(|host| {
+//!     host.effects.insert("my_effect".into(), Box::new(|_| vec![]));
+//!     host.resumes.push_back(vec![]);
+//! });
+//! // Resumed once, then ends at the second sleep.
+//! let ended = std::panic::catch_unwind(|| MyMain::new(starstream::sleep::<(), MyMain>));
+//! assert!(ended.is_err());
+//! mock::with(|host| assert_eq!(host.logs, [11, 21, 12, 22]));
+//! ```
+
+extern crate std;
+
+use core::{
+    mem::{size_of, MaybeUninit},
+    num::NonZeroU32,
+    panic::Location,
+    slice,
+};
+use std::{
+    boxed::Box,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    string::String,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    vec::Vec,
+};
+
+use crate::{
+    bridge::{ClientId, Height, Root},
+    hash::{bytes_of, Part},
+    poison,
+    testkit::{Aborted, Rng},
+    utxo::BatchResume,
+    AnyToken, CodeHash, FfiSafe, HostError, PublicKey, RawError, StateId, TokenHandle,
+    UtxoHandle, UtxoId, UtxoStatus,
+};
+
+/// A closure standing in for an effect handler or a called script: it gets
+/// the payload bytes and returns the reply bytes.
+pub type Responder = Box<dyn FnMut(&[u8]) -> Vec<u8> + Send>;
+
+/// Everything the mock host knows. Fields are public so tests can set up the
+/// environment before running contract code and inspect it afterwards.
+pub struct MockHost {
+    /// Values passed to `log`, oldest first.
+    pub logs: Vec<u32>,
+    /// Lines logged with `log::line` or a `log::Writer`, oldest first.
+    pub log_lines: Vec<String>,
+    pub coordination_code: CodeHash,
+    pub this_code: CodeHash,
+    pub caller_code: CodeHash,
+    pub is_coordination: bool,
+    pub is_simulation: bool,
+    pub block_height: u64,
+    pub block_time: u64,
+    pub chain_id: u64,
+    /// Returned by `env::host_abi_version`; `ABI_VERSION` unless a test
+    /// simulates an older or newer host.
+    pub abi_version: u32,
+    pub signer_count: u32,
+    /// The keys this transaction is signed by, for `tx_signed_by`, or `None`
+    /// to accept every key as the real host does for now.
+    pub signers: Option<Vec<PublicKey>>,
+    /// The `(key, message)` pairs that verify, for `SignedMessage::is_valid`,
+    /// or `None` to accept every signature.
+    pub signatures: Option<Vec<(PublicKey, Vec<u8>)>>,
+
+    // Transaction
+    pub fee: u64,
+    pub fee_paid: u64,
+    /// Read by `tx::payload`.
+    pub payload: Vec<u8>,
+    /// Set by `tx::set_result`.
+    pub result: Option<Vec<u8>>,
+    /// `(topic, payload)` for each `tx::emit_event`, oldest first.
+    pub events: Vec<(String, Vec<u8>)>,
+    pub scratch: HashMap<Vec<u8>, Vec<u8>>,
+    /// Payloads stored with `Blob::new`; handle `n` is `blobs[n - 1]`.
+    pub blobs: Vec<Vec<u8>>,
+    /// State roots `bridge::root` returns, by light client and height.
+    pub bridge_roots: HashMap<(ClientId, Height), Root>,
+    /// Public keys `crypto::eth_recover` recovers, by message hash and
+    /// signature; other signatures are malformed.
+    pub secp256k1_keys: HashMap<([u8; 32], [u8; 65]), [u8; 64]>,
+    /// Hashes passed to `tx::assert_includes_code`.
+    pub required_code: Vec<CodeHash>,
+    /// Outputs finished with `tx::OutputBuilder`.
+    pub outputs: Vec<MockOutput>,
+    /// Scripts `coordination::call` can reach, by entry point name.
+    pub scripts: HashMap<String, Responder>,
+
+    // The UTXO under test
+    /// `(state name, data)` for each `sleep`, oldest first.
+    pub yields: Vec<(String, Vec<u8>)>,
+    /// Resume arguments for successive `sleep`s. A `sleep` with none queued
+    /// panics, which ends the UTXO body under test.
+    pub resumes: VecDeque<Vec<u8>>,
+    /// Read by `env::init_args`.
+    pub init_args: Option<Vec<u8>>,
+    pub memo: [u8; 64],
+    /// Set by `sleep_until`.
+    pub deadline: Option<u64>,
+    /// Returned by `expired()` after the next `sleep_until`.
+    pub expired: bool,
+    /// Set by `reject`.
+    pub rejection: Option<NonZeroU32>,
+    pub call_count: u32,
+    /// Set by `migrate_to`: the new code, entry point, and handed-over state.
+    pub migration: Option<(CodeHash, String, Vec<u8>)>,
+    /// Read by `migrated_state`.
+    pub migrated_state: Option<Vec<u8>>,
+    /// Messages for `channel::recv`, oldest first.
+    pub inbox: VecDeque<Vec<u8>>,
+    /// Messages from `channel::send`, oldest first.
+    pub sent: Vec<(UtxoId, Vec<u8>)>,
+    /// Handlers `effect::raise` can reach that aren't registered by contract
+    /// code, by effect name.
+    pub effects: HashMap<String, Responder>,
+
+    /// Minted tokens by handle, as their intermediate's bytes.
+    pub tokens: BTreeMap<u32, Vec<u8>>,
+    /// Stand-ins for imported UTXOs, by handle: each gets the resume
+    /// argument's bytes and returns the bytes yielded. A stubbed UTXO is
+    /// always `Yielded`; other handles are `Consumed`. See
+    /// [`testkit::stub_utxo`].
+    ///
+    /// [`testkit::stub_utxo`]: crate::testkit::stub_utxo
+    pub utxos: BTreeMap<u32, Responder>,
+    /// The state each imported UTXO is yielded in and its data, by handle,
+    /// for `state fn` imports. Other handles are in no state.
+    pub utxo_states: HashMap<u32, (StateId, Vec<u8>)>,
+    /// The owners `utxo_owner!` queries return, by handle; querying another
+    /// handle panics.
+    pub utxo_owners: HashMap<u32, PublicKey>,
+    /// `(import, handle, args)` for each spawn of an imported UTXO type,
+    /// oldest first. The new UTXO has no stub in [`utxos`](Self::utxos)
+    /// until a test inserts one under its handle.
+    pub spawned: Vec<(String, u32, Vec<u8>)>,
+    /// Host operations to fail on purpose; see [`Fault`].
+    pub faults: Vec<Fault>,
+
+    /// Source for the randomness import once the host has one, and for test
+    /// fixtures meanwhile. Seeded with 0; see [`testkit::seed`].
+    ///
+    /// [`testkit::seed`]: crate::testkit::seed
+    pub rng: Rng,
+
+    /// Every traced host call, oldest first; see [`testkit::trace`].
+    ///
+    /// [`testkit::trace`]: crate::testkit::trace
+    pub trace: Vec<HostCall>,
+
+    /// What host calls cost; see [`testkit::fuel_used`].
+    ///
+    /// [`testkit::fuel_used`]: crate::testkit::fuel_used
+    pub fuel_costs: FuelCosts,
+    /// Fuel charged for host calls so far.
+    pub fuel_used: u64,
+    /// How often each import has been called, and how many bytes it copied.
+    pub host_calls: BTreeMap<&'static str, HostCallStats>,
+
+    next_handle: u32,
+    last_error: (u32, u32),
+    current_import: &'static str,
+    /// How many times each fallible operation has run, for [`Fault::nth`].
+    pub(crate) op_counts: HashMap<HostOp, u32>,
+    /// Where each UTXO handle was last resumed, for double-resume traps.
+    pub(crate) resume_sites: HashMap<u32, &'static Location<'static>>,
+    /// Handles poisoned by fatal failures; see [`crate::poison`].
+    pub(crate) poisoned: HashSet<(poison::Kind, u32)>,
+    /// State names by ID, as registered in the metadata on wasm.
+    state_names: HashMap<StateId, String>,
+    handlers: HashMap<String, extern "C" fn()>,
+    effect_payload: Vec<u8>,
+    effect_reply: Option<Vec<u8>>,
+    pending_outputs: BTreeMap<u32, MockOutput>,
+    checkpoints: Vec<Checkpoint>,
+}
+
+/// A host call recorded in [`MockHost::trace`], with the bytes that crossed
+/// the boundary.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HostCall {
+    Log(u32),
+    LogLine(String),
+    Yield {
+        state: String,
+        data: Vec<u8>,
+    },
+    /// `token` is the token type's Rust name.
+    Mint {
+        token: &'static str,
+        handle: u32,
+        intermediate: Vec<u8>,
+    },
+    Burn {
+        token: &'static str,
+        handle: u32,
+        intermediate: Vec<u8>,
+    },
+    Event {
+        topic: String,
+        payload: Vec<u8>,
+    },
+    Raise {
+        effect: String,
+        payload: Vec<u8>,
+    },
+    Call {
+        entry: String,
+        args: Vec<u8>,
+    },
+}
+
+/// Calls to one import, counted in [`MockHost::host_calls`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HostCallStats {
+    pub calls: u64,
+    /// Bytes copied across the boundary, either way.
+    pub bytes: u64,
+}
+
+/// A fallible host operation that [`Fault`]s can target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum HostOp {
+    Mint,
+    Burn,
+    /// Resuming an imported UTXO.
+    Resume,
+    /// `coordination::call`.
+    Call,
+    /// `tx::pay_fee`.
+    PayFee,
+}
+
+/// Makes a host operation fail with `error` instead of running, as the real
+/// host would report it through `last_host_error`.
+#[derive(Clone, Debug)]
+pub struct Fault {
+    pub op: HostOp,
+    /// Which run of `op` fails, counting from 1 over the mock host's
+    /// lifetime, or `None` for every run. A fault for one run is removed
+    /// once it fires.
+    pub nth: Option<u32>,
+    pub error: HostError,
+}
+
+/// The cost model for the mock host's fuel accounting. The weights are
+/// placeholders until the host publishes a fee schedule; set them to match
+/// the deployment being targeted.
+#[derive(Clone)]
+pub struct FuelCosts {
+    /// Charged for each host call.
+    pub per_call: u64,
+    /// Charged for each byte copied across the boundary, either way.
+    pub per_byte: u64,
+    /// Replaces `per_call` for the named imports, such as
+    /// `"starstream_sha256"`. Token mints and burns are `"starstream_mint"`
+    /// and `"starstream_burn"`.
+    pub calls: HashMap<String, u64>,
+}
+
+impl Default for FuelCosts {
+    fn default() -> Self {
+        FuelCosts {
+            per_call: 100,
+            per_byte: 1,
+            calls: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MockOutput {
+    pub code: Option<CodeHash>,
+    pub entry: String,
+    pub args: Vec<u8>,
+    /// Handles of the attached tokens.
+    pub tokens: Vec<u32>,
+}
+
+/// What `tx::atomic` rolls back in the mock host.
+#[derive(Clone)]
+struct Checkpoint {
+    fee_paid: u64,
+    events: usize,
+    scratch: HashMap<Vec<u8>, Vec<u8>>,
+    outputs: usize,
+    tokens: BTreeMap<u32, Vec<u8>>,
+}
+
+impl MockHost {
+    pub fn new() -> Self {
+        MockHost {
+            logs: Vec::new(),
+            log_lines: Vec::new(),
+            coordination_code: CodeHash::zero(),
+            this_code: CodeHash::zero(),
+            caller_code: CodeHash::zero(),
+            is_coordination: false,
+            is_simulation: false,
+            block_height: 0,
+            block_time: 0,
+            chain_id: 0,
+            abi_version: crate::ABI_VERSION,
+            signer_count: 0,
+            signers: None,
+            signatures: None,
+            fee: 0,
+            fee_paid: 0,
+            payload: Vec::new(),
+            result: None,
+            events: Vec::new(),
+            scratch: HashMap::new(),
+            blobs: Vec::new(),
+            bridge_roots: HashMap::new(),
+            secp256k1_keys: HashMap::new(),
+            required_code: Vec::new(),
+            outputs: Vec::new(),
+            scripts: HashMap::new(),
+            yields: Vec::new(),
+            resumes: VecDeque::new(),
+            init_args: None,
+            memo: [0; 64],
+            deadline: None,
+            expired: false,
+            rejection: None,
+            call_count: 0,
+            migration: None,
+            migrated_state: None,
+            inbox: VecDeque::new(),
+            sent: Vec::new(),
+            effects: HashMap::new(),
+            tokens: BTreeMap::new(),
+            utxos: BTreeMap::new(),
+            utxo_states: HashMap::new(),
+            utxo_owners: HashMap::new(),
+            spawned: Vec::new(),
+            faults: Vec::new(),
+            rng: Rng::new(0),
+            trace: Vec::new(),
+            fuel_costs: FuelCosts::default(),
+            fuel_used: 0,
+            host_calls: BTreeMap::new(),
+            next_handle: 1,
+            last_error: (0, 0),
+            current_import: "",
+            op_counts: HashMap::new(),
+            resume_sites: HashMap::new(),
+            poisoned: HashSet::new(),
+            state_names: HashMap::new(),
+            handlers: HashMap::new(),
+            effect_payload: Vec::new(),
+            effect_reply: None,
+            pending_outputs: BTreeMap::new(),
+            checkpoints: Vec::new(),
+        }
+    }
+
+    /// A copy of everything but [`scripts`](Self::scripts),
+    /// [`effects`](Self::effects), and [`utxos`](Self::utxos), which are left
+    /// empty since closures can't be cloned.
+    pub(crate) fn copy_state(&self) -> Self {
+        MockHost {
+            logs: self.logs.clone(),
+            log_lines: self.log_lines.clone(),
+            coordination_code: self.coordination_code,
+            this_code: self.this_code,
+            caller_code: self.caller_code,
+            is_coordination: self.is_coordination,
+            is_simulation: self.is_simulation,
+            block_height: self.block_height,
+            block_time: self.block_time,
+            chain_id: self.chain_id,
+            abi_version: self.abi_version,
+            signer_count: self.signer_count,
+            signers: self.signers.clone(),
+            signatures: self.signatures.clone(),
+            fee: self.fee,
+            fee_paid: self.fee_paid,
+            payload: self.payload.clone(),
+            result: self.result.clone(),
+            events: self.events.clone(),
+            scratch: self.scratch.clone(),
+            blobs: self.blobs.clone(),
+            bridge_roots: self.bridge_roots.clone(),
+            secp256k1_keys: self.secp256k1_keys.clone(),
+            required_code: self.required_code.clone(),
+            outputs: self.outputs.clone(),
+            scripts: HashMap::new(),
+            yields: self.yields.clone(),
+            resumes: self.resumes.clone(),
+            init_args: self.init_args.clone(),
+            memo: self.memo,
+            deadline: self.deadline,
+            expired: self.expired,
+            rejection: self.rejection,
+            call_count: self.call_count,
+            migration: self.migration.clone(),
+            migrated_state: self.migrated_state.clone(),
+            inbox: self.inbox.clone(),
+            sent: self.sent.clone(),
+            effects: HashMap::new(),
+            tokens: self.tokens.clone(),
+            utxos: BTreeMap::new(),
+            utxo_states: self.utxo_states.clone(),
+            utxo_owners: self.utxo_owners.clone(),
+            spawned: self.spawned.clone(),
+            faults: self.faults.clone(),
+            rng: self.rng.clone(),
+            trace: self.trace.clone(),
+            fuel_costs: self.fuel_costs.clone(),
+            fuel_used: self.fuel_used,
+            host_calls: self.host_calls.clone(),
+            next_handle: self.next_handle,
+            last_error: self.last_error,
+            current_import: self.current_import,
+            op_counts: self.op_counts.clone(),
+            resume_sites: self.resume_sites.clone(),
+            poisoned: self.poisoned.clone(),
+            state_names: self.state_names.clone(),
+            handlers: self.handlers.clone(),
+            effect_payload: self.effect_payload.clone(),
+            effect_reply: self.effect_reply.clone(),
+            pending_outputs: self.pending_outputs.clone(),
+            checkpoints: self.checkpoints.clone(),
+        }
+    }
+
+    pub(crate) fn handle(&mut self) -> u32 {
+        let handle = self.next_handle;
+        self.next_handle += 1;
+        handle
+    }
+
+    fn fail(&mut self, error: HostError) {
+        self.last_error = (3, error as u32);
+    }
+
+    /// Count a run of `op`, and return the error to fail it with if a
+    /// [`Fault`] matches this run.
+    fn inject(&mut self, op: HostOp) -> Option<HostError> {
+        let count = self.op_counts.entry(op).or_insert(0);
+        *count += 1;
+        let count = *count;
+        let index = (self.faults.iter())
+            .position(|fault| fault.op == op && fault.nth.is_none_or(|nth| nth == count))?;
+        let fault = match self.faults[index].nth {
+            Some(_) => self.faults.remove(index),
+            None => self.faults[index].clone(),
+        };
+        Some(fault.error)
+    }
+}
+
+impl Default for MockHost {
+    fn default() -> Self {
+        MockHost::new()
+    }
+}
+
+std::thread_local! {
+    // Shared with the threads of UTXOs this thread spawns; see `spawn`.
+    static HOST: RefCell<Arc<Mutex<MockHost>>> = RefCell::new(Arc::new(Mutex::new(MockHost::new())));
+}
+
+/// Run `f` with this thread's mock host. Don't call contract code from `f`,
+/// since the host is borrowed for its duration.
+pub fn with<R>(f: impl FnOnce(&mut MockHost) -> R) -> R {
+    let host = HOST.with(|host| host.borrow().clone());
+    // A test that panicked mid-update may have poisoned it; carry on.
+    let mut host = host.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(&mut host)
+}
+
+/// Replace this thread's mock host with a fresh one.
+pub fn reset() {
+    with(|host| *host = MockHost::new());
+}
+
+/// Clear the error channel at the start of a fallible import.
+fn fallible<R>(f: impl FnOnce(&mut MockHost) -> R) -> R {
+    with(|host| {
+        host.last_error = (0, 0);
+        f(host)
+    })
+}
+
+unsafe fn bytes<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    charge_bytes(len);
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len)
+    }
+}
+
+unsafe fn string(ptr: *const u8, len: usize) -> String {
+    String::from_utf8_lossy(bytes(ptr, len)).into_owned()
+}
+
+/// Copy `src` to `dst` for the contract, panicking like the real host does on
+/// a size mismatch.
+unsafe fn write(what: &str, src: &[u8], dst: *mut (), size: usize) {
+    charge_bytes(size);
+    copy(what, src, dst, size);
+}
+
+unsafe fn copy(what: &str, src: &[u8], dst: *mut (), size: usize) {
+    assert!(
+        src.len() == size,
+        "{what} size mismatch: got {} bytes, expected {size}",
+        src.len()
+    );
+    core::ptr::copy_nonoverlapping(src.as_ptr(), dst as *mut u8, size);
+}
+
+/// Charge a host call to [`MockHost::fuel_used`] and count it in
+/// [`MockHost::host_calls`].
+fn charge(import: &'static str) {
+    with(|host| {
+        let cost = match host.fuel_costs.calls.get(import) {
+            Some(cost) => *cost,
+            None => host.fuel_costs.per_call,
+        };
+        host.fuel_used += cost;
+        host.host_calls.entry(import).or_default().calls += 1;
+        host.current_import = import;
+    });
+}
+
+/// Charge bytes copied by the host call being made.
+fn charge_bytes(len: usize) {
+    with(|host| {
+        host.fuel_used += len as u64 * host.fuel_costs.per_byte;
+        let stats = host.host_calls.entry(host.current_import).or_default();
+        stats.bytes += len as u64;
+    });
+}
+
+fn no_utxo(utxo: u32) -> ! {
+    panic!("the mock host has no UTXO with handle {utxo}")
+}
+
+// ----------------------------------------------------------------------------
+// Common environment
+
+// `env::abort_with` is a panic whose message `testkit::Aborted` reads the
+// code back from.
+#[doc(hidden)]
+pub fn __abort_with(code: u32, msg: &str) -> ! {
+    if code == crate::env::PANIC_CODE {
+        panic!("{msg}");
+    }
+    panic!("{ABORT_PREFIX}{code}: {msg}")
+}
+
+pub(crate) const ABORT_PREFIX: &str = "aborted with code ";
+
+#[no_mangle]
+extern "C" fn starstream_log(value: u32) {
+    charge("starstream_log");
+    with(|host| {
+        host.logs.push(value);
+        host.trace.push(HostCall::Log(value));
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_log_bytes(ptr: *const u8, len: usize) {
+    charge("starstream_log_bytes");
+    let line = String::from_utf8_lossy(bytes(ptr, len)).into_owned();
+    with(|host| {
+        host.log_lines.push(line.clone());
+        host.trace.push(HostCall::LogLine(line));
+    });
+}
+
+#[no_mangle]
+extern "C" fn starstream_coordination_code() -> CodeHash {
+    charge("starstream_coordination_code");
+    with(|host| host.coordination_code)
+}
+
+#[no_mangle]
+extern "C" fn starstream_this_code() -> CodeHash {
+    charge("starstream_this_code");
+    with(|host| host.this_code)
+}
+
+#[no_mangle]
+extern "C" fn starstream_last_error() -> RawError {
+    charge("starstream_last_error");
+    with(|host| RawError {
+        kind: host.last_error.0,
+        code: host.last_error.1,
+    })
+}
+
+#[no_mangle]
+extern "C" fn starstream_is_coordination() -> bool {
+    charge("starstream_is_coordination");
+    with(|host| host.is_coordination)
+}
+
+#[no_mangle]
+extern "C" fn starstream_is_simulation() -> bool {
+    charge("starstream_is_simulation");
+    with(|host| host.is_simulation)
+}
+
+#[no_mangle]
+extern "C" fn starstream_caller_code() -> CodeHash {
+    charge("starstream_caller_code");
+    with(|host| host.caller_code)
+}
+
+#[no_mangle]
+extern "C" fn starstream_block_height() -> u64 {
+    charge("starstream_block_height");
+    with(|host| host.block_height)
+}
+
+#[no_mangle]
+extern "C" fn starstream_block_time() -> u64 {
+    charge("starstream_block_time");
+    with(|host| host.block_time)
+}
+
+#[no_mangle]
+extern "C" fn starstream_chain_id() -> u64 {
+    charge("starstream_chain_id");
+    with(|host| host.chain_id)
+}
+
+#[no_mangle]
+extern "C" fn starstream_host_abi_version() -> u32 {
+    charge("starstream_host_abi_version");
+    with(|host| host.abi_version)
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_sha256(parts: *const Part, count: usize, out: *mut [u8; 32]) {
+    charge("starstream_sha256");
+    let mut message = Vec::new();
+    for part in slice::from_raw_parts(parts, count) {
+        message.push(bytes(part.ptr, part.len));
+    }
+    *out = crate::hash::soft::sha256(&message);
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_blake2b_256(parts: *const Part, count: usize, out: *mut [u8; 32]) {
+    charge("starstream_blake2b_256");
+    let mut message = Vec::new();
+    for part in slice::from_raw_parts(parts, count) {
+        message.push(bytes(part.ptr, part.len));
+    }
+    *out = crate::hash::soft::blake2b_256(&message);
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_secp256k1_recover(
+    hash: *const [u8; 32],
+    signature: *const [u8; 65],
+    out: *mut [u8; 64],
+) -> bool {
+    charge("starstream_secp256k1_recover");
+    match with(|host| host.secp256k1_keys.get(&(*hash, *signature)).copied()) {
+        Some(key) => {
+            *out = key;
+            true
+        }
+        None => false,
+    }
+}
+
+// ----------------------------------------------------------------------------
+// UTXO environment
+
+// Wasm hosts read state names from the metadata, which the mock can't, so
+// `state!` and `sleep_named` pass them here before yielding.
+#[doc(hidden)]
+pub fn __name_state(state: StateId, name: &str) {
+    with(|host| {
+        host.state_names.entry(state).or_insert_with(|| name.into());
+    });
+}
+
+unsafe fn sleep(state: StateId, data: &[u8], resume_arg: *mut (), resume_arg_size: usize) {
+    let name = with(|host| host.state_names.get(&state).cloned());
+    let name = name.unwrap_or_else(|| std::format!("{state}"));
+    with(|host| {
+        host.yields.push((name.clone(), data.to_vec()));
+        host.trace.push(HostCall::Yield {
+            state: name.clone(),
+            data: data.to_vec(),
+        });
+    });
+    // A spawned UTXO waits for its driver rather than reading the queue.
+    let resume = match FIBER.with(|fiber| fiber.borrow().is_some()) {
+        true => Some(suspend(name, data)),
+        false => with(|host| host.resumes.pop_front()),
+    };
+    let Some(resume) = resume else {
+        panic!("UTXO slept with no resume queued");
+    };
+    write("resume argument", &resume, resume_arg, resume_arg_size);
+    with(|host| host.call_count += 1);
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn starstream_yield(
+    state: StateId,
+    data: *const (),
+    data_size: usize,
+    resume_arg: *mut (),
+    resume_arg_size: usize,
+) {
+    charge("starstream_yield");
+    sleep(
+        state,
+        bytes(data as *const u8, data_size),
+        resume_arg,
+        resume_arg_size,
+    );
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn starstream_yield_mut(
+    state: StateId,
+    data: *mut (),
+    data_size: usize,
+    resume_arg: *mut (),
+    resume_arg_size: usize,
+) {
+    charge("starstream_yield_mut");
+    sleep(
+        state,
+        bytes(data as *const u8, data_size),
+        resume_arg,
+        resume_arg_size,
+    );
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn starstream_yield_small(
+    state: StateId,
+    data: *const (),
+    data_size: usize,
+    resume_arg_size: usize,
+) -> u64 {
+    charge("starstream_yield_small");
+    let mut word = [0; 8];
+    sleep(
+        state,
+        bytes(data as *const u8, data_size),
+        word.as_mut_ptr() as *mut (),
+        resume_arg_size,
+    );
+    u64::from_le_bytes(word)
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn starstream_yield_mut_small(
+    state: StateId,
+    data: *mut (),
+    data_size: usize,
+    resume_arg_size: usize,
+) -> u64 {
+    charge("starstream_yield_mut_small");
+    let mut word = [0; 8];
+    sleep(
+        state,
+        bytes(data as *const u8, data_size),
+        word.as_mut_ptr() as *mut (),
+        resume_arg_size,
+    );
+    u64::from_le_bytes(word)
+}
+
+#[no_mangle]
+extern "C-unwind" fn starstream_set_deadline(block: u64) {
+    charge("starstream_set_deadline");
+    with(|host| host.deadline = Some(block));
+}
+
+#[no_mangle]
+extern "C-unwind" fn starstream_expired() -> bool {
+    charge("starstream_expired");
+    with(|host| host.expired)
+}
+
+#[no_mangle]
+extern "C-unwind" fn starstream_reject(code: NonZeroU32) {
+    charge("starstream_reject");
+    with(|host| host.rejection = Some(code));
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn starstream_migrate(
+    new_code: *const CodeHash,
+    entry: *const u8,
+    entry_len: usize,
+    state: *const (),
+    state_size: usize,
+) {
+    charge("starstream_migrate");
+    let migration = (
+        *new_code,
+        string(entry, entry_len),
+        bytes(state as *const u8, state_size).to_vec(),
+    );
+    with(|host| host.migration = Some(migration));
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn starstream_migrated_state(state: *mut (), state_size: usize) {
+    charge("starstream_migrated_state");
+    let Some(migrated) = with(|host| host.migrated_state.clone()) else {
+        panic!("not started by a migration");
+    };
+    write("migrated state", &migrated, state, state_size);
+}
+
+#[no_mangle]
+unsafe extern "C-unwind" fn starstream_set_memo(memo: *const [u8; 64]) {
+    charge("starstream_set_memo");
+    with(|host| host.memo = *memo);
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_init_args(args: *mut (), args_size: usize) {
+    charge("starstream_init_args");
+    let Some(init_args) = with(|host| host.init_args.clone()) else {
+        panic!("not started by a spawn");
+    };
+    write("init args", &init_args, args, args_size);
+}
+
+#[no_mangle]
+extern "C" fn starstream_call_count() -> u32 {
+    charge("starstream_call_count");
+    with(|host| host.call_count)
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_channel_send(
+    to: *const UtxoId,
+    message: *const (),
+    message_size: usize,
+) {
+    charge("starstream_channel_send");
+    let message = bytes(message as *const u8, message_size).to_vec();
+    with(|host| host.sent.push((*to, message)));
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_channel_recv(message: *mut (), message_size: usize) -> bool {
+    charge("starstream_channel_recv");
+    match with(|host| host.inbox.pop_front()) {
+        Some(next) => {
+            write("message", &next, message, message_size);
+            true
+        }
+        None => false,
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Effects
+
+#[no_mangle]
+unsafe extern "C" fn starstream_raise(
+    effect: *const u8,
+    effect_len: usize,
+    payload: *const (),
+    payload_size: usize,
+    reply: *mut (),
+    reply_size: usize,
+) {
+    charge("starstream_raise");
+    let name = string(effect, effect_len);
+    let payload = bytes(payload as *const u8, payload_size).to_vec();
+    let handler = with(|host| {
+        host.trace.push(HostCall::Raise {
+            effect: name.clone(),
+            payload: payload.clone(),
+        });
+        host.handlers.get(&name).copied()
+    });
+    let answer = if let Some(handler) = handler {
+        with(|host| {
+            host.effect_payload = payload;
+            host.effect_reply = None;
+        });
+        handler();
+        with(|host| host.effect_reply.take()).unwrap_or_default()
+    } else {
+        let Some(mut responder) = with(|host| host.effects.remove(&name)) else {
+            panic!("unhandled effect {name}");
+        };
+        let answer = responder(&payload);
+        with(|host| host.effects.insert(name, responder));
+        answer
+    };
+    write("effect reply", &answer, reply, reply_size);
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_handle(
+    effect: *const u8,
+    effect_len: usize,
+    handler: Option<extern "C" fn()>,
+) -> Option<extern "C" fn()> {
+    charge("starstream_handle");
+    let name = string(effect, effect_len);
+    with(|host| match handler {
+        Some(handler) => host.handlers.insert(name, handler),
+        None => host.handlers.remove(&name),
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_effect_payload(payload: *mut (), payload_size: usize) {
+    charge("starstream_effect_payload");
+    let current = with(|host| host.effect_payload.clone());
+    write("effect payload", &current, payload, payload_size);
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_effect_reply(reply: *const (), reply_size: usize) {
+    charge("starstream_effect_reply");
+    let reply = bytes(reply as *const u8, reply_size).to_vec();
+    with(|host| host.effect_reply = Some(reply));
+}
+
+// ----------------------------------------------------------------------------
+// Coordination
+
+#[no_mangle]
+unsafe extern "C" fn starstream_call(
+    _code: *const CodeHash,
+    entry: *const u8,
+    entry_len: usize,
+    args: *const (),
+    args_size: usize,
+    ret: *mut (),
+    ret_size: usize,
+) -> u32 {
+    charge("starstream_call");
+    let entry = string(entry, entry_len);
+    let args = bytes(args as *const u8, args_size);
+    let script = fallible(|host| {
+        host.trace.push(HostCall::Call {
+            entry: entry.clone(),
+            args: args.to_vec(),
+        });
+        match host.inject(HostOp::Call) {
+            Some(error) => Err(error),
+            None => host.scripts.remove(&entry).ok_or(HostError::NotFound),
+        }
+    });
+    let mut script = match script {
+        Ok(script) => script,
+        Err(error) => {
+            with(|host| host.fail(error));
+            return 1;
+        }
+    };
+    let answer = script(args);
+    with(|host| host.scripts.insert(entry, script));
+    if answer.len() != ret_size {
+        with(|host| host.fail(HostError::SizeMismatch));
+        return 1;
+    }
+    write("return value", &answer, ret, ret_size);
+    0
+}
+
+#[no_mangle]
+extern "C" fn starstream_tx_payload_len() -> usize {
+    charge("starstream_tx_payload_len");
+    with(|host| host.payload.len())
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_tx_payload(payload: *mut (), payload_size: usize) {
+    charge("starstream_tx_payload");
+    let current = with(|host| host.payload.clone());
+    write("payload", &current, payload, payload_size);
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_tx_result(result: *const (), result_size: usize) {
+    charge("starstream_tx_result");
+    let result = bytes(result as *const u8, result_size).to_vec();
+    with(|host| host.result = Some(result));
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_tx_require_code(code: *const CodeHash) {
+    charge("starstream_tx_require_code");
+    with(|host| host.required_code.push(*code));
+}
+
+#[no_mangle]
+extern "C" fn starstream_tx_signer_count() -> u32 {
+    charge("starstream_tx_signer_count");
+    with(|host| host.signer_count)
+}
+
+#[no_mangle]
+extern "C" fn starstream_tx_fee() -> u64 {
+    charge("starstream_tx_fee");
+    with(|host| host.fee)
+}
+
+#[no_mangle]
+extern "C" fn starstream_tx_pay_fee(token: u32, amount: u64) {
+    charge("starstream_tx_pay_fee");
+    // Intermediates are opaque to the mock host, so any token covers any
+    // amount, and stays with the script.
+    fallible(|host| {
+        if let Some(error) = host.inject(HostOp::PayFee) {
+            host.fail(error);
+        } else if host.tokens.contains_key(&token) {
+            host.fee_paid += amount;
+        } else {
+            host.fail(HostError::NotFound);
+        }
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_emit_event(
+    topic: *const u8,
+    topic_len: usize,
+    payload: *const (),
+    payload_size: usize,
+) {
+    charge("starstream_emit_event");
+    let event = (
+        string(topic, topic_len),
+        bytes(payload as *const u8, payload_size).to_vec(),
+    );
+    with(|host| {
+        host.trace.push(HostCall::Event {
+            topic: event.0.clone(),
+            payload: event.1.clone(),
+        });
+        host.events.push(event);
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_emit_event_parts(
+    topic: *const u8,
+    topic_len: usize,
+    parts: *const Part,
+    count: usize,
+) {
+    charge("starstream_emit_event_parts");
+    let topic = string(topic, topic_len);
+    let mut payload = Vec::new();
+    for part in slice::from_raw_parts(parts, count) {
+        payload.extend_from_slice(bytes(part.ptr, part.len));
+    }
+    with(|host| {
+        host.trace.push(HostCall::Event {
+            topic: topic.clone(),
+            payload: payload.clone(),
+        });
+        host.events.push((topic, payload));
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_tx_event(
+    topic: *const u8,
+    topic_len: usize,
+    index: u32,
+    payload: *mut (),
+    payload_size: usize,
+) -> bool {
+    charge("starstream_tx_event");
+    let topic = string(topic, topic_len);
+    let event = with(|host| {
+        (host.events.iter())
+            .filter(|(t, _)| *t == topic)
+            .nth(index as usize)
+            .map(|(_, payload)| payload.clone())
+    });
+    match event {
+        Some(event) => {
+            write("event payload", &event, payload, payload_size);
+            true
+        }
+        None => false,
+    }
+}
+
+fn blob(handle: u32) -> Vec<u8> {
+    let blob = with(|host| host.blobs.get((handle as usize).wrapping_sub(1)).cloned());
+    blob.unwrap_or_else(|| panic!("the mock host has no blob with handle {handle}"))
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_blob_new(ptr: *const u8, len: usize) -> u32 {
+    charge("starstream_blob_new");
+    let blob = bytes(ptr, len).to_vec();
+    with(|host| {
+        host.blobs.push(blob);
+        host.blobs.len() as u32
+    })
+}
+
+#[no_mangle]
+extern "C" fn starstream_blob_len(handle: u32) -> usize {
+    charge("starstream_blob_len");
+    blob(handle).len()
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_blob_read(
+    handle: u32,
+    offset: usize,
+    out: *mut u8,
+    out_len: usize,
+) -> usize {
+    charge("starstream_blob_read");
+    let blob = blob(handle);
+    let rest = blob.get(offset..).unwrap_or_default();
+    let n = rest.len().min(out_len);
+    charge_bytes(n);
+    core::ptr::copy_nonoverlapping(rest.as_ptr(), out, n);
+    n
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_bridge_root(
+    client: ClientId,
+    height: *const Height,
+    out: *mut Root,
+) -> bool {
+    charge("starstream_bridge_root");
+    let height = *height;
+    match with(|host| host.bridge_roots.get(&(client, height)).copied()) {
+        Some(root) => {
+            *out = root;
+            true
+        }
+        None => false,
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_scratch_set(
+    key: *const u8,
+    key_len: usize,
+    value: *const u8,
+    value_len: usize,
+) {
+    charge("starstream_scratch_set");
+    let (key, value) = (
+        bytes(key, key_len).to_vec(),
+        bytes(value, value_len).to_vec(),
+    );
+    with(|host| host.scratch.insert(key, value));
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_scratch_get(
+    key: *const u8,
+    key_len: usize,
+    out: *mut u8,
+    out_len: usize,
+    value_len: *mut usize,
+) -> bool {
+    charge("starstream_scratch_get");
+    let key = bytes(key, key_len);
+    match with(|host| host.scratch.get(key).cloned()) {
+        Some(value) => {
+            let n = value.len().min(out_len);
+            core::ptr::copy_nonoverlapping(value.as_ptr(), out, n);
+            *value_len = value.len();
+            true
+        }
+        None => false,
+    }
+}
+
+#[no_mangle]
+extern "C" fn starstream_checkpoint() -> u32 {
+    charge("starstream_checkpoint");
+    with(|host| {
+        let checkpoint = Checkpoint {
+            fee_paid: host.fee_paid,
+            events: host.events.len(),
+            scratch: host.scratch.clone(),
+            outputs: host.outputs.len(),
+            tokens: host.tokens.clone(),
+        };
+        host.checkpoints.push(checkpoint);
+        host.checkpoints.len() as u32
+    })
+}
+
+fn end_checkpoint(host: &mut MockHost, checkpoint: u32) -> Checkpoint {
+    assert!(
+        checkpoint as usize == host.checkpoints.len(),
+        "checkpoint {checkpoint} ended out of order"
+    );
+    host.checkpoints.pop().unwrap()
+}
+
+#[no_mangle]
+extern "C" fn starstream_rollback(checkpoint: u32) {
+    charge("starstream_rollback");
+    with(|host| {
+        let saved = end_checkpoint(host, checkpoint);
+        host.fee_paid = saved.fee_paid;
+        host.events.truncate(saved.events);
+        host.scratch = saved.scratch;
+        host.outputs.truncate(saved.outputs);
+        host.tokens = saved.tokens;
+    });
+}
+
+#[no_mangle]
+extern "C" fn starstream_release(checkpoint: u32) {
+    charge("starstream_release");
+    with(|host| {
+        end_checkpoint(host, checkpoint);
+    });
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_output_begin(
+    code: *const CodeHash,
+    entry: *const u8,
+    entry_len: usize,
+) -> u32 {
+    charge("starstream_output_begin");
+    let output = MockOutput {
+        code: Some(*code),
+        entry: string(entry, entry_len),
+        ..MockOutput::default()
+    };
+    with(|host| {
+        let handle = host.handle();
+        host.pending_outputs.insert(handle, output);
+        handle
+    })
+}
+
+fn pending_output<R>(output: u32, f: impl FnOnce(&mut MockOutput) -> R) -> R {
+    with(|host| match host.pending_outputs.get_mut(&output) {
+        Some(pending) => f(pending),
+        None => panic!("invalid output handle: {output}"),
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_output_args(output: u32, args: *const (), args_size: usize) {
+    charge("starstream_output_args");
+    let args = bytes(args as *const u8, args_size).to_vec();
+    pending_output(output, |pending| pending.args = args);
+}
+
+#[no_mangle]
+extern "C" fn starstream_output_attach(output: u32, token: u32) {
+    charge("starstream_output_attach");
+    pending_output(output, |pending| pending.tokens.push(token));
+}
+
+#[no_mangle]
+extern "C" fn starstream_output_finish(output: u32) {
+    charge("starstream_output_finish");
+    with(|host| match host.pending_outputs.remove(&output) {
+        Some(pending) => host.outputs.push(pending),
+        None => panic!("invalid output handle: {output}"),
+    });
+}
+
+// ----------------------------------------------------------------------------
+// UTXO handles
+
+#[no_mangle]
+unsafe extern "C" fn starstream_tx_inputs(_cursor: u32, _out: *mut u32, _out_len: usize) -> usize {
+    charge("starstream_tx_inputs");
+    0
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_tx_outputs(_cursor: u32, _out: *mut u32, _out_len: usize) -> usize {
+    charge("starstream_tx_outputs");
+    0
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_find_utxos(
+    _code: *const CodeHash,
+    _owner: *const PublicKey,
+    _cursor: u32,
+    _out: *mut u32,
+    _out_len: usize,
+) -> usize {
+    charge("starstream_find_utxos");
+    0
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_lookup_utxo(_id: *const UtxoId) -> u32 {
+    charge("starstream_lookup_utxo");
+    UtxoHandle::<()>::INVALID
+}
+
+#[no_mangle]
+extern "C" fn starstream_utxo_id(utxo: u32) -> UtxoId {
+    charge("starstream_utxo_id");
+    no_utxo(utxo)
+}
+
+#[no_mangle]
+extern "C" fn starstream_utxo_expire(utxo: u32) {
+    charge("starstream_utxo_expire");
+    no_utxo(utxo)
+}
+
+#[no_mangle]
+extern "C" fn starstream_utxo_last_error(utxo: u32) -> RawError {
+    charge("starstream_utxo_last_error");
+    no_utxo(utxo)
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_utxo_memo(utxo: u32, _out: *mut [u8; 64]) {
+    charge("starstream_utxo_memo");
+    no_utxo(utxo)
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_utxo_attach(utxo: u32, _token: u32) {
+    charge("starstream_utxo_attach");
+    no_utxo(utxo)
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_utxo_detach(
+    utxo: u32,
+    _token_module: *const u8,
+    _token_module_len: usize,
+    _token_name: *const u8,
+    _token_name_len: usize,
+    _amount: u64,
+) -> u32 {
+    charge("starstream_utxo_detach");
+    no_utxo(utxo)
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_utxo_detach_amount(
+    utxo: u32,
+    _token_module: *const u8,
+    _token_module_len: usize,
+    _token_name: *const u8,
+    _token_name_len: usize,
+    _amount: u64,
+) -> u32 {
+    charge("starstream_utxo_detach_amount");
+    no_utxo(utxo)
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_utxo_tokens(
+    utxo: u32,
+    _cursor: u32,
+    _out: *mut AnyToken,
+    _out_len: usize,
+) -> usize {
+    charge("starstream_utxo_tokens");
+    no_utxo(utxo)
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_utxo_resume_batch(
+    _entries: *const BatchResume<'_>,
+    len: usize,
+) -> u32 {
+    charge("starstream_utxo_resume_batch");
+    assert!(len == 0, "the mock host has no UTXOs to resume");
+    0
+}
+
+#[no_mangle]
+unsafe extern "C" fn starstream_utxo_is(
+    utxo: u32,
+    _module: *const u8,
+    _module_len: usize,
+    _name: *const u8,
+    _name_len: usize,
+) -> bool {
+    charge("starstream_utxo_is");
+    no_utxo(utxo)
+}
+
+#[doc(hidden)]
+pub fn __utxo_status<T: ?Sized>(utxo: UtxoHandle<T>) -> UtxoStatus {
+    charge("starstream_status");
+    with(|host| match host.utxos.contains_key(&utxo.raw()) {
+        true => UtxoStatus::Yielded,
+        false => UtxoStatus::Consumed,
+    })
+}
+
+#[doc(hidden)]
+pub unsafe fn __resume<T: ?Sized>(
+    utxo: UtxoHandle<T>,
+    resume_arg: *const (),
+    resume_arg_size: usize,
+    yield_out: *mut (),
+    yield_out_size: usize,
+) -> u32 {
+    charge("starstream_resume");
+    let arg = bytes(resume_arg as *const u8, resume_arg_size);
+    let stub = fallible(|host| match host.inject(HostOp::Resume) {
+        Some(error) => Err(error),
+        None => host.utxos.remove(&utxo.raw()).ok_or(HostError::AlreadyConsumed),
+    });
+    let mut stub = match stub {
+        Ok(stub) => stub,
+        Err(error) => {
+            with(|host| host.fail(error));
+            return 1;
+        }
+    };
+    let yielded = stub(arg);
+    with(|host| host.utxos.insert(utxo.raw(), stub));
+    write("yielded value", &yielded, yield_out, yield_out_size);
+    0
+}
+
+#[doc(hidden)]
+pub unsafe fn __utxo_state<T: ?Sized>(
+    utxo: UtxoHandle<T>,
+    state: StateId,
+    data_out: *mut (),
+    data_out_size: usize,
+) -> u32 {
+    charge("starstream_state");
+    let data = with(|host| match host.utxo_states.get(&utxo.raw()) {
+        Some((yielded_in, data)) if *yielded_in == state => Some(data.clone()),
+        _ => None,
+    });
+    match data {
+        // A size of 0 only asks whether it is yielded in `state`.
+        Some(data) if data_out_size != 0 => {
+            write("state data", &data, data_out, data_out_size);
+            1
+        }
+        Some(_) => 1,
+        None => 0,
+    }
+}
+
+#[doc(hidden)]
+pub fn __utxo_owner<T: ?Sized>(utxo: UtxoHandle<T>) -> PublicKey {
+    charge("starstream_query_owner");
+    match with(|host| host.utxo_owners.get(&utxo.raw()).copied()) {
+        Some(owner) => owner,
+        None => no_utxo(utxo.raw()),
+    }
+}
+
+#[doc(hidden)]
+pub unsafe fn __spawn<T: ?Sized>(
+    import: &'static str,
+    args: *const (),
+    args_size: usize,
+) -> UtxoHandle<T> {
+    charge("starstream_spawn");
+    let args = bytes(args as *const u8, args_size).to_vec();
+    let raw = with(|host| {
+        let handle = host.handle();
+        host.spawned.push((import.into(), handle, args));
+        handle
+    });
+    UtxoHandle::from_raw(raw).expect("mock handles start at 1")
+}
+
+// ----------------------------------------------------------------------------
+// Signatures
+
+// Keys have no representation yet, so a registered key matches any key; what
+// tests control is whether anything verifies.
+
+#[doc(hidden)]
+pub fn __tx_signed_by(key: PublicKey) -> bool {
+    with(|host| match &host.signers {
+        Some(signers) => signers.contains(&key),
+        None => true,
+    })
+}
+
+#[doc(hidden)]
+pub fn __signature_valid(message: &[u8]) -> bool {
+    with(|host| match &host.signatures {
+        Some(signatures) => signatures.iter().any(|(_, signed)| signed == message),
+        None => true,
+    })
+}
+
+// ----------------------------------------------------------------------------
+// Tokens
+
+#[doc(hidden)]
+pub fn __mint<T: ?Sized, I: FfiSafe>(intermediate: I) -> Option<TokenHandle<T>> {
+    charge("starstream_mint");
+    TokenHandle::from_raw(mint::<T>(bytes_of(&intermediate)))
+}
+
+/// # Safety
+///
+/// `out` must have room for a handle per intermediate.
+#[doc(hidden)]
+pub unsafe fn __mint_batch<T: ?Sized, I: FfiSafe>(
+    intermediates: &[I],
+    out: *mut Option<TokenHandle<T>>,
+) -> usize {
+    charge("starstream_mint_batch");
+    for (index, intermediate) in intermediates.iter().enumerate() {
+        let Some(handle) = TokenHandle::from_raw(mint::<T>(bytes_of(intermediate))) else {
+            return index;
+        };
+        out.add(index).write(Some(handle));
+    }
+    intermediates.len()
+}
+
+fn mint<T: ?Sized>(intermediate: &[u8]) -> u32 {
+    charge_bytes(intermediate.len());
+    let bytes = intermediate.to_vec();
+    fallible(|host| {
+        if let Some(error) = host.inject(HostOp::Mint) {
+            host.fail(error);
+            return TokenHandle::<T>::INVALID;
+        }
+        let handle = host.handle();
+        host.trace.push(HostCall::Mint {
+            token: core::any::type_name::<T>(),
+            handle,
+            intermediate: bytes.clone(),
+        });
+        host.tokens.insert(handle, bytes);
+        handle
+    })
+}
+
+#[doc(hidden)]
+pub fn __burn<T: ?Sized, I: FfiSafe>(handle: TokenHandle<T>) -> I {
+    charge("starstream_burn");
+    let mut intermediate = MaybeUninit::<I>::uninit();
+    if !unsafe { burn(handle, intermediate.as_mut_ptr()) } {
+        // Placeholder, discarded by `try_burn`; any bytes are a valid I.
+        return unsafe { MaybeUninit::zeroed().assume_init() };
+    }
+    unsafe { intermediate.assume_init() }
+}
+
+/// # Safety
+///
+/// `out` must have room for an intermediate per handle.
+#[doc(hidden)]
+pub unsafe fn __burn_batch<T: ?Sized, I: FfiSafe>(
+    handles: &[TokenHandle<T>],
+    out: *mut I,
+) -> usize {
+    charge("starstream_burn_batch");
+    for (index, handle) in handles.iter().enumerate() {
+        if !burn(*handle, out.add(index)) {
+            return index;
+        }
+    }
+    handles.len()
+}
+
+/// Burn `handle` into `out`, or record the failure and return false.
+unsafe fn burn<T: ?Sized, I: FfiSafe>(handle: TokenHandle<T>, out: *mut I) -> bool {
+    let taken = fallible(|host| match host.inject(HostOp::Burn) {
+        Some(error) => Err(error),
+        None => host.tokens.remove(&handle.raw()).ok_or(HostError::NotFound),
+    });
+    let bytes = match taken {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            with(|host| host.fail(error));
+            return false;
+        }
+    };
+    with(|host| {
+        host.trace.push(HostCall::Burn {
+            token: core::any::type_name::<T>(),
+            handle: handle.raw(),
+            intermediate: bytes.clone(),
+        })
+    });
+    write("intermediate", &bytes, out as *mut (), size_of::<I>());
+    true
+}
+
+// ----------------------------------------------------------------------------
+// Scheduler
+
+/// How a UTXO started with [`spawn`] last stopped.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MockStatus {
+    /// Asleep in the named state, waiting to be resumed.
+    Yielded(String),
+    /// Its body returned, ending its lifetime.
+    Returned,
+    /// Its body panicked, like a trap on the real host.
+    Errored,
+}
+
+enum Stop {
+    Yielded(String, Vec<u8>),
+    Returned,
+    Errored(String),
+}
+
+/// The UTXO side of a [`MockUtxo`]'s channels.
+struct Fiber {
+    stops: Sender<Stop>,
+    resumes: Receiver<Vec<u8>>,
+}
+
+std::thread_local! {
+    static FIBER: RefCell<Option<Fiber>> = const { RefCell::new(None) };
+}
+
+/// Unwinds a spawned UTXO whose [`MockUtxo`] was dropped while it slept.
+struct Abandoned;
+
+/// Report a `sleep` to the driver and wait for its resume argument.
+fn suspend(name: String, data: &[u8]) -> Vec<u8> {
+    let resume = FIBER.with(|fiber| {
+        let fiber = fiber.borrow();
+        let fiber = fiber.as_ref().unwrap();
+        let _ = fiber.stops.send(Stop::Yielded(name, data.to_vec()));
+        fiber.resumes.recv()
+    });
+    match resume {
+        Ok(resume) => resume,
+        Err(_) => panic::resume_unwind(Box::new(Abandoned)),
+    }
+}
+
+/// A UTXO whose body runs on its own thread, so that `sleep` really
+/// suspends it and [`resume`](MockUtxo::resume) continues where it left off.
+/// `Yield` and `Resume` are the types its body passes to `sleep`.
+///
+/// Only one of the test thread and its UTXOs runs at a time, and they all
+/// share the test thread's [`MockHost`], so a test behaves as it would with
+/// the scheduler on the real host.
+pub struct MockUtxo<Yield, Resume> {
+    status: MockStatus,
+    data: Vec<u8>,
+    error: Option<String>,
+    stops: Receiver<Stop>,
+    resumes: Option<Sender<Vec<u8>>>,
+    thread: Option<JoinHandle<()>>,
+    /// Where it was last resumed, for the trap on resuming it after it ends.
+    last_resume: Option<&'static Location<'static>>,
+    _phantom: PhantomData<fn(Resume) -> Yield>,
+}
+
+/// Start a UTXO body, such as `|| MyMain::new(starstream::sleep)`, and run
+/// it until it first sleeps or ends.
+pub fn spawn<Yield: FfiSafe, Resume: FfiSafe>(
+    body: impl FnOnce() + Send + 'static,
+) -> MockUtxo<Yield, Resume> {
+    let host = HOST.with(|host| host.borrow().clone());
+    let (stop_tx, stops) = mpsc::channel();
+    let (resumes, resume_rx) = mpsc::channel();
+    let thread = thread::spawn(move || {
+        HOST.with(|this| *this.borrow_mut() = host);
+        let fiber = Fiber {
+            stops: stop_tx.clone(),
+            resumes: resume_rx,
+        };
+        FIBER.with(|this| *this.borrow_mut() = Some(fiber));
+        let stop = match panic::catch_unwind(AssertUnwindSafe(body)) {
+            Ok(()) => Stop::Returned,
+            Err(payload) => Stop::Errored(Aborted::from_panic(&*payload).message),
+        };
+        let _ = stop_tx.send(stop);
+    });
+    let mut utxo = MockUtxo {
+        status: MockStatus::Returned,
+        data: Vec::new(),
+        error: None,
+        stops,
+        resumes: Some(resumes),
+        thread: Some(thread),
+        last_resume: None,
+        _phantom: PhantomData,
+    };
+    utxo.wait();
+    utxo
+}
+
+impl<Yield: FfiSafe, Resume: FfiSafe> MockUtxo<Yield, Resume> {
+    fn wait(&mut self) {
+        match self.stops.recv() {
+            Ok(Stop::Yielded(name, data)) => {
+                self.status = MockStatus::Yielded(name);
+                self.data = data;
+            }
+            Ok(Stop::Returned) => self.status = MockStatus::Returned,
+            Ok(Stop::Errored(message)) => {
+                self.status = MockStatus::Errored;
+                self.error = Some(message);
+            }
+            Err(_) => self.status = MockStatus::Errored,
+        }
+    }
+
+    pub fn status(&self) -> &MockStatus {
+        &self.status
+    }
+
+    /// The panic message of a UTXO that has [`Errored`](MockStatus::Errored).
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Whether the UTXO is asleep and can be resumed.
+    pub fn is_alive(&self) -> bool {
+        matches!(self.status, MockStatus::Yielded(_))
+    }
+
+    /// The value the UTXO is asleep with, or `None` if it has ended.
+    pub fn yielded(&self) -> Option<Yield> {
+        if !self.is_alive() {
+            return None;
+        }
+        let mut data = MaybeUninit::<Yield>::uninit();
+        unsafe {
+            copy(
+                "yielded value",
+                &self.data,
+                data.as_mut_ptr() as *mut (),
+                size_of::<Yield>(),
+            );
+            Some(data.assume_init())
+        }
+    }
+
+    /// Continue the UTXO from its `sleep` with `arg`, and run it until it
+    /// sleeps again or ends. Panics if it has already ended.
+    #[track_caller]
+    pub fn resume(&mut self, arg: Resume) -> &MockStatus {
+        let here = Location::caller();
+        if !self.is_alive() {
+            match self.last_resume {
+                Some(previous) => panic!(
+                    "UTXO resumed at {here} after it ended; last resumed at {previous}"
+                ),
+                None => panic!("UTXO resumed at {here} after it ended"),
+            }
+        }
+        self.last_resume = Some(here);
+        let arg = bytes_of(&arg).to_vec();
+        if let Some(resumes) = &self.resumes {
+            let _ = resumes.send(arg);
+        }
+        self.wait();
+        &self.status
+    }
+}
+
+impl<Yield, Resume> Drop for MockUtxo<Yield, Resume> {
+    fn drop(&mut self) {
+        // Closing the channel unwinds a sleeping body, so its thread ends.
+        self.resumes = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{vec, vec::Vec};
+
+    use super::*;
+    use crate::{coordination, testkit, tx, Token};
+
+    crate::token_import! {
+        from "tok";
+        type Coin;
+        intermediate struct CoinI { amount: u64 }
+        mint fn starstream_mint_Coin;
+        burn fn starstream_burn_Coin;
+    }
+
+    crate::utxo_import! {
+        "utxo";
+        Counter;
+        starstream_status_Counter;
+        starstream_resume_Counter;
+        ();
+        u64;
+    }
+
+    crate::utxo_states! {
+        from "utxo";
+        for Counter;
+        state fn starstream_state_Counter;
+        enum CounterState {
+            Counting(u64) = "counting" => counting,
+        }
+    }
+
+    crate::utxo_owner! {
+        from "utxo";
+        for Counter;
+        query fn starstream_query_Counter_owner;
+    }
+
+    crate::utxo_spawn! {
+        from "utxo";
+        for Counter;
+        spawn fn starstream_spawn_Counter_new(u64) => new;
+    }
+
+    fn fill() {
+        tx::emit_event("filled", &7u64);
+        tx::scratch_set(b"k", b"v");
+        let _ = CoinI { amount: 3 }.mint();
+        let coin = Coin::mint(CoinI { amount: 5 });
+        tx::pay_fee_from(coin, 2).unwrap();
+    }
+
+    fn effects() -> (usize, usize, usize, u64) {
+        with(|host| {
+            (
+                host.events.len(),
+                host.scratch.len(),
+                host.tokens.len(),
+                host.fee_paid,
+            )
+        })
+    }
+
+    #[test]
+    fn atomic_rolls_back_on_error() {
+        let undone = tx::atomic(|_| {
+            fill();
+            Err::<(), _>(())
+        });
+        assert!(undone.is_err());
+        assert_eq!(effects(), (0, 0, 0, 0));
+        with(|host| assert!(host.checkpoints.is_empty()));
+    }
+
+    #[test]
+    fn atomic_keeps_effects_on_success() {
+        tx::atomic(|_| {
+            fill();
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        assert_eq!(effects(), (1, 1, 2, 2));
+        with(|host| assert!(host.checkpoints.is_empty()));
+    }
+
+    #[test]
+    fn nested_rollback_only_undoes_the_inner_scope() {
+        tx::atomic(|_| {
+            tx::emit_event("outer", &1u64);
+            let inner = tx::atomic(|_| {
+                fill();
+                Err::<(), _>(())
+            });
+            assert!(inner.is_err());
+            Ok::<_, ()>(())
+        })
+        .unwrap();
+        with(|host| {
+            let topics: Vec<_> = host
+                .events
+                .iter()
+                .map(|(topic, _)| topic.as_str())
+                .collect();
+            assert_eq!(topics, ["outer"]);
+            assert!(host.scratch.is_empty() && host.tokens.is_empty());
+        });
+    }
+
+    #[test]
+    fn fault_fires_on_the_nth_run_only() {
+        testkit::fail_nth(HostOp::Mint, 2, HostError::Unauthorized);
+        assert!(Coin::try_mint(CoinI { amount: 1 }).is_ok());
+        assert_eq!(
+            Coin::try_mint(CoinI { amount: 1 }).err(),
+            Some(HostError::Unauthorized)
+        );
+        assert!(Coin::try_mint(CoinI { amount: 1 }).is_ok());
+        with(|host| {
+            assert!(host.faults.is_empty());
+            assert_eq!(host.tokens.len(), 2);
+        });
+    }
+
+    #[test]
+    fn fault_for_every_run_stays() {
+        testkit::fail_always(HostOp::Burn, HostError::NotFound);
+        let coin = Coin::mint(CoinI { amount: 9 });
+        assert_eq!(coin.try_burn().err(), Some(HostError::NotFound));
+        assert_eq!(coin.try_burn().err(), Some(HostError::NotFound));
+        with(|host| {
+            assert_eq!(host.faults.len(), 1);
+            assert_eq!(host.tokens.len(), 1);
+        });
+    }
+
+    #[test]
+    fn failed_call_leaves_the_script_deployed() {
+        with(|host| {
+            let script: Responder = Box::new(|_| vec![1, 0, 0, 0]);
+            host.scripts.insert("ping".into(), script);
+        });
+        testkit::fail_nth(HostOp::Call, 1, HostError::TooDeep);
+        let call = || coordination::call::<_, u32>(CodeHash::zero(), "ping", &0u32);
+        assert_eq!(call().err(), Some(HostError::TooDeep));
+        assert_eq!(call().ok(), Some(1));
+    }
+
+    #[test]
+    fn fatal_fault_poisons_the_token() {
+        testkit::fail_nth(HostOp::PayFee, 1, HostError::Unauthorized);
+        testkit::fail_nth(HostOp::PayFee, 2, HostError::SizeMismatch);
+        let coin = Coin::mint(CoinI { amount: 5 });
+        assert_eq!(
+            tx::pay_fee_from(coin, 1).err(),
+            Some(HostError::Unauthorized)
+        );
+        assert_eq!(
+            tx::pay_fee_from(coin, 1).err(),
+            Some(HostError::SizeMismatch)
+        );
+        assert_eq!(tx::pay_fee_from(coin, 1).err(), Some(HostError::Poisoned));
+        with(|host| assert_eq!(host.fee_paid, 0));
+    }
+
+    #[test]
+    fn instance_flags_are_per_thread() {
+        // As parallel tests, or a test and the UTXOs it spawns, would be.
+        let _guard = crate::ReentrancyGuard::enter();
+        let _operation = crate::__TokenOperation::enter();
+        std::thread::spawn(|| {
+            assert!(crate::ReentrancyGuard::try_enter().is_some());
+            crate::assert_not_in_token_operation();
+        })
+        .join()
+        .unwrap();
+        assert!(crate::ReentrancyGuard::try_enter().is_none());
+    }
+
+    #[test]
+    fn imported_utxo_macros_use_the_mock_host() {
+        let counter = Counter::new(5);
+        let raw = counter.0.raw();
+        with(|host| {
+            let (import, handle, args) = &host.spawned[0];
+            assert_eq!(import, "starstream_spawn_Counter_new");
+            assert_eq!(*handle, raw);
+            assert_eq!(*args, 5u64.to_le_bytes());
+        });
+        assert_eq!(counter.state(), None);
+
+        let key = crate::PrivateKey.public_key();
+        with(|host| {
+            let state = (StateId::of("counting"), 6u64.to_le_bytes().to_vec());
+            host.utxo_states.insert(raw, state);
+            host.utxo_owners.insert(raw, key);
+        });
+        assert_eq!(counter.state(), Some(CounterState::Counting));
+        assert_eq!(counter.counting(), Some(6));
+        assert!(crate::OwnedUtxo::owner(counter) == key);
+    }
+}
@@ -0,0 +1,153 @@
+//! Events in the Paima engine's format, so games built on Paima can index
+//! Starstream contract activity with the engine's own game input parsing
+//! instead of a bespoke adapter. Enabled by the `paima` feature, which turns
+//! on `evm-events`.
+//!
+//! Paima reads game inputs from the `PaimaGameInteraction` logs of its L2
+//! contract: the user's address, indexed, then the input as `bytes` and the
+//! value paid with it. The input is in Paima's concise encoding, a command
+//! prefix followed by `|`-separated values, with `*` marking the values that
+//! identify game state:
+//!
+//! ```ignore
+//! // "j|*lobby42|3": join lobby42 in seat 3.
+//! let mut input = Concise::<64>::new("j");
+//! input.state_identifier("lobby42").uint(3);
+//! paima::emit_interaction(player, input.finish()?, 0);
+//! ```
+//!
+//! The log is emitted like any other [`evm`] log, so the engine's EVM
+//! primitives see it under its usual topic.
+
+use core::fmt;
+
+use crate::{evm, tx};
+
+/// The Solidity signature of the event Paima reads game inputs from.
+pub const INTERACTION_SIGNATURE: &str = "PaimaGameInteraction(address,bytes,uint256)";
+
+/// Why a [`Concise`] input couldn't be built.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The input is longer than the buffer.
+    TooLong,
+    /// A value contains the `|` separator.
+    Separator,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Error::TooLong => "Paima input longer than its buffer",
+            Error::Separator => "Paima input value contains '|'",
+        })
+    }
+}
+
+/// Builds a game input in Paima's concise encoding in a buffer of `N`
+/// bytes. The first error is kept and returned by [`finish`](Self::finish),
+/// so values can be added without checking each one.
+pub struct Concise<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    error: Option<Error>,
+}
+
+impl<const N: usize> Concise<N> {
+    /// An input for the command `prefix`.
+    pub fn new(prefix: &str) -> Self {
+        let mut input = Concise {
+            buf: [0; N],
+            len: 0,
+            error: None,
+        };
+        input.push(b"", prefix.as_bytes());
+        input
+    }
+
+    /// Add a value.
+    pub fn value(&mut self, value: &str) -> &mut Self {
+        self.push(b"|", value.as_bytes())
+    }
+
+    /// Add a value that identifies game state, such as a lobby, which Paima
+    /// marks with `*`.
+    pub fn state_identifier(&mut self, value: &str) -> &mut Self {
+        self.push(b"|*", value.as_bytes())
+    }
+
+    /// Add an unsigned integer, in decimal.
+    pub fn uint(&mut self, value: u64) -> &mut Self {
+        let mut digits = [0; 20];
+        let mut start = digits.len();
+        let mut rest = value;
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        self.push(b"|", &digits[start..])
+    }
+
+    /// Add a boolean, which Paima encodes as `T` for true and nothing for
+    /// false.
+    pub fn bool(&mut self, value: bool) -> &mut Self {
+        self.value(if value { "T" } else { "" })
+    }
+
+    /// The encoded input, or the first error.
+    pub fn finish(&self) -> Result<&[u8], Error> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(&self.buf[..self.len]),
+        }
+    }
+
+    /// Append `marker`, such as the separator, then `value`.
+    fn push(&mut self, marker: &[u8], value: &[u8]) -> &mut Self {
+        if self.error.is_some() {
+            return self;
+        }
+        if value.contains(&b'|') {
+            self.error = Some(Error::Separator);
+            return self;
+        }
+        let end = self.len + marker.len() + value.len();
+        match self.buf.get_mut(self.len..end) {
+            Some(out) => {
+                let (m, v) = out.split_at_mut(marker.len());
+                m.copy_from_slice(marker);
+                v.copy_from_slice(value);
+                self.len = end;
+            }
+            None => self.error = Some(Error::TooLong),
+        }
+        self
+    }
+}
+
+/// Emit a `PaimaGameInteraction` log for `input` from the user with address
+/// `user`, who paid `value` with it.
+pub fn emit_interaction(user: [u8; 20], input: &[u8], value: u128) {
+    let topic = evm::topic(INTERACTION_SIGNATURE);
+    let padding = [0; 32];
+    // `bytes` is dynamic: its place in the data holds the offset of its
+    // length, and its contents follow the static parameters, padded to a
+    // whole word.
+    tx::emit_event_parts(
+        evm::LOG_TOPIC,
+        [
+            &[2],
+            &topic,
+            &evm::address(user),
+            &evm::uint(0x40),
+            &evm::uint(value),
+            &evm::uint(input.len() as u128),
+            input,
+            &padding[..input.len().wrapping_neg() % 32],
+        ],
+    );
+}
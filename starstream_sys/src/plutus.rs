@@ -0,0 +1,453 @@
+//! Plutus Data, the encoding of Cardano datums and redeemers, so contracts
+//! that mirror or bridge Cardano-native assets and scripts can pass their
+//! intermediates and resume arguments to and from Plutus validators.
+//!
+//! Plutus Data is CBOR made of integers, byte strings, lists, maps, and
+//! constructors, a constructor being the index of a variant and a list of
+//! fields, as values of a Plutus `data` type are. [`ToData`] and
+//! [`FromData`] write and read a type on a CBOR [`Encoder`] or [`Decoder`],
+//! in the form `cardano-node` produces, so the bytes hash the same as the
+//! datum on chain:
+//!
+//! ```ignore
+//! // data Swap = Swap { owner :: BuiltinByteString, amount :: Integer }
+//! impl ToData for SwapIntermediate {
+//!     fn to_data(&self, e: &mut Encoder) {
+//!         plutus::constr(e, 0, &[&self.owner, &self.amount]);
+//!     }
+//! }
+//!
+//! impl FromData<'_> for SwapIntermediate {
+//!     fn from_data(d: &mut Decoder) -> Result<Self, Error> {
+//!         let mut swap = plutus::read_constr(d)?.expect(0)?;
+//!         let value = SwapIntermediate {
+//!             owner: swap.field()?,
+//!             amount: swap.field()?,
+//!         };
+//!         swap.finish()?;
+//!         Ok(value)
+//!     }
+//! }
+//! ```
+//!
+//! Maps are CBOR maps of Plutus Data, written with [`Encoder::map`] and read
+//! with [`Decoder::map`]. Integers are limited to 64 bits; Plutus's bigger
+//! ones are CBOR bignums, which read as [`Error::UnexpectedType`].
+
+use crate::cbor::{Decoder, Encoder, Error, Type};
+
+/// A type with a Plutus Data encoding.
+pub trait ToData {
+    fn to_data(&self, e: &mut Encoder);
+}
+
+/// A type that can be read from Plutus Data, possibly borrowing from the
+/// input.
+pub trait FromData<'a>: Sized {
+    fn from_data(d: &mut Decoder<'a>) -> Result<Self, Error>;
+}
+
+/// The longest chunk of a byte string; longer ones are split.
+const CHUNK: usize = 64;
+
+/// Constructors `0..=6` are tags `121..=127`, and `7..=127` are tags
+/// `1280..=1400`; the rest are tag 102 on `[index, fields]`.
+const SMALL_TAGS: u64 = 121;
+const LARGE_TAGS: u64 = 1280;
+const ANY_TAG: u64 = 102;
+
+/// Write constructor `index` with `fields`.
+pub fn constr(e: &mut Encoder, index: u64, fields: &[&dyn ToData]) {
+    match index {
+        0..=6 => e.tag(SMALL_TAGS + index),
+        7..=127 => e.tag(LARGE_TAGS + index - 7),
+        _ => e.tag(ANY_TAG).array(2).uint(index),
+    };
+    list(e, fields);
+}
+
+/// Write a list of `items`. Byte strings are lists of bytes in Rust but not
+/// in Plutus, so `[T]` isn't [`ToData`] itself.
+pub fn list<T: ToData>(e: &mut Encoder, items: &[T]) {
+    // Only the empty list has a definite length.
+    if items.is_empty() {
+        e.array(0);
+        return;
+    }
+    e.begin_array();
+    for item in items {
+        item.to_data(e);
+    }
+    e.end();
+}
+
+/// The fields of a constructor being read, from [`read_constr`].
+pub struct Constr<'d, 'a> {
+    /// The index of the constructor's variant.
+    pub index: u64,
+    d: &'d mut Decoder<'a>,
+    /// The number of fields not yet read, or `None` for a list of
+    /// indefinite length.
+    remaining: Option<u64>,
+}
+
+impl<'a> Constr<'_, 'a> {
+    /// The fields, or [`Error::UnexpectedType`] if this is another
+    /// constructor.
+    pub fn expect(self, index: u64) -> Result<Self, Error> {
+        match self.index == index {
+            true => Ok(self),
+            false => Err(Error::UnexpectedType),
+        }
+    }
+
+    /// Read the next field.
+    pub fn field<T: FromData<'a>>(&mut self) -> Result<T, Error> {
+        match &mut self.remaining {
+            Some(0) => return Err(Error::UnexpectedType),
+            Some(remaining) => *remaining -= 1,
+            None if self.d.peek()? == Type::Break => return Err(Error::UnexpectedType),
+            None => {}
+        }
+        T::from_data(self.d)
+    }
+
+    /// Read the end of the fields, or fail with [`Error::UnexpectedType`] if
+    /// there are more.
+    pub fn finish(self) -> Result<(), Error> {
+        match self.remaining {
+            Some(0) => Ok(()),
+            None if self.d.end() => Ok(()),
+            _ => Err(Error::UnexpectedType),
+        }
+    }
+}
+
+/// Read the start of a constructor. On failure, the decoder may be left
+/// inside the item.
+pub fn read_constr<'d, 'a>(d: &'d mut Decoder<'a>) -> Result<Constr<'d, 'a>, Error> {
+    let index = match d.tag()? {
+        tag @ 121..=127 => tag - SMALL_TAGS,
+        tag @ 1280..=1400 => tag - LARGE_TAGS + 7,
+        ANY_TAG if d.array()? == Some(2) => d.uint()?,
+        _ => return Err(Error::UnexpectedType),
+    };
+    let remaining = d.array()?;
+    Ok(Constr {
+        index,
+        d,
+        remaining,
+    })
+}
+
+/// Read a list, calling `item` for each element.
+pub fn read_list<'a>(
+    d: &mut Decoder<'a>,
+    mut item: impl FnMut(&mut Decoder<'a>) -> Result<(), Error>,
+) -> Result<(), Error> {
+    match d.array()? {
+        Some(len) => (0..len).try_for_each(|_| item(d)),
+        None => {
+            while !d.end() {
+                item(d)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Encode `value` as Plutus Data into `buf`, returning the bytes written.
+pub fn to_slice<'a, T: ToData + ?Sized>(value: &T, buf: &'a mut [u8]) -> Result<&'a [u8], Error> {
+    let mut e = Encoder::new(buf);
+    value.to_data(&mut e);
+    e.finish()
+}
+
+/// Decode a `T` from Plutus Data that must make up all of `data`.
+pub fn from_slice<'a, T: FromData<'a>>(data: &'a [u8]) -> Result<T, Error> {
+    let mut d = Decoder::new(data);
+    let value = T::from_data(&mut d)?;
+    match d.is_done() {
+        true => Ok(value),
+        false => Err(Error::Malformed),
+    }
+}
+
+macro_rules! impl_int {
+    ($($ty:ty),*) => {$(
+        impl ToData for $ty {
+            fn to_data(&self, e: &mut Encoder) {
+                e.encode(self);
+            }
+        }
+
+        impl FromData<'_> for $ty {
+            fn from_data(d: &mut Decoder) -> Result<Self, Error> {
+                d.decode()
+            }
+        }
+    )*};
+}
+
+impl_int!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+impl ToData for [u8] {
+    fn to_data(&self, e: &mut Encoder) {
+        if self.len() <= CHUNK {
+            e.bytes(self);
+            return;
+        }
+        e.begin_bytes();
+        for chunk in self.chunks(CHUNK) {
+            e.bytes(chunk);
+        }
+        e.end();
+    }
+}
+
+/// Only byte strings of up to 64 bytes, which aren't split, can be
+/// borrowed.
+impl<'a> FromData<'a> for &'a [u8] {
+    fn from_data(d: &mut Decoder<'a>) -> Result<Self, Error> {
+        d.bytes()
+    }
+}
+
+impl<const N: usize> ToData for [u8; N] {
+    fn to_data(&self, e: &mut Encoder) {
+        self[..].to_data(e);
+    }
+}
+
+impl<const N: usize> FromData<'_> for [u8; N] {
+    fn from_data(d: &mut Decoder) -> Result<Self, Error> {
+        let mut value = [0; N];
+        let start = d.clone();
+        match d.bytes_into(&mut value) {
+            Ok(len) if len == N => Ok(value),
+            Ok(_) | Err(Error::Overflow) => {
+                *d = start;
+                Err(Error::UnexpectedType)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// `False` is constructor 0 and `True` constructor 1, as in Plutus.
+impl ToData for bool {
+    fn to_data(&self, e: &mut Encoder) {
+        constr(e, *self as u64, &[]);
+    }
+}
+
+impl FromData<'_> for bool {
+    fn from_data(d: &mut Decoder) -> Result<Self, Error> {
+        let value = read_constr(d)?;
+        let index = value.index;
+        value.finish()?;
+        match index {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::UnexpectedType),
+        }
+    }
+}
+
+/// Plutus's unit, constructor 0 with no fields.
+impl ToData for () {
+    fn to_data(&self, e: &mut Encoder) {
+        constr(e, 0, &[]);
+    }
+}
+
+impl FromData<'_> for () {
+    fn from_data(d: &mut Decoder) -> Result<Self, Error> {
+        read_constr(d)?.expect(0)?.finish()
+    }
+}
+
+/// Plutus's `Maybe`: `Just` is constructor 0 and `Nothing` constructor 1.
+impl<T: ToData> ToData for Option<T> {
+    fn to_data(&self, e: &mut Encoder) {
+        match self {
+            Some(value) => constr(e, 0, &[value]),
+            None => constr(e, 1, &[]),
+        }
+    }
+}
+
+impl<'a, T: FromData<'a>> FromData<'a> for Option<T> {
+    fn from_data(d: &mut Decoder<'a>) -> Result<Self, Error> {
+        let mut maybe = read_constr(d)?;
+        let value = match maybe.index {
+            0 => Some(maybe.field()?),
+            1 => None,
+            _ => return Err(Error::UnexpectedType),
+        };
+        maybe.finish()?;
+        Ok(value)
+    }
+}
+
+impl<T: ToData + ?Sized> ToData for &T {
+    fn to_data(&self, e: &mut Encoder) {
+        (**self).to_data(e);
+    }
+}
+
+/// Its 32 bytes.
+impl ToData for crate::UtxoId {
+    fn to_data(&self, e: &mut Encoder) {
+        self.0.to_data(e);
+    }
+}
+
+impl FromData<'_> for crate::UtxoId {
+    fn from_data(d: &mut Decoder) -> Result<Self, Error> {
+        <[u8; 32]>::from_data(d).map(crate::UtxoId)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::hash;
+
+    fn hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Constructor `index` with `fields`, encoded.
+    fn encode_constr(index: u64, fields: &[&dyn ToData]) -> Vec<u8> {
+        let mut buf = [0; 256];
+        let mut e = Encoder::new(&mut buf);
+        constr(&mut e, index, fields);
+        e.finish().unwrap().to_vec()
+    }
+
+    /// The index of the constructor `data` is, which has no fields.
+    fn read_index(data: &[u8]) -> Result<u64, Error> {
+        let mut d = Decoder::new(data);
+        let value = read_constr(&mut d)?;
+        let index = value.index;
+        value.finish()?;
+        assert!(d.is_done());
+        Ok(index)
+    }
+
+    #[test]
+    fn constructors_0_to_6_are_tags_121_to_127() {
+        for (index, data) in (0..=6).zip([
+            "d87980", "d87a80", "d87b80", "d87c80", "d87d80", "d87e80", "d87f80",
+        ]) {
+            assert_eq!(encode_constr(index, &[]), hex(data));
+            assert_eq!(read_index(&hex(data)), Ok(index));
+        }
+    }
+
+    #[test]
+    fn constructors_7_to_127_are_tags_1280_to_1400() {
+        for (index, data) in [(7, "d9050080"), (8, "d9050180"), (127, "d9057880")] {
+            assert_eq!(encode_constr(index, &[]), hex(data));
+            assert_eq!(read_index(&hex(data)), Ok(index));
+        }
+    }
+
+    #[test]
+    fn other_constructors_are_tag_102() {
+        // `[index, fields]`, with no fields.
+        let vectors = [
+            (128, "d86682188080"),
+            (200, "d8668218c880"),
+            (u64::MAX, "d866821bffffffffffffffff80"),
+        ];
+        for (index, data) in vectors {
+            assert_eq!(encode_constr(index, &[]), hex(data));
+            assert_eq!(read_index(&hex(data)), Ok(index));
+        }
+        assert_eq!(encode_constr(200, &[&1u8]), hex("d8668218c89f01ff"));
+        // Tag 102 must be on a pair.
+        assert_eq!(read_index(&hex("d86683183280")), Err(Error::UnexpectedType));
+    }
+
+    #[test]
+    fn tags_between_the_ranges_are_not_constructors() {
+        for data in ["d87880", "d88080", "d904ff80", "d9057980"] {
+            assert_eq!(read_index(&hex(data)), Err(Error::UnexpectedType), "{data}");
+        }
+    }
+
+    #[test]
+    fn fields_are_an_indefinite_list_unless_empty() {
+        assert_eq!(encode_constr(1, &[&1u8, &-2i64]), hex("d87a9f0121ff"));
+        assert_eq!(encode_constr(0, &[]), hex("d87980"));
+        // Other encoders may write a definite length, which reads the same.
+        let mut d = Decoder::new(&[0xd8, 0x79, 0x82, 0x01, 0x02]);
+        let mut value = read_constr(&mut d).unwrap().expect(0).unwrap();
+        assert_eq!((value.field::<u8>(), value.field::<u8>()), (Ok(1), Ok(2)));
+        assert_eq!(value.field::<u8>(), Err(Error::UnexpectedType));
+        value.finish().unwrap();
+        // Unread fields fail `finish`.
+        let data = hex("d8799f0102ff");
+        let mut d = Decoder::new(&data);
+        let mut value = read_constr(&mut d).unwrap();
+        assert_eq!(value.field::<u8>(), Ok(1));
+        assert_eq!(value.finish(), Err(Error::UnexpectedType));
+    }
+
+    #[test]
+    fn byte_strings_over_64_bytes_are_chunked() {
+        let mut buf = [0; 256];
+        let short = [7; 64];
+        let data = to_slice(&short, &mut buf).unwrap();
+        assert_eq!(data[..2], [0x58, 0x40]);
+        assert_eq!(from_slice::<&[u8]>(data), Ok(&short[..]));
+        let mut buf = [0; 256];
+        let long = [7; 65];
+        let data = to_slice(&long, &mut buf).unwrap();
+        assert_eq!(data[..3], [0x5f, 0x58, 0x40]);
+        assert_eq!(data[data.len() - 3..], [0x41, 7, 0xff]);
+        assert_eq!(from_slice::<[u8; 65]>(data), Ok(long));
+        assert_eq!(from_slice::<[u8; 64]>(data), Err(Error::UnexpectedType));
+    }
+
+    // Datum hashes as `cardano-cli transaction hash-script-data` prints
+    // them, for `{"constructor": 0, "fields": []}` and `{"int": 42}`.
+    #[test]
+    fn datum_hashes_match_cardano_cli() {
+        let mut buf = [0; 16];
+        assert_eq!(
+            hash::blake2b_256(to_slice(&(), &mut buf).unwrap())[..],
+            hex("923918e403bf43c34b4ef6b48eb2ee04babed17320d8d1b9ff9ad086e86f44ec"),
+        );
+        let mut buf = [0; 16];
+        assert_eq!(
+            hash::blake2b_256(to_slice(&42u64, &mut buf).unwrap())[..],
+            hex("9e1199a988ba72ffd6e9c269cadb3b53b5f360ff99f112d9b2ee30c4d74ad88b"),
+        );
+    }
+
+    #[test]
+    fn prelude_types() {
+        let mut buf = [0; 16];
+        assert_eq!(to_slice(&false, &mut buf).unwrap(), hex("d87980"));
+        assert_eq!(to_slice(&true, &mut buf).unwrap(), hex("d87a80"));
+        assert_eq!(to_slice(&Some(5u64), &mut buf).unwrap(), hex("d8799f05ff"));
+        assert_eq!(to_slice(&None::<u64>, &mut buf).unwrap(), hex("d87a80"));
+        assert_eq!(from_slice::<bool>(&hex("d87a80")), Ok(true));
+        assert_eq!(
+            from_slice::<bool>(&hex("d87b80")),
+            Err(Error::UnexpectedType)
+        );
+        assert_eq!(from_slice::<Option<u64>>(&hex("d8799f05ff")), Ok(Some(5)));
+        assert_eq!(from_slice::<()>(&hex("d87980f6")), Err(Error::Malformed));
+    }
+}
@@ -0,0 +1,85 @@
+//! Poisoned handles: a handle whose `try_*` operation failed fatally (see
+//! [`HostError::is_fatal`]) may refer to half-written state, so later
+//! `try_*` operations on it fail with [`HostError::Poisoned`] without
+//! reaching the host.
+//!
+//! Handles are `Copy`, so the mark is kept here by raw handle rather than in
+//! the wrapper. The generated token and UTXO imports call these.
+
+use crate::HostError;
+
+/// What a raw handle refers to; token and UTXO handles are numbered apart.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Kind {
+    Token,
+    Utxo,
+}
+
+/// `Err(HostError::Poisoned)` if the handle has been poisoned.
+pub fn check(kind: Kind, raw: u32) -> Result<(), HostError> {
+    match table::contains(kind, raw) {
+        true => Err(HostError::Poisoned),
+        false => Ok(()),
+    }
+}
+
+/// Poison the handle if `error` is fatal, and pass `error` on.
+pub fn record(kind: Kind, raw: u32, error: HostError) -> HostError {
+    if error.is_fatal() {
+        table::insert(kind, raw);
+    }
+    error
+}
+
+#[cfg(any(feature = "mock-host", miri))]
+mod table {
+    use super::Kind;
+
+    pub fn contains(kind: Kind, raw: u32) -> bool {
+        crate::mock::with(|host| host.poisoned.contains(&(kind, raw)))
+    }
+
+    pub fn insert(kind: Kind, raw: u32) {
+        crate::mock::with(|host| host.poisoned.insert((kind, raw)));
+    }
+}
+
+/// The most recently poisoned handles. Only the last few are kept, since
+/// wasm has no allocator to grow into; a fatal failure is rare, and usually
+/// ends the transaction soon after.
+#[cfg(not(any(feature = "mock-host", miri)))]
+mod table {
+    use core::cell::UnsafeCell;
+
+    use super::Kind;
+
+    struct Poisoned {
+        entries: [Option<(Kind, u32)>; 8],
+        next: usize,
+    }
+
+    struct Table(UnsafeCell<Poisoned>);
+
+    // Wasm contracts are single-threaded.
+    unsafe impl Sync for Table {}
+
+    static POISONED: Table = Table(UnsafeCell::new(Poisoned {
+        entries: [None; 8],
+        next: 0,
+    }));
+
+    pub fn contains(kind: Kind, raw: u32) -> bool {
+        let poisoned = unsafe { &*POISONED.0.get() };
+        poisoned.entries.contains(&Some((kind, raw)))
+    }
+
+    pub fn insert(kind: Kind, raw: u32) {
+        if contains(kind, raw) {
+            return;
+        }
+        let poisoned = unsafe { &mut *POISONED.0.get() };
+        let next = poisoned.next;
+        poisoned.entries[next] = Some((kind, raw));
+        poisoned.next = (next + 1) % poisoned.entries.len();
+    }
+}
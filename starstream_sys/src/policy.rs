@@ -0,0 +1,117 @@
+//! Authorization rules over who signed the transaction, composed from parts
+//! instead of hand-written per contract.
+//!
+//! ```ignore
+//! // Two of three directors, or the owner alone.
+//! let policy = policy::weighted(&[(alice, 1), (bob, 1), (carol, 1)], 2).or(owner);
+//! policy.require();
+//! ```
+
+use crate::{tx_signed_by, PublicKey};
+
+/// A rule that the transaction's signers either satisfy or don't.
+pub trait Policy {
+    fn is_satisfied(&self) -> bool;
+
+    /// Trap unless the policy is satisfied.
+    fn require(&self) {
+        if !self.is_satisfied() {
+            trap!("signer policy not satisfied");
+        }
+    }
+
+    /// Satisfied when both `self` and `other` are.
+    fn and<P: Policy>(self, other: P) -> And<Self, P>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// Satisfied when either `self` or `other` is.
+    fn or<P: Policy>(self, other: P) -> Or<Self, P>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+}
+
+/// A single key is satisfied when it signed.
+impl Policy for PublicKey {
+    fn is_satisfied(&self) -> bool {
+        tx_signed_by(*self)
+    }
+}
+
+impl<P: Policy + ?Sized> Policy for &P {
+    fn is_satisfied(&self) -> bool {
+        (**self).is_satisfied()
+    }
+}
+
+pub struct And<A, B>(A, B);
+
+impl<A: Policy, B: Policy> Policy for And<A, B> {
+    fn is_satisfied(&self) -> bool {
+        self.0.is_satisfied() && self.1.is_satisfied()
+    }
+}
+
+pub struct Or<A, B>(A, B);
+
+impl<A: Policy, B: Policy> Policy for Or<A, B> {
+    fn is_satisfied(&self) -> bool {
+        self.0.is_satisfied() || self.1.is_satisfied()
+    }
+}
+
+pub struct AllOf<'a>(&'a [PublicKey]);
+
+/// Satisfied when every one of `keys` signed. Satisfied if `keys` is empty.
+pub fn all_of(keys: &[PublicKey]) -> AllOf<'_> {
+    AllOf(keys)
+}
+
+impl Policy for AllOf<'_> {
+    fn is_satisfied(&self) -> bool {
+        self.0.iter().all(Policy::is_satisfied)
+    }
+}
+
+pub struct AnyOf<'a>(&'a [PublicKey]);
+
+/// Satisfied when at least one of `keys` signed. Never satisfied if `keys` is
+/// empty.
+pub fn any_of(keys: &[PublicKey]) -> AnyOf<'_> {
+    AnyOf(keys)
+}
+
+impl Policy for AnyOf<'_> {
+    fn is_satisfied(&self) -> bool {
+        self.0.iter().any(Policy::is_satisfied)
+    }
+}
+
+pub struct Weighted<'a> {
+    keys: &'a [(PublicKey, u32)],
+    threshold: u32,
+}
+
+/// Satisfied when the weights of the keys that signed add up to at least
+/// `threshold`. A key listed twice counts twice.
+pub fn weighted(keys: &[(PublicKey, u32)], threshold: u32) -> Weighted<'_> {
+    Weighted { keys, threshold }
+}
+
+impl Policy for Weighted<'_> {
+    fn is_satisfied(&self) -> bool {
+        let total: u64 = self
+            .keys
+            .iter()
+            .filter(|(key, _)| key.is_satisfied())
+            .map(|(_, weight)| u64::from(*weight))
+            .sum();
+        total >= u64::from(self.threshold)
+    }
+}
@@ -0,0 +1,129 @@
+//! Protobuf-encoded payloads, for reusing the message definitions of
+//! existing backend services. Enabled by the `protobuf` feature, which
+//! brings in `prost`; since prost messages allocate, a contract using it
+//! must provide a `#[global_allocator]`.
+//!
+//! Payloads cross the host boundary at a fixed size, so a message travels
+//! as a [`Proto`]: its encoding in a buffer of `N` bytes, with the length
+//! alongside. A `Proto` is [`FfiSafe`], so it can be yielded, passed as a
+//! resume argument, or emitted as an event, and it is a CBOR byte string of
+//! the encoding, so it can be read as a coordination script's `#[payload]`:
+//!
+//! ```ignore
+//! #[derive(prost::Message)]
+//! pub struct Order {
+//!     #[prost(uint64, tag = "1")]
+//!     pub price: u64,
+//!     #[prost(string, tag = "2")]
+//!     pub memo: String,
+//! }
+//!
+//! coordination_export! {
+//!     fn place(#[payload] order: Proto<Order, 256>) -> u64 {
+//!         let order = order.decode().unwrap_or_else(|_| env::abort_with(BAD_ORDER));
+//!         tx::emit_event("order", &Proto::<_, 256>::encode(&order).unwrap());
+//!         order.price
+//!     }
+//! }
+//! ```
+//!
+//! In the `FfiSafe` layout, the first four bytes are the length of the
+//! encoding, little-endian, and the encoding follows, padded with zeros to
+//! `N` bytes; other hosts and clients pack and unpack it the same way.
+
+use core::{fmt, marker::PhantomData};
+
+use prost::Message;
+
+use crate::{cbor, FfiSafe};
+
+/// Why a [`Proto`] couldn't be made or read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The encoding is longer than the payload holds.
+    TooLong,
+    /// The bytes aren't an encoding of the message.
+    Decode(prost::DecodeError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TooLong => f.write_str("protobuf message longer than its payload"),
+            Error::Decode(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// A protobuf message `M` encoded in a fixed-size payload of `N` bytes.
+#[repr(C)]
+pub struct Proto<M, const N: usize> {
+    len: [u8; 4],
+    bytes: [u8; N],
+    message: PhantomData<fn() -> M>,
+}
+
+// Any length and bytes are a valid value; `decode` checks them.
+unsafe impl<M, const N: usize> FfiSafe for Proto<M, N> {}
+
+impl<M: Message + Default, const N: usize> Proto<M, N> {
+    /// Encode `message`, or fail if its encoding is longer than `N` bytes.
+    pub fn encode(message: &M) -> Result<Self, Error> {
+        let mut bytes = [0; N];
+        // prost only fails to encode when the buffer is too small.
+        message
+            .encode(&mut &mut bytes[..])
+            .map_err(|_| Error::TooLong)?;
+        Ok(Proto {
+            len: (message.encoded_len() as u32).to_le_bytes(),
+            bytes,
+            message: PhantomData,
+        })
+    }
+
+    /// Decode the message.
+    pub fn decode(&self) -> Result<M, Error> {
+        M::decode(self.as_bytes()?).map_err(Error::Decode)
+    }
+}
+
+impl<M, const N: usize> Proto<M, N> {
+    /// The encoding, or [`Error::TooLong`] if the length is past the end of
+    /// the buffer.
+    pub fn as_bytes(&self) -> Result<&[u8], Error> {
+        let len = u32::from_le_bytes(self.len) as usize;
+        self.bytes.get(..len).ok_or(Error::TooLong)
+    }
+}
+
+impl<M, const N: usize> cbor::Encode for Proto<M, N> {
+    fn encode(&self, e: &mut cbor::Encoder) {
+        e.bytes(self.as_bytes().unwrap_or(&self.bytes));
+    }
+}
+
+impl<M, const N: usize> cbor::Decode<'_> for Proto<M, N> {
+    fn decode(d: &mut cbor::Decoder) -> Result<Self, cbor::Error> {
+        let mut bytes = [0; N];
+        let len = d.bytes_into(&mut bytes)?;
+        Ok(Proto {
+            len: (len as u32).to_le_bytes(),
+            bytes,
+            message: PhantomData,
+        })
+    }
+}
+
+impl<M, const N: usize> Clone for Proto<M, N> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<M, const N: usize> Copy for Proto<M, N> {}
+
+impl<M, const N: usize> fmt::Debug for Proto<M, N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Proto").field(&self.as_bytes()).finish()
+    }
+}
@@ -0,0 +1,10 @@
+//! Ready-made UTXO types for common contract patterns. Like
+//! [`commit_reveal`](crate::commit_reveal), each is a UTXO body here, a macro
+//! that exports it from a contract, and a macro that imports it into
+//! coordination scripts.
+
+pub mod amm;
+pub mod auction;
+pub mod escrow;
+pub mod multisig;
+pub mod timelock;
@@ -0,0 +1,806 @@
+//! A constant-product market maker: a pool of two token types that anyone
+//! can swap one for the other against, at a price set by the ratio of its
+//! reserves, as in Uniswap v2.
+//!
+//! Liquidity providers deposit both tokens in the pool's ratio and receive
+//! shares, a token the contract exports alongside the pool, which they later
+//! burn to withdraw their part of the reserves. Each swap pays a fee to the
+//! pool, so the product of the reserves, the invariant, only grows; the pool
+//! checks it on every swap, and does all its arithmetic with [`Amount`],
+//! which fails instead of wrapping.
+//!
+//! As with an [`escrow`](super::escrow), the pool checks the operations and
+//! the coordination script that opened it moves the tokens: the import
+//! macro's `swap`, `add_liquidity`, and `remove_liquidity` attach them, and
+//! detach what they pay out with
+//! [`Utxo::detach_amount`](crate::Utxo::detach_amount), since each reserve
+//! is the deposits of many operations. Only the script that opened a pool
+//! can operate it. A pool records its two [`TokenType`]s, and the macro
+//! checks the tokens of each operation against them and takes the amounts
+//! deposited from the tokens attached, so the reserves are always what the
+//! pool holds. An operation that would give less than its caller's minimum
+//! is rejected with [`SLIPPAGE`], and one that is empty or would overflow
+//! with [`INVALID`], and the pool stays as it was.
+//!
+//! A contract exports the pool and its shares with
+//! [`amm_export!`](crate::amm_export), and coordination scripts import them
+//! with [`amm_import!`](crate::amm_import).
+
+use core::{mem::size_of, num::NonZeroU32};
+
+use crate::{
+    env, hash, sleep_state, starstream_reject,
+    utxo::{self, RawResume},
+    CodeHash, FfiSafe, StateId, Token, Utxo,
+};
+
+/// The rejection code of an operation that would give less than the
+/// minimum its caller asked for.
+pub const SLIPPAGE: NonZeroU32 = NonZeroU32::new(1).unwrap();
+
+/// The rejection code of an operation on nothing, against an empty pool, or
+/// whose result doesn't fit in an [`Amount`].
+pub const INVALID: NonZeroU32 = NonZeroU32::new(2).unwrap();
+
+/// Fees are in parts of this, basis points.
+pub const FEE_DENOMINATOR: u32 = 10_000;
+
+/// A token amount whose arithmetic is checked: every operation returns
+/// `None` rather than overflowing.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default, FfiSafe)]
+#[repr(transparent)]
+pub struct Amount(pub u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    /// `self * num / den`, rounded down, without overflowing in between.
+    /// `None` if `den` is zero or the result doesn't fit.
+    pub fn mul_div(self, num: Amount, den: Amount) -> Option<Amount> {
+        let product = u128::from(self.0) * u128::from(num.0);
+        let quotient = product.checked_div(u128::from(den.0))?;
+        u64::try_from(quotient).ok().map(Amount)
+    }
+}
+
+/// A token type, as the SHA-256 of the module and name the host identifies
+/// it by.
+#[derive(Clone, Copy, PartialEq, Eq, FfiSafe)]
+#[repr(C)]
+pub struct TokenType {
+    pub hash: [u8; 32],
+}
+
+impl TokenType {
+    /// The type of `T`.
+    pub fn of<T: Token>() -> TokenType {
+        // The length keeps the module and name from running together.
+        let module_len = (T::MODULE.len() as u32).to_le_bytes();
+        let hash = hash::sha256_parts([&module_len, T::MODULE.as_bytes(), T::NAME.as_bytes()]);
+        TokenType { hash }
+    }
+}
+
+/// Which way a swap goes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[repr(u32)]
+pub enum Direction {
+    /// Pay token A, receive token B.
+    AToB,
+    /// Pay token B, receive token A.
+    BToA,
+}
+
+/// What a pool is spawned with.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct PoolTerms {
+    /// The two tokens the pool trades, which must differ.
+    pub token_a: TokenType,
+    pub token_b: TokenType,
+    /// The fee on each swap's input, in parts of [`FEE_DENOMINATOR`].
+    pub fee: u32,
+}
+
+/// The state a pool yields in.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Pool {
+    pub reserve_a: Amount,
+    pub reserve_b: Amount,
+    /// The shares outstanding.
+    pub shares: Amount,
+    pub fee: u32,
+    /// Zero: the padding that would otherwise follow `script`.
+    pub _reserved: u32,
+    /// The coordination script that opened it, and so may operate it.
+    pub script: CodeHash,
+    /// What `reserve_a` and `reserve_b` are of.
+    pub token_a: TokenType,
+    pub token_b: TokenType,
+}
+
+impl Pool {
+    /// The types a swap in `direction` takes in and pays out.
+    pub fn token_types(&self, direction: Direction) -> (TokenType, TokenType) {
+        match direction {
+            Direction::AToB => (self.token_a, self.token_b),
+            Direction::BToA => (self.token_b, self.token_a),
+        }
+    }
+
+    /// Swap `amount_in` for at least `min_out`: the pool after, and the
+    /// amount out.
+    pub fn swap(
+        &self,
+        direction: Direction,
+        amount_in: Amount,
+        min_out: Amount,
+    ) -> Result<(Pool, Amount), NonZeroU32> {
+        let (reserve_in, reserve_out) = match direction {
+            Direction::AToB => (self.reserve_a, self.reserve_b),
+            Direction::BToA => (self.reserve_b, self.reserve_a),
+        };
+        if amount_in == Amount::ZERO || reserve_in == Amount::ZERO || reserve_out == Amount::ZERO {
+            return Err(INVALID);
+        }
+        // out = in' * reserve_out / (reserve_in + in'), where in' is the
+        // input less the fee, scaled by the denominator on both sides.
+        let in_less_fee = u128::from(amount_in.0) * u128::from(FEE_DENOMINATOR - self.fee);
+        let den = u128::from(reserve_in.0) * u128::from(FEE_DENOMINATOR) + in_less_fee;
+        // Less than `reserve_out`, so it fits.
+        let amount_out = Amount((in_less_fee * u128::from(reserve_out.0) / den) as u64);
+        if amount_out == Amount::ZERO {
+            return Err(INVALID);
+        }
+        if amount_out < min_out {
+            return Err(SLIPPAGE);
+        }
+        let new_in = reserve_in.checked_add(amount_in).ok_or(INVALID)?;
+        let new_out = reserve_out.checked_sub(amount_out).ok_or(INVALID)?;
+        // The invariant: the product of the reserves never falls.
+        let k = u128::from(reserve_in.0) * u128::from(reserve_out.0);
+        if u128::from(new_in.0) * u128::from(new_out.0) < k {
+            return Err(INVALID);
+        }
+        let (reserve_a, reserve_b) = match direction {
+            Direction::AToB => (new_in, new_out),
+            Direction::BToA => (new_out, new_in),
+        };
+        let pool = Pool {
+            reserve_a,
+            reserve_b,
+            ..*self
+        };
+        Ok((pool, amount_out))
+    }
+
+    /// Deposit `amount_a` and `amount_b` for at least `min_shares`: the pool
+    /// after, and the shares minted. Shares are for the smaller side of the
+    /// deposit at the pool's ratio, and the excess of the other side goes to
+    /// the pool. The first deposit sets the ratio.
+    pub fn add_liquidity(
+        &self,
+        amount_a: Amount,
+        amount_b: Amount,
+        min_shares: Amount,
+    ) -> Result<(Pool, Amount), NonZeroU32> {
+        if amount_a == Amount::ZERO || amount_b == Amount::ZERO {
+            return Err(INVALID);
+        }
+        let minted = match self.shares {
+            Amount::ZERO => {
+                let product = u128::from(amount_a.0) * u128::from(amount_b.0);
+                // The square root of a product of two u64s fits.
+                Amount(product.isqrt() as u64)
+            }
+            shares => {
+                let for_a = amount_a.mul_div(shares, self.reserve_a).ok_or(INVALID)?;
+                let for_b = amount_b.mul_div(shares, self.reserve_b).ok_or(INVALID)?;
+                for_a.min(for_b)
+            }
+        };
+        if minted == Amount::ZERO {
+            return Err(INVALID);
+        }
+        if minted < min_shares {
+            return Err(SLIPPAGE);
+        }
+        let pool = Pool {
+            reserve_a: self.reserve_a.checked_add(amount_a).ok_or(INVALID)?,
+            reserve_b: self.reserve_b.checked_add(amount_b).ok_or(INVALID)?,
+            shares: self.shares.checked_add(minted).ok_or(INVALID)?,
+            ..*self
+        };
+        Ok((pool, minted))
+    }
+
+    /// Burn `shares` for at least `min_a` and `min_b`: the pool after, and
+    /// the amounts out, the shares' part of each reserve, rounded down.
+    pub fn remove_liquidity(
+        &self,
+        shares: Amount,
+        min_a: Amount,
+        min_b: Amount,
+    ) -> Result<(Pool, Amount, Amount), NonZeroU32> {
+        if shares == Amount::ZERO || shares > self.shares {
+            return Err(INVALID);
+        }
+        let amount_a = self.reserve_a.mul_div(shares, self.shares).ok_or(INVALID)?;
+        let amount_b = self.reserve_b.mul_div(shares, self.shares).ok_or(INVALID)?;
+        if amount_a < min_a || amount_b < min_b {
+            return Err(SLIPPAGE);
+        }
+        let pool = Pool {
+            reserve_a: self.reserve_a.checked_sub(amount_a).ok_or(INVALID)?,
+            reserve_b: self.reserve_b.checked_sub(amount_b).ok_or(INVALID)?,
+            shares: self.shares.checked_sub(shares).ok_or(INVALID)?,
+            ..*self
+        };
+        Ok((pool, amount_a, amount_b))
+    }
+}
+
+/// The state name pools yield under.
+pub const POOL: &str = "amm_pool";
+
+/// What a pool is resumed with, each the arguments of the [`Pool`] method of
+/// the same name. It is sent as its [`RawResume`] wire form.
+#[derive(Clone, Copy)]
+pub enum Action {
+    Swap {
+        direction: Direction,
+        amount_in: Amount,
+        min_out: Amount,
+    },
+    AddLiquidity {
+        amount_a: Amount,
+        amount_b: Amount,
+        min_shares: Amount,
+    },
+    RemoveLiquidity {
+        shares: Amount,
+        min_a: Amount,
+        min_b: Amount,
+    },
+}
+
+/// The wire form of [`Action::Swap`]'s arguments.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+struct SwapArgs {
+    direction: u32,
+    _reserved: u32,
+    amount_in: Amount,
+    min_out: Amount,
+}
+
+impl Action {
+    const SWAP: u32 = 0;
+    const ADD_LIQUIDITY: u32 = 1;
+    const REMOVE_LIQUIDITY: u32 = 2;
+
+    /// The payload words of the wire form.
+    pub const WORDS: usize =
+        utxo::__resume_words(&[size_of::<SwapArgs>(), size_of::<[Amount; 3]>()]);
+
+    /// The wire form, as the resume import takes it.
+    pub fn into_raw(self) -> RawResume<{ Action::WORDS }> {
+        match self {
+            Action::Swap {
+                direction,
+                amount_in,
+                min_out,
+            } => {
+                let args = SwapArgs {
+                    direction: direction as u32,
+                    _reserved: 0,
+                    amount_in,
+                    min_out,
+                };
+                RawResume::new(Action::SWAP, args)
+            }
+            Action::AddLiquidity {
+                amount_a,
+                amount_b,
+                min_shares,
+            } => RawResume::new(Action::ADD_LIQUIDITY, [amount_a, amount_b, min_shares]),
+            Action::RemoveLiquidity {
+                shares,
+                min_a,
+                min_b,
+            } => RawResume::new(Action::REMOVE_LIQUIDITY, [shares, min_a, min_b]),
+        }
+    }
+
+    /// Decode the wire form, as the pool's `sleep_state` returns it. Traps
+    /// on a tag or direction that names no variant.
+    pub fn from_raw(raw: RawResume<{ Action::WORDS }>) -> Action {
+        match raw.tag {
+            Action::SWAP => {
+                let args: SwapArgs = raw.payload();
+                let direction = match args.direction {
+                    0 => Direction::AToB,
+                    1 => Direction::BToA,
+                    other => utxo::__unknown_tag("Direction", other),
+                };
+                Action::Swap {
+                    direction,
+                    amount_in: args.amount_in,
+                    min_out: args.min_out,
+                }
+            }
+            Action::ADD_LIQUIDITY => {
+                let [amount_a, amount_b, min_shares] = raw.payload();
+                Action::AddLiquidity {
+                    amount_a,
+                    amount_b,
+                    min_shares,
+                }
+            }
+            Action::REMOVE_LIQUIDITY => {
+                let [shares, min_a, min_b] = raw.payload();
+                Action::RemoveLiquidity {
+                    shares,
+                    min_a,
+                    min_b,
+                }
+            }
+            other => utxo::__unknown_tag("Action", other),
+        }
+    }
+}
+
+/// The intermediate of a pool's shares.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Shares {
+    pub amount: u64,
+}
+
+/// Trap on operating a pool that has ended, for the import macro.
+#[doc(hidden)]
+#[cold]
+pub fn __ended() -> ! {
+    trap!("pool has ended")
+}
+
+/// Trap unless `In` and `Out` are the types a swap in `direction` takes in
+/// and pays out, for the import macro. A deposit or withdrawal is of
+/// `token_a` and `token_b`, as for [`Direction::AToB`].
+#[doc(hidden)]
+pub fn __check_types<In: Token, Out: Token>(pool: &Pool, direction: Direction) {
+    if pool.token_types(direction) != (TokenType::of::<In>(), TokenType::of::<Out>()) {
+        trap!(
+            "{} and {} aren't the tokens of this pool",
+            In::NAME,
+            Out::NAME
+        );
+    }
+}
+
+/// Attach `token` to `pool`, returning the amount it adds, for the import
+/// macro.
+#[doc(hidden)]
+pub fn __deposit<P: Utxo + Copy, T: Token>(pool: P, token: T) -> Amount {
+    let held = pool.amount_of::<T>();
+    pool.attach(token);
+    Amount(pool.amount_of::<T>() - held)
+}
+
+/// Trap on an operation the pool would reject with `code`, for the import
+/// macro.
+#[doc(hidden)]
+#[cold]
+pub fn __rejected(code: NonZeroU32) -> ! {
+    trap!("pool would reject the operation with code {}", code)
+}
+
+#[doc(hidden)]
+pub fn __pool_main(state: StateId) {
+    let terms: PoolTerms = env::init_args();
+    if terms.fee >= FEE_DENOMINATOR {
+        trap!("pool fee of {} out of {}", terms.fee, FEE_DENOMINATOR);
+    }
+    if terms.token_a == terms.token_b {
+        trap!("pool of one token type against itself");
+    }
+    let mut pool = Pool {
+        reserve_a: Amount::ZERO,
+        reserve_b: Amount::ZERO,
+        shares: Amount::ZERO,
+        fee: terms.fee,
+        _reserved: 0,
+        script: env::caller_code(),
+        token_a: terms.token_a,
+        token_b: terms.token_b,
+    };
+    loop {
+        let action = Action::from_raw(sleep_state(state, &pool));
+        env::assert_called_by(pool.script);
+        let next = match action {
+            Action::Swap {
+                direction,
+                amount_in,
+                min_out,
+            } => pool
+                .swap(direction, amount_in, min_out)
+                .map(|(pool, _)| pool),
+            Action::AddLiquidity {
+                amount_a,
+                amount_b,
+                min_shares,
+            } => pool
+                .add_liquidity(amount_a, amount_b, min_shares)
+                .map(|(pool, _)| pool),
+            Action::RemoveLiquidity {
+                shares,
+                min_a,
+                min_b,
+            } => pool
+                .remove_liquidity(shares, min_a, min_b)
+                .map(|(pool, ..)| pool),
+        };
+        match next {
+            Ok(next) => pool = next,
+            Err(code) => starstream_reject(code),
+        }
+    }
+}
+
+/// Export a pool UTXO type from a contract, with the token of its shares. A
+/// pool is spawned with its [`PoolTerms`](crate::standards::amm::PoolTerms)
+/// and resumed with each operation, for as long as it is used.
+///
+/// Only coordination code of the exporting contract may mint or burn
+/// shares, as for `StarNft` in `example_contract`, so the pool's scripts are
+/// deployed with it.
+///
+/// ```ignore
+/// amm_export! {
+///     new fn starstream_new_Pool_new;
+///     mint fn starstream_mint_PoolShare;
+///     burn fn starstream_burn_PoolShare;
+/// }
+/// ```
+#[macro_export]
+macro_rules! amm_export {
+    (
+        new fn $new_fn:ident;
+        mint fn $mint_fn:ident;
+        burn fn $burn_fn:ident;
+    ) => {
+        #[no_mangle]
+        pub extern "C" fn $new_fn() {
+            $crate::__check_abi();
+            // `state!` needs a literal: this is `standards::amm::POOL`.
+            $crate::standards::amm::__pool_main($crate::state!("amm_pool"))
+        }
+
+        $crate::token_export! {
+            for $crate::standards::amm::Shares;
+            mint fn $mint_fn(this: Self) -> TokenStorage {
+                assert!($crate::coordination_code() == $crate::this_code());
+                $crate::TokenStorage {
+                    id: 0,
+                    amount: this.amount,
+                }
+            }
+            burn fn $burn_fn(storage: TokenStorage) -> Self {
+                assert!($crate::coordination_code() == $crate::this_code());
+                $crate::standards::amm::Shares {
+                    amount: storage.amount,
+                }
+            }
+        }
+    };
+}
+
+/// Import a pool UTXO type and its shares exported with `amm_export!`. The
+/// shares are imported as with `token_import!`, with an intermediate laid
+/// out as [`Shares`](crate::standards::amm::Shares).
+///
+/// ```ignore
+/// amm_import! {
+///     from "starstream_utxo:example_contract";
+///     type Pool;
+///     spawn fn starstream_spawn_Pool_new;
+///     status fn starstream_status_Pool;
+///     resume fn starstream_resume_Pool;
+///     state fn starstream_state_Pool;
+///
+///     shares from "starstream_token:example_contract";
+///     type PoolShare;
+///     intermediate struct PoolShareIntermediate;
+///     mint fn starstream_mint_PoolShare;
+///     burn fn starstream_burn_PoolShare;
+/// }
+///
+/// let pool = Pool::open(PoolTerms {
+///     token_a: TokenType::of::<Gold>(),
+///     token_b: TokenType::of::<Silver>(),
+///     fee: 30,
+/// });
+/// let shares = pool.add_liquidity(gold, silver, Amount(1));
+/// // ... in later transactions:
+/// let silver: Silver = pool.swap(Direction::AToB, gold, Amount(35));
+/// let (gold, silver): (Gold, Silver) = pool.remove_liquidity(shares, Amount(1), Amount(1));
+/// ```
+#[macro_export]
+macro_rules! amm_import {
+    (
+        from $module:expr;
+        type $name:ident;
+        spawn fn $spawn_fn:ident;
+        status fn $status_fn:ident;
+        resume fn $resume_fn:ident;
+        state fn $state_fn:ident;
+
+        shares from $shares_module:expr;
+        type $shares_name:ident;
+        intermediate struct $intermediate_name:ident;
+        mint fn $mint_fn:ident;
+        burn fn $burn_fn:ident;
+    ) => {
+        $crate::utxo_import! {
+            $module;
+            $name;
+            $status_fn;
+            $resume_fn;
+            $crate::utxo::RawResume<{ $crate::standards::amm::Action::WORDS }>;
+            ();
+        }
+
+        $crate::token_import! {
+            from $shares_module;
+            type $shares_name;
+            // As `standards::amm::Shares`.
+            intermediate struct $intermediate_name {
+                pub amount: u64,
+            }
+            mint fn $mint_fn;
+            burn fn $burn_fn;
+        }
+
+        $crate::__state_import!($module; $name; $state_fn);
+        $crate::__spawn_import!($module; $name; $spawn_fn($crate::standards::amm::PoolTerms));
+
+        impl $name {
+            /// Open an empty pool on `terms`.
+            #[inline]
+            pub fn open(terms: $crate::standards::amm::PoolTerms) -> Self {
+                unsafe {
+                    $spawn_fn(
+                        &terms,
+                        core::mem::size_of::<$crate::standards::amm::PoolTerms>(),
+                    )
+                }
+            }
+
+            /// The reserves and shares, or `None` if the pool has ended.
+            pub fn pool(self) -> Option<$crate::standards::amm::Pool> {
+                let mut pool = core::mem::MaybeUninit::<$crate::standards::amm::Pool>::uninit();
+                unsafe {
+                    if $state_fn(
+                        self,
+                        const { $crate::StateId::of($crate::standards::amm::POOL) },
+                        pool.as_mut_ptr() as *mut (),
+                        core::mem::size_of::<$crate::standards::amm::Pool>(),
+                    ) != 0
+                    {
+                        Some(pool.assume_init())
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            /// Swap `input` for at least `min_out` of the other token,
+            /// `Out`. Traps if `In` and `Out` aren't the pool's tokens in
+            /// `direction`, or if the swap is rejected.
+            #[track_caller]
+            pub fn swap<In: $crate::Token, Out: $crate::Token>(
+                self,
+                direction: $crate::standards::amm::Direction,
+                input: In,
+                min_out: $crate::standards::amm::Amount,
+            ) -> Out {
+                let Some(pool) = self.pool() else {
+                    $crate::standards::amm::__ended()
+                };
+                $crate::standards::amm::__check_types::<In, Out>(&pool, direction);
+                let amount_in = $crate::standards::amm::__deposit(self, input);
+                let amount_out = match pool.swap(direction, amount_in, min_out) {
+                    Ok((_, amount_out)) => amount_out,
+                    Err(code) => $crate::standards::amm::__rejected(code),
+                };
+                let action = $crate::standards::amm::Action::Swap {
+                    direction,
+                    amount_in,
+                    min_out,
+                };
+                <Self as $crate::Utxo>::resume(self, action.into_raw());
+                <Self as $crate::Utxo>::detach_amount(self, amount_out.0)
+            }
+
+            /// Deposit `a` and `b` for at least `min_shares`, and mint them.
+            /// Traps if `A` and `B` aren't the pool's tokens, or if the
+            /// deposit is rejected.
+            #[track_caller]
+            pub fn add_liquidity<A: $crate::Token, B: $crate::Token>(
+                self,
+                a: A,
+                b: B,
+                min_shares: $crate::standards::amm::Amount,
+            ) -> $shares_name {
+                let Some(pool) = self.pool() else {
+                    $crate::standards::amm::__ended()
+                };
+                $crate::standards::amm::__check_types::<A, B>(
+                    &pool,
+                    $crate::standards::amm::Direction::AToB,
+                );
+                let amount_a = $crate::standards::amm::__deposit(self, a);
+                let amount_b = $crate::standards::amm::__deposit(self, b);
+                let minted = match pool.add_liquidity(amount_a, amount_b, min_shares) {
+                    Ok((_, minted)) => minted,
+                    Err(code) => $crate::standards::amm::__rejected(code),
+                };
+                let action = $crate::standards::amm::Action::AddLiquidity {
+                    amount_a,
+                    amount_b,
+                    min_shares,
+                };
+                <Self as $crate::Utxo>::resume(self, action.into_raw());
+                $intermediate_name { amount: minted.0 }.mint()
+            }
+
+            /// Burn `shares` for at least `min_a` of token `A` and `min_b`
+            /// of token `B`. Traps if `A` and `B` aren't the pool's tokens,
+            /// or if the withdrawal is rejected.
+            #[track_caller]
+            pub fn remove_liquidity<A: $crate::Token, B: $crate::Token>(
+                self,
+                shares: $shares_name,
+                min_a: $crate::standards::amm::Amount,
+                min_b: $crate::standards::amm::Amount,
+            ) -> (A, B) {
+                let Some(pool) = self.pool() else {
+                    $crate::standards::amm::__ended()
+                };
+                $crate::standards::amm::__check_types::<A, B>(
+                    &pool,
+                    $crate::standards::amm::Direction::AToB,
+                );
+                let shares = $crate::standards::amm::Amount(
+                    <$shares_name as $crate::Token>::burn(shares).amount,
+                );
+                let (amount_a, amount_b) = match pool.remove_liquidity(shares, min_a, min_b) {
+                    Ok((_, amount_a, amount_b)) => (amount_a, amount_b),
+                    Err(code) => $crate::standards::amm::__rejected(code),
+                };
+                let action = $crate::standards::amm::Action::RemoveLiquidity {
+                    shares,
+                    min_a,
+                    min_b,
+                };
+                <Self as $crate::Utxo>::resume(self, action.into_raw());
+                (
+                    <Self as $crate::Utxo>::detach_amount(self, amount_a.0),
+                    <Self as $crate::Utxo>::detach_amount(self, amount_b.0),
+                )
+            }
+        }
+    };
+}
+
+#[cfg(all(test, feature = "mock-host"))]
+// The tokens are only named by type, never minted.
+#[allow(dead_code)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::{
+        hash,
+        mock::{self, MockStatus, MockUtxo},
+    };
+
+    crate::token_import! {
+        from "tok";
+        type Gold;
+        intermediate struct GoldI { amount: u64 }
+        mint fn starstream_mint_Gold;
+        burn fn starstream_burn_Gold;
+    }
+
+    crate::token_import! {
+        from "tok";
+        type Silver;
+        intermediate struct SilverI { amount: u64 }
+        mint fn starstream_mint_Silver;
+        burn fn starstream_burn_Silver;
+    }
+
+    type MockPool = MockUtxo<Pool, RawResume<{ Action::WORDS }>>;
+
+    fn open(fee: u32) -> MockPool {
+        let terms = PoolTerms {
+            token_a: TokenType::of::<Gold>(),
+            token_b: TokenType::of::<Silver>(),
+            fee,
+        };
+        mock::with(|host| host.init_args = Some(hash::bytes_of(&terms).to_vec()));
+        mock::spawn(|| __pool_main(crate::state!("amm_pool")))
+    }
+
+    #[test]
+    fn token_types_differ_by_module_and_name() {
+        assert!(TokenType::of::<Gold>() != TokenType::of::<Silver>());
+        let pool = open(30).yielded().unwrap();
+        assert!(
+            pool.token_types(Direction::AToB)
+                == (TokenType::of::<Gold>(), TokenType::of::<Silver>())
+        );
+        assert!(
+            pool.token_types(Direction::BToA)
+                == (TokenType::of::<Silver>(), TokenType::of::<Gold>())
+        );
+        __check_types::<Silver, Gold>(&pool, Direction::BToA);
+        let swapped =
+            std::panic::catch_unwind(|| __check_types::<Silver, Gold>(&pool, Direction::AToB));
+        assert!(swapped.is_err());
+    }
+
+    #[test]
+    fn swaps_against_a_seeded_pool() {
+        let mut pool = open(30);
+        let seed = Action::AddLiquidity {
+            amount_a: Amount(1_000),
+            amount_b: Amount(4_000),
+            min_shares: Amount(1),
+        };
+        pool.resume(seed.into_raw());
+        assert_eq!(pool.yielded().unwrap().shares, Amount(2_000));
+
+        // 10 less 0.3% into 1000:4000 gives 39, so asking for 40 is rejected.
+        let swap = |min_out| Action::Swap {
+            direction: Direction::AToB,
+            amount_in: Amount(10),
+            min_out: Amount(min_out),
+        };
+        pool.resume(swap(40).into_raw());
+        assert_eq!(mock::with(|host| host.rejection.take()), Some(SLIPPAGE));
+        assert_eq!(pool.yielded().unwrap().reserve_b, Amount(4_000));
+
+        let status = pool.resume(swap(39).into_raw());
+        assert_eq!(*status, MockStatus::Yielded("amm_pool".into()));
+        let after = pool.yielded().unwrap();
+        assert_eq!(
+            (after.reserve_a, after.reserve_b),
+            (Amount(1_010), Amount(3_961))
+        );
+        assert_eq!(mock::with(|host| host.rejection.take()), None);
+    }
+
+    #[test]
+    fn pool_of_one_token_traps() {
+        let terms = PoolTerms {
+            token_a: TokenType::of::<Gold>(),
+            token_b: TokenType::of::<Gold>(),
+            fee: 30,
+        };
+        mock::with(|host| host.init_args = Some(hash::bytes_of(&terms).to_vec()));
+        let pool: MockPool = mock::spawn(|| __pool_main(crate::state!("amm_pool")));
+        assert_eq!(*pool.status(), MockStatus::Errored);
+        assert!(pool.error().unwrap().contains("against itself"));
+    }
+}
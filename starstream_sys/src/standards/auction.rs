@@ -0,0 +1,477 @@
+//! Auctions of whatever tokens a seller attaches, in two kinds:
+//!
+//! - English: bids rise until the deadline and the highest wins. The bids
+//!   are held in the auction as attached tokens, and each bid refunds the
+//!   one it beats.
+//! - Dutch: the price falls with the block height from a start price to a
+//!   floor, and the first buyer at the current price wins.
+//!
+//! As with an [`escrow`](super::escrow), the auction checks the bids and the
+//! coordination script that opened it moves the tokens: the import macros'
+//! `bid`, `settle`, and `buy` do so, and say what is left to hand over.
+//! Only the script that opened an auction can bid in it or settle it, so
+//! only that script can detach what it holds. A bid is the amount of the
+//! tokens attached with it, so the auction always holds every bid it
+//! records.
+//!
+//! A bid or purchase that isn't signed by its bidder, or is too low, is
+//! rejected with [`NOT_SIGNED`] or [`TOO_LOW`], and the auction stays open.
+//!
+//! Contracts export auctions with
+//! [`english_auction_export!`](crate::english_auction_export) and
+//! [`dutch_auction_export!`](crate::dutch_auction_export), and coordination
+//! scripts import them with
+//! [`english_auction_import!`](crate::english_auction_import) and
+//! [`dutch_auction_import!`](crate::dutch_auction_import).
+
+use core::num::NonZeroU32;
+
+use crate::{
+    env, policy::Policy, sleep_until_state, starstream_reject, CodeHash, Expiry, FfiSafe,
+    PublicKey, StateId,
+};
+
+/// The rejection code of a bid or purchase its bidder didn't sign.
+pub const NOT_SIGNED: NonZeroU32 = NonZeroU32::new(1).unwrap();
+
+/// The rejection code of a bid below [`Bidding::minimum_bid`], or a
+/// purchase whose maximum price is below the current price.
+pub const TOO_LOW: NonZeroU32 = NonZeroU32::new(2).unwrap();
+
+/// Trap on using an auction that has ended, for the import macros.
+#[doc(hidden)]
+#[cold]
+pub fn __ended(what: &str) -> ! {
+    trap!("{} an auction that has ended", what)
+}
+
+// ----------------------------------------------------------------------------
+// English
+
+/// The rules of an English auction.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct EnglishTerms {
+    pub seller: PublicKey,
+    /// The lowest first bid.
+    pub reserve: u64,
+    /// How much each bid must beat the last by, at least 1.
+    pub min_increment: u64,
+    /// The block height after which bids close and the auction can be
+    /// settled.
+    pub deadline: u64,
+}
+
+/// The state an English auction yields in until it is settled.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Bidding {
+    pub terms: EnglishTerms,
+    pub high_bidder: PublicKey,
+    /// The highest bid so far, or 0 before the first.
+    pub high_bid: u64,
+    /// The coordination script that opened it, and so may bid and settle.
+    pub script: CodeHash,
+}
+
+impl Bidding {
+    /// The lowest bid the auction accepts next.
+    pub fn minimum_bid(&self) -> u64 {
+        match self.high_bid {
+            0 => self.terms.reserve.max(1),
+            high => high.saturating_add(self.terms.min_increment.max(1)),
+        }
+    }
+}
+
+/// The state name English auctions yield under.
+pub const BIDDING: &str = "auction_bidding";
+
+/// What an English auction is resumed with: a bid of `amount`, the amount of
+/// the tokens attached to the auction with it.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Bid {
+    pub bidder: PublicKey,
+    pub amount: u64,
+}
+
+/// The bid a new one beat, returned by the import's `bid` so the script can
+/// pass the tokens back to their bidder.
+pub struct Outbid<T> {
+    pub bidder: PublicKey,
+    pub refund: T,
+}
+
+/// How an English auction ended, returned by the import's `settle`. The
+/// script pays `payment` to the seller and hands the lot to `winner`, or
+/// back to the seller if no one bid.
+pub struct Settlement<T> {
+    pub seller: PublicKey,
+    pub winner: Option<PublicKey>,
+    /// The winning bid's tokens.
+    pub payment: Option<T>,
+}
+
+#[doc(hidden)]
+pub fn __english_main(state: StateId) {
+    let terms: EnglishTerms = env::init_args();
+    let mut bidding = Bidding {
+        terms,
+        high_bidder: terms.seller,
+        high_bid: 0,
+        script: env::caller_code(),
+    };
+    while let Expiry::Resumed(bid) = sleep_until_state::<Bid, _>(state, &bidding, terms.deadline) {
+        env::assert_called_by(bidding.script);
+        if !bid.bidder.is_satisfied() {
+            starstream_reject(NOT_SIGNED);
+        } else if bid.amount < bidding.minimum_bid() {
+            starstream_reject(TOO_LOW);
+        } else {
+            bidding.high_bidder = bid.bidder;
+            bidding.high_bid = bid.amount;
+        }
+    }
+    // Settled, and the script pays out on its side.
+    env::assert_called_by(bidding.script);
+}
+
+/// Export an English auction UTXO type from a contract. It is spawned with
+/// its [`EnglishTerms`](crate::standards::auction::EnglishTerms), resumed
+/// with each bid, and expires to settle.
+///
+/// ```ignore
+/// english_auction_export! {
+///     new fn starstream_new_English_new;
+/// }
+/// ```
+#[macro_export]
+macro_rules! english_auction_export {
+    (
+        new fn $new_fn:ident;
+    ) => {
+        #[no_mangle]
+        pub extern "C" fn $new_fn() {
+            $crate::__check_abi();
+            // `state!` needs a literal: this is `standards::auction::BIDDING`.
+            $crate::standards::auction::__english_main($crate::state!("auction_bidding"))
+        }
+    };
+}
+
+/// Import an English auction UTXO type exported with
+/// `english_auction_export!`.
+///
+/// ```ignore
+/// english_auction_import! {
+///     from "starstream_utxo:example_contract";
+///     type English;
+///     spawn fn starstream_spawn_English_new;
+///     status fn starstream_status_English;
+///     resume fn starstream_resume_English;
+///     state fn starstream_state_English;
+/// }
+///
+/// let auction = English::open(EnglishTerms { seller, reserve: 10, min_increment: 1, deadline });
+/// auction.attach(lot);
+/// // ... in later transactions, signed by each bidder:
+/// if let Some(outbid) = auction.bid(bidder, payment) {
+///     give(outbid.bidder, outbid.refund);
+/// }
+/// // ... after the deadline:
+/// let settlement = auction.settle::<StarToken>();
+/// let lot: StarNft = auction.detach(1);
+/// ```
+#[macro_export]
+macro_rules! english_auction_import {
+    (
+        from $module:expr;
+        type $name:ident;
+        spawn fn $spawn_fn:ident;
+        status fn $status_fn:ident;
+        resume fn $resume_fn:ident;
+        state fn $state_fn:ident;
+    ) => {
+        $crate::utxo_import! {
+            $module;
+            $name;
+            $status_fn;
+            $resume_fn;
+            $crate::standards::auction::Bid;
+            ();
+        }
+
+        $crate::__state_import!($module; $name; $state_fn);
+        $crate::__spawn_import!($module; $name; $spawn_fn($crate::standards::auction::EnglishTerms));
+
+        impl $name {
+            /// Open an auction on `terms`. Attach the lot with
+            /// `Utxo::attach`.
+            #[inline]
+            pub fn open(terms: $crate::standards::auction::EnglishTerms) -> Self {
+                unsafe {
+                    $spawn_fn(
+                        &terms,
+                        core::mem::size_of::<$crate::standards::auction::EnglishTerms>(),
+                    )
+                }
+            }
+
+            /// The bidding so far, or `None` once settled.
+            pub fn bidding(self) -> Option<$crate::standards::auction::Bidding> {
+                let mut bidding =
+                    core::mem::MaybeUninit::<$crate::standards::auction::Bidding>::uninit();
+                unsafe {
+                    if $state_fn(
+                        self,
+                        const { $crate::StateId::of($crate::standards::auction::BIDDING) },
+                        bidding.as_mut_ptr() as *mut (),
+                        core::mem::size_of::<$crate::standards::auction::Bidding>(),
+                    ) != 0
+                    {
+                        Some(bidding.assume_init())
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            /// Bid `payment`, in a transaction signed by `bidder`. The
+            /// payment is attached to the auction, and the bid it beats, if
+            /// any, detached and returned. Traps if the bid is rejected or
+            /// the bidding has closed.
+            #[track_caller]
+            pub fn bid<T: $crate::Token>(
+                self,
+                bidder: $crate::PublicKey,
+                payment: T,
+            ) -> Option<$crate::standards::auction::Outbid<T>> {
+                let Some(before) = self.bidding() else {
+                    $crate::standards::auction::__ended("bid in")
+                };
+                // The bid is what attaching `payment` adds, whatever else of
+                // `T` the auction holds.
+                let held = <Self as $crate::Utxo>::amount_of::<T>(self);
+                <Self as $crate::Utxo>::attach(self, payment);
+                let amount = <Self as $crate::Utxo>::amount_of::<T>(self) - held;
+                let bid = $crate::standards::auction::Bid { bidder, amount };
+                <Self as $crate::Utxo>::resume(self, bid);
+                (before.high_bid > 0).then(|| $crate::standards::auction::Outbid {
+                    bidder: before.high_bidder,
+                    refund: <Self as $crate::Utxo>::detach(self, before.high_bid),
+                })
+            }
+
+            /// Close the auction once its deadline has passed, detaching the
+            /// winning bid, which was paid in `T`. Detach the lot after, in
+            /// the same transaction. Traps before the deadline.
+            pub fn settle<T: $crate::Token>(self) -> $crate::standards::auction::Settlement<T> {
+                let Some(bidding) = self.bidding() else {
+                    $crate::standards::auction::__ended("settle")
+                };
+                <Self as $crate::Utxo>::expire(self);
+                let payment = (bidding.high_bid > 0)
+                    .then(|| <Self as $crate::Utxo>::detach(self, bidding.high_bid));
+                $crate::standards::auction::Settlement {
+                    seller: bidding.terms.seller,
+                    winner: payment.as_ref().map(|_| bidding.high_bidder),
+                    payment,
+                }
+            }
+        }
+    };
+}
+
+// ----------------------------------------------------------------------------
+// Dutch
+
+/// The rules of a Dutch auction.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct DutchTerms {
+    pub seller: PublicKey,
+    /// The price at `start_height`.
+    pub start_price: u64,
+    /// The price never falls below this.
+    pub floor_price: u64,
+    /// How much the price falls each block.
+    pub decay: u64,
+    pub start_height: u64,
+    /// The block height after which the lot can no longer be bought, and
+    /// the auction expires unsold.
+    pub deadline: u64,
+}
+
+impl DutchTerms {
+    /// The price at block `height`.
+    pub fn price_at(&self, height: u64) -> u64 {
+        let elapsed = height.saturating_sub(self.start_height);
+        let fall = self.decay.saturating_mul(elapsed);
+        self.start_price.saturating_sub(fall).max(self.floor_price)
+    }
+}
+
+/// The state a Dutch auction yields in until it ends.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Listed {
+    pub terms: DutchTerms,
+    /// The coordination script that opened it, and so may end it.
+    pub script: CodeHash,
+}
+
+/// The state name Dutch auctions yield under.
+pub const LISTED: &str = "auction_listed";
+
+/// What a Dutch auction is resumed with: a purchase at the current price,
+/// if it is at most `max_price`.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Buy {
+    pub buyer: PublicKey,
+    pub max_price: u64,
+}
+
+#[doc(hidden)]
+pub fn __dutch_main(state: StateId) {
+    let listed = Listed {
+        terms: env::init_args(),
+        script: env::caller_code(),
+    };
+    let deadline = listed.terms.deadline;
+    while let Expiry::Resumed(buy) = sleep_until_state::<Buy, _>(state, &listed, deadline) {
+        env::assert_called_by(listed.script);
+        if !buy.buyer.is_satisfied() {
+            starstream_reject(NOT_SIGNED);
+        } else if buy.max_price < listed.terms.price_at(env::block_height()) {
+            starstream_reject(TOO_LOW);
+        } else {
+            // Sold, and the script pays out on its side.
+            return;
+        }
+    }
+    // Unsold, and the script returns the lot.
+    env::assert_called_by(listed.script);
+}
+
+/// Export a Dutch auction UTXO type from a contract. It is spawned with its
+/// [`DutchTerms`](crate::standards::auction::DutchTerms) and resumed to buy
+/// the lot, or expires unsold.
+///
+/// ```ignore
+/// dutch_auction_export! {
+///     new fn starstream_new_Dutch_new;
+/// }
+/// ```
+#[macro_export]
+macro_rules! dutch_auction_export {
+    (
+        new fn $new_fn:ident;
+    ) => {
+        #[no_mangle]
+        pub extern "C" fn $new_fn() {
+            $crate::__check_abi();
+            // `state!` needs a literal: this is `standards::auction::LISTED`.
+            $crate::standards::auction::__dutch_main($crate::state!("auction_listed"))
+        }
+    };
+}
+
+/// Import a Dutch auction UTXO type exported with `dutch_auction_export!`.
+///
+/// ```ignore
+/// dutch_auction_import! {
+///     from "starstream_utxo:example_contract";
+///     type Dutch;
+///     spawn fn starstream_spawn_Dutch_new;
+///     status fn starstream_status_Dutch;
+///     resume fn starstream_resume_Dutch;
+///     state fn starstream_state_Dutch;
+/// }
+///
+/// let auction = Dutch::open(DutchTerms { seller, start_price: 100, floor_price: 20, decay: 1, start_height, deadline });
+/// auction.attach(lot);
+/// // ... in a later transaction, signed by the buyer:
+/// let price = auction.buy(buyer, 80);
+/// let lot: StarNft = auction.detach(1);
+/// pay(seller, price);
+/// ```
+#[macro_export]
+macro_rules! dutch_auction_import {
+    (
+        from $module:expr;
+        type $name:ident;
+        spawn fn $spawn_fn:ident;
+        status fn $status_fn:ident;
+        resume fn $resume_fn:ident;
+        state fn $state_fn:ident;
+    ) => {
+        $crate::utxo_import! {
+            $module;
+            $name;
+            $status_fn;
+            $resume_fn;
+            $crate::standards::auction::Buy;
+            ();
+        }
+
+        $crate::__state_import!($module; $name; $state_fn);
+        $crate::__spawn_import!($module; $name; $spawn_fn($crate::standards::auction::DutchTerms));
+
+        impl $name {
+            /// Open an auction on `terms`. Attach the lot with
+            /// `Utxo::attach`.
+            #[inline]
+            pub fn open(terms: $crate::standards::auction::DutchTerms) -> Self {
+                unsafe {
+                    $spawn_fn(
+                        &terms,
+                        core::mem::size_of::<$crate::standards::auction::DutchTerms>(),
+                    )
+                }
+            }
+
+            /// The terms, or `None` once the auction has ended.
+            pub fn terms(self) -> Option<$crate::standards::auction::DutchTerms> {
+                let mut listed =
+                    core::mem::MaybeUninit::<$crate::standards::auction::Listed>::uninit();
+                unsafe {
+                    if $state_fn(
+                        self,
+                        const { $crate::StateId::of($crate::standards::auction::LISTED) },
+                        listed.as_mut_ptr() as *mut (),
+                        core::mem::size_of::<$crate::standards::auction::Listed>(),
+                    ) != 0
+                    {
+                        Some(listed.assume_init().terms)
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            /// The price in this block, or `None` once the auction has
+            /// ended.
+            pub fn price(self) -> Option<u64> {
+                let terms = self.terms()?;
+                Some(terms.price_at($crate::env::block_height()))
+            }
+
+            /// Buy the lot at the current price, if it is at most
+            /// `max_price`, in a transaction signed by `buyer`, and return
+            /// the price, which the script must pay the seller. Detach the
+            /// lot after, in the same transaction. Traps if the purchase is
+            /// rejected or the auction has ended.
+            #[track_caller]
+            pub fn buy(self, buyer: $crate::PublicKey, max_price: u64) -> u64 {
+                let Some(price) = self.price() else {
+                    $crate::standards::auction::__ended("buy from")
+                };
+                let buy = $crate::standards::auction::Buy { buyer, max_price };
+                <Self as $crate::Utxo>::resume(self, buy);
+                price
+            }
+        }
+    };
+}
@@ -0,0 +1,192 @@
+//! Escrow: tokens held for a trade between a buyer and a seller, with an
+//! arbiter to settle disputes, for marketplaces and other trades where
+//! neither side should have to go first.
+//!
+//! A coordination script opens an escrow with its [`Terms`] and attaches the
+//! buyer's payment to it. The escrow then ends in one of three ways:
+//!
+//! - release, signed by the buyer or the arbiter: the seller is paid
+//! - refund, signed by the seller or the arbiter: the buyer is repaid
+//! - expiry, once the deadline has passed, signed by anyone: the buyer is
+//!   repaid
+//!
+//! The escrow checks the signatures, and the script moves the tokens,
+//! detaching them with [`Utxo::detach`](crate::Utxo::detach) in the
+//! transaction that ends the escrow. The host only lets a script detach
+//! tokens from a UTXO that accepted its resume in the same transaction, and
+//! only the script that opened an escrow can end it, so a script that pays
+//! out only as above ensures every payout was authorized.
+//!
+//! A contract exports the escrow UTXO with
+//! [`escrow_export!`](crate::escrow_export), and coordination scripts import
+//! it with [`escrow_import!`](crate::escrow_import).
+
+use crate::{
+    env, policy::Policy, sleep_until_state, CodeHash, Expiry, FfiSafe, PublicKey, StateId,
+};
+
+/// Who may end an escrow, and when.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Terms {
+    pub buyer: PublicKey,
+    pub seller: PublicKey,
+    pub arbiter: PublicKey,
+    /// The block height after which the escrow can only expire.
+    pub deadline: u64,
+}
+
+/// The state an escrow yields in until it ends.
+#[derive(FfiSafe)]
+#[repr(C)]
+pub struct Open {
+    pub terms: Terms,
+    /// The coordination script that opened it, and so may end it.
+    pub script: CodeHash,
+}
+
+/// The state name escrows yield under.
+pub const OPEN: &str = "escrow_open";
+
+/// What an escrow is resumed with: [`RELEASE`](Action::RELEASE) or
+/// [`REFUND`](Action::REFUND). A `u32` rather than an enum, since the host
+/// may pass any value; the escrow traps on others.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, FfiSafe)]
+#[repr(transparent)]
+pub struct Action(pub u32);
+
+impl Action {
+    /// Pay the seller.
+    pub const RELEASE: Action = Action(0);
+    /// Repay the buyer.
+    pub const REFUND: Action = Action(1);
+}
+
+#[doc(hidden)]
+pub fn __escrow_main(state: StateId) {
+    let open = Open {
+        terms: env::init_args(),
+        script: env::caller_code(),
+    };
+    let terms = open.terms;
+    match sleep_until_state(state, &open, terms.deadline) {
+        Expiry::Resumed(Action::RELEASE) => terms.buyer.or(terms.arbiter).require(),
+        Expiry::Resumed(Action::REFUND) => terms.seller.or(terms.arbiter).require(),
+        Expiry::Resumed(Action(action)) => trap!("escrow has no action {}", action),
+        Expiry::Expired => {}
+    }
+    // Ended, and the script pays out on its side.
+    env::assert_called_by(open.script);
+}
+
+/// Export an escrow UTXO type from a contract. It is spawned with its
+/// [`Terms`](crate::standards::escrow::Terms) and resumed once, to end it.
+///
+/// ```ignore
+/// escrow_export! {
+///     new fn starstream_new_Escrow_new;
+/// }
+/// ```
+#[macro_export]
+macro_rules! escrow_export {
+    (
+        new fn $new_fn:ident;
+    ) => {
+        #[no_mangle]
+        pub extern "C" fn $new_fn() {
+            $crate::__check_abi();
+            // `state!` needs a literal: this is `standards::escrow::OPEN`.
+            $crate::standards::escrow::__escrow_main($crate::state!("escrow_open"))
+        }
+    };
+}
+
+/// Import an escrow UTXO type exported with `escrow_export!`.
+///
+/// ```ignore
+/// escrow_import! {
+///     from "starstream_utxo:example_contract";
+///     type Escrow;
+///     spawn fn starstream_spawn_Escrow_new;
+///     status fn starstream_status_Escrow;
+///     resume fn starstream_resume_Escrow;
+///     state fn starstream_state_Escrow;
+/// }
+///
+/// let escrow = Escrow::open(Terms { buyer, seller, arbiter, deadline });
+/// escrow.attach(payment);
+/// // ... in a later transaction, signed by the buyer:
+/// escrow.release();
+/// let payment: StarToken = escrow.detach(amount);
+/// ```
+#[macro_export]
+macro_rules! escrow_import {
+    (
+        from $module:expr;
+        type $name:ident;
+        spawn fn $spawn_fn:ident;
+        status fn $status_fn:ident;
+        resume fn $resume_fn:ident;
+        state fn $state_fn:ident;
+    ) => {
+        $crate::utxo_import! {
+            $module;
+            $name;
+            $status_fn;
+            $resume_fn;
+            $crate::standards::escrow::Action;
+            ();
+        }
+
+        $crate::__state_import!($module; $name; $state_fn);
+        $crate::__spawn_import!($module; $name; $spawn_fn($crate::standards::escrow::Terms));
+
+        impl $name {
+            /// Open an escrow on `terms`. Attach the payment with
+            /// `Utxo::attach`.
+            #[inline]
+            pub fn open(terms: $crate::standards::escrow::Terms) -> Self {
+                unsafe {
+                    $spawn_fn(
+                        &terms,
+                        core::mem::size_of::<$crate::standards::escrow::Terms>(),
+                    )
+                }
+            }
+
+            /// The terms, or `None` once the escrow has ended.
+            pub fn terms(self) -> Option<$crate::standards::escrow::Terms> {
+                let mut open = core::mem::MaybeUninit::<$crate::standards::escrow::Open>::uninit();
+                unsafe {
+                    if $state_fn(
+                        self,
+                        const { $crate::StateId::of($crate::standards::escrow::OPEN) },
+                        open.as_mut_ptr() as *mut (),
+                        core::mem::size_of::<$crate::standards::escrow::Open>(),
+                    ) != 0
+                    {
+                        Some(open.assume_init().terms)
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            /// End the escrow for the seller, in a transaction signed by the
+            /// buyer or the arbiter, which must pay the seller. Traps
+            /// otherwise, or after the deadline.
+            #[track_caller]
+            pub fn release(self) {
+                <Self as $crate::Utxo>::resume(self, $crate::standards::escrow::Action::RELEASE)
+            }
+
+            /// End the escrow for the buyer, in a transaction signed by the
+            /// seller or the arbiter, which must repay the buyer. Traps
+            /// otherwise, or after the deadline; then use `Utxo::expire`.
+            #[track_caller]
+            pub fn refund(self) {
+                <Self as $crate::Utxo>::resume(self, $crate::standards::escrow::Action::REFUND)
+            }
+        }
+    };
+}
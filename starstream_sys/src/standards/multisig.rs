@@ -0,0 +1,327 @@
+//! Multisig wallets: tokens held in a UTXO that releases them only in
+//! transactions signed by enough of its keys, for treasuries and shared
+//! accounts that no single key should control.
+//!
+//! A wallet's [`Signers`] are up to [`MAX_SIGNERS`] keys, each with a
+//! weight, and a threshold: a transaction is signed by enough of them when
+//! the weights of its signers add up to the threshold, as for
+//! [`policy::weighted`](crate::policy::weighted). The signers can release
+//! tokens, rotate the wallet to new signers, or close it.
+//!
+//! As with an [`escrow`](super::escrow), the wallet checks the signatures
+//! and the coordination script that opened it moves the tokens: the import
+//! macro's `release` detaches them once the wallet agrees. Only the script
+//! that opened a wallet can operate it, and the host only lets a script
+//! detach from a UTXO that accepted its resume, so nothing leaves the wallet
+//! without its signers. An operation that isn't signed by enough signers is
+//! rejected with [`NOT_SIGNED`], and a rotation to signers that could never
+//! sign with [`INVALID`], and the wallet stays as it was.
+//!
+//! A contract exports the wallet UTXO with
+//! [`multisig_export!`](crate::multisig_export), and coordination scripts
+//! import it with [`multisig_import!`](crate::multisig_import).
+
+use core::{mem::size_of, num::NonZeroU32};
+
+use crate::{
+    env,
+    policy::{self, Policy},
+    sleep_state, starstream_reject,
+    utxo::{self, RawResume},
+    CodeHash, FfiSafe, PublicKey, StateId,
+};
+
+/// The rejection code of an operation not signed by enough signers.
+pub const NOT_SIGNED: NonZeroU32 = NonZeroU32::new(1).unwrap();
+
+/// The rejection code of a rotation to signers that aren't
+/// [valid](Signers::is_valid).
+pub const INVALID: NonZeroU32 = NonZeroU32::new(2).unwrap();
+
+/// The most keys a wallet can have.
+pub const MAX_SIGNERS: usize = 8;
+
+/// One key of a wallet, and what its signature counts for.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Signer {
+    pub key: PublicKey,
+    pub weight: u32,
+}
+
+/// The keys of a wallet and how many of them must sign. As a [`Policy`], it
+/// is satisfied when the weights of the keys that signed add up to at least
+/// `threshold`.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Signers {
+    /// The keys, of which the first `len` are used. [`MAX_SIGNERS`] of
+    /// them, spelled out because recorded layouts can't name constants.
+    pub keys: [Signer; 8],
+    pub len: u32,
+    pub threshold: u32,
+}
+
+impl Signers {
+    /// `threshold` of `keys`, each counting once. `None` unless that is
+    /// [valid](Self::is_valid).
+    pub fn new(keys: &[PublicKey], threshold: u32) -> Option<Signers> {
+        Signers::collect(keys.iter().map(|&key| Signer { key, weight: 1 }), threshold)
+    }
+
+    /// Keys with weights, and the total weight that must sign. `None` unless
+    /// that is [valid](Self::is_valid).
+    pub fn weighted(keys: &[(PublicKey, u32)], threshold: u32) -> Option<Signers> {
+        let keys = keys.iter().map(|&(key, weight)| Signer { key, weight });
+        Signers::collect(keys, threshold)
+    }
+
+    fn collect(mut keys: impl ExactSizeIterator<Item = Signer>, threshold: u32) -> Option<Signers> {
+        if keys.len() > MAX_SIGNERS {
+            return None;
+        }
+        let len = keys.len() as u32;
+        // Unused slots repeat the first key, with no weight.
+        let first = keys.next()?;
+        let mut signers = Signers {
+            keys: [Signer { weight: 0, ..first }; MAX_SIGNERS],
+            len,
+            threshold,
+        };
+        for (slot, signer) in signers
+            .keys
+            .iter_mut()
+            .zip(core::iter::once(first).chain(keys))
+        {
+            *slot = signer;
+        }
+        signers.is_valid().then_some(signers)
+    }
+
+    /// The keys in use.
+    pub fn keys(&self) -> &[Signer] {
+        &self.keys[..(self.len as usize).min(MAX_SIGNERS)]
+    }
+
+    /// Whether the signers can sign at all, but not without signatures:
+    /// there are at most [`MAX_SIGNERS`], the threshold is above zero, and
+    /// the weights of all of them add up to at least the threshold.
+    pub fn is_valid(&self) -> bool {
+        let total: u64 = self.keys().iter().map(|k| u64::from(k.weight)).sum();
+        self.len as usize <= MAX_SIGNERS && self.threshold > 0 && total >= u64::from(self.threshold)
+    }
+}
+
+impl Policy for Signers {
+    fn is_satisfied(&self) -> bool {
+        let mut keys = [(self.keys[0].key, 0); MAX_SIGNERS];
+        for (pair, signer) in keys.iter_mut().zip(self.keys()) {
+            *pair = (signer.key, signer.weight);
+        }
+        policy::weighted(&keys[..self.keys().len()], self.threshold).is_satisfied()
+    }
+}
+
+/// The state a wallet yields in until it is closed.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Wallet {
+    pub signers: Signers,
+    /// The coordination script that opened it, and so may operate it.
+    pub script: CodeHash,
+}
+
+/// The state name wallets yield under.
+pub const WALLET: &str = "multisig_wallet";
+
+/// What a wallet is resumed with. Each must be signed by enough of its
+/// current signers. It is sent as its [`RawResume`] wire form.
+#[derive(Clone, Copy)]
+pub enum Action {
+    /// Let the script detach tokens in this transaction.
+    Release,
+    /// Replace the signers.
+    Rotate(Signers),
+    /// End the wallet, once the script has detached what it holds.
+    Close,
+}
+
+impl Action {
+    const RELEASE: u32 = 0;
+    const ROTATE: u32 = 1;
+    const CLOSE: u32 = 2;
+
+    /// The payload words of the wire form.
+    pub const WORDS: usize = utxo::__resume_words(&[size_of::<Signers>()]);
+
+    /// The wire form, as the resume import takes it.
+    pub fn into_raw(self) -> RawResume<{ Action::WORDS }> {
+        match self {
+            Action::Release => RawResume::new(Action::RELEASE, ()),
+            Action::Rotate(signers) => RawResume::new(Action::ROTATE, signers),
+            Action::Close => RawResume::new(Action::CLOSE, ()),
+        }
+    }
+
+    /// Decode the wire form, as the wallet's `sleep_state` returns it. Traps
+    /// on a tag that names no variant.
+    pub fn from_raw(raw: RawResume<{ Action::WORDS }>) -> Action {
+        match raw.tag {
+            Action::RELEASE => Action::Release,
+            Action::ROTATE => Action::Rotate(raw.payload()),
+            Action::CLOSE => Action::Close,
+            other => utxo::__unknown_tag("Action", other),
+        }
+    }
+}
+
+#[doc(hidden)]
+pub fn __wallet_main(state: StateId) {
+    let mut wallet = Wallet {
+        signers: env::init_args(),
+        script: env::caller_code(),
+    };
+    if !wallet.signers.is_valid() {
+        trap!("multisig signers can never sign");
+    }
+    loop {
+        let action = Action::from_raw(sleep_state(state, &wallet));
+        env::assert_called_by(wallet.script);
+        if !wallet.signers.is_satisfied() {
+            starstream_reject(NOT_SIGNED);
+            continue;
+        }
+        match action {
+            Action::Release => {}
+            Action::Rotate(signers) if signers.is_valid() => wallet.signers = signers,
+            Action::Rotate(_) => starstream_reject(INVALID),
+            // Closed, and the script has paid out on its side.
+            Action::Close => return,
+        }
+    }
+}
+
+/// Export a multisig wallet UTXO type from a contract. It is spawned with
+/// its [`Signers`](crate::standards::multisig::Signers) and resumed with
+/// each operation until it is closed.
+///
+/// ```ignore
+/// multisig_export! {
+///     new fn starstream_new_Multisig_new;
+/// }
+/// ```
+#[macro_export]
+macro_rules! multisig_export {
+    (
+        new fn $new_fn:ident;
+    ) => {
+        #[no_mangle]
+        pub extern "C" fn $new_fn() {
+            $crate::__check_abi();
+            // `state!` needs a literal: this is `standards::multisig::WALLET`.
+            $crate::standards::multisig::__wallet_main($crate::state!("multisig_wallet"))
+        }
+    };
+}
+
+/// Import a multisig wallet UTXO type exported with `multisig_export!`.
+///
+/// ```ignore
+/// multisig_import! {
+///     from "starstream_utxo:example_contract";
+///     type Multisig;
+///     spawn fn starstream_spawn_Multisig_new;
+///     status fn starstream_status_Multisig;
+///     resume fn starstream_resume_Multisig;
+///     state fn starstream_state_Multisig;
+/// }
+///
+/// let wallet = Multisig::open(Signers::new(&[alice, bob, carol], 2).unwrap());
+/// wallet.attach(treasury);
+/// // ... in later transactions, signed by two of the three:
+/// let payment: StarToken = wallet.release(100);
+/// wallet.rotate(Signers::new(&[alice, bob, dave], 2).unwrap());
+/// ```
+#[macro_export]
+macro_rules! multisig_import {
+    (
+        from $module:expr;
+        type $name:ident;
+        spawn fn $spawn_fn:ident;
+        status fn $status_fn:ident;
+        resume fn $resume_fn:ident;
+        state fn $state_fn:ident;
+    ) => {
+        $crate::utxo_import! {
+            $module;
+            $name;
+            $status_fn;
+            $resume_fn;
+            $crate::utxo::RawResume<{ $crate::standards::multisig::Action::WORDS }>;
+            ();
+        }
+
+        $crate::__state_import!($module; $name; $state_fn);
+        $crate::__spawn_import!($module; $name; $spawn_fn($crate::standards::multisig::Signers));
+
+        impl $name {
+            /// Open a wallet for `signers`. Attach tokens to it with
+            /// `Utxo::attach`.
+            #[inline]
+            pub fn open(signers: $crate::standards::multisig::Signers) -> Self {
+                unsafe {
+                    $spawn_fn(
+                        &signers,
+                        core::mem::size_of::<$crate::standards::multisig::Signers>(),
+                    )
+                }
+            }
+
+            /// The current signers, or `None` once the wallet is closed.
+            pub fn signers(self) -> Option<$crate::standards::multisig::Signers> {
+                let mut wallet =
+                    core::mem::MaybeUninit::<$crate::standards::multisig::Wallet>::uninit();
+                unsafe {
+                    if $state_fn(
+                        self,
+                        const { $crate::StateId::of($crate::standards::multisig::WALLET) },
+                        wallet.as_mut_ptr() as *mut (),
+                        core::mem::size_of::<$crate::standards::multisig::Wallet>(),
+                    ) != 0
+                    {
+                        Some(wallet.assume_init().signers)
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            /// Detach `amount` of `T` from the wallet. Traps unless the
+            /// transaction is signed by enough signers.
+            #[track_caller]
+            pub fn release<T: $crate::Token>(self, amount: u64) -> T {
+                let action = $crate::standards::multisig::Action::Release.into_raw();
+                <Self as $crate::Utxo>::resume(self, action);
+                <Self as $crate::Utxo>::detach(self, amount)
+            }
+
+            /// Replace the signers with `signers`. Traps unless the
+            /// transaction is signed by enough of the current ones, or if
+            /// `signers` isn't valid.
+            #[track_caller]
+            pub fn rotate(self, signers: $crate::standards::multisig::Signers) {
+                let action = $crate::standards::multisig::Action::Rotate(signers).into_raw();
+                <Self as $crate::Utxo>::resume(self, action)
+            }
+
+            /// End the wallet. Release what it holds first, in the same
+            /// transaction. Traps unless the transaction is signed by enough
+            /// signers.
+            #[track_caller]
+            pub fn close(self) {
+                let action = $crate::standards::multisig::Action::Close.into_raw();
+                <Self as $crate::Utxo>::resume(self, action)
+            }
+        }
+    };
+}
@@ -0,0 +1,177 @@
+//! Timelocks: tokens held in a UTXO until a block height, as a building
+//! block for vesting schedules, bonds, and penalty boxes.
+//!
+//! A coordination script opens a timelock with its [`LockTerms`] and
+//! attaches whatever tokens it wraps. Once [`env::block_height`] reaches the
+//! unlock height, the script can release it, detaching the tokens in the same
+//! transaction; before then a release is rejected with [`TOO_EARLY`] and the
+//! timelock stays as it was. Only the script that opened a timelock can
+//! release it, and the host only lets a script detach from a UTXO that
+//! accepted its resume, so the script decides who the tokens go to: a
+//! vesting script opens one timelock per tranche, for example.
+//!
+//! A contract exports the timelock UTXO with
+//! [`timelock_export!`](crate::timelock_export), and coordination scripts
+//! import it with [`timelock_import!`](crate::timelock_import).
+
+use core::num::NonZeroU32;
+
+use crate::{env, sleep_state, starstream_reject, CodeHash, FfiSafe, StateId};
+
+/// The rejection code of a release before the unlock height.
+pub const TOO_EARLY: NonZeroU32 = NonZeroU32::new(1).unwrap();
+
+/// When a timelock can be released.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct LockTerms {
+    /// The first block height at which it can be released.
+    pub unlock_height: u64,
+}
+
+impl LockTerms {
+    /// Whether a timelock on these terms can be released at block `height`.
+    pub fn is_unlocked_at(&self, height: u64) -> bool {
+        height >= self.unlock_height
+    }
+}
+
+/// The state a timelock yields in until it is released.
+#[derive(Clone, Copy, FfiSafe)]
+#[repr(C)]
+pub struct Locked {
+    pub terms: LockTerms,
+    /// The coordination script that opened it, and so may release it.
+    pub script: CodeHash,
+}
+
+/// The state name timelocks yield under.
+pub const LOCKED: &str = "timelock_locked";
+
+#[doc(hidden)]
+pub fn __timelock_main(state: StateId) {
+    let locked = Locked {
+        terms: env::init_args(),
+        script: env::caller_code(),
+    };
+    loop {
+        let () = sleep_state(state, &locked);
+        env::assert_called_by(locked.script);
+        if locked.terms.is_unlocked_at(env::block_height()) {
+            // Released, and the script detaches the tokens on its side.
+            return;
+        }
+        starstream_reject(TOO_EARLY);
+    }
+}
+
+/// Export a timelock UTXO type from a contract. It is spawned with its
+/// [`LockTerms`](crate::standards::timelock::LockTerms) and resumed to
+/// release it.
+///
+/// ```ignore
+/// timelock_export! {
+///     new fn starstream_new_Timelock_new;
+/// }
+/// ```
+#[macro_export]
+macro_rules! timelock_export {
+    (
+        new fn $new_fn:ident;
+    ) => {
+        #[no_mangle]
+        pub extern "C" fn $new_fn() {
+            $crate::__check_abi();
+            // `state!` needs a literal: this is `standards::timelock::LOCKED`.
+            $crate::standards::timelock::__timelock_main($crate::state!("timelock_locked"))
+        }
+    };
+}
+
+/// Import a timelock UTXO type exported with `timelock_export!`.
+///
+/// ```ignore
+/// timelock_import! {
+///     from "starstream_utxo:example_contract";
+///     type Timelock;
+///     spawn fn starstream_spawn_Timelock_new;
+///     status fn starstream_status_Timelock;
+///     resume fn starstream_resume_Timelock;
+///     state fn starstream_state_Timelock;
+/// }
+///
+/// let lock = Timelock::open(LockTerms { unlock_height: env::block_height() + 1_000 });
+/// lock.attach(bond);
+/// // ... in a transaction at or after the unlock height:
+/// lock.release();
+/// let bond: StarToken = lock.detach(amount);
+/// ```
+#[macro_export]
+macro_rules! timelock_import {
+    (
+        from $module:expr;
+        type $name:ident;
+        spawn fn $spawn_fn:ident;
+        status fn $status_fn:ident;
+        resume fn $resume_fn:ident;
+        state fn $state_fn:ident;
+    ) => {
+        $crate::utxo_import! {
+            $module;
+            $name;
+            $status_fn;
+            $resume_fn;
+            ();
+            ();
+        }
+
+        $crate::__state_import!($module; $name; $state_fn);
+        $crate::__spawn_import!($module; $name; $spawn_fn($crate::standards::timelock::LockTerms));
+
+        impl $name {
+            /// Open a timelock on `terms`. Attach the tokens it wraps with
+            /// `Utxo::attach`.
+            #[inline]
+            pub fn open(terms: $crate::standards::timelock::LockTerms) -> Self {
+                unsafe {
+                    $spawn_fn(
+                        &terms,
+                        core::mem::size_of::<$crate::standards::timelock::LockTerms>(),
+                    )
+                }
+            }
+
+            /// The terms, or `None` once the timelock is released.
+            pub fn terms(self) -> Option<$crate::standards::timelock::LockTerms> {
+                let mut locked =
+                    core::mem::MaybeUninit::<$crate::standards::timelock::Locked>::uninit();
+                unsafe {
+                    if $state_fn(
+                        self,
+                        const { $crate::StateId::of($crate::standards::timelock::LOCKED) },
+                        locked.as_mut_ptr() as *mut (),
+                        core::mem::size_of::<$crate::standards::timelock::Locked>(),
+                    ) != 0
+                    {
+                        Some(locked.assume_init().terms)
+                    } else {
+                        None
+                    }
+                }
+            }
+
+            /// Whether the timelock can be released in this transaction.
+            pub fn is_unlocked(self) -> bool {
+                self.terms()
+                    .is_some_and(|terms| terms.is_unlocked_at($crate::env::block_height()))
+            }
+
+            /// End the timelock. Detach the tokens it wraps after, in the
+            /// same transaction. Traps before the unlock height.
+            #[track_caller]
+            pub fn release(self) {
+                <Self as $crate::Utxo>::resume(self, ())
+            }
+        }
+    };
+}
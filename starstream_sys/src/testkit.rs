@@ -0,0 +1,664 @@
+//! Fixtures for contract tests on the [`mock`](crate::mock) host. Enabled by
+//! the `mock-host` feature, and under Miri.
+//!
+//! Everything here is deterministic, so a failing test fails the same way on
+//! every run and every machine.
+
+pub mod proptest;
+
+extern crate std;
+
+use std::{
+    any::Any,
+    boxed::Box,
+    collections::BTreeMap,
+    format,
+    mem::{size_of, MaybeUninit},
+    panic::{self, AssertUnwindSafe},
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    cbor, env,
+    hash::{bytes_of, soft},
+    mock::{self, Fault, HostCall, HostOp, MockHost, MockOutput},
+    tx::MAX_PAYLOAD,
+    CodeHash, FfiSafe, HostError, PrivateKey, PublicKey, Utxo, UtxoHandle,
+};
+
+// ----------------------------------------------------------------------------
+// Keys
+
+/// A key pair for tests, derived from its index by [`keys`].
+#[derive(Clone, Copy)]
+pub struct TestKey {
+    /// What the key pair is derived from. Distinct for every index.
+    pub seed: [u8; 32],
+    pub private: PrivateKey,
+    pub public: PublicKey,
+}
+
+/// The first `N` test key pairs. The same index always gives the same key,
+/// in every test, so expected signers can be written down once.
+///
+/// Keys have no representation yet, so every pair currently signs and
+/// verifies like every other; only [`TestKey::seed`] tells them apart.
+pub fn keys<const N: usize>() -> [TestKey; N] {
+    core::array::from_fn(|index| key(index as u32))
+}
+
+/// The test key pair at `index`, as returned by [`keys`].
+pub fn key(index: u32) -> TestKey {
+    let mut message = *b"starstream testkit key ....";
+    message[23..].copy_from_slice(&index.to_le_bytes());
+    let private = PrivateKey;
+    TestKey {
+        seed: soft::sha256(&[&message]),
+        public: private.public_key(),
+        private,
+    }
+}
+
+/// Sign the mock host's transaction with exactly `keys`, so that
+/// `tx_signed_by` holds for them alone. Until this is called, every key
+/// counts as a signer.
+pub fn sign_tx(keys: &[TestKey]) {
+    mock::with(|host| {
+        host.signers = Some(keys.iter().map(|key| key.public).collect());
+        host.signer_count = keys.len() as u32;
+    });
+}
+
+/// Make `message` verify as signed by `key`. Once anything is registered,
+/// only registered signatures verify; until then, every signature does.
+pub fn register_signature(key: &TestKey, message: &[u8]) {
+    mock::with(|host| {
+        let signatures = host.signatures.get_or_insert_with(Vec::new);
+        signatures.push((key.public, message.to_vec()));
+    });
+}
+
+// ----------------------------------------------------------------------------
+// Randomness
+
+/// A small seedable generator (SplitMix64). Not suitable for anything but
+/// tests.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    pub fn fill(&mut self, out: &mut [u8]) {
+        for chunk in out.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// Reseed this thread's mock host's generator, which serves the randomness
+/// import and [`random_bytes`].
+pub fn seed(seed: u64) {
+    mock::with(|host| host.rng = Rng::new(seed));
+}
+
+/// `N` bytes from the mock host's generator, e.g. for a commitment salt.
+pub fn random_bytes<const N: usize>() -> [u8; N] {
+    let mut out = [0; N];
+    mock::with(|host| host.rng.fill(&mut out));
+    out
+}
+
+// ----------------------------------------------------------------------------
+// Time
+
+/// Seconds [`advance_blocks`] moves the block time forward per block.
+pub const BLOCK_SECONDS: u64 = 10;
+
+/// Move the mock host `n` blocks forward, as seen by `env::block_height`,
+/// and the block time [`BLOCK_SECONDS`] per block, so vesting, auction, and
+/// expiry logic can be tested across time.
+pub fn advance_blocks(n: u64) {
+    mock::with(|host| {
+        host.block_height += n;
+        host.block_time += n * BLOCK_SECONDS;
+    });
+}
+
+/// Set the block time, in Unix seconds, as seen by `env::block_time`. Block
+/// times never decrease on the real host, so this panics if `time` is
+/// earlier than the current one.
+#[track_caller]
+pub fn set_time(time: u64) {
+    mock::with(|host| {
+        assert!(
+            time >= host.block_time,
+            "block time can't go back from {} to {time}",
+            host.block_time,
+        );
+        host.block_time = time;
+    });
+}
+
+// ----------------------------------------------------------------------------
+// Fuel
+
+/// Fuel the mock host has charged so far, under the costs in
+/// [`MockHost::fuel_costs`]. Contract code runs natively here, so only host
+/// calls and the bytes they copy are metered, not wasm instructions; a test
+/// can still pin the figure to catch a change that makes more or larger
+/// host calls.
+///
+/// ```ignore
+/// let before = testkit::fuel_used();
+/// Tx::new().call(MARKET, "buy", &listing).execute()?;
+/// assert!(testkit::fuel_used() - before <= 2_000);
+/// ```
+pub fn fuel_used() -> u64 {
+    mock::with(|host| host.fuel_used)
+}
+
+// ----------------------------------------------------------------------------
+// Snapshots
+
+/// A copy of the mock host's state, taken by [`snapshot`].
+pub struct Snapshot {
+    host: MockHost,
+}
+
+/// Copy this thread's mock host: its ledger of tokens, outputs, and events,
+/// and the rest of its environment, so a test can run one expensive setup
+/// and then [`restore`] it before exploring each branch.
+///
+/// Registered scripts and effect responders aren't part of a snapshot, and
+/// stay as they are across a `restore`. Neither are the bodies of UTXOs
+/// started with [`mock::spawn`], which keep running from wherever they are.
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        host: mock::with(|host| host.copy_state()),
+    }
+}
+
+/// Put this thread's mock host back as it was when `snapshot` was taken. A
+/// snapshot can be restored any number of times.
+pub fn restore(snapshot: &Snapshot) {
+    mock::with(|host| {
+        let mut restored = snapshot.host.copy_state();
+        restored.scripts = core::mem::take(&mut host.scripts);
+        restored.effects = core::mem::take(&mut host.effects);
+        *host = restored;
+    });
+}
+
+// ----------------------------------------------------------------------------
+// Aborts
+
+/// How contract code stopped, as caught by [`catch_abort`]. On the mock host
+/// a trap is a Rust panic, whose message the real host would print.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Aborted {
+    pub message: String,
+    /// The code passed to [`env::abort_with`](crate::env::abort_with), or
+    /// [`PANIC_CODE`](crate::env::PANIC_CODE) for a panic.
+    pub code: u32,
+}
+
+impl Aborted {
+    pub(crate) fn from_panic(payload: &(dyn Any + Send)) -> Aborted {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => match payload.downcast_ref::<String>() {
+                Some(message) => message.clone(),
+                None => "contract aborted".to_string(),
+            },
+        };
+        Aborted::from_message(message)
+    }
+
+    pub(crate) fn from_message(message: String) -> Aborted {
+        let code = (message.strip_prefix(mock::ABORT_PREFIX))
+            .and_then(|rest| rest.split_once(':'))
+            .and_then(|(code, _)| code.parse().ok())
+            .unwrap_or(env::PANIC_CODE);
+        Aborted { message, code }
+    }
+
+    /// Whether the contract panicked, rather than rejecting with
+    /// [`env::abort_with`](crate::env::abort_with).
+    pub fn is_panic(&self) -> bool {
+        self.code == env::PANIC_CODE
+    }
+}
+
+/// Run `f`, returning how it aborted if it did.
+pub fn catch_abort<R>(f: impl FnOnce() -> R) -> Result<R, Aborted> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| Aborted::from_panic(&*payload))
+}
+
+/// Assert that `f` aborts with a message containing `expected`, such as
+/// `"mint failed: unauthorized"` or `"code 42"`, so a test checks that a call failed for
+/// the right reason rather than for any reason.
+#[track_caller]
+pub fn assert_aborts_with<R>(expected: &str, f: impl FnOnce() -> R) -> Aborted {
+    match catch_abort(f) {
+        Ok(_) => panic!("expected an abort with {expected:?}, but it returned"),
+        Err(aborted) => {
+            assert!(
+                aborted.message.contains(expected),
+                "expected an abort with {expected:?}, got {:?}",
+                aborted.message,
+            );
+            aborted
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Faults
+
+/// Make the `nth` run of `op` from now on fail with `error`, counting from
+/// 1, so a test can exercise a `try_*` error path deterministically:
+///
+/// ```ignore
+/// testkit::fail_nth(HostOp::Resume, 3, HostError::AlreadyConsumed);
+/// ```
+pub fn fail_nth(op: HostOp, nth: u32, error: HostError) {
+    mock::with(|host| {
+        let done = host.op_counts.get(&op).copied().unwrap_or(0);
+        host.faults.push(Fault {
+            op,
+            nth: Some(done + nth),
+            error,
+        });
+    });
+}
+
+/// Make every run of `op` fail with `error` from now on, such as every mint
+/// with `HostError::Unauthorized`.
+pub fn fail_always(op: HostOp, error: HostError) {
+    mock::with(|host| {
+        host.faults.push(Fault {
+            op,
+            nth: None,
+            error,
+        })
+    });
+}
+
+/// An imported UTXO whose resumes run `f` on the mock host, so coordination
+/// code driving a UTXO can be tested without its exporter. It stays
+/// `Yielded` for as long as the mock host lasts.
+pub fn stub_utxo<T: Utxo + 'static>(
+    mut f: impl FnMut(T::Resume) -> T::Yield + Send + 'static,
+) -> T {
+    let responder: mock::Responder = Box::new(move |arg| bytes_of(&f(decode(arg))).to_vec());
+    let raw = mock::with(|host| {
+        let handle = host.handle();
+        host.utxos.insert(handle, responder);
+        handle
+    });
+    T::from_handle(UtxoHandle::from_raw(raw).expect("mock handles start at 1"))
+}
+
+// ----------------------------------------------------------------------------
+// Tracing
+
+/// The host calls made on this thread's mock host so far, oldest first.
+///
+/// ```ignore
+/// let trace = testkit::trace();
+/// trace
+///     .expect_event::<Minted>("TokenMinted")
+///     .with(|minted| minted.amount == 100);
+/// trace.expect_log(21);
+/// ```
+pub fn trace() -> Trace {
+    Trace {
+        calls: mock::with(|host| host.trace.clone()),
+    }
+}
+
+/// Forget the calls traced so far, e.g. after a test's setup.
+pub fn clear_trace() {
+    mock::with(|host| host.trace.clear());
+}
+
+/// A snapshot of [`MockHost::trace`] with assertions over it.
+#[derive(Clone, Debug)]
+pub struct Trace {
+    pub calls: Vec<HostCall>,
+}
+
+impl Trace {
+    /// The payloads of events emitted under `topic`, oldest first.
+    pub fn events<T: FfiSafe>(&self, topic: &str) -> Vec<T> {
+        (self.calls.iter())
+            .filter_map(|call| match call {
+                HostCall::Event { topic: t, payload } if t == topic => Some(decode(payload)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Assert that at least one event was emitted under `topic`, and narrow
+    /// down further with [`Matches::with`].
+    #[track_caller]
+    pub fn expect_event<T: FfiSafe>(&self, topic: &str) -> Matches<T> {
+        let found = self.events(topic);
+        assert!(
+            !found.is_empty(),
+            "expected an event {topic:?}, but none was emitted"
+        );
+        Matches {
+            what: topic.to_string(),
+            found,
+        }
+    }
+
+    /// Assert that `value` was logged.
+    #[track_caller]
+    pub fn expect_log(&self, value: u32) {
+        assert!(
+            self.calls.contains(&HostCall::Log(value)),
+            "expected {value} to be logged",
+        );
+    }
+
+    /// Assert that the line `text` was logged.
+    #[track_caller]
+    pub fn expect_log_line(&self, text: &str) {
+        assert!(
+            (self.calls.iter()).any(|call| matches!(call, HostCall::LogLine(line) if line == text)),
+            "expected {text:?} to be logged",
+        );
+    }
+
+    /// The intermediates minted as tokens of type `Token`, oldest first.
+    pub fn mints<Token, I: FfiSafe>(&self) -> Vec<I> {
+        let name = core::any::type_name::<Token>();
+        (self.calls.iter())
+            .filter_map(|call| match call {
+                HostCall::Mint {
+                    token,
+                    intermediate,
+                    ..
+                } if *token == name => Some(decode(intermediate)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The intermediates burned from tokens of type `Token`, oldest first.
+    pub fn burns<Token, I: FfiSafe>(&self) -> Vec<I> {
+        let name = core::any::type_name::<Token>();
+        (self.calls.iter())
+            .filter_map(|call| match call {
+                HostCall::Burn {
+                    token,
+                    intermediate,
+                    ..
+                } if *token == name => Some(decode(intermediate)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The values yielded in state `state`, oldest first.
+    pub fn yields<T: FfiSafe>(&self, state: &str) -> Vec<T> {
+        (self.calls.iter())
+            .filter_map(|call| match call {
+                HostCall::Yield { state: s, data } if s == state => Some(decode(data)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Traced values that matched so far; see [`Trace::expect_event`].
+pub struct Matches<T> {
+    what: String,
+    pub found: Vec<T>,
+}
+
+impl<T> Matches<T> {
+    /// Assert that at least one of the matches satisfies `predicate`, and
+    /// keep only those that do.
+    #[track_caller]
+    pub fn with(self, predicate: impl Fn(&T) -> bool) -> Matches<T> {
+        let found: Vec<T> = self
+            .found
+            .into_iter()
+            .filter(|value| predicate(value))
+            .collect();
+        assert!(!found.is_empty(), "no {:?} matched", self.what);
+        Matches {
+            what: self.what,
+            found,
+        }
+    }
+
+    /// Assert exactly `count` matches.
+    #[track_caller]
+    pub fn times(self, count: usize) -> Matches<T> {
+        assert!(
+            self.found.len() == count,
+            "expected {count} {:?}, found {}",
+            self.what,
+            self.found.len(),
+        );
+        self
+    }
+}
+
+#[track_caller]
+fn decode<T: FfiSafe>(bytes: &[u8]) -> T {
+    assert!(
+        bytes.len() == size_of::<T>(),
+        "traced {} bytes, but the type expected is {} bytes",
+        bytes.len(),
+        size_of::<T>(),
+    );
+    let mut value = MaybeUninit::<T>::uninit();
+    unsafe {
+        core::ptr::copy_nonoverlapping(bytes.as_ptr(), value.as_mut_ptr() as *mut u8, bytes.len());
+        value.assume_init()
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Transactions
+
+/// Make the coordination script entry point `entry` reachable by [`Tx::call`]
+/// and `coordination::call`. `f` is an export taking its arguments from the
+/// payload, as `coordination_export!` generates for `#[payload]`; for other
+/// entry points, insert a responder into [`MockHost::scripts`] directly.
+pub fn deploy(entry: &str, f: extern "C-unwind" fn()) {
+    let responder: mock::Responder = Box::new(move |args| {
+        let (payload, result) = mock::with(|host| {
+            let payload = core::mem::replace(&mut host.payload, args.to_vec());
+            (payload, host.result.take())
+        });
+        f();
+        mock::with(|host| {
+            host.payload = payload;
+            core::mem::replace(&mut host.result, result).unwrap_or_default()
+        })
+    });
+    mock::with(|host| host.scripts.insert(entry.to_string(), responder));
+}
+
+/// A transaction to run against the mock host, built up as a scenario:
+///
+/// ```ignore
+/// let [alice, bob] = testkit::keys();
+/// testkit::deploy("list", market::list);
+/// testkit::deploy("buy", market::buy);
+/// let listed = Tx::new().sign(&alice).call(MARKET, "list", &price).execute()?;
+/// let bought = Tx::new().sign(&bob).call(MARKET, "buy", &listing).execute()?;
+/// bought.trace.expect_event::<Sold>("Sold").times(1);
+/// ```
+#[derive(Default)]
+pub struct Tx {
+    signers: Vec<TestKey>,
+    calls: Vec<(CodeHash, String, Vec<u8>)>,
+}
+
+/// What a successful [`Tx`] changed on the mock host.
+pub struct Receipt {
+    /// The value each call returned with `tx::set_result`, in call order,
+    /// or empty if it set none.
+    pub results: Vec<Vec<u8>>,
+    /// UTXOs the transaction created. The mock host has no UTXOs to spend,
+    /// so there are no inputs to report.
+    pub outputs: Vec<MockOutput>,
+    /// Tokens minted and not burned again, by handle, as intermediate bytes.
+    pub minted: BTreeMap<u32, Vec<u8>>,
+    /// Tokens that existed before the transaction and were burned by it.
+    pub burned: BTreeMap<u32, Vec<u8>>,
+    /// The host calls the transaction made, including its events.
+    pub trace: Trace,
+}
+
+impl Tx {
+    pub fn new() -> Tx {
+        Tx::default()
+    }
+
+    /// Add `key` to the transaction's signers.
+    pub fn sign(mut self, key: &TestKey) -> Tx {
+        self.signers.push(*key);
+        self
+    }
+
+    /// Run entry point `entry` of the script with hash `script`, deployed
+    /// with [`deploy`], with `args`, encoded as CBOR, as its payload. Calls
+    /// run in order.
+    pub fn call<Args: cbor::Encode + ?Sized>(
+        mut self,
+        script: CodeHash,
+        entry: &str,
+        args: &Args,
+    ) -> Tx {
+        let mut payload = vec![0; MAX_PAYLOAD];
+        let len = cbor::to_slice(args, &mut payload)
+            .unwrap_or_else(|e| panic!("arguments to {entry:?}: {e}"))
+            .len();
+        payload.truncate(len);
+        self.calls.push((script, entry.to_string(), payload));
+        self
+    }
+
+    /// Run the calls as one transaction. If any of them aborts, the mock
+    /// host is put back as it was, as the real host drops a failed
+    /// transaction, and the abort is returned.
+    pub fn execute(self) -> Result<Receipt, Aborted> {
+        let before = snapshot();
+        let (outputs, traced) = mock::with(|host| (host.outputs.len(), host.trace.len()));
+        sign_tx(&self.signers);
+        let run = catch_abort(|| {
+            let mut results = Vec::new();
+            for (script, entry, args) in self.calls {
+                let Some(mut responder) = mock::with(|host| {
+                    host.coordination_code = script;
+                    host.this_code = script;
+                    host.is_coordination = true;
+                    host.scripts.remove(&entry)
+                }) else {
+                    panic!("no script deployed for entry point {entry:?}");
+                };
+                let result = responder(&args);
+                mock::with(|host| host.scripts.insert(entry, responder));
+                results.push(result);
+            }
+            results
+        });
+        let results = match run {
+            Ok(results) => results,
+            Err(aborted) => {
+                restore(&before);
+                return Err(aborted);
+            }
+        };
+        let tokens_before = &before.host.tokens;
+        Ok(mock::with(|host| Receipt {
+            results,
+            outputs: host.outputs[outputs..].to_vec(),
+            minted: (host.tokens.iter())
+                .filter(|(handle, _)| !tokens_before.contains_key(handle))
+                .map(|(handle, bytes)| (*handle, bytes.clone()))
+                .collect(),
+            burned: (tokens_before.iter())
+                .filter(|(handle, _)| !host.tokens.contains_key(handle))
+                .map(|(handle, bytes)| (*handle, bytes.clone()))
+                .collect(),
+            trace: Trace {
+                calls: host.trace[traced..].to_vec(),
+            },
+        }))
+    }
+}
+
+impl Receipt {
+    /// The value call number `index` returned.
+    #[track_caller]
+    pub fn result<T: FfiSafe>(&self, index: usize) -> T {
+        decode(&self.results[index])
+    }
+
+    /// What the transaction did, in the text format `starstream_it_runner`'s
+    /// `diff` module replays the same calls against compiled contracts and
+    /// compares with, to catch where the mock host's emulation of the ABI
+    /// diverges from the real one.
+    pub fn transcript(&self) -> String {
+        let calls = &self.trace.calls;
+        let mut lines = Vec::new();
+        for call in calls {
+            if let HostCall::Log(value) = call {
+                lines.push(format!("log {value}"));
+            }
+        }
+        for call in calls {
+            if let HostCall::Event { topic, payload } = call {
+                lines.push(format!("event {topic} {}", hex(payload)));
+            }
+        }
+        for call in calls {
+            if let HostCall::Mint { token, .. } = call {
+                lines.push(format!("mint {}", short_name(token)));
+            }
+        }
+        for call in calls {
+            if let HostCall::Burn { token, .. } = call {
+                lines.push(format!("burn {}", short_name(token)));
+            }
+        }
+        for result in &self.results {
+            lines.push(format!("result {}", hex(result)));
+        }
+        lines.iter().map(|line| format!("{line}\n")).collect()
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// `StarNft` for `example_contract::StarNft`, as the runner names tokens.
+fn short_name(type_name: &str) -> &str {
+    type_name.rsplit("::").next().unwrap_or(type_name)
+}
@@ -0,0 +1,341 @@
+//! The transaction a coordination script is running in: its payload and
+//! result, which UTXOs it spends, which it creates, who signed it, the fee it
+//! owes, and the events emitted so far, plus outputs for the host to create
+//! when the script succeeds and sections to undo if they fail.
+//!
+//! Lets a script check invariants such as "exactly one output goes back to
+//! the seller" against what actually happened, rather than trusting its
+//! arguments. Only available to coordination scripts, except where noted.
+
+use core::mem::{size_of, MaybeUninit};
+
+use crate::{
+    cbor::{self, Decoder},
+    hash::Part,
+    utxo::{AnyUtxo, HandlePages},
+    last_host_error, poison, CodeHash, FfiSafe, HostError, PublicKey, Token,
+};
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_tx_inputs(cursor: u32, out: *mut u32, out_len: usize) -> usize;
+    unsafe fn starstream_tx_outputs(cursor: u32, out: *mut u32, out_len: usize) -> usize;
+    safe fn starstream_tx_signer_count() -> u32;
+    safe fn starstream_tx_payload_len() -> usize;
+    unsafe fn starstream_tx_payload(payload: *mut (), payload_size: usize);
+    unsafe fn starstream_tx_result(result: *const (), result_size: usize);
+    unsafe fn starstream_tx_require_code(code: *const CodeHash);
+}
+
+/// The longest payload [`payload`] reads, and so the longest arguments
+/// `coordination::call` passes.
+pub const MAX_PAYLOAD: usize = 4096;
+
+/// Decode the payload submitted with this transaction, or the arguments
+/// passed to `coordination::call`. Payloads are CBOR, and must be exactly one
+/// `T`; anything else traps, saying where the payload went wrong.
+#[track_caller]
+pub fn payload<T: for<'a> cbor::Decode<'a>>() -> T {
+    let len = starstream_tx_payload_len();
+    let mut buf = [0; MAX_PAYLOAD];
+    let Some(bytes) = buf.get_mut(..len) else {
+        trap!(
+            "payload of {} bytes is longer than the {} a script reads",
+            len,
+            MAX_PAYLOAD
+        );
+    };
+    unsafe { starstream_tx_payload(bytes.as_mut_ptr() as *mut (), len) };
+    let mut d = Decoder::new(bytes);
+    let value = match d.decode::<T>() {
+        Ok(value) => value,
+        Err(e) => trap!(
+            "malformed {} payload at byte {}: {}",
+            core::any::type_name::<T>(),
+            d.position(),
+            e,
+        ),
+    };
+    if !d.is_done() {
+        trap!(
+            "malformed {} payload: {} trailing bytes",
+            core::any::type_name::<T>(),
+            d.rest().len(),
+        );
+    }
+    value
+}
+
+/// Set the result of this transaction, such as the id of a created UTXO or
+/// an executed price, which the host records in the transaction receipt for
+/// the submitter. In a script run by `coordination::call`, this is instead
+/// the value returned to the caller. Setting it again replaces it.
+pub fn set_result<T: FfiSafe>(result: &T) {
+    unsafe { starstream_tx_result(result as *const T as *const (), size_of::<T>()) }
+}
+
+/// Fail the transaction unless the coordination script with hash `code` also
+/// runs in it, as the entry point or through `coordination::call`. Lets logic
+/// be split across modules, such as a contract that relies on a separate
+/// validator or oracle script. The check is made when the transaction ends,
+/// so the script may run before or after this call. Unlike the rest of this
+/// module, UTXOs and tokens may call this too.
+pub fn assert_includes_code(code: CodeHash) {
+    unsafe { starstream_tx_require_code(&code) }
+}
+
+/// UTXOs that existed before this transaction and that it has touched so far,
+/// whether passed in as arguments or found with `utxo::find`.
+pub fn inputs() -> impl Iterator<Item = AnyUtxo> {
+    HandlePages::new(|cursor, out, out_len| unsafe { starstream_tx_inputs(cursor, out, out_len) })
+}
+
+/// UTXOs created by this transaction that are still alive, and so will be in
+/// the UTXO set if it commits now. Outputs from [`OutputBuilder`] aren't
+/// included, since they are only created once the script returns.
+pub fn outputs() -> impl Iterator<Item = AnyUtxo> {
+    HandlePages::new(|cursor, out, out_len| unsafe { starstream_tx_outputs(cursor, out, out_len) })
+}
+
+/// The keys that signed this transaction.
+pub fn signers() -> impl Iterator<Item = PublicKey> {
+    // TODO: read the keys themselves once PublicKey has a representation.
+    (0..starstream_tx_signer_count()).map(|_| PublicKey { _0: () })
+}
+
+// ----------------------------------------------------------------------------
+// Fees
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    safe fn starstream_tx_fee() -> u64;
+    safe fn starstream_tx_pay_fee(token: u32, amount: u64);
+}
+
+/// The fee this transaction owes. The host fails the transaction if the
+/// scripts it runs haven't paid at least this much by the time it returns.
+pub fn fee() -> u64 {
+    starstream_tx_fee()
+}
+
+/// Pay `amount` toward the transaction fee out of a token this script holds,
+/// such as one detached from a sponsor's UTXO. The rest of the token stays
+/// with the script under the same handle, unless it is used up entirely.
+pub fn pay_fee_from<T: Token>(token: T, amount: u64) -> Result<(), HostError> {
+    let raw = token.handle().raw();
+    poison::check(poison::Kind::Token, raw)?;
+    starstream_tx_pay_fee(raw, amount);
+    match last_host_error() {
+        Some(e) => Err(poison::record(poison::Kind::Token, raw, e)),
+        None => Ok(()),
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Events
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_emit_event(
+        topic: *const u8,
+        topic_len: usize,
+        payload: *const (),
+        payload_size: usize,
+    );
+    unsafe fn starstream_emit_event_parts(
+        topic: *const u8,
+        topic_len: usize,
+        parts: *const Part,
+        count: usize,
+    );
+    unsafe fn starstream_tx_event(
+        topic: *const u8,
+        topic_len: usize,
+        index: u32,
+        payload: *mut (),
+        payload_size: usize,
+    ) -> bool;
+}
+
+/// Emit an event under `topic`. It is recorded in the transaction receipt,
+/// and contracts running later in the same transaction can read it with
+/// [`events_by_topic`]. Any contract may emit events.
+pub fn emit_event<T: FfiSafe>(topic: &str, payload: &T) {
+    unsafe {
+        starstream_emit_event(
+            topic.as_ptr(),
+            topic.len(),
+            payload as *const T as *const (),
+            size_of::<T>(),
+        )
+    }
+}
+
+/// Emit an event under `topic` whose payload is the concatenation of
+/// `parts`, such as a fixed header and a variable-length body. The host
+/// gathers the parts itself, so nothing is copied into an intermediate
+/// buffer and the whole event is one host call, cheap enough for tight
+/// loops like batch settlements.
+pub fn emit_event_parts<const N: usize>(topic: &str, parts: [&[u8]; N]) {
+    let parts = parts.map(|part| Part {
+        ptr: part.as_ptr(),
+        len: part.len(),
+    });
+    unsafe { starstream_emit_event_parts(topic.as_ptr(), topic.len(), parts.as_ptr(), N) }
+}
+
+/// Payloads of the events emitted so far in this transaction under `topic`,
+/// oldest first, so that one module can react to another, such as a fee
+/// module charging for each `"swap"`. The host traps if one isn't the size of
+/// a `T`. Any contract may read events.
+pub fn events_by_topic<T: FfiSafe>(topic: &str) -> impl Iterator<Item = T> + '_ {
+    (0..).map_while(move |index| {
+        let mut payload = MaybeUninit::<T>::uninit();
+        unsafe {
+            starstream_tx_event(
+                topic.as_ptr(),
+                topic.len(),
+                index,
+                payload.as_mut_ptr() as *mut (),
+                size_of::<T>(),
+            )
+            .then(|| payload.assume_init())
+        }
+    })
+}
+
+// ----------------------------------------------------------------------------
+// Scratch storage
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_scratch_set(
+        key: *const u8,
+        key_len: usize,
+        value: *const u8,
+        value_len: usize,
+    );
+    unsafe fn starstream_scratch_get(
+        key: *const u8,
+        key_len: usize,
+        out: *mut u8,
+        out_len: usize,
+        value_len: *mut usize,
+    ) -> bool;
+}
+
+/// Store `value` under `key` for the rest of this transaction, replacing any
+/// previous value. Every script, UTXO, and token running in the transaction
+/// shares one scratch space, so cooperating contracts can pass intermediate
+/// results without threading them through resume arguments. Keys should be
+/// namespaced, such as `b"dex/last_price"`. Nothing is kept once the
+/// transaction ends.
+pub fn scratch_set(key: &[u8], value: &[u8]) {
+    unsafe { starstream_scratch_set(key.as_ptr(), key.len(), value.as_ptr(), value.len()) }
+}
+
+/// Copy the value stored under `key` into `out`, returning its full length,
+/// or `None` if nothing is stored. If `out` is too short, only its length is
+/// copied.
+pub fn scratch_get(key: &[u8], out: &mut [u8]) -> Option<usize> {
+    let mut len = 0;
+    unsafe {
+        starstream_scratch_get(key.as_ptr(), key.len(), out.as_mut_ptr(), out.len(), &mut len)
+    }
+    .then_some(len)
+}
+
+// ----------------------------------------------------------------------------
+// Atomic sections
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    safe fn starstream_checkpoint() -> u32;
+    safe fn starstream_rollback(checkpoint: u32);
+    safe fn starstream_release(checkpoint: u32);
+}
+
+/// Proof of running inside [`atomic`]. Helpers whose effects should always be
+/// undoable, like an optimistic fill, can take a `&Scope` to require it.
+pub struct Scope {
+    _private: (),
+}
+
+/// Run `f`, and if it returns an error, undo everything it did to the
+/// transaction: UTXOs it created, resumed, or consumed, tokens it minted,
+/// burned, attached, or detached, outputs, channel messages, fees, events,
+/// and scratch storage. This script's own memory is not rolled back, and
+/// handles obtained inside `f` are invalid after a rollback. A trap inside
+/// `f` still fails the whole transaction.
+///
+/// ```ignore
+/// let filled = tx::atomic(|_| fill_from(cheapest_pool, amount))
+///     .or_else(|_| tx::atomic(|_| fill_from(fallback_pool, amount)))?;
+/// ```
+pub fn atomic<T, E>(f: impl FnOnce(&Scope) -> Result<T, E>) -> Result<T, E> {
+    let checkpoint = starstream_checkpoint();
+    let result = f(&Scope { _private: () });
+    match result {
+        Ok(_) => starstream_release(checkpoint),
+        Err(_) => starstream_rollback(checkpoint),
+    }
+    result
+}
+
+// ----------------------------------------------------------------------------
+// Outputs
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_output_begin(
+        code: *const CodeHash,
+        entry: *const u8,
+        entry_len: usize,
+    ) -> u32;
+    unsafe fn starstream_output_args(output: u32, args: *const (), args_size: usize);
+    safe fn starstream_output_attach(output: u32, token: u32);
+    safe fn starstream_output_finish(output: u32);
+}
+
+/// Declares a UTXO for the host to create once this coordination script
+/// returns successfully, rather than spawning it immediately.
+///
+/// ```ignore
+/// OutputBuilder::new(code, "starstream_new_Escrow_new")
+///     .args(&EscrowArgs { seller, price })
+///     .attach(nft)
+///     .finish();
+/// ```
+#[must_use = "the output is only created if `finish` is called"]
+pub struct OutputBuilder {
+    output: u32,
+}
+
+impl OutputBuilder {
+    /// Start an output running `entry` of the contract with hash `code`.
+    pub fn new(code: CodeHash, entry: &str) -> OutputBuilder {
+        OutputBuilder {
+            output: unsafe { starstream_output_begin(&code, entry.as_ptr(), entry.len()) },
+        }
+    }
+
+    /// Set the argument struct the UTXO reads with `env::init_args`.
+    pub fn args<T: FfiSafe>(self, args: &T) -> OutputBuilder {
+        unsafe {
+            starstream_output_args(self.output, args as *const T as *const (), size_of::<T>())
+        };
+        self
+    }
+
+    /// Attach a token this script holds, such as one from `Utxo::detach`.
+    pub fn attach<T: Token>(self, token: T) -> OutputBuilder {
+        starstream_output_attach(self.output, token.handle().raw());
+        self
+    }
+
+    /// Commit the output. Tokens attached to a builder that is never finished
+    /// stay with the script, and so fail the transaction if left unattached.
+    pub fn finish(self) {
+        starstream_output_finish(self.output)
+    }
+}
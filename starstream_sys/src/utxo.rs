@@ -0,0 +1,459 @@
+//! Working with UTXO handles beyond the `Utxo` trait: type-erased handles,
+//! discovery, batch resumes, and typestate handles.
+
+use core::{
+    fmt,
+    marker::PhantomData,
+    mem::{size_of, MaybeUninit},
+    panic::Location,
+    ptr,
+};
+
+use crate::{
+    starstream_utxo_id, CodeHash, FfiSafe, HostError, PublicKey, Utxo, UtxoError, UtxoHandle,
+    UtxoId, UtxoStatus,
+};
+
+#[link(wasm_import_module = "env")]
+unsafe extern "C" {
+    unsafe fn starstream_utxo_resume_batch(entries: *const BatchResume<'_>, len: usize) -> u32;
+    unsafe fn starstream_utxo_is(
+        utxo: u32,
+        module: *const u8,
+        module_len: usize,
+        name: *const u8,
+        name_len: usize,
+    ) -> bool;
+    unsafe fn starstream_find_utxos(
+        code: *const CodeHash,
+        owner: *const PublicKey,
+        cursor: u32,
+        out: *mut u32,
+        out_len: usize,
+    ) -> usize;
+    unsafe fn starstream_lookup_utxo(id: *const UtxoId) -> Option<AnyUtxo>;
+}
+
+// ----------------------------------------------------------------------------
+// Type-erased handles
+
+/// A handle to a UTXO of any type, as returned by [`find`].
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct AnyUtxo(UtxoHandle<AnyUtxo>);
+
+impl AnyUtxo {
+    #[inline]
+    pub(crate) fn from_raw(raw: u32) -> Option<AnyUtxo> {
+        UtxoHandle::from_raw(raw).map(AnyUtxo)
+    }
+
+    #[inline]
+    pub fn id(self) -> UtxoId {
+        starstream_utxo_id(self.0.raw())
+    }
+
+    /// Whether this is a UTXO of type `T`.
+    pub fn is<T: Utxo>(self) -> bool {
+        unsafe {
+            starstream_utxo_is(
+                self.0.raw(),
+                T::MODULE.as_ptr(),
+                T::MODULE.len(),
+                T::NAME.as_ptr(),
+                T::NAME.len(),
+            )
+        }
+    }
+
+    pub fn downcast<T: Utxo>(self) -> Option<T> {
+        self.is::<T>().then(|| {
+            T::from_handle(UtxoHandle {
+                ptr: self.0.ptr,
+                _phantom: PhantomData,
+            })
+        })
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Discovery
+
+/// Live UTXOs matching `code` and `owner`, where `None` matches anything.
+/// Matching by owner only finds UTXOs following the ownership convention
+/// (see `Owned`).
+pub fn find(code: Option<CodeHash>, owner: Option<PublicKey>) -> impl Iterator<Item = AnyUtxo> {
+    HandlePages::new(move |cursor, out, out_len| unsafe {
+        starstream_find_utxos(
+            code.as_ref().map_or(ptr::null(), |c| c),
+            owner.as_ref().map_or(ptr::null(), |o| o),
+            cursor,
+            out,
+            out_len,
+        )
+    })
+}
+
+/// The live UTXO with id `id`, whether it existed before this transaction or
+/// was created by it. Lets scripts take compact ids as arguments instead of
+/// handles.
+pub fn lookup(id: UtxoId) -> Option<AnyUtxo> {
+    unsafe { starstream_lookup_utxo(&id) }
+}
+
+/// Live UTXOs of type `T`, optionally only those owned by `owner`.
+pub fn find_of<T: Utxo>(owner: Option<PublicKey>) -> impl Iterator<Item = T> {
+    find(None, owner).filter_map(AnyUtxo::downcast)
+}
+
+/// Pages through UTXO handles from a host import taking
+/// `(cursor, out, out_len)` and returning how many it wrote.
+pub(crate) struct HandlePages<F> {
+    fetch: F,
+    cursor: u32,
+    page: [MaybeUninit<u32>; HANDLE_PAGE_LEN],
+    len: usize,
+    pos: usize,
+    done: bool,
+}
+
+const HANDLE_PAGE_LEN: usize = 16;
+
+impl<F: FnMut(u32, *mut u32, usize) -> usize> HandlePages<F> {
+    pub(crate) fn new(fetch: F) -> Self {
+        HandlePages {
+            fetch,
+            cursor: 0,
+            page: [MaybeUninit::uninit(); HANDLE_PAGE_LEN],
+            len: 0,
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<F: FnMut(u32, *mut u32, usize) -> usize> Iterator for HandlePages<F> {
+    type Item = AnyUtxo;
+
+    fn next(&mut self) -> Option<AnyUtxo> {
+        if self.pos == self.len {
+            if self.done {
+                return None;
+            }
+            self.len = (self.fetch)(
+                self.cursor,
+                self.page.as_mut_ptr() as *mut u32,
+                HANDLE_PAGE_LEN,
+            );
+            self.cursor += self.len as u32;
+            self.pos = 0;
+            self.done = self.len < HANDLE_PAGE_LEN;
+            if self.len == 0 {
+                return None;
+            }
+        }
+        let raw = unsafe { self.page[self.pos].assume_init() };
+        self.pos += 1;
+        match AnyUtxo::from_raw(raw) {
+            Some(utxo) => Some(utxo),
+            None => trap!("host listed an invalid UTXO handle"),
+        }
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Batches
+
+/// One entry of a [`resume_batch`]: a UTXO and the bytes of its resume
+/// argument.
+#[repr(C)]
+pub struct BatchResume<'a> {
+    utxo: u32,
+    arg: *const u8,
+    arg_len: usize,
+    _phantom: PhantomData<&'a [u8]>,
+}
+
+#[cfg(target_arch = "wasm32")]
+crate::assert_abi_layout!(BatchResume<'_>, 12, 4, utxo: 0, arg: 4, arg_len: 8);
+
+impl<'a> BatchResume<'a> {
+    pub fn new<U: Utxo>(utxo: U, arg: &'a U::Resume) -> Self {
+        BatchResume {
+            utxo: utxo.handle().raw(),
+            arg: arg as *const U::Resume as *const u8,
+            arg_len: size_of::<U::Resume>(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+/// Resume every UTXO in `batch`, in order, with one host call. Whatever they
+/// yield is discarded.
+///
+/// Stops at the first UTXO that rejects its resume, traps, or can't be
+/// resumed at all, such as one already consumed, returning its index in
+/// `batch` and the error; later entries are not resumed.
+pub fn resume_batch(batch: &[BatchResume<'_>]) -> Result<(), (usize, UtxoError)> {
+    match unsafe { starstream_utxo_resume_batch(batch.as_ptr(), batch.len()) } {
+        0 => Ok(()),
+        failed => Err((failed as usize - 1, crate::__last_resume_error())),
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Tagged resume arguments
+
+/// The wire form of a resume argument with several variants, such as the
+/// resume enums `utxo_import!` declares: the variant's index and its
+/// argument's bytes. The payload is in words so there is no padding, and
+/// every bit pattern is a valid `RawResume`, so unlike the enum it is
+/// [`FfiSafe`]. The enum's `from_raw` checks the tag.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct RawResume<const WORDS: usize> {
+    pub tag: u32,
+    pub payload: [u32; WORDS],
+}
+
+unsafe impl<const WORDS: usize> FfiSafe for RawResume<WORDS> {}
+
+impl<const WORDS: usize> RawResume<WORDS> {
+    /// Variant `tag` with argument `arg`, zero-filled after it.
+    pub fn new<T: FfiSafe>(tag: u32, arg: T) -> Self {
+        const { assert!(size_of::<T>() <= 4 * WORDS, "resume argument too large") };
+        let mut raw = RawResume {
+            tag,
+            payload: [0; WORDS],
+        };
+        unsafe { ptr::write_unaligned(raw.payload.as_mut_ptr() as *mut T, arg) };
+        raw
+    }
+
+    /// The argument, read as a `T`.
+    pub fn payload<T: FfiSafe>(&self) -> T {
+        const { assert!(size_of::<T>() <= 4 * WORDS, "resume argument too large") };
+        // Any bytes are a valid `T`.
+        unsafe { ptr::read_unaligned(self.payload.as_ptr() as *const T) }
+    }
+}
+
+/// The `WORDS` of a [`RawResume`] that fits arguments of each of `sizes`.
+#[doc(hidden)]
+pub const fn __resume_words(sizes: &[usize]) -> usize {
+    let mut words = 0;
+    let mut i = 0;
+    while i < sizes.len() {
+        let needed = sizes[i].div_ceil(4);
+        if needed > words {
+            words = needed;
+        }
+        i += 1;
+    }
+    words
+}
+
+/// Trap on a [`RawResume`] tag that names no variant of `name`.
+#[doc(hidden)]
+#[cold]
+pub fn __unknown_tag(name: &str, tag: u32) -> ! {
+    trap!("{} has no variant with tag {}", name, tag)
+}
+
+// ----------------------------------------------------------------------------
+// Resume diagnostics
+
+/// `Utxo::resume`: resume, trapping on failure. Resuming a UTXO that has
+/// already ended is a common mistake, so that trap also says where it was
+/// last resumed, which is usually the resume it ended in.
+#[track_caller]
+pub(crate) fn resume_or_trap<T: Utxo>(handle: UtxoHandle<T>, arg: T::Resume) -> T::Yield {
+    let here = Location::caller();
+    let previous = resume_sites::replace(handle.raw(), here);
+    match T::from_handle(handle).try_resume(arg) {
+        Ok(yielded) => yielded,
+        Err(e) => resume_failed(handle, e, here, previous),
+    }
+}
+
+#[cold]
+fn resume_failed<T: Utxo>(
+    handle: UtxoHandle<T>,
+    error: UtxoError,
+    here: &Location,
+    previous: Option<&Location>,
+) -> ! {
+    match (error, previous) {
+        (UtxoError::Host(HostError::AlreadyConsumed), Some(previous)) => trap!(
+            "UTXO {} resumed at {} after it ended; last resumed at {}",
+            Label(handle),
+            here,
+            previous,
+        ),
+        (UtxoError::Host(HostError::AlreadyConsumed), None) => {
+            trap!("UTXO {} resumed at {} after it ended", Label(handle), here)
+        }
+        (error, _) => trap!("resume rejected: {}", error),
+    }
+}
+
+/// Names a UTXO in a trap message: by id, or by handle on the mock host,
+/// which doesn't give stubbed UTXOs ids.
+struct Label<T: Utxo>(UtxoHandle<T>);
+
+impl<T: Utxo> fmt::Display for Label<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        #[cfg(any(feature = "mock-host", miri))]
+        return write!(f, "with handle {}", self.0.raw());
+        #[cfg(not(any(feature = "mock-host", miri)))]
+        return write!(f, "{}", T::from_handle(self.0).id());
+    }
+}
+
+/// Where recently resumed UTXOs were last resumed, by raw handle.
+#[cfg(any(feature = "mock-host", miri))]
+mod resume_sites {
+    use core::panic::Location;
+
+    pub fn replace(
+        handle: u32,
+        site: &'static Location<'static>,
+    ) -> Option<&'static Location<'static>> {
+        crate::mock::with(|host| host.resume_sites.insert(handle, site))
+    }
+}
+
+/// Where recently resumed UTXOs were last resumed, by raw handle. Only the
+/// last few are kept, since wasm has no allocator to grow into.
+#[cfg(not(any(feature = "mock-host", miri)))]
+mod resume_sites {
+    use core::{cell::UnsafeCell, panic::Location};
+
+    type Site = &'static Location<'static>;
+
+    struct Sites {
+        entries: [Option<(u32, Site)>; 8],
+        next: usize,
+    }
+
+    struct Table(UnsafeCell<Sites>);
+
+    // Wasm contracts are single-threaded.
+    unsafe impl Sync for Table {}
+
+    static SITES: Table = Table(UnsafeCell::new(Sites {
+        entries: [None; 8],
+        next: 0,
+    }));
+
+    pub fn replace(handle: u32, site: Site) -> Option<Site> {
+        let sites = unsafe { &mut *SITES.0.get() };
+        for (h, s) in sites.entries.iter_mut().flatten() {
+            if *h == handle {
+                return Some(core::mem::replace(s, site));
+            }
+        }
+        let next = sites.next;
+        sites.entries[next] = Some((handle, site));
+        sites.next = (next + 1) % sites.entries.len();
+        None
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Typestate handles
+
+/// Type-level UTXO states for [`UtxoHandle`], so that for linear flows,
+/// resuming a UTXO that isn't known to be yielded fails to compile.
+///
+/// Only [`Any`](state::Any) handles are `Copy`. A resume uses up the
+/// [`Yielded`](state::Yielded) handle, so the same one can't be resumed
+/// twice:
+///
+/// ```compile_fail,E0382
+/// use starstream::{utxo::Status, Utxo, UtxoHandle};
+///
+/// fn resume_twice<T: Utxo<Resume = ()>>(handle: UtxoHandle<T>) {
+///     if let Status::Yielded(yielded) = handle.status() {
+///         yielded.resume(());
+///         yielded.resume(());
+///     }
+/// }
+/// ```
+///
+/// Instead, check the status of the handle the resume gives back:
+///
+/// ```ignore
+/// if let Status::Yielded(yielded) = handle.status() {
+///     let (_, handle) = yielded.resume(());
+///     if let Status::Yielded(yielded) = handle.status() {
+///         yielded.resume(());
+///     }
+/// }
+/// ```
+pub mod state {
+    /// Status unknown. The default, and what every handle starts as.
+    pub struct Any;
+    /// Known to be yielded, so it can be resumed.
+    pub struct Yielded;
+    /// Known to have returned.
+    pub struct Returned;
+}
+
+/// The result of [`UtxoHandle::status`], carrying a handle in the new state.
+pub enum Status<T> {
+    Yielded(UtxoHandle<T, state::Yielded>),
+    Returned(UtxoHandle<T, state::Returned>),
+    Consumed,
+    Errored,
+}
+
+impl<T, S> UtxoHandle<T, S> {
+    fn cast<S2>(self) -> UtxoHandle<T, S2> {
+        UtxoHandle {
+            ptr: self.ptr,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Forget the tracked state.
+    #[inline]
+    pub fn any(self) -> UtxoHandle<T> {
+        self.cast()
+    }
+}
+
+impl<T: Utxo, S> UtxoHandle<T, S> {
+    /// The underlying UTXO, for operations that work in any state.
+    #[inline]
+    pub fn utxo(self) -> T {
+        T::from_handle(self.cast())
+    }
+}
+
+impl<T: Utxo> UtxoHandle<T> {
+    /// Check the status, moving into the matching state.
+    pub fn status(self) -> Status<T> {
+        match T::from_handle(self).status() {
+            UtxoStatus::Yielded => Status::Yielded(self.cast()),
+            UtxoStatus::Returned => Status::Returned(self.cast()),
+            UtxoStatus::Consumed => Status::Consumed,
+            UtxoStatus::Errored => Status::Errored,
+        }
+    }
+}
+
+impl<T: Utxo> UtxoHandle<T, state::Yielded> {
+    /// Resume the UTXO. Its status afterwards is unknown until checked again.
+    #[track_caller]
+    pub fn resume(self, arg: T::Resume) -> (T::Yield, UtxoHandle<T>) {
+        let handle = self.any();
+        (handle.utxo().resume(arg), handle)
+    }
+
+    /// Like `resume`, but a rejection by the UTXO is returned as an error.
+    pub fn try_resume(self, arg: T::Resume) -> Result<(T::Yield, UtxoHandle<T>), UtxoError> {
+        let handle = self.any();
+        Ok((handle.utxo().try_resume(arg)?, handle))
+    }
+}